@@ -0,0 +1,73 @@
+// See LICENSE file for copyright and license details.
+
+// Run with `cargo bench --package core`.
+
+extern crate cgmath;
+extern crate core;
+
+use std::time::{Instant};
+use cgmath::{Vector2};
+use core::{MapPos};
+use core::fov::{Fov};
+use core::map::{Map, Terrain};
+use core::types::{Size2, ZInt};
+
+const RANGE: ZInt = 8;
+const ITERATIONS: u32 = 200;
+
+fn test_map() -> Map<Terrain> {
+    let size = Size2{w: 24, h: 24};
+    let mut map = Map::new(&size);
+    for pos in map.get_iter() {
+        // some deterministic pattern of woods and buildings
+        let n = pos.v.x * 7 + pos.v.y * 13;
+        if n % 11 == 0 {
+            *map.tile_mut(&pos) = Terrain::Trees;
+        } else if n % 17 == 0 {
+            *map.tile_mut(&pos) = Terrain::City;
+        }
+    }
+    map
+}
+
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    let start = Instant::now();
+    for _ in 0 .. ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let ns = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    println!("{:<24} {:>12} ns/iter", name, ns / ITERATIONS as u64);
+}
+
+fn main() {
+    let map = test_map();
+    let origins: Vec<MapPos> = map.get_iter().collect();
+    let target = MapPos{v: Vector2{x: 12, y: 12}};
+    let mut fov = Fov::new();
+    bench("fov (no cache)", || {
+        fov.clear_cache();
+        for origin in &origins {
+            fov.fov(&map, origin, RANGE, &mut |_| {});
+        }
+    });
+    bench("fov (cached)", || {
+        for origin in &origins {
+            fov.fov(&map, origin, RANGE, &mut |_| {});
+        }
+    });
+    bench("los (via fov, no cache)", || {
+        fov.clear_cache();
+        for origin in &origins {
+            let mut v = false;
+            fov.fov(&map, origin, RANGE, &mut |p| if *p == target { v = true });
+        }
+    });
+    bench("los", || {
+        for origin in &origins {
+            fov.los(&map, origin, &target, RANGE);
+        }
+    });
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -1,152 +1,865 @@
 // See LICENSE file for copyright and license details.
 
-use types::{Size2};
+use std::cell::{RefCell};
+use std::collections::{HashSet};
+use rand::{Rng, XorShiftRng};
+use types::{ZInt, ZFloat};
 use game_state::{GameState, GameStateMut};
 use partial_state::{PartialState};
-use map::{distance};
-use pathfinder::{self, Pathfinder, path_cost, truncate_path};
-use dir::{Dir};
-use unit::{Unit};
+use map::{Map, distance};
+use pathfinder::{Pathfinder};
+use dir::{Dir, dirs};
+use unit::{Unit, UnitTypeId, UnitClass};
 use db::{Db};
+use bot::{Bot};
+use scenario::{Scenario};
 use ::{
     CoreEvent,
     Command,
     MoveMode,
+    MovePoints,
+    AttackPoints,
+    ReactionFireMode,
     PlayerId,
+    UnitId,
+    MapPos,
     ExactPos,
+    AiDifficulty,
+    AiPosture,
+    VisionSharing,
     check_command,
+    cover_bonus,
+    get_attack_odds,
     get_free_exact_pos,
+    is_target_spotted,
+    is_transporter,
+    is_towed,
+    move_cost_modifier,
+    free_transport_capacity,
+    seeded_rng,
 };
 
+/// Infantry far from enemies rides a transport if there is one nearby
+const LOAD_DISTANCE: ZInt = 6;
+
+/// Transports unload infantry this close to enemies
+const UNLOAD_DISTANCE: ZInt = 4;
+
+/// Moves that improve a position by less than this are not worth it
+const MIN_MOVE_IMPROVEMENT: ZFloat = 0.01;
+
+/// Defenders within this distance of an objective are holding it
+const HOLD_RADIUS: ZInt = 1;
+
+/// Defenders value cover and objectives more than attackers value
+/// cover and closeness to enemies
+const DEFENSE_COVER_FACTOR: ZFloat = 2.0;
+const DEFENSE_APPROACH_FACTOR: ZFloat = 3.0;
+
+/// Unused attack points of defenders become reactive ones so
+/// an attack must be this much better than `min_attack_score`
+const DEFENSE_MIN_ATTACK_FACTOR: ZFloat = 2.0;
+
+/// Units that see this far lead the search for enemies
+const SCOUT_LOS_RANGE: ZInt = 8;
+
+/// Distance between the scouts and the rest of the force
+const FOLLOW_DISTANCE: ZInt = 2;
+
+/// Penalty for every other own unit in the tile: spread out formation
+/// is harder to spot and to ambush
+const CROWD_PENALTY: ZFloat = 0.5;
+
+/// Coefficients of the utility function that rates attacks and positions
+struct Weights {
+    /// Value of destroying a whole enemy unit
+    kill: ZFloat,
+
+    /// Value of one point of cover bonus
+    cover: ZFloat,
+
+    /// Penalty for losing a whole unit to enemy fire
+    threat: ZFloat,
+
+    /// Value of being one tile closer to the goal of the posture:
+    /// the nearest known enemy (or unexplored tiles if there are none)
+    /// in attack, the nearest objective in defense
+    approach: ZFloat,
+
+    /// Attacks that score less than this are not worth attack points
+    min_attack_score: ZFloat,
+
+    /// Amplitude of a random component of every score
+    noise: ZFloat,
+
+    /// Move in `MoveMode::Hunt` under enemy fire
+    use_hunt: bool,
+
+    use_transports: bool,
+
+    /// Last known positions of enemies older than this are ignored
+    memory_turns: ZInt,
+}
+
+fn weights(difficulty: &AiDifficulty) -> Weights {
+    match *difficulty {
+        AiDifficulty::Easy => Weights {
+            kill: 1.0,
+            cover: 0.0,
+            threat: 0.0,
+            approach: 0.1,
+            min_attack_score: 0.0,
+            noise: 0.5,
+            use_hunt: false,
+            use_transports: false,
+            memory_turns: 0,
+        },
+        AiDifficulty::Normal => Weights {
+            kill: 1.0,
+            cover: 0.05,
+            threat: 0.5,
+            approach: 0.1,
+            min_attack_score: 0.02,
+            noise: 0.1,
+            use_hunt: true,
+            use_transports: true,
+            memory_turns: 1,
+        },
+        AiDifficulty::Hard => Weights {
+            kill: 1.0,
+            cover: 0.1,
+            threat: 1.0,
+            approach: 0.1,
+            min_attack_score: 0.05,
+            noise: 0.0,
+            use_hunt: true,
+            use_transports: true,
+            memory_turns: 3,
+        },
+    }
+}
+
+/// Enemy that is visible now or was seen recently
+struct KnownEnemy {
+    unit: Unit,
+
+    /// 1.0 for visible enemies, less for old last known positions
+    certainty: ZFloat,
+}
+
+/// Where to look for enemies when none are known
+struct Exploration {
+    /// Fogged tiles on the edge of the explored area
+    /// and positions of heard noises
+    targets: Vec<MapPos>,
+
+    /// Positions of units that lead the search
+    scouts: Vec<MapPos>,
+}
+
+fn is_scout(db: &Db, unit: &Unit) -> bool {
+    db.unit_type(&unit.type_id).los_range >= SCOUT_LOS_RANGE
+}
+
+fn nearest_distance(positions: &[MapPos], pos: &MapPos) -> Option<ZInt> {
+    positions.iter().map(|p| distance(p, pos)).min()
+}
+
+/// Stub of a unit that is not in the state: a remembered
+/// enemy or an own unit at some other position
+fn phantom_unit(db: &Db, type_id: &UnitTypeId, player_id: &PlayerId, pos: &ExactPos) -> Unit {
+    let unit_type = db.unit_type(type_id);
+    Unit {
+        id: UnitId{id: -1},
+        pos: pos.clone(),
+        player_id: player_id.clone(),
+        type_id: type_id.clone(),
+        move_points: MovePoints{n: 0},
+        attack_points: AttackPoints{n: 0},
+        reactive_attack_points: None,
+        reaction_fire_mode: ReactionFireMode::Normal,
+        count: unit_type.count,
+        morale: 100,
+        passenger_ids: Vec::new(),
+        towed_unit_id: None,
+        is_contact: false,
+    }
+}
+
 pub struct Ai {
     id: PlayerId,
     state: PartialState,
     pathfinder: Pathfinder,
+    vision_sharing: VisionSharing,
+    weights: Weights,
+    posture: AiPosture,
+
+    /// Tiles that the AI holds in `AiPosture::Defend`
+    objectives: Vec<MapPos>,
+
+    /// The last turn when the tile was visible
+    last_seen: Map<Option<ZInt>>,
+
+    /// Units that got a rejected command during this turn
+    stuck_unit_ids: HashSet<UnitId>,
+
+    /// Noise of the scores is rolled in `&self` methods
+    rng: RefCell<XorShiftRng>,
 }
 
 impl Ai {
-    pub fn new(id: &PlayerId, map_size: &Size2) -> Ai {
+    pub fn new(
+        id: &PlayerId,
+        scenario: &Scenario,
+        vision_sharing: &VisionSharing,
+        difficulty: &AiDifficulty,
+        posture: &AiPosture,
+        seed: u64,
+    ) -> Ai {
         Ai {
             id: id.clone(),
-            state: PartialState::new(map_size, id),
-            pathfinder: Pathfinder::new(map_size),
+            state: PartialState::from_scenario(scenario, id),
+            pathfinder: Pathfinder::new(scenario.size()),
+            vision_sharing: vision_sharing.clone(),
+            weights: weights(difficulty),
+            posture: posture.clone(),
+            objectives: scenario.objectives.clone(),
+            last_seen: Map::new(scenario.size()),
+            stuck_unit_ids: HashSet::new(),
+            rng: RefCell::new(seeded_rng(seed)),
         }
     }
 
-    pub fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
-        self.state.apply_event(db, event);
+    fn random_noise(&self) -> ZFloat {
+        if self.weights.noise > 0.0 {
+            self.rng.borrow_mut().gen_range(0.0, self.weights.noise)
+        } else {
+            0.0
+        }
     }
 
-    // TODO: move fill_map here
-    fn get_best_pos(&self, db: &Db, unit: &Unit) -> Option<ExactPos> {
-        let mut best_pos = None;
-        let mut best_cost = pathfinder::max_cost();
-        for (_, enemy) in self.state.units() {
-            if enemy.player_id == self.id {
+    /// Units in the order of their ids: scores get the noise
+    /// in the same order so games with the same seed are the same
+    fn units(&self) -> Vec<&Unit> {
+        let mut units: Vec<_> = self.state.units().values().collect();
+        units.sort_by_key(|unit| unit.id.id);
+        units
+    }
+
+    fn is_passenger(&self, unit: &Unit) -> bool {
+        for other in self.units() {
+            if other.passenger_ids.contains(&unit.id) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn own_units(&self) -> Vec<&Unit> {
+        let mut units = Vec::new();
+        for unit in self.units() {
+            if unit.player_id == self.id
+                && !self.stuck_unit_ids.contains(&unit.id)
+            {
+                units.push(unit);
+            }
+        }
+        units
+    }
+
+    fn known_enemies(&self, db: &Db) -> Vec<KnownEnemy> {
+        let mut enemies = Vec::new();
+        for unit in self.units() {
+            if unit.player_id != self.id {
+                enemies.push(KnownEnemy {
+                    unit: phantom_unit(db, &unit.type_id, &unit.player_id, &unit.pos),
+                    certainty: 1.0,
+                });
+            }
+        }
+        let mut ghosts: Vec<_> = self.state.ghosts().values().collect();
+        ghosts.sort_by_key(|ghost| ghost.unit_id.id);
+        for ghost in ghosts {
+            let age = self.state.turn() - ghost.turn;
+            if age > self.weights.memory_turns {
                 continue;
             }
-            for i in 0 .. 6 {
-                let dir = Dir::from_int(i);
-                let destination = Dir::get_neighbour_pos(&enemy.pos.map_pos, &dir);
-                if !self.state.map().is_inboard(&destination) {
-                    continue;
-                }
-                let exact_destination = match get_free_exact_pos(
-                    db, &self.state, &unit.type_id, &destination
-                ) {
-                    Some(pos) => pos,
-                    None => continue,
-                };
-                let path = match self.pathfinder.get_path(&exact_destination) {
-                    Some(path) => path,
-                    None => continue,
-                };
-                let cost = path_cost(db, &self.state, unit, &path);
-                if best_cost.n > cost.n {
-                    best_cost.n = cost.n;
-                    best_pos = Some(exact_destination);
+            enemies.push(KnownEnemy {
+                unit: phantom_unit(db, &ghost.type_id, &ghost.player_id, &ghost.pos),
+                certainty: 1.0 / (age + 1) as ZFloat,
+            });
+        }
+        enemies
+    }
+
+    fn distance_to_nearest_enemy(&self, enemies: &[KnownEnemy], pos: &MapPos) -> Option<ZInt> {
+        enemies.iter()
+            .map(|enemy| distance(&enemy.unit.pos.map_pos, pos))
+            .min()
+    }
+
+    fn distance_to_nearest_objective(&self, pos: &MapPos) -> Option<ZInt> {
+        nearest_distance(&self.objectives, pos)
+    }
+
+    fn update_last_seen(&mut self) {
+        let turn = self.state.turn();
+        for pos in self.state.map().get_iter() {
+            if self.state.is_tile_visible(&pos) {
+                *self.last_seen.tile_mut(&pos) = Some(turn);
+            }
+        }
+    }
+
+    fn is_explored(&self, pos: &MapPos) -> bool {
+        self.last_seen.tile(pos).is_some()
+    }
+
+    fn exploration(&self, db: &Db) -> Exploration {
+        let mut targets: Vec<_> = self.state.noises().iter()
+            .map(|noise| noise.pos.clone())
+            .collect();
+        for pos in self.state.map().get_iter() {
+            if self.is_explored(&pos) {
+                continue;
+            }
+            let is_frontier = dirs().any(|dir| {
+                let neighbour_pos = Dir::get_neighbour_pos(&pos, &dir);
+                self.state.map().is_inboard(&neighbour_pos)
+                    && self.is_explored(&neighbour_pos)
+            });
+            if is_frontier {
+                targets.push(pos);
+            }
+        }
+        if targets.is_empty() {
+            // the whole map was seen at least once so
+            // recheck the tiles that were seen too long ago
+            let oldest = self.state.map().get_iter()
+                .filter(|pos| !self.state.is_tile_visible(pos))
+                .filter_map(|pos| *self.last_seen.tile(&pos))
+                .min();
+            for pos in self.state.map().get_iter() {
+                if !self.state.is_tile_visible(&pos)
+                    && oldest.is_some()
+                    && *self.last_seen.tile(&pos) == oldest
+                {
+                    targets.push(pos);
                 }
             }
         }
-        best_pos
+        let scouts = self.own_units().iter()
+            .filter(|unit| is_scout(db, unit))
+            .map(|unit| unit.pos.map_pos.clone())
+            .collect();
+        Exploration {
+            targets: targets,
+            scouts: scouts,
+        }
+    }
+
+    /// Scouts go to the nearest unexplored tile and the
+    /// rest of the force follows them in a loose formation
+    fn exploration_distance(
+        &self,
+        db: &Db,
+        exploration: &Exploration,
+        unit: &Unit,
+        pos: &MapPos,
+    ) -> ZFloat {
+        if is_scout(db, unit) || exploration.scouts.is_empty() {
+            return match nearest_distance(&exploration.targets, pos) {
+                Some(dist) => dist as ZFloat,
+                None => 0.0,
+            };
+        }
+        let scouts: Vec<_> = exploration.scouts.iter()
+            .filter(|scout_pos| **scout_pos != unit.pos.map_pos)
+            .cloned()
+            .collect();
+        let dist = match nearest_distance(&scouts, pos) {
+            Some(dist) => (dist - FOLLOW_DISTANCE).abs() as ZFloat,
+            None => 0.0,
+        };
+        let crowd = self.state.units_at(pos).iter()
+            .filter(|other| other.player_id == self.id && other.id != unit.id)
+            .count();
+        dist + CROWD_PENALTY * crowd as ZFloat
     }
 
-    fn is_close_to_enemies(&self, db: &Db, unit: &Unit) -> bool {
-        for (_, target) in self.state.units() {
-            if target.player_id == self.id {
+    fn is_in_range(&self, db: &Db, unit: &Unit, target_pos: &MapPos) -> bool {
+        let unit_type = db.unit_type(&unit.type_id);
+        let weapon_type = db.weapon_type(&unit_type.weapon_type_id);
+        let dist = distance(&unit.pos.map_pos, target_pos);
+        dist <= weapon_type.max_distance && dist >= weapon_type.min_distance
+    }
+
+    /// Expected fraction of the unit that enemies destroy
+    /// with one shot each if the unit stands at `pos`
+    fn threat(&self, db: &Db, enemies: &[KnownEnemy], unit: &Unit, pos: &ExactPos) -> ZFloat {
+        let target = phantom_unit(db, &unit.type_id, &unit.player_id, pos);
+        let mut threat = 0.0;
+        for enemy in enemies {
+            let enemy_type = db.unit_type(&enemy.unit.type_id);
+            let weapon_type = db.weapon_type(&enemy_type.weapon_type_id);
+            let dist = distance(&enemy.unit.pos.map_pos, &pos.map_pos);
+            if dist > weapon_type.max_distance || dist < weapon_type.min_distance {
                 continue;
             }
-            let attacker_type = db.unit_type(&unit.type_id);
-            let weapon_type = db.weapon_type(&attacker_type.weapon_type_id);
-            if distance(&unit.pos.map_pos, &target.pos.map_pos) <= weapon_type.max_distance {
-                return true;
+            if !weapon_type.is_inderect && !::los(
+                &self.state, enemy_type, &enemy.unit.pos.map_pos, &pos.map_pos)
+            {
+                continue;
             }
+            let odds = get_attack_odds(db, &self.state, &enemy.unit, &target);
+            threat += enemy.certainty * odds.expected_killed / unit.count as ZFloat;
         }
-        false
+        threat
     }
 
-    pub fn try_get_attack_command(&self, db: &Db) -> Option<Command> {
-        for (_, unit) in self.state.units() {
-            if unit.player_id != self.id {
+    fn is_target_spotted(&self, db: &Db, unit: &Unit, target: &Unit) -> bool {
+        let unit_type = db.unit_type(&unit.type_id);
+        if !db.weapon_type(&unit_type.weapon_type_id).is_inderect {
+            return true;
+        }
+        match self.vision_sharing {
+            VisionSharing::Full => true,
+            VisionSharing::CommandNet => {
+                is_target_spotted(db, &self.state, unit, target)
+            },
+        }
+    }
+
+    fn attack_score(&self, db: &Db, attacker: &Unit, defender: &Unit) -> ZFloat {
+        let odds = get_attack_odds(db, &self.state, attacker, defender);
+        let mut score = self.weights.kill * odds.expected_killed
+            / defender.count as ZFloat;
+        if self.posture == AiPosture::Defend {
+            // a defender that opens fire gives away its position
+            // so it's only worth it if the exchange is favorable
+            if self.is_in_range(db, defender, &attacker.pos.map_pos) {
+                let odds = get_attack_odds(db, &self.state, defender, attacker);
+                score -= self.weights.threat * odds.expected_killed
+                    / attacker.count as ZFloat;
+            }
+        }
+        score
+    }
+
+    /// How good it is for `unit` to stand at `pos`
+    fn position_score(
+        &self,
+        db: &Db,
+        enemies: &[KnownEnemy],
+        exploration: &Exploration,
+        unit: &Unit,
+        pos: &ExactPos,
+    ) -> ZFloat {
+        let stub = phantom_unit(db, &unit.type_id, &unit.player_id, pos);
+        let cover = cover_bonus(db, &self.state, &stub) as ZFloat;
+        let mut score = self.weights.cover * cover;
+        score -= self.weights.threat * self.threat(db, enemies, unit, pos);
+        match self.posture {
+            AiPosture::Attack => {
+                if let Some(dist) = self.distance_to_nearest_enemy(enemies, &pos.map_pos) {
+                    score -= self.weights.approach * dist as ZFloat;
+                } else {
+                    score -= self.weights.approach * self.exploration_distance(
+                        db, exploration, unit, &pos.map_pos);
+                }
+            },
+            AiPosture::Defend => {
+                score += self.weights.cover * cover * (DEFENSE_COVER_FACTOR - 1.0);
+                if let Some(dist) = self.distance_to_nearest_objective(&pos.map_pos) {
+                    let dist = if dist > HOLD_RADIUS { dist - HOLD_RADIUS } else { 0 };
+                    score -= self.weights.approach * DEFENSE_APPROACH_FACTOR
+                        * dist as ZFloat;
+                }
+            },
+        }
+        let unit_type = db.unit_type(&unit.type_id);
+        let weapon_type = db.weapon_type(&unit_type.weapon_type_id);
+        let mut best_target_score = 0.0;
+        for enemy in enemies {
+            let dist = distance(&enemy.unit.pos.map_pos, &pos.map_pos);
+            if dist > weapon_type.max_distance || dist < weapon_type.min_distance {
                 continue;
             }
+            let target_score = enemy.certainty
+                * self.attack_score(db, &stub, &enemy.unit);
+            if target_score > best_target_score {
+                best_target_score = target_score;
+            }
+        }
+        // it's only a chance to shoot so it worth less than a real attack
+        score + best_target_score / 2.0
+    }
+
+    fn try_get_attack_command(&self, db: &Db) -> Option<Command> {
+        let mut best_command = None;
+        let mut best_score = match self.posture {
+            AiPosture::Attack => self.weights.min_attack_score,
+            AiPosture::Defend => {
+                self.weights.min_attack_score * DEFENSE_MIN_ATTACK_FACTOR
+            },
+        };
+        for unit in self.own_units() {
             if unit.attack_points.n <= 0 {
                 continue;
             }
-            for (_, target) in self.state.units() {
+            for target in self.units() {
                 if target.player_id == self.id {
                     continue;
                 }
+                if !self.is_target_spotted(db, unit, target) {
+                    continue;
+                }
                 let command = Command::AttackUnit {
                     attacker_id: unit.id.clone(),
                     defender_id: target.id.clone(),
                 };
-                if let Ok(()) = check_command(db, &self.state, &command) {
-                    return Some(command);
+                if check_command(db, &self.state, &command).is_err() {
+                    continue;
+                }
+                let score = self.attack_score(db, unit, target) + self.random_noise();
+                if score > best_score {
+                    best_score = score;
+                    best_command = Some(command);
                 }
             }
         }
+        best_command
+    }
+
+    /// Defenders rely on the reaction fire so nobody holds fire
+    fn try_get_reaction_fire_command(&self, db: &Db) -> Option<Command> {
+        for unit in self.own_units() {
+            let unit_type = db.unit_type(&unit.type_id);
+            if !db.weapon_type(&unit_type.weapon_type_id).reaction_fire {
+                continue;
+            }
+            if unit.reaction_fire_mode != ReactionFireMode::Normal {
+                return Some(Command::SetReactionFireMode {
+                    unit_id: unit.id.clone(),
+                    mode: ReactionFireMode::Normal,
+                });
+            }
+        }
         None
     }
 
-    pub fn try_get_move_command(&mut self, db: &Db) -> Option<Command> {
-        for (_, unit) in self.state.units() {
-            if unit.player_id != self.id {
+    fn try_get_unload_command(&self, db: &Db, enemies: &[KnownEnemy]) -> Option<Command> {
+        for transporter in self.own_units() {
+            if !is_transporter(db, transporter) {
                 continue;
             }
-            if self.is_close_to_enemies(db, unit) {
+            let pos = &transporter.pos.map_pos;
+            match self.distance_to_nearest_enemy(enemies, pos) {
+                Some(dist) if dist <= UNLOAD_DISTANCE => {},
+                _ => continue,
+            }
+            for passenger_id in &transporter.passenger_ids {
+                let passenger = self.state.unit(passenger_id);
+                let mut positions = vec![pos.clone()];
+                for dir in dirs() {
+                    positions.push(Dir::get_neighbour_pos(pos, &dir));
+                }
+                for map_pos in &positions {
+                    if !self.state.map().is_inboard(map_pos) {
+                        continue;
+                    }
+                    let exact_pos = match get_free_exact_pos(
+                        db, &self.state, &passenger.type_id, map_pos)
+                    {
+                        Some(exact_pos) => exact_pos,
+                        None => continue,
+                    };
+                    let command = Command::UnloadUnit {
+                        transporter_id: transporter.id.clone(),
+                        passenger_id: passenger_id.clone(),
+                        pos: exact_pos,
+                    };
+                    if check_command(db, &self.state, &command).is_ok() {
+                        return Some(command);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn try_get_load_command(&self, db: &Db, enemies: &[KnownEnemy]) -> Option<Command> {
+        for passenger in self.own_units() {
+            if db.unit_type(&passenger.type_id).class != UnitClass::Infantry
+                || self.is_passenger(passenger)
+            {
                 continue;
             }
+            let pos = &passenger.pos.map_pos;
+            match self.distance_to_nearest_enemy(enemies, pos) {
+                Some(dist) if dist > LOAD_DISTANCE => {},
+                _ => continue,
+            }
+            for transporter in self.own_units() {
+                if !is_transporter(db, transporter)
+                    || transporter.move_points.n <= 0
+                    || free_transport_capacity(db, &self.state, transporter) < passenger.count
+                {
+                    continue;
+                }
+                let command = Command::LoadUnit {
+                    transporter_id: transporter.id.clone(),
+                    passenger_id: passenger.id.clone(),
+                };
+                if check_command(db, &self.state, &command).is_ok() {
+                    return Some(command);
+                }
+            }
+        }
+        None
+    }
+
+    /// Finds the best reachable position for the unit and
+    /// returns the move command with the improvement of the score
+    fn get_best_move(
+        &mut self,
+        db: &Db,
+        enemies: &[KnownEnemy],
+        exploration: &Exploration,
+        unit_id: &UnitId,
+    ) -> Option<(Command, ZFloat)> {
+        {
+            let unit = self.state.unit(unit_id);
             self.pathfinder.fill_map(db, &self.state, unit);
-            // TODO: if no enemy is visible then move to random invisible tile
-            let destination = match self.get_best_pos(db, unit) {
-                Some(destination) => destination,
-                None => continue,
-            };
-            let path = match self.pathfinder.get_path(&destination) {
-                Some(path) => path,
-                None => continue,
-            };
-            if unit.move_points.n == 0 {
+        }
+        let unit = self.state.unit(unit_id);
+        let current_score = self.position_score(
+            db, enemies, exploration, unit, &unit.pos);
+        let mut best_pos = None;
+        let mut best_score = current_score + MIN_MOVE_IMPROVEMENT;
+        for map_pos in self.state.map().get_iter() {
+            let tile = self.pathfinder.get_map().tile(&map_pos);
+            if tile.cost().n == 0 || tile.cost().n > unit.move_points.n {
                 continue;
             }
-            let path = truncate_path(db, &self.state, &path, unit);
-            return Some(Command::Move {
-                unit_id: unit.id.clone(),
-                path: path,
-                mode: MoveMode::Fast,
-            });
+            let pos = ExactPos {
+                map_pos: map_pos.clone(),
+                slot_id: tile.slot_id().clone(),
+            };
+            let score = self.position_score(db, enemies, exploration, unit, &pos)
+                + self.random_noise();
+            if score > best_score {
+                best_score = score;
+                best_pos = Some(pos);
+            }
         }
-        None
+        let destination = match best_pos {
+            Some(pos) => pos,
+            None => return None,
+        };
+        let path = match self.pathfinder.get_path(&destination) {
+            Some(path) => path,
+            None => return None,
+        };
+        let cost = self.pathfinder.get_map().tile(&destination).cost().n;
+        let is_under_fire = self.threat(db, enemies, unit, &destination) > 0.0;
+        let mode = if self.weights.use_hunt && is_under_fire
+            && cost * move_cost_modifier(&MoveMode::Hunt) <= unit.move_points.n
+        {
+            MoveMode::Hunt
+        } else {
+            MoveMode::Fast
+        };
+        let command = Command::Move {
+            unit_id: unit_id.clone(),
+            path: path,
+            mode: mode,
+        };
+        if check_command(db, &self.state, &command).is_err() {
+            return None;
+        }
+        Some((command, best_score - current_score))
     }
 
-    pub fn get_command(&mut self, db: &Db) -> Command {
+    fn try_get_move_command(
+        &mut self,
+        db: &Db,
+        enemies: &[KnownEnemy],
+        exploration: &Exploration,
+    ) -> Option<Command> {
+        let mut unit_ids = Vec::new();
+        for unit in self.own_units() {
+            if unit.move_points.n > 0
+                && !self.is_passenger(unit)
+                && !is_towed(&self.state, &unit.id)
+            {
+                unit_ids.push(unit.id.clone());
+            }
+        }
+        let mut best_command = None;
+        let mut best_improvement = 0.0;
+        for unit_id in &unit_ids {
+            if let Some((command, improvement)) = self.get_best_move(
+                db, enemies, exploration, unit_id) {
+                if improvement > best_improvement {
+                    best_improvement = improvement;
+                    best_command = Some(command);
+                }
+            }
+        }
+        best_command
+    }
+}
+
+impl Bot for Ai {
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
+        if let CoreEvent::EndTurn{ref new_id, ..}
+            | CoreEvent::EndPlanning{ref new_id, ..} = *event
+        {
+            if *new_id == self.id {
+                self.stuck_unit_ids.clear();
+            }
+        }
+        self.state.apply_event(db, event);
+    }
+
+    /// Core rejected the command because the state of the AI is not
+    /// complete (a hidden unit blocks the path, for example) so the
+    /// unit is left alone until the next turn.
+    fn command_rejected(&mut self, command: &Command) {
+        let unit_id = match *command {
+            Command::Move{ref unit_id, ..} => unit_id,
+            Command::AttackUnit{ref attacker_id, ..} => attacker_id,
+            Command::Assault{ref attacker_id, ..} => attacker_id,
+            Command::LoadUnit{ref passenger_id, ..} => passenger_id,
+            Command::UnloadUnit{ref transporter_id, ..} => transporter_id,
+            Command::SetReactionFireMode{ref unit_id, ..} => unit_id,
+            Command::EndTurn | Command::CreateUnit{..} => return,
+        };
+        self.stuck_unit_ids.insert(unit_id.clone());
+    }
+
+    fn get_command(&mut self, db: &Db) -> Command {
+        self.update_last_seen();
+        let enemies = self.known_enemies(db);
+        let exploration = self.exploration(db);
+        if self.posture == AiPosture::Defend {
+            if let Some(cmd) = self.try_get_reaction_fire_command(db) {
+                return cmd;
+            }
+        }
         if let Some(cmd) = self.try_get_attack_command(db) {
-            cmd
-        } else if let Some(cmd) = self.try_get_move_command(db) {
-            cmd
-        } else {
-            Command::EndTurn
+            return cmd;
+        }
+        if self.weights.use_transports {
+            if let Some(cmd) = self.try_get_unload_command(db, &enemies) {
+                return cmd;
+            }
+            if self.posture == AiPosture::Attack {
+                if let Some(cmd) = self.try_get_load_command(db, &enemies) {
+                    return cmd;
+                }
+            }
+        }
+        if let Some(cmd) = self.try_get_move_command(db, &enemies, &exploration) {
+            return cmd;
+        }
+        Command::EndTurn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{Size2};
+    use db::{Db};
+    use bot::{Bot};
+    use scenario::{Scenario};
+    use ::{
+        CoreEvent,
+        Command,
+        UnitInfo,
+        PlayerId,
+        UnitId,
+        MapPos,
+        ExactPos,
+        SlotId,
+        AiDifficulty,
+        AiPosture,
+        VisionSharing,
+        ReactionFireMode,
+    };
+    use test_utils::{create_unit_event};
+    use super::{Ai};
+
+    #[test]
+    fn test_scout_explores_fog() {
+        let db = Db::new();
+        let player_id = PlayerId{id: 1};
+        let mut ai = Ai::new(
+            &player_id,
+            &Scenario::new(&Size2{w: 24, h: 8}),
+            &VisionSharing::Full,
+            &AiDifficulty::Hard,
+            &AiPosture::Attack,
+            0,
+        );
+        ai.apply_event(&db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 0},
+            new_id: player_id.clone(),
+        });
+        for &(id, x, type_name) in &[(0, 22, "scout"), (1, 23, "soldier")] {
+            ai.apply_event(&db, &CoreEvent::CreateUnit {
+                unit_info: UnitInfo {
+                    unit_id: UnitId{id: id},
+                    pos: ExactPos {
+                        map_pos: MapPos{v: Vector2{x: x, y: 4}},
+                        slot_id: SlotId::Id(0),
+                    },
+                    type_id: db.unit_type_id(type_name),
+                    player_id: player_id.clone(),
+                    passenger_ids: Vec::new(),
+                    towed_unit_id: None,
+                    is_contact: false,
+                },
+            });
+        }
+        match ai.get_command(&db) {
+            Command::Move{ref unit_id, ref path, ..} => {
+                assert_eq!(unit_id.id, 0);
+                assert!(path.last().unwrap().map_pos.v.x < 22);
+            },
+            _ => panic!("Scout must go to the fog"),
+        }
+    }
+
+    #[test]
+    fn test_defender_uses_reaction_fire() {
+        let db = Db::new();
+        let player_id = PlayerId{id: 1};
+        let mut ai = Ai::new(
+            &player_id,
+            &Scenario::new(&Size2{w: 8, h: 8}),
+            &VisionSharing::Full,
+            &AiDifficulty::Normal,
+            &AiPosture::Defend,
+            0,
+        );
+        ai.apply_event(&db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 0},
+            new_id: player_id.clone(),
+        });
+        ai.apply_event(&db, &create_unit_event(&db, 0, "soldier", 1, (4, 4, 0)));
+        ai.apply_event(&db, &CoreEvent::SetReactionFireMode {
+            unit_id: UnitId{id: 0},
+            mode: ReactionFireMode::HoldFire,
+        });
+        match ai.get_command(&db) {
+            Command::SetReactionFireMode{ref unit_id, ref mode} => {
+                assert_eq!(unit_id.id, 0);
+                assert!(*mode == ReactionFireMode::Normal);
+            },
+            _ => panic!("Defender must not hold fire"),
         }
     }
 }
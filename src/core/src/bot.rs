@@ -0,0 +1,311 @@
+// See LICENSE file for copyright and license details.
+
+//! Players controlled by programs
+//!
+//! A `Bot` sees the same filtered events that a human player sees
+//! and answers with commands that are checked by `Core` like any other.
+//!
+//! `ProcessBot` runs a bot written in any language as a child process
+//! and talks to it over stdin/stdout, one message per line:
+//!
+//! ```text
+//! core -> bot: version <protocol_version>
+//! core -> bot: player <player_id>
+//! core -> bot: event <event>
+//! core -> bot: get_command
+//! bot -> core: <command>
+//! core -> bot: rejected
+//! ```
+//!
+//! `version` and `player` are sent once when the bot starts.
+//! After the batch of new `event`s the core sends `get_command`
+//! and waits for exactly one command line. The turn is over
+//! when the bot answers with `end_turn`. If the command can not
+//! be executed the core replies with `rejected` and asks again.
+//! Events and commands are written as described in `protocol`.
+//!
+//! Bot that sends a line that can't be parsed, dies, does not answer
+//! in time or gets too many rejections during one turn just ends
+//! its turn. `Bot::error` tells what went wrong. Core also ends the turn
+//! of any bot after `MAX_BOT_COMMANDS_PER_TURN` commands.
+//! Bots can`t use debug commands like `create_unit`.
+
+use std::{fmt, thread};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, Command as ProcessCommand, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration};
+use types::{ZInt};
+use db::{Db};
+use protocol::{PROTOCOL_VERSION, ParseError, event_to_string, parse_command};
+use ::{Command, CoreEvent, PlayerId};
+
+/// Why a bot has lost its turn
+#[derive(Debug)]
+pub enum BotError {
+    /// Bot has died or closed its end of the pipe
+    Io(io::Error),
+
+    /// Bot has not answered in time, see `ProcessBot::set_timeout`
+    Timeout,
+
+    /// Bot has sent something that is not a command
+    BadCommand(ParseError, String),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BotError::Io(ref err) => write!(f, "Bot is dead: {}", err),
+            BotError::Timeout => write!(f, "Bot has not answered in time"),
+            BotError::BadCommand(ref err, ref line) => {
+                write!(f, "Bad command from bot: {}: {}", err, line)
+            },
+        }
+    }
+}
+
+pub trait Bot {
+    /// Called for every event that the player is allowed to see
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent);
+
+    /// Called until `Command::EndTurn` is returned
+    fn get_command(&mut self, db: &Db) -> Command;
+
+    /// Called when core could not execute the last command
+    fn command_rejected(&mut self, _: &Command) {}
+
+    /// Last failure of the bot, bots that can`t fail have none
+    fn error(&self) -> Option<&BotError> {
+        None
+    }
+}
+
+/// After this many rejected commands the turn is ended
+const MAX_REJECTED_COMMANDS: ZInt = 10;
+
+/// How long a bot can think about one command by default
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+pub struct ProcessBot {
+    child: Option<Child>,
+    stdin: Box<dyn Write + Send>,
+
+    /// Lines of the bot are read by a thread of their own
+    /// so a silent bot can`t block the game
+    lines: Receiver<io::Result<String>>,
+
+    timeout: Duration,
+    is_alive: bool,
+    error: Option<BotError>,
+    rejected_commands_count: ZInt,
+}
+
+impl ProcessBot {
+    pub fn new(
+        path: &str,
+        args: &[String],
+        player_id: &PlayerId,
+    ) -> io::Result<ProcessBot> {
+        let mut child = ProcessCommand::new(path)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut bot = ProcessBot::with_pipes(Box::new(stdin), stdout, player_id);
+        bot.child = Some(child);
+        Ok(bot)
+    }
+
+    /// Bot on the other end of already opened pipes,
+    /// it is not killed when it fails
+    pub fn with_pipes<R: Read + Send + 'static>(
+        stdin: Box<dyn Write + Send>,
+        stdout: R,
+        player_id: &PlayerId,
+    ) -> ProcessBot {
+        let stdout = BufReader::new(stdout);
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut bot = ProcessBot {
+            child: None,
+            stdin: stdin,
+            lines: rx,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            is_alive: true,
+            error: None,
+            rejected_commands_count: 0,
+        };
+        bot.send(&format!("version {}", PROTOCOL_VERSION));
+        bot.send(&format!("player {}", player_id.id));
+        bot
+    }
+
+    /// How long the bot can think about one command
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Bot that has failed once is not asked anymore
+    fn fail(&mut self, error: BotError) {
+        self.is_alive = false;
+        self.error = Some(error);
+        if let Some(ref mut child) = self.child {
+            let _ = child.kill();
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        if !self.is_alive {
+            return;
+        }
+        let result = writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush());
+        if let Err(err) = result {
+            self.fail(BotError::Io(err));
+        }
+    }
+
+    fn receive(&mut self) -> Option<String> {
+        if !self.is_alive {
+            return None;
+        }
+        match self.lines.recv_timeout(self.timeout) {
+            Ok(Ok(line)) => Some(line),
+            Ok(Err(err)) => {
+                self.fail(BotError::Io(err));
+                None
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                let err = io::Error::new(
+                    io::ErrorKind::UnexpectedEof, "Bot closed its stdout");
+                self.fail(BotError::Io(err));
+                None
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                self.fail(BotError::Timeout);
+                None
+            },
+        }
+    }
+}
+
+impl Bot for ProcessBot {
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
+        let line = format!("event {}", event_to_string(db, event));
+        self.send(&line);
+    }
+
+    fn get_command(&mut self, db: &Db) -> Command {
+        if self.rejected_commands_count >= MAX_REJECTED_COMMANDS {
+            self.rejected_commands_count = 0;
+            return Command::EndTurn;
+        }
+        self.send("get_command");
+        let line = match self.receive() {
+            Some(line) => line,
+            None => return Command::EndTurn,
+        };
+        match parse_command(db, &line) {
+            Ok(command) => {
+                if let Command::EndTurn = command {
+                    self.rejected_commands_count = 0;
+                }
+                command
+            },
+            Err(err) => {
+                self.error = Some(BotError::BadCommand(err, line.trim().to_owned()));
+                self.rejected_commands_count = 0;
+                Command::EndTurn
+            },
+        }
+    }
+
+    fn command_rejected(&mut self, _: &Command) {
+        self.rejected_commands_count += 1;
+        self.send("rejected");
+    }
+
+    fn error(&self) -> Option<&BotError> {
+        self.error.as_ref()
+    }
+}
+
+impl Drop for ProcessBot {
+    fn drop(&mut self) {
+        if let Some(ref mut child) = self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::{Duration, Instant};
+    use db::{Db};
+    use ::{Command, PlayerId};
+    use super::{Bot, BotError, ProcessBot};
+
+    /// Bot that thinks until the other end of the channel is dropped
+    struct SilentPipe(Receiver<()>);
+
+    impl Read for SilentPipe {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            let _ = self.0.recv();
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_silent_bot_loses_its_turn() {
+        let db = Db::new();
+        let (_tx, rx) = channel();
+        let mut bot = ProcessBot::with_pipes(
+            Box::new(io::sink()), SilentPipe(rx), &PlayerId{id: 1});
+        bot.set_timeout(Duration::from_millis(100));
+        let start_time = Instant::now();
+        match bot.get_command(&db) {
+            Command::EndTurn => {},
+            _ => panic!("Silent bot must end its turn"),
+        }
+        assert!(start_time.elapsed() < Duration::from_secs(5));
+        match bot.error() {
+            Some(&BotError::Timeout) => {},
+            _ => panic!("Bot must time out"),
+        }
+    }
+
+    #[test]
+    fn test_closed_bot_loses_its_turn() {
+        let db = Db::new();
+        let mut bot = ProcessBot::with_pipes(
+            Box::new(io::sink()), io::empty(), &PlayerId{id: 1});
+        match bot.get_command(&db) {
+            Command::EndTurn => {},
+            _ => panic!("Dead bot must end its turn"),
+        }
+        match bot.error() {
+            Some(&BotError::Io(_)) => {},
+            _ => panic!("Bot must be dead"),
+        }
+    }
+
+    #[test]
+    fn test_bad_bot_program() {
+        let result = ProcessBot::new("./no_such_bot", &[], &PlayerId{id: 1});
+        assert!(result.is_err());
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -0,0 +1,229 @@
+// See LICENSE file for copyright and license details.
+
+//! Turn timer and chess-clock style time controls
+//!
+//! `Core` never looks at the real time directly: it asks its `Clock`
+//! so tests can move the time forward by hand with `ManualClock`.
+//! Only human players are timed, bots play their turns at once.
+
+use std::cell::{Cell};
+use std::collections::{HashMap};
+use std::rc::{Rc};
+use std::time::{Duration, Instant};
+use ::{PlayerId};
+
+pub trait Clock {
+    /// Time since some fixed moment in the past
+    fn now(&self) -> Duration;
+}
+
+pub struct RealClock {
+    start: Instant,
+}
+
+impl RealClock {
+    pub fn new() -> RealClock {
+        RealClock{start: Instant::now()}
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        RealClock::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Stands still until somebody calls `advance`,
+/// all clones show the same time
+#[derive(Clone)]
+pub struct ManualClock {
+    time: Rc<Cell<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock{time: Rc::new(Cell::new(Duration::from_secs(0)))}
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.time.set(self.time.get() + duration);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.time.get()
+    }
+}
+
+/// `None` means no limit
+#[derive(Clone, Default)]
+pub struct TimeControl {
+    /// Time of every single turn
+    pub turn_limit: Option<Duration>,
+
+    /// Total time of all turns of a player
+    pub time_bank: Option<Duration>,
+}
+
+impl TimeControl {
+    pub fn is_enabled(&self) -> bool {
+        self.turn_limit.is_some() || self.time_bank.is_some()
+    }
+
+    /// Time for a whole new turn of a player with this much in the bank
+    pub fn time_per_turn(&self, bank: Option<Duration>) -> Option<Duration> {
+        min_limit(self.turn_limit, bank)
+    }
+}
+
+fn min_limit(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a < b { a } else { b }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+pub fn saturating_sub(a: Duration, b: Duration) -> Duration {
+    a.checked_sub(b).unwrap_or_else(|| Duration::from_secs(0))
+}
+
+pub struct TurnTimer {
+    control: TimeControl,
+
+    /// Players that are timed, bots are not
+    player_ids: Vec<PlayerId>,
+
+    /// Time left in the banks of the timed players
+    banks: HashMap<PlayerId, Duration>,
+
+    turn_start: Duration,
+}
+
+impl TurnTimer {
+    pub fn new(control: &TimeControl, player_ids: &[PlayerId], now: Duration) -> TurnTimer {
+        let mut banks = HashMap::new();
+        if let Some(bank) = control.time_bank {
+            for player_id in player_ids {
+                banks.insert(player_id.clone(), bank);
+            }
+        }
+        TurnTimer {
+            control: control.clone(),
+            player_ids: player_ids.to_vec(),
+            banks: banks,
+            turn_start: now,
+        }
+    }
+
+    pub fn control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    pub fn bank(&self, player_id: &PlayerId) -> Option<Duration> {
+        self.banks.get(player_id).cloned()
+    }
+
+    fn is_timed(&self, player_id: &PlayerId) -> bool {
+        self.control.is_enabled() && self.player_ids.contains(player_id)
+    }
+
+    /// Time that the player has for a whole new turn
+    pub fn time_per_turn(&self, player_id: &PlayerId) -> Option<Duration> {
+        if !self.is_timed(player_id) {
+            return None;
+        }
+        self.control.time_per_turn(self.bank(player_id))
+    }
+
+    /// Time left to the player whose turn was started by the last `start_turn`
+    pub fn time_left(&self, player_id: &PlayerId, now: Duration) -> Option<Duration> {
+        let elapsed = saturating_sub(now, self.turn_start);
+        self.time_per_turn(player_id)
+            .map(|time| saturating_sub(time, elapsed))
+    }
+
+    /// Charges the player's bank for the turn that has just ended
+    /// and starts the clock of the next turn
+    pub fn end_turn(&mut self, player_id: &PlayerId, now: Duration) {
+        let elapsed = saturating_sub(now, self.turn_start);
+        if let Some(bank) = self.banks.get_mut(player_id) {
+            *bank = saturating_sub(*bank, elapsed);
+        }
+        self.start_turn(now);
+    }
+
+    pub fn start_turn(&mut self, now: Duration) {
+        self.turn_start = now;
+    }
+}
+
+/// `m:ss`
+pub fn duration_to_str(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration};
+    use ::{PlayerId};
+    use super::{Clock, ManualClock, TimeControl, TurnTimer, duration_to_str};
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn test_turn_limit_and_bank() {
+        let clock = ManualClock::new();
+        let control = TimeControl {
+            turn_limit: Some(secs(60)),
+            time_bank: Some(secs(100)),
+        };
+        let ids = [PlayerId{id: 0}, PlayerId{id: 1}];
+        let mut timer = TurnTimer::new(&control, &ids, clock.now());
+        clock.advance(secs(50));
+        assert_eq!(timer.time_left(&ids[0], clock.now()), Some(secs(10)));
+        timer.end_turn(&ids[0], clock.now());
+        assert_eq!(timer.bank(&ids[0]), Some(secs(50)));
+        assert_eq!(timer.bank(&ids[1]), Some(secs(100)));
+        // the bank is smaller than the turn limit now
+        assert_eq!(timer.time_per_turn(&ids[0]), Some(secs(50)));
+        clock.advance(secs(70));
+        assert_eq!(timer.time_left(&ids[1], clock.now()), Some(secs(0)));
+        timer.end_turn(&ids[1], clock.now());
+        assert_eq!(timer.bank(&ids[1]), Some(secs(30)));
+    }
+
+    #[test]
+    fn test_no_limits() {
+        let timer = TurnTimer::new(&TimeControl::default(), &[PlayerId{id: 0}], secs(0));
+        assert!(timer.time_left(&PlayerId{id: 0}, secs(1000)).is_none());
+        let control = TimeControl{turn_limit: Some(secs(10)), time_bank: None};
+        let timer = TurnTimer::new(&control, &[PlayerId{id: 0}], secs(0));
+        assert!(timer.time_left(&PlayerId{id: 1}, secs(1000)).is_none());
+    }
+
+    #[test]
+    fn test_duration_to_str() {
+        assert_eq!(duration_to_str(&secs(0)), "0:00");
+        assert_eq!(duration_to_str(&secs(65)), "1:05");
+        assert_eq!(duration_to_str(&secs(600)), "10:00");
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -118,8 +118,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 7,
             cover_los_range: 0,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: true,
+            is_hq: false,
         },
         UnitType {
             name: "heavy_tank".to_owned(),
@@ -135,8 +138,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 7,
             cover_los_range: 0,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "medium_tank".to_owned(),
@@ -152,8 +158,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 7,
             cover_los_range: 0,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "light_tank".to_owned(),
@@ -169,8 +178,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 7,
             cover_los_range: 0,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "light_spg".to_owned(),
@@ -186,8 +198,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 7,
             cover_los_range: 0,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "field_gun".to_owned(),
@@ -204,8 +219,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 7,
             cover_los_range: 0,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: true,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "jeep".to_owned(),
@@ -221,8 +239,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 8,
             cover_los_range: 0,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: true,
             is_big: false,
+            is_hq: true,
         },
         UnitType {
             name: "truck".to_owned(),
@@ -238,8 +259,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 0},
             los_range: 6,
             cover_los_range: 0,
-            is_transporter: true,
+            transport_capacity: 8,
+            is_towable: false,
+            can_tow: true,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "soldier".to_owned(),
@@ -255,8 +279,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 6,
             cover_los_range: 1,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "smg".to_owned(),
@@ -272,8 +299,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 6,
             cover_los_range: 1,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "scout".to_owned(),
@@ -289,8 +319,11 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 1},
             los_range: 8,
             cover_los_range: 2,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
         },
         UnitType {
             name: "mortar".to_owned(),
@@ -306,8 +339,52 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             reactive_attack_points: AttackPoints{n: 0},
             los_range: 6,
             cover_los_range: 1,
-            is_transporter: false,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
             is_big: false,
+            is_hq: false,
+        },
+        // generic types for detected but not identified enemy units
+        UnitType {
+            name: "infantry_contact".to_owned(),
+            class: UnitClass::Infantry,
+            size: 4,
+            count: 1,
+            armor: 1,
+            toughness: 2,
+            weapon_skill: 5,
+            weapon_type_id: weapon_type_id(weapon_types, "rifle"),
+            move_points: MovePoints{n: 3},
+            attack_points: AttackPoints{n: 2},
+            reactive_attack_points: AttackPoints{n: 1},
+            los_range: 6,
+            cover_los_range: 1,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
+            is_big: false,
+            is_hq: false,
+        },
+        UnitType {
+            name: "vehicle_contact".to_owned(),
+            class: UnitClass::Vehicle,
+            size: 6,
+            count: 1,
+            armor: 5,
+            toughness: 9,
+            weapon_skill: 5,
+            weapon_type_id: weapon_type_id(weapon_types, "machine_gun"),
+            move_points: MovePoints{n: 4},
+            attack_points: AttackPoints{n: 2},
+            reactive_attack_points: AttackPoints{n: 1},
+            los_range: 7,
+            cover_los_range: 0,
+            transport_capacity: 0,
+            is_towable: false,
+            can_tow: false,
+            is_big: false,
+            is_hq: false,
         },
     ]
 }
@@ -317,6 +394,12 @@ pub struct Db {
     weapon_types: Vec<WeaponType>,
 }
 
+impl Default for Db {
+    fn default() -> Self {
+        Db::new()
+    }
+}
+
 impl Db {
     pub fn new() -> Db {
         let weapon_types = get_weapon_types();
@@ -327,7 +410,7 @@ impl Db {
         }
     }
 
-    fn unit_type_id_opt(&self, name: &str) -> Option<UnitTypeId> {
+    pub fn unit_type_id_opt(&self, name: &str) -> Option<UnitTypeId> {
         for (id, unit_type) in self.unit_types.iter().enumerate() {
             if unit_type.name == name {
                 return Some(UnitTypeId{id: id as ZInt});
@@ -336,10 +419,34 @@ impl Db {
         None
     }
 
+    /// Like `unit_type_id_opt` but contact types are not found:
+    /// commands and scenarios can`t create units of these types
+    pub fn real_unit_type_id_opt(&self, name: &str) -> Option<UnitTypeId> {
+        match self.unit_type_id_opt(name) {
+            Some(ref id) if self.is_contact_type(id) => None,
+            id => id,
+        }
+    }
+
+    /// All types that units can be created with, without the contact types
+    pub fn real_unit_type_ids(&self) -> Vec<UnitTypeId> {
+        (0 .. self.unit_types.len())
+            .map(|id| UnitTypeId{id: id as ZInt})
+            .filter(|id| !self.is_contact_type(id))
+            .collect()
+    }
+
     pub fn unit_type(&self, unit_type_id: &UnitTypeId) -> &UnitType {
         &self.unit_types[unit_type_id.id as usize]
     }
 
+    pub fn unit_type_opt(&self, unit_type_id: &UnitTypeId) -> Option<&UnitType> {
+        if unit_type_id.id < 0 {
+            return None;
+        }
+        self.unit_types.get(unit_type_id.id as usize)
+    }
+
     pub fn weapon_type(&self, type_id: &WeaponTypeId) -> &WeaponType {
         &self.weapon_types[type_id.id as usize]
     }
@@ -351,6 +458,20 @@ impl Db {
         }
     }
 
+    /// Generic type that is shown instead of the real one
+    /// until the unit is identified
+    pub fn contact_type_id(&self, class: &UnitClass) -> UnitTypeId {
+        match *class {
+            UnitClass::Infantry => self.unit_type_id("infantry_contact"),
+            UnitClass::Vehicle => self.unit_type_id("vehicle_contact"),
+        }
+    }
+
+    pub fn is_contact_type(&self, type_id: &UnitTypeId) -> bool {
+        *type_id == self.contact_type_id(&UnitClass::Infantry)
+            || *type_id == self.contact_type_id(&UnitClass::Vehicle)
+    }
+
     pub fn weapon_type_id(&self, name: &str) -> WeaponTypeId {
         weapon_type_id(&self.weapon_types, name)
     }
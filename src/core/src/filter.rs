@@ -5,9 +5,32 @@ use internal_state::{InternalState};
 use game_state::{GameState};
 use unit::{Unit};
 use db::{Db};
-use fow::{Fow};
+use fow::{Fow, UnitVisibility};
 use ::{CoreEvent, AttackInfo, UnitInfo, UnitId, PlayerId, unit_to_info};
 
+/// Info about a detected unit that does not give away its type and size
+fn contact_info(db: &Db, unit: &Unit) -> UnitInfo {
+    let class = &db.unit_type(&unit.type_id).class;
+    UnitInfo {
+        unit_id: unit.id.clone(),
+        pos: unit.pos.clone(),
+        type_id: db.contact_type_id(class),
+        player_id: unit.player_id.clone(),
+        passenger_ids: Vec::new(),
+        towed_unit_id: None,
+        is_contact: true,
+    }
+}
+
+/// Enemies learn nothing about the cargo of a visible unit
+fn enemy_info(unit: &Unit) -> UnitInfo {
+    UnitInfo {
+        passenger_ids: Vec::new(),
+        towed_unit_id: None,
+        .. unit_to_info(unit)
+    }
+}
+
 pub fn get_visible_enemies(
     db: &Db,
     state: &InternalState,
@@ -25,33 +48,91 @@ pub fn get_visible_enemies(
     visible_enemies
 }
 
+pub fn get_enemy_contacts(
+    db: &Db,
+    state: &InternalState,
+    fow: &Fow,
+    player_id: &PlayerId,
+) -> HashSet<UnitId> {
+    let mut contacts = HashSet::new();
+    for (id, unit) in state.units() {
+        if unit.player_id != *player_id
+            && fow.unit_visibility(db, state, unit, &unit.pos) == UnitVisibility::Contact
+        {
+            contacts.insert(id.clone());
+        }
+    }
+    contacts
+}
+
+fn visibility(
+    id: &UnitId,
+    visible: &HashSet<UnitId>,
+    contacts: &HashSet<UnitId>,
+) -> UnitVisibility {
+    if visible.contains(id) {
+        UnitVisibility::Full
+    } else if contacts.contains(id) {
+        UnitVisibility::Contact
+    } else {
+        UnitVisibility::No
+    }
+}
+
 pub fn show_or_hide_passive_enemies(
+    db: &Db,
     units: &HashMap<UnitId, Unit>,
     active_unit_ids: &HashSet<UnitId>,
     old: &HashSet<UnitId>,
     new: &HashSet<UnitId>,
+    old_contacts: &HashSet<UnitId>,
+    new_contacts: &HashSet<UnitId>,
 ) -> Vec<CoreEvent> {
     let mut events = Vec::new();
-    let located_units = new.difference(old);
-    for id in located_units {
-        if active_unit_ids.contains(id) {
+    let ids = old.union(new).chain(old_contacts.union(new_contacts));
+    let mut checked_ids = HashSet::new();
+    for id in ids {
+        if !checked_ids.insert(id.clone()) {
             continue;
         }
-        let unit = units.get(&id).expect("Can`t find unit");
-        events.push(CoreEvent::ShowUnit {
-            unit_info: unit_to_info(unit),
-        });
-    }
-    let lost_units = old.difference(new);
-    for id in lost_units {
-        if active_unit_ids.contains(id) {
+        let old_visibility = visibility(id, old, old_contacts);
+        let new_visibility = visibility(id, new, new_contacts);
+        if old_visibility == new_visibility {
+            continue;
+        }
+        // active units are shown and hidden by `filter_events`,
+        // but it knows nothing about contacts
+        let is_dead_contact = old_visibility == UnitVisibility::Contact
+            && units.get(id).is_none();
+        if active_unit_ids.contains(id)
+            && new_visibility != UnitVisibility::Contact
+            && !is_dead_contact
+        {
             continue;
         }
-        events.push(CoreEvent::HideUnit{unit_id: id.clone()});
+        match new_visibility {
+            UnitVisibility::No => {
+                events.push(CoreEvent::HideUnit{unit_id: id.clone()});
+            },
+            UnitVisibility::Contact => {
+                let unit = units.get(id).expect("Can`t find unit");
+                events.push(CoreEvent::ShowUnit {
+                    unit_info: contact_info(db, unit),
+                });
+            },
+            UnitVisibility::Full => {
+                let unit = units.get(id).expect("Can`t find unit");
+                events.push(CoreEvent::ShowUnit {
+                    unit_info: enemy_info(unit),
+                });
+            },
+        }
     }
     events
 }
 
+/// `defender` is the attacked unit as it was before the attack
+/// because a destroyed unit is already removed from the `state`
 // TODO: join state and fow into TmpPartialState
 pub fn filter_events(
     db: &Db,
@@ -59,6 +140,7 @@ pub fn filter_events(
     player_id: &PlayerId,
     fow: &Fow,
     event: &CoreEvent,
+    defender: Option<&Unit>,
 ) -> (Vec<CoreEvent>, HashSet<UnitId>) {
     let mut active_unit_ids = HashSet::new();
     let mut events = vec![];
@@ -74,22 +156,32 @@ pub fn filter_events(
                     events.push(CoreEvent::ShowUnit {
                         unit_info: UnitInfo {
                             pos: from.clone(),
-                            .. unit_to_info(unit)
+                            .. enemy_info(unit)
                         },
                     });
                 }
                 if prev_vis || next_vis {
                     events.push(event.clone());
                 }
-                if prev_vis && !next_vis {
+                // `show_or_hide_passive_enemies` turns it into a contact
+                let is_next_contact = fow.unit_visibility(db, state, unit, to)
+                    == UnitVisibility::Contact;
+                if prev_vis && !next_vis && !is_next_contact {
                     events.push(CoreEvent::HideUnit {
                         unit_id: unit.id.clone(),
                     });
                 }
-                active_unit_ids.insert(unit_id.clone());
+                if prev_vis || next_vis {
+                    active_unit_ids.insert(unit_id.clone());
+                } else if fow.unit_visibility(db, state, unit, from) == UnitVisibility::Contact
+                    && fow.unit_visibility(db, state, unit, to) == UnitVisibility::Contact
+                {
+                    events.push(event.clone());
+                    active_unit_ids.insert(unit_id.clone());
+                }
             }
         },
-        &CoreEvent::EndTurn{..} => {
+        &CoreEvent::EndTurn{..} | &CoreEvent::EndPlanning{..} => {
             events.push(event.clone());
         },
         &CoreEvent::CreateUnit{ref unit_info} => {
@@ -105,12 +197,24 @@ pub fn filter_events(
             let attacker_id = attack_info.attacker_id.clone()
                 .expect("Core must know about everything");
             let attacker = state.unit(&attacker_id);
+            let defender = defender.expect("Core must know about the defender");
+            let defender_visibility = if defender.player_id == *player_id {
+                UnitVisibility::Full
+            } else {
+                fow.unit_visibility(db, state, defender, &defender.pos)
+            };
+            if *player_id != attacker.player_id
+                && defender_visibility == UnitVisibility::No
+            {
+                // nobody tells you about the fights that you can`t see
+                return (events, active_unit_ids);
+            }
             if *player_id != attacker.player_id && !attack_info.is_ambush {
                 // show attacker if this is not ambush
                 let attacker = state.unit(&attacker_id);
                 if !fow.is_visible(db, state, attacker, &attacker.pos) {
                     events.push(CoreEvent::ShowUnit {
-                        unit_info: unit_to_info(&attacker),
+                        unit_info: enemy_info(&attacker),
                     });
                 }
                 active_unit_ids.insert(attacker_id.clone());
@@ -124,37 +228,28 @@ pub fn filter_events(
                 } else {
                     None
                 },
+                // losses would give away the size of a contact
+                killed: if defender_visibility == UnitVisibility::Contact {
+                    0
+                } else {
+                    attack_info.killed
+                },
                 .. attack_info.clone()
             };
             events.push(CoreEvent::AttackUnit{attack_info: attack_info});
         },
         &CoreEvent::ShowUnit{..} => panic!(),
         &CoreEvent::HideUnit{..} => panic!(),
-        &CoreEvent::LoadUnit{ref passenger_id, ref from, ref to, ref transporter_id} => {
+        &CoreEvent::LoadUnit{ref passenger_id, ref from, ref to, ..} => {
             let passenger = state.unit(passenger_id);
-            let transporter = state.unit(transporter_id.as_ref().unwrap());
-            let is_transporter_vis = fow.is_visible(
-                db, state, transporter, &transporter.pos);
-            let is_passenger_vis = fow.is_visible(
-                db, state, passenger, from);
             if passenger.player_id == *player_id {
                 events.push(event.clone());
-            } else if is_passenger_vis || is_transporter_vis {
-                if !fow.is_visible(db, state, passenger, from) {
-                    events.push(CoreEvent::ShowUnit {
-                        unit_info: UnitInfo {
-                            pos: from.clone(),
-                            .. unit_to_info(passenger)
-                        },
-                    });
-                }
-                let filtered_transporter_id = if is_transporter_vis {
-                    transporter_id.clone()
-                } else {
-                    None
-                };
+            } else if fow.unit_visibility(db, state, passenger, from) != UnitVisibility::No {
+                // enemy sees the unit or the contact getting in but learns
+                // nothing about the cargo of the transporter: the unit just
+                // disappears without a ghost, see `InternalState::apply_event`
                 events.push(CoreEvent::LoadUnit {
-                    transporter_id: filtered_transporter_id,
+                    transporter_id: None,
                     passenger_id: passenger_id.clone(),
                     from: from.clone(),
                     to: to.clone(),
@@ -172,7 +267,9 @@ pub fn filter_events(
                 db, state, passenger, to);
             if passenger.player_id == *player_id {
                 events.push(event.clone());
-            } else if is_passenger_vis || is_transporter_vis {
+            } else if is_passenger_vis {
+                // an unseen passenger stays unknown just like
+                // the one that bails out, see `CoreEvent::BailOut`
                 let filtered_transporter_id = if is_transporter_vis {
                     transporter_id.clone()
                 } else {
@@ -184,19 +281,60 @@ pub fn filter_events(
                     from: from.clone(),
                     to: to.clone(),
                 });
-                if !is_passenger_vis {
-                    events.push(CoreEvent::HideUnit {
-                        unit_id: passenger.id.clone(),
+            }
+        },
+        &CoreEvent::BailOut{ref unit_info, ref transporter_id, ref from, ref to, ref killed, ref suppression} => {
+            active_unit_ids.insert(unit_info.unit_id.clone());
+            if unit_info.player_id == *player_id {
+                events.push(event.clone());
+            } else if let Some(passenger) = state.units().get(&unit_info.unit_id) {
+                // enemies never learn about passengers that died inside
+                // and a passenger that bails out unseen stays unknown:
+                // if it can be detected as a contact
+                // `show_or_hide_passive_enemies` shows it as one
+                let transporter = state.unit(transporter_id.as_ref().unwrap());
+                let is_transporter_vis = fow.is_visible(
+                    db, state, transporter, from);
+                let is_passenger_vis = fow.is_visible(
+                    db, state, passenger, to);
+                if is_passenger_vis {
+                    let filtered_transporter_id = if is_transporter_vis {
+                        transporter_id.clone()
+                    } else {
+                        None
+                    };
+                    events.push(CoreEvent::BailOut {
+                        transporter_id: filtered_transporter_id,
+                        unit_info: unit_info.clone(),
+                        from: from.clone(),
+                        to: to.clone(),
+                        killed: killed.clone(),
+                        suppression: suppression.clone(),
                     });
                 }
             }
         },
+        // enemies who have seen the unit created know
+        // its reactive attack points too
+        &CoreEvent::ConvertAp{ref unit_id} => {
+            let unit = state.unit(unit_id);
+            if unit.player_id == *player_id
+                || fow.is_visible(db, state, unit, &unit.pos)
+            {
+                events.push(event.clone());
+            }
+        },
         &CoreEvent::SetReactionFireMode{ref unit_id, ..} => {
             let unit = state.unit(unit_id);
             if unit.player_id == *player_id {
                 events.push(event.clone());
             }
         },
+        &CoreEvent::Noise{ref listener_id, ..} => {
+            if *listener_id == *player_id {
+                events.push(event.clone());
+            }
+        },
     }
     (events, active_unit_ids)
 }
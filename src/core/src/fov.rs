@@ -1,22 +1,107 @@
 // See LICENSE file for copyright and license details.
 
-/// Fielf of View
+/// Field of View
+///
+/// Shadow casting on a hex grid. Angles and sizes of shadows of all tiles
+/// around an origin are calculated only once and then reused
+/// for every origin with the same row parity. Shadows are round so
+/// a tile must also have a clear `hex::line` to be visible.
 
+use std::cell::{RefCell};
+use std::collections::{HashMap};
 use std::f32::consts::{PI};
-use cgmath::{InnerSpace};
+use std::rc::{Rc};
+use cgmath::{Vector2, InnerSpace};
 use types::{ZInt, ZFloat};
 use map::{Map, Terrain, distance, spiral_iter};
+use hex::{line};
 use geom;
 use ::{MapPos};
 
-struct Shadow {
-    left: ZFloat,
-    right: ZFloat,
+/// Precalculated info about a tile relative to an origin
+struct TileInfo {
+    offset: Vector2<ZInt>,
+    angle: ZFloat,
+
+    /// Half of the angular size of a shadow cast by this tile
+    shadow_size: ZFloat,
+}
+
+/// Tiles around an origin in spiral order. Hex offsets depend
+/// on the parity of the origin's row so there are two sets of them.
+struct Table {
+    range: ZInt,
+    tiles: [Vec<TileInfo>; 2],
+    indices: [HashMap<(ZInt, ZInt), usize>; 2],
+}
+
+fn parity(pos: &MapPos) -> usize {
+    if pos.v.y % 2 == 0 { 0 } else { 1 }
 }
 
-fn is_tile_visible(angle: ZFloat, shadows: &[Shadow]) -> bool {
-    for shadow in shadows {
-        if shadow.left < angle && shadow.right > angle {
+fn calc_tiles(parity: usize, range: ZInt) -> Vec<TileInfo> {
+    // `distance` and `spiral_iter` are not designed for negative coordinates
+    let origin = MapPos{v: Vector2{x: range, y: range * 2 + parity as ZInt}};
+    let origin3d = geom::map_pos_to_world_pos(&origin);
+    let obstacle_radius = geom::HEX_IN_RADIUS * 1.1;
+    let mut tiles = Vec::new();
+    for pos in spiral_iter(&origin, range) {
+        let diff = geom::map_pos_to_world_pos(&pos) - origin3d;
+        tiles.push(TileInfo {
+            offset: pos.v - origin.v,
+            angle: diff.x.atan2(diff.y),
+            shadow_size: (obstacle_radius / diff.magnitude()).asin(),
+        });
+    }
+    tiles
+}
+
+fn calc_indices(tiles: &[TileInfo]) -> HashMap<(ZInt, ZInt), usize> {
+    let mut indices = HashMap::new();
+    for (i, tile) in tiles.iter().enumerate() {
+        indices.insert((tile.offset.x, tile.offset.y), i);
+    }
+    indices
+}
+
+impl Table {
+    fn new(range: ZInt) -> Table {
+        let tiles = [calc_tiles(0, range), calc_tiles(1, range)];
+        let indices = [calc_indices(&tiles[0]), calc_indices(&tiles[1])];
+        Table {
+            range: range,
+            tiles: tiles,
+            indices: indices,
+        }
+    }
+
+    /// Tiles of the spiral around `origin` up to `range` (including)
+    fn tiles(&self, origin: &MapPos, range: ZInt) -> &[TileInfo] {
+        assert!(range <= self.range);
+        let tiles = &self.tiles[parity(origin)];
+        // every ring has six more tiles than the previous one
+        let count = 3 * range * (range + 1);
+        &tiles[.. count as usize]
+    }
+
+    fn index(&self, from: &MapPos, to: &MapPos) -> Option<usize> {
+        let offset = to.v - from.v;
+        self.indices[parity(from)].get(&(offset.x, offset.y)).cloned()
+    }
+}
+
+fn is_in_shadow(angle: ZFloat, obstacle: &TileInfo) -> bool {
+    let left = obstacle.angle - obstacle.shadow_size;
+    let right = obstacle.angle + obstacle.shadow_size;
+    if left < angle && right > angle {
+        return true;
+    }
+    right > PI && -PI < angle && right - PI * 2.0 > angle
+}
+
+fn is_tile_visible(angle: ZFloat, obstacles: &[&TileInfo]) -> bool {
+    for obstacle in obstacles {
+        if is_in_shadow(angle, obstacle) {
             return false;
         }
     }
@@ -31,37 +116,243 @@ fn is_obstacle(terrain: &Terrain) -> bool {
     }
 }
 
-// TODO: precalculate all 'atan2' and 'asin' stuff
-pub fn fov(
+/// Round shadows leave gaps at the corners of obstacle tiles,
+/// the straight line through the tiles closes them
+fn is_line_clear(map: &Map<Terrain>, from: &MapPos, to: &MapPos) -> bool {
+    let positions = line(from, to);
+    if positions.len() <= 2 {
+        return true;
+    }
+    positions[1 .. positions.len() - 1].iter()
+        .all(|pos| !map.is_inboard(pos) || !is_obstacle(map.tile(pos)))
+}
+
+fn calc_fov(
+    table: &Table,
     map: &Map<Terrain>,
     origin: &MapPos,
     range: ZInt,
-    callback: &mut FnMut(&MapPos),
-) {
-    callback(origin);
-    let mut shadows = vec!();
-    let origin3d = geom::map_pos_to_world_pos(origin);
-    for pos in spiral_iter(origin, range) {
+) -> Vec<MapPos> {
+    let mut visible = vec![origin.clone()];
+    let mut obstacles = Vec::new();
+    for tile in table.tiles(origin, range) {
+        let pos = MapPos{v: origin.v + tile.offset};
         if !map.is_inboard(&pos) {
             continue;
         }
-        let pos3d = geom::map_pos_to_world_pos(&pos);
-        let diff = pos3d - origin3d;
-        let distance = diff.magnitude();
-        let angle = diff.x.atan2(diff.y); // TODO: optimize
-        if is_tile_visible(angle, &shadows) {
-            callback(&pos);
+        if is_tile_visible(tile.angle, &obstacles) && is_line_clear(map, origin, &pos) {
+            visible.push(pos.clone());
         }
         if is_obstacle(map.tile(&pos)) {
-            let obstacle_radius = geom::HEX_IN_RADIUS * 1.1;
-            let a = (obstacle_radius / distance).asin();
-            let shadow = Shadow{left: angle - a, right: angle + a};
-            if shadow.right > PI {
-                shadows.push(Shadow{left: -PI, right: shadow.right - PI * 2.0});
+            obstacles.push(tile);
+        }
+    }
+    visible
+}
+
+/// Checks only the tiles that are closer to the origin than the target
+/// and gives the same result as a full `calc_fov` would.
+fn calc_los(
+    table: &Table,
+    map: &Map<Terrain>,
+    from: &MapPos,
+    to: &MapPos,
+    range: ZInt,
+) -> bool {
+    if !map.is_inboard(to) || distance(from, to) > range {
+        return false;
+    }
+    if from == to {
+        return true;
+    }
+    let index = table.index(from, to).expect("Target is out of the table");
+    let tiles = table.tiles(from, range);
+    let angle = tiles[index].angle;
+    for tile in &tiles[.. index] {
+        let pos = MapPos{v: from.v + tile.offset};
+        if !map.is_inboard(&pos) {
+            continue;
+        }
+        if is_obstacle(map.tile(&pos)) && is_in_shadow(angle, tile) {
+            return false;
+        }
+    }
+    is_line_clear(map, from, to)
+}
+
+/// Visible tiles for (x, y, range) of an origin
+type Cache = HashMap<(ZInt, ZInt, ZInt), Rc<Vec<MapPos>>>;
+
+/// Field of view calculator with a cache of results for every origin.
+///
+/// The cache knows nothing about the map so its owner must call
+/// `clear_cache` after every change of terrain or objects.
+/// Clones share the table and cached results so they are cheap,
+/// `clear_cache` gives the changed clone a cache of its own.
+#[derive(Clone)]
+pub struct Fov {
+    table: RefCell<Rc<Table>>,
+    cache: Rc<RefCell<Cache>>,
+}
+
+impl Fov {
+    pub fn new() -> Fov {
+        Fov {
+            table: RefCell::new(Rc::new(Table::new(1))),
+            cache: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    fn table(&self, range: ZInt) -> Rc<Table> {
+        if self.table.borrow().range < range {
+            *self.table.borrow_mut() = Rc::new(Table::new(range));
+        }
+        self.table.borrow().clone()
+    }
+
+    /// Calls `callback` for every tile visible from `origin`
+    pub fn fov(
+        &self,
+        map: &Map<Terrain>,
+        origin: &MapPos,
+        range: ZInt,
+        callback: &mut FnMut(&MapPos),
+    ) {
+        let key = (origin.v.x, origin.v.y, range);
+        let cached = self.cache.borrow().get(&key).cloned();
+        let visible = match cached {
+            Some(visible) => visible,
+            None => {
+                let table = self.table(range);
+                let visible = Rc::new(calc_fov(&table, map, origin, range));
+                self.cache.borrow_mut().insert(key, visible.clone());
+                visible
+            },
+        };
+        for pos in visible.iter() {
+            callback(pos);
+        }
+    }
+
+    /// Point to point line of sight check. Much cheaper than a full `fov`.
+    pub fn los(
+        &self,
+        map: &Map<Terrain>,
+        from: &MapPos,
+        to: &MapPos,
+        range: ZInt,
+    ) -> bool {
+        let table = self.table(range);
+        calc_los(&table, map, from, to, range)
+    }
+
+    /// Other clones keep the old cache: their maps have not changed
+    pub fn clear_cache(&mut self) {
+        self.cache = Rc::new(RefCell::new(HashMap::new()));
+    }
+}
+
+impl Default for Fov {
+    fn default() -> Self {
+        Fov::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{Size2};
+    use map::{Map, Terrain};
+    use hex::{line};
+    use ::{MapPos};
+    use super::{Fov};
+
+    fn test_map() -> Map<Terrain> {
+        let mut map = Map::new(&Size2{w: 12, h: 12});
+        let obstacles = [
+            (3, 3, Terrain::Trees),
+            (4, 3, Terrain::Trees),
+            (6, 5, Terrain::City),
+            (5, 8, Terrain::City),
+            (8, 2, Terrain::Trees),
+            (1, 7, Terrain::Trees),
+        ];
+        for &(x, y, ref terrain) in &obstacles {
+            *map.tile_mut(&MapPos{v: Vector2{x: x, y: y}}) = terrain.clone();
+        }
+        map
+    }
+
+    #[test]
+    fn test_los_matches_fov() {
+        let map = test_map();
+        let fov = Fov::new();
+        let range = 7;
+        for origin in map.get_iter() {
+            let mut visible = Vec::new();
+            fov.fov(&map, &origin, range, &mut |pos| visible.push(pos.clone()));
+            for pos in map.get_iter() {
+                assert_eq!(
+                    visible.contains(&pos),
+                    fov.los(&map, &origin, &pos, range),
+                    "{} -> {}", origin, pos,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_los_follows_hex_line() {
+        let map = test_map();
+        let fov = Fov::new();
+        let range = 7;
+        for origin in map.get_iter() {
+            for pos in map.get_iter() {
+                if !fov.los(&map, &origin, &pos, range) {
+                    continue;
+                }
+                let positions = line(&origin, &pos);
+                for p in positions.iter().skip(1).take(positions.len().saturating_sub(2)) {
+                    assert!(!map.is_inboard(p) || *map.tile(p) == Terrain::Plain,
+                        "{} -> {} through {}", origin, pos, p);
+                }
             }
-            shadows.push(shadow);
         }
     }
+
+    #[test]
+    fn test_cache_is_cleared() {
+        let mut map = test_map();
+        let mut fov = Fov::new();
+        let origin = MapPos{v: Vector2{x: 5, y: 5}};
+        let target = MapPos{v: Vector2{x: 5, y: 1}};
+        let blocker = MapPos{v: Vector2{x: 5, y: 3}};
+        let is_visible = |fov: &Fov, map: &Map<Terrain>| {
+            let mut v = false;
+            fov.fov(map, &origin, 5, &mut |pos| if *pos == target { v = true });
+            v
+        };
+        assert!(is_visible(&fov, &map));
+        *map.tile_mut(&blocker) = Terrain::City;
+        assert!(is_visible(&fov, &map));
+        fov.clear_cache();
+        assert!(!is_visible(&fov, &map));
+    }
+
+    #[test]
+    fn test_clones_share_cache() {
+        let map = test_map();
+        let fov = Fov::new();
+        let origin = MapPos{v: Vector2{x: 5, y: 5}};
+        fov.fov(&map, &origin, 5, &mut |_| {});
+        let mut clone = fov.clone();
+        assert_eq!(clone.cache.borrow().len(), 1);
+        clone.fov(&map, &MapPos{v: Vector2{x: 2, y: 2}}, 5, &mut |_| {});
+        assert_eq!(fov.cache.borrow().len(), 2);
+        clone.clear_cache();
+        assert!(clone.cache.borrow().is_empty());
+        assert_eq!(fov.cache.borrow().len(), 2);
+    }
 }
 
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -1,49 +1,64 @@
 // See LICENSE file for copyright and license details.
 
 use std::default::{Default};
+use std::collections::{HashMap};
 use types::{Size2, ZInt};
 use internal_state::{InternalState};
 use game_state::{GameState};
 use map::{Map, Terrain, distance};
-use fov::{fov};
 use db::{Db};
 use unit::{Unit, UnitType, UnitClass};
-use ::{CoreEvent, PlayerId, MapPos, ExactPos};
+use ::{CoreEvent, PlayerId, UnitId, MapPos, ExactPos};
 
 #[derive(Clone, PartialEq, PartialOrd)]
 pub enum TileVisibility {
     No,
-    // Bad,
+    Bad,
     Normal,
     Excellent,
 }
 
+/// What a player knows about some enemy unit
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub enum UnitVisibility {
+    No,
+
+    /// Unit's class and position are known but not its type or size
+    Contact,
+
+    Full,
+}
+
+/// How far beyond its normal line of sight a unit can detect contacts
+const CONTACT_RANGE: ZInt = 2;
+
 impl Default for TileVisibility {
     fn default() -> Self { TileVisibility::No }
 }
 
 pub fn fov_unit(
     db: &Db,
-    terrain: &Map<Terrain>,
+    state: &InternalState,
     fow: &mut Map<TileVisibility>,
     unit: &Unit,
 ) {
-    fov_unit_in_pos(db, terrain, fow, unit, &unit.pos.map_pos);
+    fov_unit_in_pos(db, state, fow, unit, &unit.pos.map_pos);
 }
 
 pub fn fov_unit_in_pos(
     db: &Db,
-    terrain: &Map<Terrain>,
+    state: &InternalState,
     fow: &mut Map<TileVisibility>,
     unit: &Unit,
     origin: &MapPos,
 ) {
     let unit_type = db.unit_type(&unit.type_id);
-    let range = &unit_type.los_range;
-    fov(
+    let range = unit_type.los_range + CONTACT_RANGE;
+    let terrain = state.map();
+    state.fov().fov(
         terrain,
         origin,
-        *range,
+        range,
         &mut |pos| {
             let distance = distance(origin, pos);
             let vis = calc_visibility(terrain.tile(pos), unit_type, &distance);
@@ -65,15 +80,77 @@ fn calc_visibility(terrain: &Terrain, unit_type: &UnitType, distance: &ZInt)
             &Terrain::Trees => TileVisibility::Normal,
             &Terrain::Plain => TileVisibility::Excellent,
         }
+    } else if *distance <= unit_type.los_range + CONTACT_RANGE {
+        match terrain {
+            &Terrain::City => TileVisibility::No,
+            &Terrain::Trees => TileVisibility::No,
+            &Terrain::Plain => TileVisibility::Bad,
+        }
     } else {
         TileVisibility::No
     }
 }
 
+fn unit_type_visibility(tile: &TileVisibility, unit_type: &UnitType) -> UnitVisibility {
+    match *tile {
+        TileVisibility::Excellent => UnitVisibility::Full,
+        TileVisibility::Normal => match unit_type.class {
+            UnitClass::Infantry => UnitVisibility::Contact,
+            UnitClass::Vehicle => UnitVisibility::Full,
+        },
+        TileVisibility::Bad => match unit_type.class {
+            UnitClass::Infantry => UnitVisibility::No,
+            UnitClass::Vehicle => UnitVisibility::Contact,
+        },
+        TileVisibility::No => UnitVisibility::No,
+    }
+}
+
+fn is_inside_of_other_unit<S: GameState>(
+    state: &S,
+    unit: &Unit,
+    pos: &ExactPos,
+) -> bool {
+    for (_, other_unit) in state.units() {
+        let is_inside = other_unit.passenger_ids.contains(&unit.id)
+            || other_unit.towed_unit_id.as_ref() == Some(&unit.id);
+        if is_inside && other_unit.pos == *pos {
+            return true;
+        }
+    }
+    false
+}
+
+/// What `unit` sees of `target` with its own eyes right now
+pub fn unit_sees<S: GameState>(
+    db: &Db,
+    state: &S,
+    unit: &Unit,
+    target: &Unit,
+) -> UnitVisibility {
+    let unit_type = db.unit_type(&unit.type_id);
+    let from = &unit.pos.map_pos;
+    let to = &target.pos.map_pos;
+    let range = unit_type.los_range + CONTACT_RANGE;
+    if is_inside_of_other_unit(state, target, &target.pos)
+        || !state.fov().los(state.map(), from, to, range)
+    {
+        return UnitVisibility::No;
+    }
+    let distance = distance(from, to);
+    let tile = calc_visibility(state.map().tile(to), unit_type, &distance);
+    unit_type_visibility(&tile, db.unit_type(&target.type_id))
+}
+
 /// Fog of War
+#[derive(Clone)]
 pub struct Fow {
     map: Map<TileVisibility>,
     player_id: PlayerId,
+
+    /// Enemy units that gave away their positions by firing,
+    /// other units in their tiles stay hidden
+    revealed_units: HashMap<UnitId, ExactPos>,
 }
 
 impl Fow {
@@ -81,6 +158,7 @@ impl Fow {
         Fow {
             map: Map::new(map_size),
             player_id: player_id.clone(),
+            revealed_units: HashMap::new(),
         }
     }
 
@@ -88,19 +166,34 @@ impl Fow {
         match *self.map.tile(pos) {
             TileVisibility::Excellent => true,
             TileVisibility::Normal => true,
+            TileVisibility::Bad => false,
             TileVisibility::No => false,
         }
     }
 
-    fn check_terrain_visibility(&self, unit_type: &UnitType, pos: &MapPos) -> bool {
-        match *self.map.tile(pos) {
-            TileVisibility::Excellent => true,
-            TileVisibility::Normal => match unit_type.class {
-                UnitClass::Infantry => false,
-                UnitClass::Vehicle => true,
-            },
-            TileVisibility::No => false,
+    fn terrain_visibility(&self, unit_type: &UnitType, pos: &MapPos) -> UnitVisibility {
+        unit_type_visibility(self.map.tile(pos), unit_type)
+    }
+
+    pub fn check_terrain_visibility(&self, unit_type: &UnitType, pos: &MapPos) -> bool {
+        self.terrain_visibility(unit_type, pos) == UnitVisibility::Full
+    }
+
+    pub fn unit_visibility(
+        &self,
+        db: &Db,
+        state: &InternalState,
+        unit: &Unit,
+        pos: &ExactPos,
+    ) -> UnitVisibility {
+        if is_inside_of_other_unit(state, unit, pos) {
+            return UnitVisibility::No;
+        }
+        if self.revealed_units.get(&unit.id) == Some(pos) {
+            return UnitVisibility::Full;
         }
+        let unit_type = db.unit_type(&unit.type_id);
+        self.terrain_visibility(unit_type, &pos.map_pos)
     }
 
     pub fn is_visible(
@@ -110,15 +203,7 @@ impl Fow {
         unit: &Unit,
         pos: &ExactPos,
     ) -> bool {
-        for (_, other_unit) in state.units() {
-            if let Some(ref passenger_id) = other_unit.passenger_id {
-                if *passenger_id == unit.id && other_unit.pos == *pos {
-                    return false;
-                }
-            }
-        }
-        let unit_type = db.unit_type(&unit.type_id);
-        self.check_terrain_visibility(unit_type, &pos.map_pos)
+        self.unit_visibility(db, state, unit, pos) == UnitVisibility::Full
     }
 
     fn clear(&mut self) {
@@ -129,9 +214,10 @@ impl Fow {
 
     fn reset(&mut self, db: &Db, state: &InternalState) {
         self.clear();
+        self.revealed_units.clear();
         for (_, unit) in state.units() {
             if unit.player_id == self.player_id {
-                fov_unit(db, state.map(), &mut self.map, &unit);
+                fov_unit(db, state, &mut self.map, &unit);
             }
         }
     }
@@ -147,10 +233,14 @@ impl Fow {
                 let unit = state.unit(unit_id);
                 if unit.player_id == self.player_id {
                     fov_unit_in_pos(
-                        db, state.map(), &mut self.map, unit, &to.map_pos);
+                        db, state, &mut self.map, unit, &to.map_pos);
+                } else {
+                    self.revealed_units.remove(unit_id);
                 }
             },
-            &CoreEvent::EndTurn{ref new_id, ..} => {
+            &CoreEvent::EndTurn{ref new_id, ..}
+                | &CoreEvent::EndPlanning{ref new_id, ..} =>
+            {
                 if self.player_id == *new_id {
                     self.reset(db, state);
                 }
@@ -158,15 +248,15 @@ impl Fow {
             &CoreEvent::CreateUnit{ref unit_info} => {
                 let unit = state.unit(&unit_info.unit_id);
                 if self.player_id == unit_info.player_id {
-                    fov_unit(db, state.map(), &mut self.map, unit);
+                    fov_unit(db, state, &mut self.map, unit);
                 }
             },
             &CoreEvent::AttackUnit{ref attack_info} => {
                 if let Some(ref attacker_id) = attack_info.attacker_id {
-                    if !attack_info.is_ambush {
-                        let pos = &state.unit(attacker_id).pos;
-                        // TODO: do not give away all units in this tile!
-                        *self.map.tile_mut(pos) = TileVisibility::Excellent;
+                    let attacker = state.unit(attacker_id);
+                    if !attack_info.is_ambush && attacker.player_id != self.player_id {
+                        self.revealed_units.insert(
+                            attacker_id.clone(), attacker.pos.clone());
                     }
                 }
             },
@@ -177,12 +267,101 @@ impl Fow {
                 if self.player_id == unit_info.player_id {
                     let unit = state.unit(&unit_info.unit_id);
                     let pos = &unit_info.pos.map_pos;
-                    fov_unit_in_pos(db, state.map(), &mut self.map, unit, pos);
+                    fov_unit_in_pos(db, state, &mut self.map, unit, pos);
+                }
+            },
+            &CoreEvent::BailOut{ref unit_info, ..} => {
+                if self.player_id == unit_info.player_id {
+                    if let Some(unit) = state.units().get(&unit_info.unit_id) {
+                        let pos = &unit_info.pos.map_pos;
+                        fov_unit_in_pos(db, state, &mut self.map, unit, pos);
+                    }
                 }
             },
             &CoreEvent::SetReactionFireMode{..} => {},
+            &CoreEvent::ConvertAp{..} => {},
+            &CoreEvent::Noise{..} => {},
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use types::{Size2, ZInt};
+    use db::{Db};
+    use internal_state::{InternalState};
+    use game_state::{GameState, GameStateMut};
+    use ::{
+        CoreEvent,
+        AttackInfo,
+        FireMode,
+        PlayerId,
+        UnitId,
+    };
+    use test_utils::{create_unit_event};
+    use super::{Fow, UnitVisibility};
+
+    fn create_unit(
+        db: &Db,
+        state: &mut InternalState,
+        fow: &mut Fow,
+        id: ZInt,
+        type_name: &str,
+        player_id: ZInt,
+        pos: (ZInt, ZInt, u8),
+    ) -> UnitId {
+        let event = create_unit_event(db, id, type_name, player_id, pos);
+        state.apply_event(db, &event);
+        fow.apply_event(db, state, &event);
+        UnitId{id: id}
+    }
+
+    #[test]
+    fn test_contacts() {
+        let db = Db::new();
+        let mut state = InternalState::new(&Size2{w: 10, h: 8});
+        let mut fow = Fow::new(state.map().size(), &PlayerId{id: 0});
+        create_unit(&db, &mut state, &mut fow, 0, "soldier", 0, (0, 1, 0));
+        let near_soldier_id = create_unit(&db, &mut state, &mut fow, 1, "soldier", 1, (4, 1, 0));
+        let far_truck_id = create_unit(&db, &mut state, &mut fow, 2, "truck", 1, (7, 1, 0));
+        let far_soldier_id = create_unit(&db, &mut state, &mut fow, 3, "soldier", 1, (8, 1, 0));
+        let check = |id: &UnitId, expected: UnitVisibility| {
+            let unit = state.unit(id);
+            assert_eq!(fow.unit_visibility(&db, &state, unit, &unit.pos), expected);
+        };
+        check(&near_soldier_id, UnitVisibility::Full);
+        check(&far_truck_id, UnitVisibility::Contact);
+        check(&far_soldier_id, UnitVisibility::No);
+    }
+
+    #[test]
+    fn test_attacker_does_not_give_away_its_tile() {
+        let db = Db::new();
+        let mut state = InternalState::new(&Size2{w: 10, h: 8});
+        let mut fow = Fow::new(state.map().size(), &PlayerId{id: 0});
+        let defender_id = create_unit(&db, &mut state, &mut fow, 0, "soldier", 0, (0, 1, 0));
+        let attacker_id = create_unit(&db, &mut state, &mut fow, 1, "soldier", 1, (8, 1, 0));
+        let neighbour_id = create_unit(&db, &mut state, &mut fow, 2, "soldier", 1, (8, 1, 1));
+        fow.apply_event(&db, &state, &CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(attacker_id.clone()),
+                defender_id: defender_id,
+                mode: FireMode::Active,
+                killed: 0,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: true,
+                is_assault: false,
+            },
+        });
+        let check = |id: &UnitId, expected: UnitVisibility| {
+            let unit = state.unit(id);
+            assert_eq!(fow.unit_visibility(&db, &state, unit, &unit.pos), expected);
+        };
+        check(&attacker_id, UnitVisibility::Full);
+        check(&neighbour_id, UnitVisibility::No);
+    }
+}
+
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -0,0 +1,315 @@
+// See LICENSE file for copyright and license details.
+
+//! Random scenarios and command streams for property tests
+//!
+//! Half of the generated commands are orders that a player could give,
+//! the rest is garbage: unknown ids, positions outside of the map,
+//! broken paths, units of the other player. `check_command` must
+//! reject everything that `Core` can`t execute, so no command may panic.
+
+use cgmath::{Vector2};
+use rand::{Rng, XorShiftRng};
+use types::{ZInt};
+use game_state::{GameState};
+use dir::{Dir, dirs};
+use map::{distance};
+use ::{
+    Core,
+    Options,
+    TurnMode,
+    Command,
+    MoveMode,
+    ReactionFireMode,
+    UnitId,
+    UnitTypeId,
+    PlayerId,
+    MapPos,
+    ExactPos,
+    SlotId,
+    get_free_exact_pos,
+};
+
+fn random_map_pos(rng: &mut XorShiftRng, core: &Core) -> MapPos {
+    let size = core.map_size();
+    MapPos{v: Vector2{x: rng.gen_range(0, size.w), y: rng.gen_range(0, size.h)}}
+}
+
+fn random_unit_type_id(rng: &mut XorShiftRng, core: &Core) -> UnitTypeId {
+    rng.choose(&core.db.real_unit_type_ids()).unwrap().clone()
+}
+
+/// Default map with some random units of both players added
+pub fn random_core(rng: &mut XorShiftRng, seed: u64, turn_mode: &TurnMode) -> Core {
+    let mut core = Core::new(&Options {
+        seed: Some(seed),
+        turn_mode: turn_mode.clone(),
+        .. Options::default()
+    }).unwrap();
+    for _ in 0 .. rng.gen_range(0, 10) {
+        let pos = random_map_pos(rng, &core);
+        let type_id = random_unit_type_id(rng, &core);
+        let player_id = PlayerId{id: rng.gen_range(0, 2)};
+        if get_free_exact_pos(&core.db, &core.state, &type_id, &pos).is_some() {
+            core.add_unit(&pos, &type_id, &player_id);
+        }
+    }
+    core
+}
+
+fn random_pos_near(rng: &mut XorShiftRng, core: &Core, pos: &MapPos) -> Option<MapPos> {
+    let pos = Dir::get_neighbour_pos(pos, &Dir::from_int(rng.gen_range(0, 6)));
+    if core.state.map().is_inboard(&pos) {
+        Some(pos)
+    } else {
+        None
+    }
+}
+
+fn step_towards(core: &Core, pos: &MapPos, target: &MapPos) -> Option<MapPos> {
+    dirs()
+        .map(|dir| Dir::get_neighbour_pos(pos, &dir))
+        .filter(|pos| core.state.map().is_inboard(pos))
+        .min_by_key(|pos| distance(pos, target))
+}
+
+fn random_move_mode(rng: &mut XorShiftRng) -> MoveMode {
+    if rng.gen() { MoveMode::Fast } else { MoveMode::Hunt }
+}
+
+/// Order that the current player could give, core still rejects most of them
+pub fn random_order(rng: &mut XorShiftRng, core: &Core) -> Command {
+    let state = &core.state;
+    let mut unit_ids: Vec<UnitId> = state.units().keys().cloned().collect();
+    unit_ids.sort();
+    let (own_ids, all_enemy_ids): (Vec<_>, Vec<_>) = unit_ids.into_iter()
+        .partition(|id| state.unit(id).player_id == *core.player_id());
+    if own_ids.is_empty() || rng.gen_range(0, 30) == 0 {
+        return Command::EndTurn;
+    }
+    let unit_id = rng.choose(&own_ids).unwrap().clone();
+    let unit = state.unit(&unit_id);
+    let mut enemy_ids: Vec<_> = core.players_info[core.player_id()]
+        .visible_enemies.iter()
+        .chain(&core.players_info[core.player_id()].enemy_contacts)
+        .cloned()
+        .collect();
+    enemy_ids.sort();
+    let transporter_ids: Vec<_> = own_ids.iter()
+        .filter(|id| state.unit(id).pos.map_pos == unit.pos.map_pos)
+        .cloned()
+        .collect();
+    match rng.gen_range(0, 6) {
+        0 | 1 | 2 => {
+            // most of the moves are towards the enemy to make fights happen
+            let target = rng.choose(&all_enemy_ids)
+                .map(|id| state.unit(id).pos.map_pos.clone());
+            let mut path = Vec::new();
+            let mut pos = unit.pos.map_pos.clone();
+            for _ in 0 .. rng.gen_range(1, 4) {
+                let next_pos = match target {
+                    Some(ref target) if rng.gen_range(0, 3) != 0 => {
+                        step_towards(core, &pos, target)
+                    },
+                    _ => random_pos_near(rng, core, &pos),
+                };
+                pos = match next_pos {
+                    Some(pos) => pos,
+                    None => break,
+                };
+                match get_free_exact_pos(&core.db, state, &unit.type_id, &pos) {
+                    Some(exact_pos) => path.push(exact_pos),
+                    None => break,
+                }
+            }
+            let mode = random_move_mode(rng);
+            Command::Move{unit_id: unit_id, path: path, mode: mode}
+        },
+        3 => match rng.choose(&enemy_ids) {
+            Some(defender_id) => Command::AttackUnit {
+                attacker_id: unit_id,
+                defender_id: defender_id.clone(),
+            },
+            None => Command::EndTurn,
+        },
+        4 => Command::LoadUnit {
+            transporter_id: rng.choose(&transporter_ids).unwrap().clone(),
+            passenger_id: unit_id,
+        },
+        _ => {
+            let mut passenger_ids = unit.passenger_ids.clone();
+            passenger_ids.extend(unit.towed_unit_id.clone());
+            let passenger_id = match rng.choose(&passenger_ids) {
+                Some(id) => id.clone(),
+                None => return Command::EndTurn,
+            };
+            let passenger_type_id = &state.unit(&passenger_id).type_id;
+            let pos = random_pos_near(rng, core, &unit.pos.map_pos)
+                .and_then(|pos| get_free_exact_pos(
+                    &core.db, state, passenger_type_id, &pos));
+            match pos {
+                Some(pos) => Command::UnloadUnit {
+                    transporter_id: unit_id,
+                    passenger_id: passenger_id,
+                    pos: pos,
+                },
+                None => Command::EndTurn,
+            }
+        },
+    }
+}
+
+/// Any id that was ever given out and some that were not
+fn random_unit_id(rng: &mut XorShiftRng, core: &Core) -> UnitId {
+    UnitId{id: rng.gen_range(-1, core.next_unit_id.id + 2)}
+}
+
+/// Positions can be outside of the map and slots may not exist
+fn random_exact_pos(rng: &mut XorShiftRng, core: &Core) -> ExactPos {
+    let size = core.map_size();
+    let map_pos = MapPos{v: Vector2{
+        x: rng.gen_range(-1, size.w + 1),
+        y: rng.gen_range(-1, size.h + 1),
+    }};
+    let slot_id = match rng.gen_range(0, 5) {
+        4 => SlotId::WholeTile,
+        n => SlotId::Id(n as u8),
+    };
+    ExactPos{map_pos: map_pos, slot_id: slot_id}
+}
+
+fn random_garbage(rng: &mut XorShiftRng, core: &Core) -> Command {
+    let unit_id = random_unit_id(rng, core);
+    match rng.gen_range(0, 8) {
+        0 => Command::EndTurn,
+        1 => Command::CreateUnit {
+            pos: random_exact_pos(rng, core),
+            type_id: if rng.gen_range(0, 4) == 0 {
+                UnitTypeId{id: rng.gen_range(-1, 100) as ZInt}
+            } else {
+                random_unit_type_id(rng, core)
+            },
+        },
+        2 => {
+            let path = (0 .. rng.gen_range(0, 4))
+                .map(|_| random_exact_pos(rng, core))
+                .collect();
+            let mode = random_move_mode(rng);
+            Command::Move{unit_id: unit_id, path: path, mode: mode}
+        },
+        3 => Command::AttackUnit {
+            attacker_id: unit_id,
+            defender_id: random_unit_id(rng, core),
+        },
+        4 => Command::Assault {
+            attacker_id: unit_id,
+            defender_id: random_unit_id(rng, core),
+        },
+        5 => Command::LoadUnit {
+            transporter_id: random_unit_id(rng, core),
+            passenger_id: unit_id,
+        },
+        6 => Command::UnloadUnit {
+            transporter_id: random_unit_id(rng, core),
+            passenger_id: unit_id,
+            pos: random_exact_pos(rng, core),
+        },
+        _ => Command::SetReactionFireMode {
+            unit_id: unit_id,
+            mode: if rng.gen() {
+                ReactionFireMode::Normal
+            } else {
+                ReactionFireMode::HoldFire
+            },
+        },
+    }
+}
+
+pub fn random_command(rng: &mut XorShiftRng, core: &Core) -> Command {
+    if rng.gen() {
+        random_order(rng, core)
+    } else {
+        random_garbage(rng, core)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game_state::{GameState, GameStateMut};
+    use partial_state::{PartialState};
+    use ::{
+        Core,
+        PlayerId,
+        TurnMode,
+        check_command,
+        find_next_player_unit_id,
+        find_prev_player_unit_id,
+        seeded_rng,
+    };
+    use super::{random_core, random_command};
+
+    /// Player's own units must be exactly the same as the real ones
+    fn check_own_units(core: &Core, player_id: &PlayerId, state: &PartialState) {
+        let mut real_ids: Vec<_> = core.state.units().values()
+            .filter(|unit| unit.player_id == *player_id)
+            .map(|unit| unit.id.clone())
+            .collect();
+        let mut known_ids: Vec<_> = state.units().values()
+            .filter(|unit| unit.player_id == *player_id)
+            .map(|unit| unit.id.clone())
+            .collect();
+        real_ids.sort();
+        known_ids.sort();
+        assert!(real_ids == known_ids);
+        for id in &real_ids {
+            let real = core.state.unit(id);
+            let known = state.unit(id);
+            assert_eq!(real.pos, known.pos);
+            assert!(real.type_id == known.type_id);
+            assert_eq!(real.count, known.count);
+            assert_eq!(real.morale, known.morale);
+            assert_eq!(real.move_points.n, known.move_points.n);
+            assert_eq!(real.attack_points.n, known.attack_points.n);
+            assert!(real.passenger_ids == known.passenger_ids);
+            assert!(real.towed_unit_id == known.towed_unit_id);
+            assert!(real.reaction_fire_mode == known.reaction_fire_mode);
+        }
+        for id in &known_ids {
+            find_next_player_unit_id(state, player_id, id);
+            find_prev_player_unit_id(state, player_id, id);
+        }
+    }
+
+    fn check_random_game(seed: u64, turn_mode: &TurnMode) {
+        let mut rng = seeded_rng(seed);
+        let mut core = random_core(&mut rng, seed, turn_mode);
+        let player_ids: Vec<_> = core.players().iter()
+            .map(|player| player.id.clone())
+            .collect();
+        let mut states: Vec<_> = player_ids.iter()
+            .map(|id| PartialState::from_scenario(core.scenario(), id))
+            .collect();
+        for _ in 0 .. 500 {
+            let command = random_command(&mut rng, &core);
+            // client side check must not panic too
+            let current_player_index = core.player_id().id as usize;
+            let _ = check_command(&core.db, &states[current_player_index], &command);
+            let _ = core.simulation_step(command);
+            for (player_id, state) in player_ids.iter().zip(states.iter_mut()) {
+                while let Some(event) = core.get_player_event(player_id) {
+                    state.apply_event(&core.db, &event);
+                }
+                check_own_units(&core, player_id, state);
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_commands() {
+        for seed in 0 .. 50 {
+            check_random_game(seed, &TurnMode::Igougo);
+            check_random_game(seed, &TurnMode::Wego);
+        }
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -4,12 +4,14 @@ use std::collections::{HashMap};
 use unit::{Unit};
 use db::{Db};
 use map::{Map, Terrain};
+use fov::{Fov};
 use ::{CoreEvent, UnitId, ObjectId, Object, MapPos};
 
 pub trait GameState {
     fn map(&self) -> &Map<Terrain>;
     fn units(&self) -> &HashMap<UnitId, Unit>;
     fn objects(&self) -> &HashMap<ObjectId, Object>;
+    fn fov(&self) -> &Fov;
 
     fn unit(&self, id: &UnitId) -> &Unit {
         &self.units()[id]
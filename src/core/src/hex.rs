@@ -0,0 +1,399 @@
+// See LICENSE file for copyright and license details.
+
+//! Hex math in cube and axial coordinates
+//!
+//! `MapPos` uses offset coordinates: rows are horizontal and even rows
+//! are shifted half a tile to the east. In axial coordinates `q` grows
+//! to the east and `r` is the row, cube coordinates add the redundant
+//! `y = -x - z` that makes distances, rotations and reflections simple.
+
+use std::ops::{Add, Sub};
+use cgmath::{Vector2};
+use types::{Size2, ZInt, ZFloat};
+use dir::{Dir, dirs};
+use ::{MapPos};
+
+/// Line through the center of a tile that `reflect` mirrors across
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Axis {
+    /// Swaps east and west, see `Cube::reflect_east_west`
+    NorthSouth,
+
+    /// Swaps north and south, see `Cube::reflect_north_south`
+    EastWest,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Axial {
+    pub q: ZInt,
+    pub r: ZInt,
+}
+
+/// `x + y + z == 0`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Cube {
+    pub x: ZInt,
+    pub y: ZInt,
+    pub z: ZInt,
+}
+
+/// In the order of `Dir::to_int`
+const DIR_TO_CUBE_DIFF: [Cube; 6] = [
+    Cube{x: 1, y: 0, z: -1},
+    Cube{x: 1, y: -1, z: 0},
+    Cube{x: 0, y: -1, z: 1},
+    Cube{x: -1, y: 0, z: 1},
+    Cube{x: -1, y: 1, z: 0},
+    Cube{x: 0, y: 1, z: -1},
+];
+
+fn div_floor(a: ZInt, b: ZInt) -> ZInt {
+    let d = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) { d - 1 } else { d }
+}
+
+fn is_inboard(map_size: &Size2, pos: &MapPos) -> bool {
+    let v = pos.v;
+    v.x >= 0 && v.y >= 0 && v.x < map_size.w && v.y < map_size.h
+}
+
+impl Axial {
+    pub fn from_map_pos(pos: &MapPos) -> Axial {
+        Axial{q: pos.v.x - div_floor(pos.v.y + 1, 2), r: pos.v.y}
+    }
+
+    pub fn to_map_pos(&self) -> MapPos {
+        MapPos{v: Vector2{x: self.q + div_floor(self.r + 1, 2), y: self.r}}
+    }
+
+    pub fn to_cube(&self) -> Cube {
+        Cube{x: self.q, y: -self.q - self.r, z: self.r}
+    }
+}
+
+impl Cube {
+    pub fn from_map_pos(pos: &MapPos) -> Cube {
+        Axial::from_map_pos(pos).to_cube()
+    }
+
+    pub fn to_map_pos(&self) -> MapPos {
+        self.to_axial().to_map_pos()
+    }
+
+    pub fn to_axial(&self) -> Axial {
+        Axial{q: self.x, r: self.z}
+    }
+
+    pub fn distance(&self, other: &Cube) -> ZInt {
+        let d = *self - *other;
+        (d.x.abs() + d.y.abs() + d.z.abs()) / 2
+    }
+
+    pub fn neighbour(&self, dir: &Dir) -> Cube {
+        *self + DIR_TO_CUBE_DIFF[dir.to_int() as usize]
+    }
+
+    /// Rotates around `center` by `steps` of 60 degrees,
+    /// a positive step turns `Dir::East` into `Dir::NorthEast`
+    pub fn rotate(&self, center: &Cube, steps: ZInt) -> Cube {
+        let mut d = *self - *center;
+        for _ in 0 .. steps.rem_euclid(6) {
+            d = Cube{x: -d.z, y: -d.x, z: -d.y};
+        }
+        *center + d
+    }
+
+    /// Mirrors across the north-south line through `center`
+    pub fn reflect_east_west(&self, center: &Cube) -> Cube {
+        let d = *self - *center;
+        *center + Cube{x: d.y, y: d.x, z: d.z}
+    }
+
+    /// Mirrors across the east-west line through `center`
+    pub fn reflect_north_south(&self, center: &Cube) -> Cube {
+        let d = *self - *center;
+        *center + Cube{x: -d.y, y: -d.x, z: -d.z}
+    }
+}
+
+impl Add for Cube {
+    type Output = Cube;
+
+    fn add(self, other: Cube) -> Cube {
+        Cube{x: self.x + other.x, y: self.y + other.y, z: self.z + other.z}
+    }
+}
+
+impl Sub for Cube {
+    type Output = Cube;
+
+    fn sub(self, other: Cube) -> Cube {
+        Cube{x: self.x - other.x, y: self.y - other.y, z: self.z - other.z}
+    }
+}
+
+fn cube_round(x: ZFloat, y: ZFloat, z: ZFloat) -> Cube {
+    let (rx, ry, rz) = (x.round(), y.round(), z.round());
+    let (dx, dy, dz) = ((rx - x).abs(), (ry - y).abs(), (rz - z).abs());
+    let (rx, ry, rz) = (rx as ZInt, ry as ZInt, rz as ZInt);
+    if dx > dy && dx > dz {
+        Cube{x: -ry - rz, y: ry, z: rz}
+    } else if dy > dz {
+        Cube{x: rx, y: -rx - rz, z: rz}
+    } else {
+        Cube{x: rx, y: ry, z: -rx - ry}
+    }
+}
+
+pub fn distance(from: &MapPos, to: &MapPos) -> ZInt {
+    Cube::from_map_pos(from).distance(&Cube::from_map_pos(to))
+}
+
+pub fn neighbour(pos: &MapPos, dir: &Dir) -> MapPos {
+    Cube::from_map_pos(pos).neighbour(dir).to_map_pos()
+}
+
+/// Neighbours that are on the board, in the order of `dirs()`
+pub fn neighbours(pos: &MapPos, map_size: &Size2) -> Vec<MapPos> {
+    dirs()
+        .map(|dir| neighbour(pos, &dir))
+        .filter(|pos| is_inboard(map_size, pos))
+        .collect()
+}
+
+/// Tiles crossed by the straight line between the centers of the tiles,
+/// both ends included. Lines along the tile edges lean to one side
+/// the same way in both directions.
+pub fn line(from: &MapPos, to: &MapPos) -> Vec<MapPos> {
+    let a = Cube::from_map_pos(from);
+    let b = Cube::from_map_pos(to);
+    let n = a.distance(&b);
+    // nudge to keep the points off the edges between tiles
+    let (ex, ey, ez) = (1e-3, 2e-3, -3e-3);
+    let mut positions = Vec::new();
+    for i in 0 .. n + 1 {
+        let t = if n == 0 { 0.0 } else { i as ZFloat / n as ZFloat };
+        let lerp = |a: ZInt, b: ZInt| a as ZFloat + (b - a) as ZFloat * t;
+        let cube = cube_round(
+            lerp(a.x, b.x) + ex, lerp(a.y, b.y) + ey, lerp(a.z, b.z) + ez);
+        positions.push(cube.to_map_pos());
+    }
+    positions
+}
+
+/// Tiles at exactly `radius` from `center` that are on the board,
+/// in the same order as `map::ring_iter` gives them
+pub fn ring(center: &MapPos, radius: ZInt, map_size: &Size2) -> Vec<MapPos> {
+    assert!(radius >= 0);
+    let center = Cube::from_map_pos(center);
+    if radius == 0 {
+        let pos = center.to_map_pos();
+        return if is_inboard(map_size, &pos) { vec![pos] } else { Vec::new() };
+    }
+    let west = DIR_TO_CUBE_DIFF[Dir::West.to_int() as usize];
+    let mut cursor = center;
+    for _ in 0 .. radius {
+        cursor = cursor + west;
+    }
+    let mut positions = Vec::new();
+    for dir in dirs() {
+        for _ in 0 .. radius {
+            cursor = cursor.neighbour(&dir);
+            let pos = cursor.to_map_pos();
+            if is_inboard(map_size, &pos) {
+                positions.push(pos);
+            }
+        }
+    }
+    positions
+}
+
+/// Tiles not farther than `radius` from `center` that are on the board:
+/// the center and then rings as `map::spiral_iter` gives them
+pub fn range(center: &MapPos, radius: ZInt, map_size: &Size2) -> Vec<MapPos> {
+    let mut positions = Vec::new();
+    for r in 0 .. radius + 1 {
+        positions.extend(ring(center, r, map_size));
+    }
+    positions
+}
+
+/// Rotates `pos` around `center` by `steps` of 60 degrees,
+/// `None` if the result is off the board
+pub fn rotate(pos: &MapPos, center: &MapPos, steps: ZInt, map_size: &Size2) -> Option<MapPos> {
+    let rotated = Cube::from_map_pos(pos)
+        .rotate(&Cube::from_map_pos(center), steps)
+        .to_map_pos();
+    if is_inboard(map_size, &rotated) { Some(rotated) } else { None }
+}
+
+/// Mirrors `pos` across `axis` through `center`,
+/// `None` if the result is off the board
+pub fn reflect(pos: &MapPos, center: &MapPos, axis: Axis, map_size: &Size2) -> Option<MapPos> {
+    let cube = Cube::from_map_pos(pos);
+    let center = Cube::from_map_pos(center);
+    let reflected = match axis {
+        Axis::NorthSouth => cube.reflect_east_west(&center),
+        Axis::EastWest => cube.reflect_north_south(&center),
+    }.to_map_pos();
+    if is_inboard(map_size, &reflected) { Some(reflected) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{Size2};
+    use map::{ring_iter, spiral_iter};
+    use dir::{Dir, dirs};
+    use ::{MapPos};
+    use super::{
+        Axis,
+        Axial,
+        Cube,
+        neighbour,
+        neighbours,
+        line,
+        ring,
+        range,
+        rotate,
+        reflect,
+        distance,
+    };
+
+    fn pos(x: i32, y: i32) -> MapPos {
+        MapPos{v: Vector2{x: x, y: y}}
+    }
+
+    fn all_positions() -> Vec<MapPos> {
+        let mut positions = Vec::new();
+        for y in -5 .. 6 {
+            for x in -5 .. 6 {
+                positions.push(pos(x, y));
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn test_conversions() {
+        for p in &all_positions() {
+            let axial = Axial::from_map_pos(p);
+            assert_eq!(axial.to_map_pos(), *p);
+            let cube = axial.to_cube();
+            assert_eq!(cube.x + cube.y + cube.z, 0);
+            assert_eq!(cube.to_axial(), axial);
+            assert_eq!(Cube::from_map_pos(p).to_map_pos(), *p);
+        }
+    }
+
+    #[test]
+    fn test_neighbours_match_dir() {
+        for p in &all_positions() {
+            for dir in dirs() {
+                assert_eq!(neighbour(p, &dir), Dir::get_neighbour_pos(p, &dir));
+                assert_eq!(distance(p, &neighbour(p, &dir)), 1);
+            }
+        }
+        let size = Size2{w: 5, h: 5};
+        assert_eq!(neighbours(&pos(0, 0), &size), vec![pos(1, 0), pos(1, 1), pos(0, 1)]);
+        assert_eq!(neighbours(&pos(2, 2), &size).len(), 6);
+    }
+
+    /// Old formula of `map::distance`, it is right for rows >= 0 only
+    fn offset_distance(from: &MapPos, to: &MapPos) -> i32 {
+        let dx = (to.v.x + to.v.y / 2) - (from.v.x + from.v.y / 2);
+        let dy = to.v.y - from.v.y;
+        (dx.abs() + dy.abs() + (dx - dy).abs()) / 2
+    }
+
+    #[test]
+    fn test_distance_matches_offset_formula() {
+        for y in 0 .. 6 {
+            for x in 0 .. 6 {
+                let from = pos(x, y);
+                for to in &all_positions() {
+                    if to.v.x >= 0 && to.v.y >= 0 {
+                        assert_eq!(distance(&from, to), offset_distance(&from, to));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_ring_and_range_match_iters() {
+        let size = Size2{w: 20, h: 20};
+        for center in &[pos(10, 10), pos(7, 9)] {
+            for radius in 1 .. 5 {
+                let expected: Vec<_> = ring_iter(center, radius).collect();
+                assert_eq!(ring(center, radius, &size), expected);
+                let expected: Vec<_> = spiral_iter(center, radius).collect();
+                let positions = range(center, radius, &size);
+                assert_eq!(positions[0], *center);
+                assert_eq!(positions[1 ..].to_vec(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ring_and_range_are_clipped() {
+        let size = Size2{w: 5, h: 5};
+        assert_eq!(ring(&pos(0, 0), 1, &size).len(), 3);
+        assert!(ring(&pos(0, 0), 0, &size) == vec![pos(0, 0)]);
+        assert!(ring(&pos(-1, 0), 0, &size).is_empty());
+        let positions = range(&pos(2, 2), 10, &size);
+        assert_eq!(positions.len(), 25);
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line(&pos(1, 1), &pos(1, 1)), vec![pos(1, 1)]);
+        assert_eq!(line(&pos(0, 3), &pos(4, 3)),
+            vec![pos(0, 3), pos(1, 3), pos(2, 3), pos(3, 3), pos(4, 3)]);
+        for from in &[pos(0, 0), pos(3, 2), pos(-2, 5)] {
+            for to in &all_positions() {
+                let positions = line(from, to);
+                assert_eq!(positions.len() as i32, distance(from, to) + 1);
+                assert_eq!(positions[0], *from);
+                assert_eq!(*positions.last().unwrap(), *to);
+                for pair in positions.windows(2) {
+                    assert_eq!(distance(&pair[0], &pair[1]), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rotation_and_reflection() {
+        let center = Cube::from_map_pos(&pos(2, 3));
+        for p in &all_positions() {
+            let cube = Cube::from_map_pos(p);
+            assert_eq!(cube.rotate(&center, 6), cube);
+            assert_eq!(cube.rotate(&center, 2).rotate(&center, -2), cube);
+            assert_eq!(cube.rotate(&center, 1).distance(&center), cube.distance(&center));
+            assert_eq!(cube.reflect_east_west(&center).reflect_east_west(&center), cube);
+            assert_eq!(cube.reflect_north_south(&center).reflect_north_south(&center), cube);
+        }
+        for dir in dirs() {
+            let next_dir = Dir::from_int((dir.to_int() + 1) % 6);
+            let opposite_dir = Dir::from_int((dir.to_int() + 3) % 6);
+            let n = center.neighbour(&dir);
+            assert_eq!(n.rotate(&center, 1), center.neighbour(&next_dir));
+            assert_eq!(n.rotate(&center, 3), center.neighbour(&opposite_dir));
+        }
+        let east = center.neighbour(&Dir::East);
+        assert_eq!(east.reflect_east_west(&center), center.neighbour(&Dir::West));
+        assert_eq!(east.reflect_north_south(&center), east);
+        let north_east = center.neighbour(&Dir::NorthEast);
+        assert_eq!(north_east.reflect_east_west(&center), center.neighbour(&Dir::NorthWest));
+        assert_eq!(north_east.reflect_north_south(&center), center.neighbour(&Dir::SouthEast));
+        let size = Size2{w: 5, h: 5};
+        assert_eq!(rotate(&pos(3, 2), &pos(2, 2), 3, &size), Some(pos(1, 2)));
+        assert_eq!(rotate(&pos(4, 2), &pos(1, 2), 3, &size), None);
+        assert_eq!(reflect(&pos(3, 2), &pos(2, 2), Axis::NorthSouth, &size), Some(pos(1, 2)));
+        assert_eq!(reflect(&pos(2, 1), &pos(2, 2), Axis::EastWest, &size), Some(pos(2, 3)));
+        assert_eq!(reflect(&pos(4, 2), &pos(1, 2), Axis::NorthSouth, &size), None);
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -1,11 +1,13 @@
 // See LICENSE file for copyright and license details.
 
+use std::cmp;
 use std::collections::{HashMap};
 use cgmath::{Vector2};
 use types::{ZInt, Size2};
 use unit::{Unit};
 use db::{Db};
 use map::{Map, Terrain};
+use fov::{Fov};
 use game_state::{GameState, GameStateMut};
 use ::{
     CoreEvent,
@@ -17,6 +19,7 @@ use ::{
     MapPos,
     ExactPos,
     SlotId,
+    HOOK_COST,
     Object,
     ObjectId,
     ObjectClass,
@@ -28,24 +31,38 @@ pub enum InfoLevel {
     Partial,
 }
 
+#[derive(Clone)]
 pub struct InternalState {
     units: HashMap<UnitId, Unit>,
     objects: HashMap<ObjectId, Object>,
     map: Map<Terrain>,
+    fov: Fov,
+}
+
+fn convert_unit_ap(db: &Db, unit: &mut Unit) {
+    let unit_type = db.unit_type(&unit.type_id);
+    let weapon_type = db.weapon_type(&unit_type.weapon_type_id);
+    if !weapon_type.reaction_fire {
+        return;
+    }
+    if let Some(ref mut reactive_attack_points) = unit.reactive_attack_points {
+        reactive_attack_points.n += unit.attack_points.n;
+    }
+    unit.attack_points.n = 0;
 }
 
 impl InternalState {
     pub fn new(map_size: &Size2) -> InternalState {
-        let mut map = Map::new(map_size);
-        // TODO: read from scenario.json?
-        *map.tile_mut(&MapPos{v: Vector2{x: 4, y: 3}}) = Terrain::Trees;
-        *map.tile_mut(&MapPos{v: Vector2{x: 4, y: 4}}) = Terrain::Trees;
-        *map.tile_mut(&MapPos{v: Vector2{x: 4, y: 5}}) = Terrain::Trees;
         let mut state = InternalState {
             units: HashMap::new(),
             objects: HashMap::new(),
-            map: map,
+            map: Map::new(map_size),
+            fov: Fov::new(),
         };
+        // TODO: read from scenario.json?
+        state.set_terrain(&MapPos{v: Vector2{x: 4, y: 3}}, Terrain::Trees);
+        state.set_terrain(&MapPos{v: Vector2{x: 4, y: 4}}, Terrain::Trees);
+        state.set_terrain(&MapPos{v: Vector2{x: 4, y: 5}}, Terrain::Trees);
         state.add_buildings(&MapPos{v: Vector2{x: 5, y: 4}}, 2);
         state.add_buildings(&MapPos{v: Vector2{x: 5, y: 5}}, 2);
         state.add_buildings(&MapPos{v: Vector2{x: 5, y: 6}}, 1);
@@ -55,13 +72,33 @@ impl InternalState {
         state
     }
 
+    /// State with a map that is not the default one, see `mapgen`
+    pub fn with_map(map: Map<Terrain>, objects: &[Object]) -> InternalState {
+        let mut state = InternalState {
+            units: HashMap::new(),
+            objects: HashMap::new(),
+            map: map,
+            fov: Fov::new(),
+        };
+        for object in objects {
+            state.add_object(object.clone());
+        }
+        state
+    }
+
+    fn set_terrain(&mut self, pos: &MapPos, terrain: Terrain) {
+        *self.map.tile_mut(pos) = terrain;
+        self.fov.clear_cache();
+    }
+
     fn add_object(&mut self, object: Object) {
         let id = ObjectId{id: self.objects.len() as ZInt + 1};
         self.objects.insert(id, object);
+        self.fov.clear_cache();
     }
 
     fn add_big_building(&mut self, pos: &MapPos) {
-        *self.map.tile_mut(pos) = Terrain::City;
+        self.set_terrain(pos, Terrain::City);
         let object = Object {
             class: ObjectClass::Building,
             pos: ExactPos {
@@ -73,7 +110,7 @@ impl InternalState {
     }
 
     fn add_buildings(&mut self, pos: &MapPos, count: ZInt) {
-        *self.map.tile_mut(pos) = Terrain::City;
+        self.set_terrain(pos, Terrain::City);
         for _ in 0 .. count {
             let slot_id = get_free_slot_for_building(self, pos).unwrap();
             let obj_pos = ExactPos{map_pos: pos.clone(), slot_id: slot_id};
@@ -88,17 +125,9 @@ impl InternalState {
     /// Converts active ap (attack points) to reactive
     fn convert_ap(&mut self, db: &Db, player_id: &PlayerId) {
         for (_, unit) in self.units.iter_mut() {
-            let unit_type = db.unit_type(&unit.type_id);
-            let weapon_type = db.weapon_type(&unit_type.weapon_type_id);
-            if unit.player_id != *player_id || !weapon_type.reaction_fire {
-                continue;
-            }
-            if let Some(ref mut reactive_attack_points)
-                = unit.reactive_attack_points
-            {
-                reactive_attack_points.n += unit.attack_points.n;
+            if unit.player_id == *player_id {
+                convert_unit_ap(db, unit);
             }
-            unit.attack_points.n = 0;
         }
     }
 
@@ -134,11 +163,17 @@ impl InternalState {
             reaction_fire_mode: ReactionFireMode::Normal,
             count: unit_type.count,
             morale: 100,
-            passenger_id: if let InfoLevel::Full = info_level {
-                unit_info.passenger_id.clone()
+            passenger_ids: if let InfoLevel::Full = info_level {
+                unit_info.passenger_ids.clone()
+            } else {
+                Vec::new()
+            },
+            towed_unit_id: if let InfoLevel::Full = info_level {
+                unit_info.towed_unit_id.clone()
             } else {
                 None
             },
+            is_contact: unit_info.is_contact,
         });
     }
 }
@@ -155,6 +190,10 @@ impl GameState for InternalState {
     fn map(&self) -> &Map<Terrain> {
         &self.map
     }
+
+    fn fov(&self) -> &Fov {
+        &self.fov
+    }
 }
 
 impl GameStateMut for InternalState {
@@ -164,19 +203,32 @@ impl GameStateMut for InternalState {
                 {
                     let unit = self.units.get_mut(unit_id).unwrap();
                     unit.pos = to.clone();
-                    assert!(unit.move_points.n > 0);
-                    unit.move_points.n -= cost.n;
-                    assert!(unit.move_points.n >= 0);
+                    // real move points of unidentified units are unknown
+                    if !unit.is_contact {
+                        assert!(unit.move_points.n >= cost.n);
+                        unit.move_points.n -= cost.n;
+                        assert!(unit.move_points.n >= 0);
+                    }
                 }
-                if let Some(passenger_id) = self.units[unit_id].passenger_id.clone() {
+                for passenger_id in self.units[unit_id].passenger_ids.clone() {
                     let passenger = self.units.get_mut(&passenger_id).unwrap();
                     passenger.pos = to.clone();
                 }
+                if let Some(towed_unit_id) = self.units[unit_id].towed_unit_id.clone() {
+                    let towed_unit = self.units.get_mut(&towed_unit_id).unwrap();
+                    towed_unit.pos = to.clone();
+                }
             },
             &CoreEvent::EndTurn{ref new_id, ref old_id} => {
                 self.refresh_units(db, new_id);
                 self.convert_ap(db, old_id);
             },
+            // units of the player who planned first are refreshed
+            // by `EndTurn` and ap of all players are converted by
+            // `ConvertAp` when everybody has planned
+            &CoreEvent::EndPlanning{ref new_id, ..} => {
+                self.refresh_units(db, new_id);
+            },
             &CoreEvent::CreateUnit{ref unit_info} => {
                 self.add_unit(db, unit_info, InfoLevel::Full);
             },
@@ -184,7 +236,11 @@ impl GameStateMut for InternalState {
                 {
                     let unit = self.units.get_mut(&attack_info.defender_id)
                         .expect("Can`t find defender");
-                    unit.count -= attack_info.killed;
+                    // size of a contact is unknown so it is
+                    // hidden explicitly when it is destroyed
+                    if !unit.is_contact {
+                        unit.count -= attack_info.killed;
+                    }
                     unit.morale -= attack_info.suppression;
                     if attack_info.remove_move_points {
                         unit.move_points.n = 0;
@@ -192,8 +248,10 @@ impl GameStateMut for InternalState {
                 }
                 let count = self.units[&attack_info.defender_id].count.clone();
                 if count <= 0 {
-                    // TODO: kill\unload passengers
-                    assert!(self.units.get(&attack_info.defender_id).is_some());
+                    assert!(self.units[&attack_info.defender_id].passenger_ids.is_empty(),
+                        "Passenger must bail out before its transporter is destroyed");
+                    assert!(self.units[&attack_info.defender_id].towed_unit_id.is_none(),
+                        "Towed gun must be unhooked before its tower is destroyed");
                     self.units.remove(&attack_info.defender_id);
                 }
                 let attacker_id = match attack_info.attacker_id.clone() {
@@ -206,6 +264,8 @@ impl GameStateMut for InternalState {
                             assert!(unit.attack_points.n >= 1);
                             unit.attack_points.n -= 1;
                         },
+                        // defender strikes back in close combat for free
+                        FireMode::Reactive if attack_info.is_assault => {},
                         FireMode::Reactive => {
                             if let Some(ref mut reactive_attack_points)
                                 = unit.reactive_attack_points
@@ -218,6 +278,8 @@ impl GameStateMut for InternalState {
                 }
             },
             &CoreEvent::ShowUnit{ref unit_info} => {
+                // contact can be identified or lose its identification
+                self.units.remove(&unit_info.unit_id);
                 self.add_unit(db, unit_info, InfoLevel::Partial);
             },
             &CoreEvent::HideUnit{ref unit_id} => {
@@ -225,11 +287,24 @@ impl GameStateMut for InternalState {
                 self.units.remove(unit_id);
             },
             &CoreEvent::LoadUnit{ref passenger_id, ref transporter_id, ref to, ..} => {
-                // TODO: hide info about passenger from enemy player
+                // enemy unit got into a transporter that it is not
+                // allowed to know about
+                if transporter_id.is_none() {
+                    self.units.remove(passenger_id);
+                    return;
+                }
                 if let &Some(ref transporter_id) = transporter_id {
-                    self.units.get_mut(transporter_id)
-                        .expect("Bad transporter_id")
-                        .passenger_id = Some(passenger_id.clone());
+                    let is_towable = db.unit_type(
+                        &self.units[passenger_id].type_id).is_towable;
+                    let transporter = self.units.get_mut(transporter_id)
+                        .expect("Bad transporter_id");
+                    if is_towable {
+                        transporter.towed_unit_id = Some(passenger_id.clone());
+                        transporter.move_points.n = cmp::max(
+                            0, transporter.move_points.n - HOOK_COST);
+                    } else {
+                        transporter.passenger_ids.push(passenger_id.clone());
+                    }
                 }
                 let passenger = self.units.get_mut(passenger_id)
                     .expect("Bad passenger_id");
@@ -238,9 +313,15 @@ impl GameStateMut for InternalState {
             },
             &CoreEvent::UnloadUnit{ref transporter_id, ref unit_info, ..} => {
                 if let &Some(ref transporter_id) = transporter_id {
-                    self.units.get_mut(transporter_id)
-                        .expect("Bad transporter_id")
-                        .passenger_id = None;
+                    let transporter = self.units.get_mut(transporter_id)
+                        .expect("Bad transporter_id");
+                    if transporter.towed_unit_id.as_ref() == Some(&unit_info.unit_id) {
+                        transporter.towed_unit_id = None;
+                        transporter.move_points.n = cmp::max(
+                            0, transporter.move_points.n - HOOK_COST);
+                    } else {
+                        transporter.passenger_ids.retain(|id| *id != unit_info.unit_id);
+                    }
                 }
                 if let Some(unit) = self.units.get_mut(&unit_info.unit_id) {
                     unit.pos = unit_info.pos.clone();
@@ -248,11 +329,40 @@ impl GameStateMut for InternalState {
                 }
                 self.add_unit(db, unit_info, InfoLevel::Partial);
             },
+            &CoreEvent::BailOut{ref transporter_id, ref unit_info, ref killed, ref suppression, ..} => {
+                if let &Some(ref transporter_id) = transporter_id {
+                    let transporter = self.units.get_mut(transporter_id)
+                        .expect("Bad transporter_id");
+                    if transporter.towed_unit_id.as_ref() == Some(&unit_info.unit_id) {
+                        transporter.towed_unit_id = None;
+                    }
+                    transporter.passenger_ids.retain(|id| *id != unit_info.unit_id);
+                }
+                if self.units.get(&unit_info.unit_id).is_none() {
+                    self.add_unit(db, unit_info, InfoLevel::Partial);
+                }
+                {
+                    let unit = self.units.get_mut(&unit_info.unit_id)
+                        .expect("Bad passenger_id");
+                    unit.pos = unit_info.pos.clone();
+                    unit.count -= *killed;
+                    unit.morale -= *suppression;
+                    unit.move_points.n = 0;
+                }
+                if self.units[&unit_info.unit_id].count <= 0 {
+                    self.units.remove(&unit_info.unit_id);
+                }
+            },
             &CoreEvent::SetReactionFireMode{ref unit_id, ref mode} => {
                 self.units.get_mut(unit_id)
                     .expect("Bad unit id")
                     .reaction_fire_mode = mode.clone();
             },
+            &CoreEvent::ConvertAp{ref unit_id} => {
+                let unit = self.units.get_mut(unit_id).expect("Bad unit id");
+                convert_unit_ap(db, unit);
+            },
+            &CoreEvent::Noise{..} => {},
         }
     }
 }
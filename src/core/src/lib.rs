@@ -8,34 +8,60 @@ pub mod map;
 pub mod db;
 pub mod unit;
 pub mod dir;
+pub mod hex;
 pub mod partial_state;
 pub mod game_state;
 pub mod pathfinder;
 pub mod misc;
 pub mod types;
+pub mod fov;
+pub mod bot;
+pub mod protocol;
+pub mod net;
+pub mod pbem;
+pub mod verify;
+pub mod orders;
+pub mod clock;
+pub mod mapgen;
+pub mod scenario;
 
 mod ai;
-mod fov;
+mod mcts;
 mod fow;
 mod internal_state;
 mod filter;
 
-use rand::{thread_rng, Rng};
-use std::{cmp, fmt};
+#[cfg(test)]
+mod fuzz;
+
+#[cfg(test)]
+mod test_utils;
+
+use rand::{thread_rng, Rng, SeedableRng, XorShiftRng};
+use std::cell::{RefCell};
+use std::{cmp, fmt, io, mem};
+use std::time::{Duration};
 use std::collections::{HashMap, HashSet, LinkedList};
 use cgmath::{Vector2};
-use types::{Size2, ZInt};
+use types::{Size2, ZInt, ZFloat};
 use misc::{clamp};
 use internal_state::{InternalState};
 use game_state::{GameState, GameStateMut};
 use partial_state::{PartialState};
-use map::{Map, Terrain, distance};
+use map::{Terrain, distance, spiral_iter};
+use dir::{Dir, dirs};
 use pathfinder::{path_cost, tile_cost};
 use unit::{Unit, UnitType, UnitTypeId, UnitClass};
 use db::{Db};
 use ai::{Ai};
-use fow::{Fow};
-use fov::{fov};
+use mcts::{MctsAi};
+use bot::{Bot, BotError, ProcessBot};
+use fow::{Fow, UnitVisibility};
+use verify::{Leak};
+use orders::{Order, OrderQueue, schedule};
+use clock::{Clock, RealClock, TimeControl, TurnTimer};
+use scenario::{Scenario, PLAYERS_COUNT, check_scenario};
+use protocol::{ParseError};
 
 #[derive(Clone)]
 pub struct MovePoints{pub n: ZInt}
@@ -93,7 +119,7 @@ pub struct ObjectId {
     pub id: ZInt,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Object {
     pub pos: ExactPos,
     pub class: ObjectClass,
@@ -134,6 +160,7 @@ pub enum Command {
     EndTurn,
     CreateUnit{pos: ExactPos, type_id: UnitTypeId},
     AttackUnit{attacker_id: UnitId, defender_id: UnitId},
+    Assault{attacker_id: UnitId, defender_id: UnitId},
     LoadUnit{transporter_id: UnitId, passenger_id: UnitId},
     UnloadUnit{transporter_id: UnitId, passenger_id: UnitId, pos: ExactPos},
     SetReactionFireMode{unit_id: UnitId, mode: ReactionFireMode},
@@ -145,7 +172,18 @@ pub struct UnitInfo {
     pub pos: ExactPos,
     pub type_id: UnitTypeId,
     pub player_id: PlayerId,
-    pub passenger_id: Option<UnitId>,
+    pub passenger_ids: Vec<UnitId>,
+    pub towed_unit_id: Option<UnitId>,
+
+    /// Unit is detected but not identified: `type_id` is a generic
+    /// contact type and `passenger_ids` are always empty
+    pub is_contact: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NoiseClass {
+    Engine,
+    Gunfire,
 }
 
 #[derive(Clone)]
@@ -158,6 +196,7 @@ pub struct AttackInfo {
     pub remove_move_points: bool,
     pub is_ambush: bool,
     pub is_inderect: bool,
+    pub is_assault: bool,
 }
 
 #[derive(Clone)]
@@ -173,6 +212,11 @@ pub enum CoreEvent {
         old_id: PlayerId,
         new_id: PlayerId,
     },
+    /// `old_id` has planned the simultaneous turn, `new_id` plans next
+    EndPlanning {
+        old_id: PlayerId,
+        new_id: PlayerId,
+    },
     CreateUnit {
         unit_info: UnitInfo,
     },
@@ -197,14 +241,56 @@ pub enum CoreEvent {
         from: ExactPos,
         to: ExactPos,
     },
+    /// Passenger is forced out of its transporter that is about to be destroyed
+    BailOut {
+        unit_info: UnitInfo,
+        transporter_id: Option<UnitId>,
+        from: ExactPos,
+        to: ExactPos,
+        killed: ZInt,
+        suppression: ZInt,
+    },
     SetReactionFireMode {
         unit_id: UnitId,
         mode: ReactionFireMode,
     },
+    /// Unit's attack points are kept for the reaction fire,
+    /// see `Core::convert_ap_of_idle_units`
+    ConvertAp {
+        unit_id: UnitId,
+    },
+    /// Some unseen enemy made a noise somewhere near `pos`
+    Noise {
+        listener_id: PlayerId,
+        pos: MapPos,
+        class: NoiseClass,
+    },
 }
 
 pub const MAX_GROUND_SLOTS_COUNT: usize = 3;
 
+/// Move points that a tower spends to hook up or unhook a gun
+pub const HOOK_COST: ZInt = 1;
+
+/// How far from an HQ unit can be to stay in the command net
+pub const RADIO_RANGE: ZInt = 5;
+
+/// How far an engine of a vehicle moving in `MoveMode::Fast` can be heard
+pub const ENGINE_NOISE_RADIUS: ZInt = 6;
+
+/// How far a shot can be heard
+pub const GUNFIRE_NOISE_RADIUS: ZInt = 8;
+
+/// How far a heard position can be from a real position of a noise source
+pub const NOISE_POS_ERROR: ZInt = 1;
+
+pub fn engine_noise_radius(mode: &MoveMode) -> ZInt {
+    match *mode {
+        MoveMode::Fast => ENGINE_NOISE_RADIUS,
+        MoveMode::Hunt => ENGINE_NOISE_RADIUS / 2,
+    }
+}
+
 pub fn move_cost_modifier(mode: &MoveMode) -> ZInt {
     match *mode {
         MoveMode::Fast => 1,
@@ -212,49 +298,55 @@ pub fn move_cost_modifier(mode: &MoveMode) -> ZInt {
     }
 }
 
-// TODO: simplify/optimize
+fn player_unit_ids<S: GameState>(state: &S, player_id: &PlayerId) -> Vec<UnitId> {
+    let mut ids: Vec<_> = state.units().values()
+        .filter(|unit| unit.player_id == *player_id)
+        .map(|unit| unit.id.clone())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Returns `unit_id` itself if it`s the only unit of the player
+/// or if it`s not a unit of the player at all
 pub fn find_next_player_unit_id<S: GameState>(
     state: &S,
     player_id: &PlayerId,
     unit_id: &UnitId,
 ) -> UnitId {
-    let mut i = state.units().iter().cycle().filter(
-        |&(_, unit)| unit.player_id == *player_id);
-    while let Some((id, _)) = i.next() {
-        if *id == *unit_id {
-            let (id, _) = i.next().unwrap();
-            return id.clone();
-        }
+    let ids = player_unit_ids(state, player_id);
+    match ids.iter().position(|id| *id == *unit_id) {
+        Some(index) => ids[(index + 1) % ids.len()].clone(),
+        None => unit_id.clone(),
     }
-    unreachable!()
 }
 
-// TODO: simplify/optimize
+/// Returns `unit_id` itself if it`s the only unit of the player
+/// or if it`s not a unit of the player at all
 pub fn find_prev_player_unit_id<S: GameState>(
     state: &S,
     player_id: &PlayerId,
     unit_id: &UnitId,
 ) -> UnitId {
-    let mut i = state.units().iter().cycle().filter(
-        |&(_, unit)| unit.player_id == *player_id).peekable();
-    while let Some((id, _)) = i.next() {
-        let &(next_id, _) = i.peek().clone().unwrap();
-        if *next_id == *unit_id {
-            return id.clone();
-        }
+    let ids = player_unit_ids(state, player_id);
+    match ids.iter().position(|id| *id == *unit_id) {
+        Some(index) => ids[(index + ids.len() - 1) % ids.len()].clone(),
+        None => unit_id.clone(),
     }
-    unreachable!()
 }
 
 pub fn get_unit_ids_at(db: &Db, state: &PartialState, pos: &MapPos) -> Vec<UnitId> {
     let units_at = state.units_at(pos);
     let mut hidden_ids = HashSet::new();
     for unit in &units_at {
-        if db.unit_type(&unit.type_id).is_transporter {
-            if let Some(ref passenger_id) = unit.passenger_id {
+        if is_transporter(db, unit) {
+            for passenger_id in &unit.passenger_ids {
                 hidden_ids.insert(passenger_id.clone());
             }
         }
+        if let Some(ref towed_unit_id) = unit.towed_unit_id {
+            hidden_ids.insert(towed_unit_id.clone());
+        }
     }
     let mut ids = Vec::new();
     for unit in &units_at {
@@ -271,14 +363,131 @@ pub fn unit_to_info(unit: &Unit) -> UnitInfo {
         pos: unit.pos.clone(),
         type_id: unit.type_id.clone(),
         player_id: unit.player_id.clone(),
-        passenger_id: unit.passenger_id.clone(),
+        passenger_ids: unit.passenger_ids.clone(),
+        towed_unit_id: unit.towed_unit_id.clone(),
+        is_contact: unit.is_contact,
+    }
+}
+
+/// Towed gun is limbered and can`t fire or move by itself
+pub fn is_towed<S: GameState>(state: &S, unit_id: &UnitId) -> bool {
+    for (_, unit) in state.units() {
+        if unit.towed_unit_id.as_ref() == Some(unit_id) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Passenger is inside of a transporter and can`t act by itself
+pub fn is_loaded<S: GameState>(state: &S, unit_id: &UnitId) -> bool {
+    for (_, unit) in state.units() {
+        if unit.passenger_ids.contains(unit_id) {
+            return true;
+        }
+    }
+    false
+}
+
+pub fn is_in_command_net<S: GameState>(db: &Db, state: &S, unit: &Unit) -> bool {
+    for (_, hq) in state.units() {
+        if hq.player_id == unit.player_id
+            && db.unit_type(&hq.type_id).is_hq
+            && distance(&hq.pos.map_pos, &unit.pos.map_pos) <= RADIO_RANGE
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// What `unit` knows about `target` in `VisionSharing::CommandNet` mode:
+/// what it sees by itself or what other units of the net report
+fn command_net_visibility<S: GameState>(
+    db: &Db,
+    state: &S,
+    unit: &Unit,
+    target: &Unit,
+) -> UnitVisibility {
+    let mut visibility = fow::unit_sees(db, state, unit, target);
+    if visibility == UnitVisibility::Full
+        || !is_in_command_net(db, state, unit)
+    {
+        return visibility;
+    }
+    for (_, other_unit) in state.units() {
+        if other_unit.player_id != unit.player_id
+            || !is_in_command_net(db, state, other_unit)
+        {
+            continue;
+        }
+        let other_visibility = fow::unit_sees(db, state, other_unit, target);
+        if other_visibility > visibility {
+            visibility = other_visibility;
+        }
+    }
+    visibility
+}
+
+/// Checks if `target` is spotted by `unit` itself or by the command net
+pub fn is_target_spotted<S: GameState>(
+    db: &Db,
+    state: &S,
+    unit: &Unit,
+    target: &Unit,
+) -> bool {
+    command_net_visibility(db, state, unit, target) != UnitVisibility::No
+}
+
+pub fn is_transporter(db: &Db, unit: &Unit) -> bool {
+    db.unit_type(&unit.type_id).transport_capacity > 0
+}
+
+/// Number of men that can still be loaded into this transporter
+pub fn free_transport_capacity<S: GameState>(
+    db: &Db,
+    state: &S,
+    transporter: &Unit,
+) -> ZInt {
+    let mut capacity = db.unit_type(&transporter.type_id).transport_capacity;
+    for passenger_id in &transporter.passenger_ids {
+        if let Some(passenger) = state.units().get(passenger_id) {
+            capacity -= passenger.count;
+        }
     }
+    capacity
 }
 
 struct PlayerInfo {
     events: LinkedList<CoreEvent>,
     fow: Fow,
     visible_enemies: HashSet<UnitId>,
+    enemy_contacts: HashSet<UnitId>,
+}
+
+fn update_passive_enemies(
+    db: &Db,
+    state: &InternalState,
+    player_id: &PlayerId,
+    active_unit_ids: &HashSet<UnitId>,
+    i: &mut PlayerInfo,
+) {
+    let new_visible_enemies = filter::get_visible_enemies(
+        db, state, &i.fow, player_id);
+    let new_enemy_contacts = filter::get_enemy_contacts(
+        db, state, &i.fow, player_id);
+    let show_hide_events = filter::show_or_hide_passive_enemies(
+        db,
+        state.units(),
+        active_unit_ids,
+        &i.visible_enemies,
+        &new_visible_enemies,
+        &i.enemy_contacts,
+        &new_enemy_contacts,
+    );
+    i.events.extend(show_hide_events);
+    i.visible_enemies = new_visible_enemies;
+    i.enemy_contacts = new_enemy_contacts;
 }
 
 pub fn print_unit_info(db: &Db, unit: &Unit) {
@@ -334,12 +543,15 @@ pub enum CommandError {
     NotEnoughAttackPoints,
     NotEnoughReactiveAttackPoints,
     BadMorale,
+    BadAttackerClass,
     OutOfRange,
     TooClose,
     NoLos,
     BadTransporterClass,
     BadPassengerClass,
-    TransporterIsNotEmpty,
+    TransporterIsFull,
+    AlreadyTowing,
+    GunIsLimbered,
     TransporterIsEmpty,
     TransporterIsTooFarAway,
     PassengerHasNotEnoughMovePoints,
@@ -351,6 +563,17 @@ pub enum CommandError {
     BadAttackerId,
     BadDefenderId,
     BadPath,
+    TargetIsNotSpotted,
+    NotYourUnit,
+    BadPos,
+    BadUnitType,
+    UnitIsLoaded,
+    FriendlyFire,
+    AlreadyHasOrder,
+    NotAvailableInWego,
+
+    /// Bots and remote players can`t create units
+    DebugOnly,
 }
 
 impl CommandError {
@@ -361,12 +584,15 @@ impl CommandError {
             CommandError::NotEnoughAttackPoints => "No attack points",
             CommandError::NotEnoughReactiveAttackPoints => "No reactive attack points",
             CommandError::BadMorale => "Can`t attack when suppresset",
+            CommandError::BadAttackerClass => "Only infantry can assault",
             CommandError::OutOfRange => "Out of range",
             CommandError::TooClose => "Too close",
             CommandError::NoLos => "No Line of Sight",
             CommandError::BadTransporterClass => "Bad transporter class",
             CommandError::BadPassengerClass => "Bad passenger class",
-            CommandError::TransporterIsNotEmpty => "Transporter is not empty",
+            CommandError::TransporterIsFull => "Not enough room in transporter",
+            CommandError::AlreadyTowing => "Already towing a gun",
+            CommandError::GunIsLimbered => "Gun is limbered",
             CommandError::TransporterIsEmpty => "Transporter is empty",
             CommandError::TransporterIsTooFarAway => "Transporter is too far away",
             CommandError::PassengerHasNotEnoughMovePoints => "Passenger has not enough move points",
@@ -378,6 +604,15 @@ impl CommandError {
             CommandError::BadAttackerId => "Bad attacker id",
             CommandError::BadDefenderId => "Bad defender id",
             CommandError::BadPath => "Bad path",
+            CommandError::TargetIsNotSpotted => "Target is not spotted",
+            CommandError::NotYourUnit => "Not your unit",
+            CommandError::BadPos => "Bad position",
+            CommandError::BadUnitType => "Bad unit type",
+            CommandError::UnitIsLoaded => "Unit is inside of a transporter",
+            CommandError::FriendlyFire => "Can`t attack own units",
+            CommandError::AlreadyHasOrder => "Unit already has an order",
+            CommandError::NotAvailableInWego => "Not available in simultaneous turns",
+            CommandError::DebugOnly => "Only available in debug mode",
         }
     }
 }
@@ -401,14 +636,20 @@ fn check_attack<S: GameState>(
     defender: &Unit,
     fire_mode: &FireMode,
 ) -> Result<(), CommandError> {
-    let reactive_attack_points = attacker
-        .reactive_attack_points.as_ref().unwrap().clone();
+    if is_towed(state, &attacker.id) {
+        return Err(CommandError::GunIsLimbered);
+    }
+    if is_loaded(state, &attacker.id) {
+        return Err(CommandError::UnitIsLoaded);
+    }
     match *fire_mode {
         FireMode::Active => if attacker.attack_points.n <= 0 {
             return Err(CommandError::NotEnoughAttackPoints);
         },
-        FireMode::Reactive => if reactive_attack_points.n <= 0 {
-            return Err(CommandError::NotEnoughReactiveAttackPoints);
+        // reactive attack points of enemy units are unknown
+        FireMode::Reactive => match attacker.reactive_attack_points {
+            Some(ref points) if points.n > 0 => {},
+            _ => return Err(CommandError::NotEnoughReactiveAttackPoints),
         },
     }
     let minimal_ok_morale = 50;
@@ -424,13 +665,41 @@ fn check_attack<S: GameState>(
         return Err(CommandError::TooClose);
     }
     if !weapon_type.is_inderect {
-        if !los(state.map(), attacker_type, &attacker.pos.map_pos, &defender.pos.map_pos) {
+        if !los(state, attacker_type, &attacker.pos.map_pos, &defender.pos.map_pos) {
             return Err(CommandError::NoLos);
         }
     }
     Ok(())
 }
 
+fn check_assault(
+    db: &Db,
+    attacker: &Unit,
+    defender: &Unit,
+) -> Result<(), CommandError> {
+    if db.unit_type(&attacker.type_id).class != UnitClass::Infantry {
+        return Err(CommandError::BadAttackerClass);
+    }
+    if attacker.attack_points.n <= 0 {
+        return Err(CommandError::NotEnoughAttackPoints);
+    }
+    if attacker.move_points.n <= 0 {
+        return Err(CommandError::NotEnoughMovePoints);
+    }
+    let minimal_ok_morale = 50;
+    if attacker.morale < minimal_ok_morale {
+        return Err(CommandError::BadMorale);
+    }
+    let distance = distance(&attacker.pos, &defender.pos);
+    if distance > 1 {
+        return Err(CommandError::OutOfRange);
+    }
+    if distance < 1 {
+        return Err(CommandError::TooClose);
+    }
+    Ok(())
+}
+
 pub fn check_command<S: GameState>(
     db: &Db,
     state: &S,
@@ -439,6 +708,12 @@ pub fn check_command<S: GameState>(
     match command {
         &Command::EndTurn => Ok(()),
         &Command::CreateUnit{ref pos, ref type_id} => {
+            if db.unit_type_opt(type_id).is_none() || db.is_contact_type(type_id) {
+                return Err(CommandError::BadUnitType);
+            }
+            if !is_exact_pos_valid(db, state, type_id, pos) {
+                return Err(CommandError::BadPos);
+            }
             if is_exact_pos_free(db, state, type_id, pos) {
                 Ok(())
             } else {
@@ -449,11 +724,25 @@ pub fn check_command<S: GameState>(
             if state.units().get(unit_id).is_none() {
                 return Err(CommandError::BadUnitId);
             }
+            if is_towed(state, unit_id) {
+                return Err(CommandError::GunIsLimbered);
+            }
+            if is_loaded(state, unit_id) {
+                return Err(CommandError::UnitIsLoaded);
+            }
+            if path.is_empty() {
+                return Err(CommandError::BadPath);
+            }
             let unit = state.unit(&unit_id);
+            let mut prev_pos = &unit.pos;
             for pos in path {
-                if !is_exact_pos_free(db, state, &unit.type_id, pos) {
+                if !is_exact_pos_valid(db, state, &unit.type_id, pos)
+                    || distance(&prev_pos.map_pos, &pos.map_pos) != 1
+                    || !is_exact_pos_free(db, state, &unit.type_id, pos)
+                {
                     return Err(CommandError::BadPath);
                 }
+                prev_pos = pos;
             }
             let cost = path_cost(db, state, unit, &path).n
                 * move_cost_modifier(mode);
@@ -471,8 +760,31 @@ pub fn check_command<S: GameState>(
             }
             let attacker = state.unit(attacker_id);
             let defender = state.unit(defender_id);
+            if attacker.player_id == defender.player_id {
+                return Err(CommandError::FriendlyFire);
+            }
             check_attack(db, state, attacker, defender, &FireMode::Active)
         },
+        &Command::Assault{ref attacker_id, ref defender_id} => {
+            if state.units().get(attacker_id).is_none() {
+                return Err(CommandError::BadAttackerId);
+            }
+            if state.units().get(defender_id).is_none() {
+                return Err(CommandError::BadDefenderId);
+            }
+            if is_towed(state, attacker_id) {
+                return Err(CommandError::GunIsLimbered);
+            }
+            if is_loaded(state, attacker_id) {
+                return Err(CommandError::UnitIsLoaded);
+            }
+            let attacker = state.unit(attacker_id);
+            let defender = state.unit(defender_id);
+            if attacker.player_id == defender.player_id {
+                return Err(CommandError::FriendlyFire);
+            }
+            check_assault(db, attacker, defender)
+        },
         &Command::LoadUnit{ref transporter_id, ref passenger_id} => {
             if state.units().get(transporter_id).is_none() {
                 return Err(CommandError::BadTransporterId);
@@ -480,20 +792,36 @@ pub fn check_command<S: GameState>(
             if state.units().get(passenger_id).is_none() {
                 return Err(CommandError::BadPassengerId);
             }
+            if transporter_id == passenger_id {
+                return Err(CommandError::BadPassengerId);
+            }
+            if is_loaded(state, passenger_id) || is_towed(state, passenger_id) {
+                return Err(CommandError::UnitIsLoaded);
+            }
             let passenger = state.unit(&passenger_id);
             let pos = passenger.pos.clone();
             let transporter = state.unit(&transporter_id);
-            if !db.unit_type(&transporter.type_id).is_transporter {
-                return Err(CommandError::BadTransporterClass);
-            }
-            match db.unit_type(&passenger.type_id).class {
-                UnitClass::Infantry => {},
-                _ => {
+            let passenger_type = db.unit_type(&passenger.type_id);
+            if passenger_type.is_towable {
+                if !db.unit_type(&transporter.type_id).can_tow {
+                    return Err(CommandError::BadTransporterClass);
+                }
+                if transporter.towed_unit_id.is_some() {
+                    return Err(CommandError::AlreadyTowing);
+                }
+                if transporter.move_points.n < HOOK_COST {
+                    return Err(CommandError::NotEnoughMovePoints);
+                }
+            } else {
+                if !is_transporter(db, transporter) {
+                    return Err(CommandError::BadTransporterClass);
+                }
+                if passenger_type.class != UnitClass::Infantry {
                     return Err(CommandError::BadPassengerClass);
                 }
-            }
-            if transporter.passenger_id.is_some() {
-                return Err(CommandError::TransporterIsNotEmpty);
+                if free_transport_capacity(db, state, transporter) < passenger.count {
+                    return Err(CommandError::TransporterIsFull);
+                }
             }
             if distance(&transporter.pos, &pos) > 1 {
                 return Err(CommandError::TransporterIsTooFarAway);
@@ -513,15 +841,27 @@ pub fn check_command<S: GameState>(
                 None => return Err(CommandError::BadPassengerId),
             };
             let transporter = state.unit(&transporter_id);
-            if !db.unit_type(&transporter.type_id).is_transporter {
-                return Err(CommandError::BadTransporterClass);
+            if transporter.towed_unit_id.as_ref() == Some(passenger_id) {
+                if transporter.move_points.n < HOOK_COST {
+                    return Err(CommandError::NotEnoughMovePoints);
+                }
+            } else {
+                if !is_transporter(db, transporter) {
+                    return Err(CommandError::BadTransporterClass);
+                }
+                if transporter.passenger_ids.is_empty() {
+                    return Err(CommandError::TransporterIsEmpty);
+                }
+                if !transporter.passenger_ids.contains(passenger_id) {
+                    return Err(CommandError::BadPassengerId);
+                }
+            }
+            if !is_exact_pos_valid(db, state, &passenger.type_id, pos) {
+                return Err(CommandError::BadPos);
             }
             if distance(&transporter.pos, &pos) > 1 {
                 return Err(CommandError::UnloadDistanceIsTooBig);
             }
-            if let None = transporter.passenger_id {
-                return Err(CommandError::TransporterIsEmpty);
-            }
             if !is_exact_pos_free(db, state, &passenger.type_id, pos) {
                 return Err(CommandError::DestinationTileIsNotEmpty);
             }
@@ -557,35 +897,333 @@ impl Default for GameType {
     }
 }
 
+/// How information about enemies is shared between units of one player
+#[derive(Clone, PartialEq, Debug)]
+pub enum VisionSharing {
+    /// Every unit knows everything that any friendly unit sees
+    Full,
+
+    /// Units share what they see only while they are
+    /// within radio range of an HQ unit
+    CommandNet,
+}
+
+impl Default for VisionSharing {
+    fn default() -> VisionSharing {
+        VisionSharing::Full
+    }
+}
+
+/// How players take turns
+#[derive(Clone, PartialEq, Debug)]
+pub enum TurnMode {
+    /// One player moves while the other one waits ("I go, you go")
+    Igougo,
+
+    /// All players plan their orders and then all the orders are
+    /// executed at once ("we go"), see `orders`
+    Wego,
+}
+
+impl Default for TurnMode {
+    fn default() -> TurnMode {
+        TurnMode::Igougo
+    }
+}
+
+/// Presets of the weights of the AI's utility function
+#[derive(Clone, PartialEq, Debug)]
+pub enum AiDifficulty {
+    /// Random-ish attacks, walks straight to the enemy
+    Easy,
+
+    /// Cares about cover and enemy fire, uses transports
+    Normal,
+
+    /// Avoids enemy fire harder and remembers enemies longer
+    Hard,
+}
+
+impl Default for AiDifficulty {
+    fn default() -> AiDifficulty {
+        AiDifficulty::Normal
+    }
+}
+
+/// What the AI player tries to achieve
+#[derive(Clone, PartialEq, Debug)]
+pub enum AiPosture {
+    /// Seek and destroy enemies
+    Attack,
+
+    /// Hold objectives of the scenario in cover, rely on reaction
+    /// fire and counterattack only when the odds are favorable
+    Defend,
+}
+
+impl Default for AiPosture {
+    fn default() -> AiPosture {
+        AiPosture::Attack
+    }
+}
+
+/// Algorithm that picks commands of the AI player
+#[derive(Clone, PartialEq, Debug)]
+pub enum AiBackend {
+    /// Rates every possible command with a utility function
+    Utility,
+
+    /// Monte-Carlo tree search over commands of the current turn.
+    /// Stops after `max_iterations` or `max_time_ms`, whichever comes first.
+    Mcts{max_iterations: ZInt, max_time_ms: u64},
+}
+
+impl Default for AiBackend {
+    fn default() -> AiBackend {
+        AiBackend::Utility
+    }
+}
+
+/// Who controls a bot player
+#[derive(Clone, PartialEq, Debug)]
+pub enum BotKind {
+    /// AI of this crate configured by the `ai_*` fields of `Options`
+    Builtin,
+
+    /// Program that speaks the line protocol described in `bot`
+    Process{path: String, args: Vec<String>},
+}
+
+/// Bots roll their own dice, every bot gets a seed of its own
+fn bot_seed(options: &Options, id: &PlayerId) -> u64 {
+    match options.seed {
+        Some(seed) => seed.wrapping_add(id.id as u64 + 1),
+        None => thread_rng().gen(),
+    }
+}
+
+/// Scenario may set the posture of some players, see `Scenario::ai_postures`
+fn ai_posture<'a>(options: &'a Options, scenario: &'a Scenario, id: &PlayerId) -> &'a AiPosture {
+    scenario.ai_postures.get(id).unwrap_or(&options.ai_posture)
+}
+
+fn create_builtin_bot(options: &Options, id: &PlayerId, scenario: &Scenario) -> Box<dyn Bot> {
+    let seed = bot_seed(options, id);
+    let ai = Ai::new(
+        id,
+        scenario,
+        &options.vision_sharing,
+        &options.ai_difficulty,
+        ai_posture(options, scenario, id),
+        seed,
+    );
+    match options.ai_backend {
+        AiBackend::Utility => Box::new(ai),
+        AiBackend::Mcts{max_iterations, max_time_ms} => {
+            Box::new(MctsAi::new(
+                id,
+                scenario,
+                &options.vision_sharing,
+                max_iterations,
+                max_time_ms,
+                seed,
+                ai,
+            ))
+        },
+    }
+}
+
+fn create_bot(
+    options: &Options,
+    kind: &BotKind,
+    id: &PlayerId,
+    scenario: &Scenario,
+) -> Result<Box<dyn Bot>, StartError> {
+    match *kind {
+        BotKind::Builtin => Ok(create_builtin_bot(options, id, scenario)),
+        BotKind::Process{ref path, ref args} => {
+            match ProcessBot::new(path, args, id) {
+                Ok(bot) => Ok(Box::new(bot)),
+                Err(err) => Err(StartError::BadBot(path.clone(), err)),
+            }
+        },
+    }
+}
+
+/// `GameType::SingleVsAi` adds a builtin bot for the second player
+/// if `Options::bots` has no bot for it
+fn get_bot_kinds(options: &Options) -> HashMap<PlayerId, BotKind> {
+    let mut kinds = options.bots.clone();
+    if options.game_type == GameType::SingleVsAi {
+        kinds.entry(PlayerId{id: 1}).or_insert(BotKind::Builtin);
+    }
+    kinds
+}
+
+fn create_bots(
+    options: &Options,
+    scenario: &Scenario,
+) -> Result<HashMap<PlayerId, Box<dyn Bot>>, StartError> {
+    let mut bots = HashMap::new();
+    for (id, kind) in get_bot_kinds(options) {
+        let bot = create_bot(options, &kind, &id, scenario)?;
+        bots.insert(id, bot);
+    }
+    Ok(bots)
+}
+
+/// What an observer sees
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum ObserverView {
+    /// Everything, events are not filtered
+    God,
+
+    /// Same events as the player gets
+    Player(PlayerId),
+}
+
 #[derive(Default)]
 pub struct Options {
     pub game_type: GameType,
+    pub vision_sharing: VisionSharing,
+    pub turn_mode: TurnMode,
+    pub time_control: TimeControl,
+    pub ai_difficulty: AiDifficulty,
+
+    /// Posture of the builtin bots unless `Scenario::ai_postures` sets it
+    pub ai_posture: AiPosture,
+
+    pub ai_backend: AiBackend,
+
+    /// Players that are controlled by programs
+    pub bots: HashMap<PlayerId, BotKind>,
+
+    /// Keep the event streams for observers, see `Core::get_observer_event`
+    pub observer: bool,
+
+    /// Seed of the dice. Games with the same seed and the same
+    /// commands of human players are the same, see `pbem`.
+    pub seed: Option<u64>,
+
+    /// Map, units and objectives, `Scenario::default_scenario` if `None`
+    pub scenario: Option<Scenario>,
+}
+
+/// Why `Core` can`t start a game
+#[derive(Debug)]
+pub enum StartError {
+    /// Program of `BotKind::Process` can`t be started
+    BadBot(String, io::Error),
+
+    /// `Options::scenario` breaks the limits of `scenario::check_scenario`
+    BadScenario(ParseError),
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StartError::BadBot(ref path, ref err) => {
+                write!(f, "Can`t start bot {}: {}", path, err)
+            },
+            StartError::BadScenario(ref err) => {
+                write!(f, "Bad scenario: {}", err)
+            },
+        }
+    }
+}
+
+/// XorShift must not be seeded with zeros only
+fn seeded_rng(seed: u64) -> XorShiftRng {
+    let lo = seed as u32;
+    let hi = (seed >> 32) as u32;
+    XorShiftRng::from_seed([lo, hi, lo ^ 0x9e37_79b9, hi ^ 0x7f4a_7c15])
 }
 
 pub struct Core {
     state: InternalState,
+    scenario: Scenario,
     players: Vec<Player>,
     current_player_id: PlayerId,
     db: Db,
-    ai: Ai,
+    bots: HashMap<PlayerId, Box<dyn Bot>>,
+
+    /// Bot's end of turn must not start the turn of the next bot
+    is_bot_turn: bool,
+
+    /// Dice are rolled in `&self` methods
+    rng: RefCell<XorShiftRng>,
+
+    /// Empty if observers are disabled
+    observer_events: HashMap<ObserverView, LinkedList<CoreEvent>>,
     players_info: HashMap<PlayerId, PlayerInfo>,
     next_unit_id: UnitId,
+    vision_sharing: VisionSharing,
+    turn_mode: TurnMode,
+
+    /// Planned orders of the current simultaneous turn
+    orders: HashMap<PlayerId, OrderQueue>,
+
+    /// Number of executed simultaneous turns
+    wego_turn: usize,
+
+    clock: Box<dyn Clock>,
+    timer: TurnTimer,
+
+    /// Found by the checks of debug builds, see `Core::take_leaks`
+    leaks: Vec<(PlayerId, Leak)>,
 }
 
-fn get_players_list(game_type: &GameType) -> Vec<Player> {
-    vec!(
-        Player {
-            id: PlayerId{id: 0},
-            class: PlayerClass::Human,
-        },
-        Player {
-            id: PlayerId{id: 1},
-            class: match *game_type {
-                GameType::SingleVsAi => PlayerClass::Ai,
-                GameType::Hotseat => PlayerClass::Human,
-            },
+fn get_players_list(options: &Options) -> Vec<Player> {
+    let bot_kinds = get_bot_kinds(options);
+    let mut players = Vec::new();
+    for id in 0 .. PLAYERS_COUNT {
+        let id = PlayerId{id: id};
+        let class = if bot_kinds.contains_key(&id) {
+            PlayerClass::Ai
+        } else {
+            PlayerClass::Human
+        };
+        players.push(Player{id: id, class: class});
+    }
+    players
+}
+
+/// Events of a view that nobody reads are not kept forever: the view
+/// that falls this far behind is closed, see `Core::is_observer_view_open`
+pub const MAX_OBSERVER_EVENTS: usize = 10_000;
+
+/// Turn of a bot that keeps sending commands is ended after this many
+/// of them, accepted or not
+pub const MAX_BOT_COMMANDS_PER_TURN: usize = 1_000;
+
+/// Closes the view instead of dropping some of its events
+/// because a view with a gap in its events can`t be trusted
+fn push_observer_events<I: Iterator<Item=CoreEvent>>(
+    observer_events: &mut HashMap<ObserverView, LinkedList<CoreEvent>>,
+    view: &ObserverView,
+    new_events: I,
+) {
+    let is_full = match observer_events.get_mut(view) {
+        Some(events) => {
+            events.extend(new_events);
+            events.len() > MAX_OBSERVER_EVENTS
         },
-    )
+        None => false,
+    };
+    if is_full {
+        observer_events.remove(view);
+    }
+}
+
+fn get_observer_events(options: &Options) -> HashMap<ObserverView, LinkedList<CoreEvent>> {
+    let mut map = HashMap::new();
+    if options.observer {
+        map.insert(ObserverView::God, LinkedList::new());
+        for player in get_players_list(options) {
+            map.insert(ObserverView::Player(player.id), LinkedList::new());
+        }
+    }
+    map
 }
 
 fn get_player_info_lists(map_size: &Size2) -> HashMap<PlayerId, PlayerInfo> {
@@ -594,52 +1232,175 @@ fn get_player_info_lists(map_size: &Size2) -> HashMap<PlayerId, PlayerInfo> {
         fow: Fow::new(map_size, &PlayerId{id: 0}),
         events: LinkedList::new(),
         visible_enemies: HashSet::new(),
+        enemy_contacts: HashSet::new(),
     });
     map.insert(PlayerId{id: 1}, PlayerInfo {
         fow: Fow::new(map_size, &PlayerId{id: 1}),
         events: LinkedList::new(),
         visible_enemies: HashSet::new(),
+        enemy_contacts: HashSet::new(),
     });
     map
 }
 
-pub fn los(
-    map: &Map<Terrain>,
-    unit_type: &UnitType,
-    from: &MapPos,
-    to: &MapPos,
-) -> bool {
-    // TODO: profile and optimize!
-    let mut v = false;
-    let range = unit_type.los_range;
-    fov(map, from, range, &mut |p| if *p == *to { v = true });
-    v
-}
-
-pub fn get_free_slot_for_building<S: GameState>(
-    state: &S,
-    pos: &MapPos,
-) -> Option<SlotId> {
-    let objects_at = state.objects_at(pos);
-    let mut slots = [false, false, false];
-    for object in &objects_at {
-        if let SlotId::Id(slot_id) = object.pos.slot_id {
-            slots[slot_id as usize] = true;
-        } else {
-            return None;
-        }
-    }
-    for i in 0..MAX_GROUND_SLOTS_COUNT {
-        if !slots[i] {
-            return Some(SlotId::Id(i as u8));
+pub fn cover_bonus<S: GameState>(db: &Db, state: &S, unit: &Unit) -> ZInt {
+    if let UnitClass::Infantry = db.unit_type(&unit.type_id).class {
+        match state.map().tile(&unit.pos) {
+            &Terrain::Plain => 0,
+            &Terrain::Trees => 2,
+            &Terrain::City => 3,
         }
+    } else {
+        0
     }
-    None
 }
 
-pub fn get_free_exact_pos<S: GameState>(
-    db: &Db,
-    state: &S,
+/// Every combat test rolls `gen_range(TEST_ROLL_MIN, TEST_ROLL_MAX)`
+const TEST_ROLL_MIN: ZInt = -5;
+const TEST_ROLL_MAX: ZInt = 5;
+
+const MAX_KILLED_INFANTRY: ZInt = 4;
+const BASE_SUPPRESSION: ZInt = 10;
+const PER_DEATH_SUPPRESSION: ZInt = 20;
+
+struct HitTestValues {
+    hit: ZInt,
+    pierce: ZInt,
+    wound: ZInt,
+}
+
+fn hit_test_values<S: GameState>(
+    db: &Db,
+    state: &S,
+    attacker: &Unit,
+    defender: &Unit,
+) -> HitTestValues {
+    let attacker_type = db.unit_type(&attacker.type_id);
+    let defender_type = db.unit_type(&defender.type_id);
+    let weapon_type = db.weapon_type(&attacker_type.weapon_type_id);
+    let cover_bonus = cover_bonus(db, state, defender);
+    HitTestValues {
+        hit: -13 - cover_bonus + defender_type.size
+            + weapon_type.accuracy + attacker_type.weapon_skill,
+        pierce: 5 + -defender_type.armor + weapon_type.ap,
+        wound: -defender_type.toughness + weapon_type.damage,
+    }
+}
+
+fn hit_test<S: GameState, R: Rng>(
+    db: &Db,
+    state: &S,
+    attacker: &Unit,
+    defender: &Unit,
+    rng: &mut R,
+) -> bool {
+    let v = hit_test_values(db, state, attacker, defender);
+    let mut test = |needed| rng.gen_range(TEST_ROLL_MIN, TEST_ROLL_MAX) < needed;
+    test(v.hit) && test(v.pierce) && test(v.wound)
+}
+
+/// Rolls the dice of an attack the same way `Core` does.
+/// AI uses it to sample outcomes of attacks in its own `PartialState`.
+pub fn roll_killed_count<S: GameState, R: Rng>(
+    db: &Db,
+    state: &S,
+    attacker: &Unit,
+    defender: &Unit,
+    rng: &mut R,
+) -> ZInt {
+    if !hit_test(db, state, attacker, defender, rng) {
+        return 0;
+    }
+    match db.unit_type(&defender.type_id).class {
+        UnitClass::Infantry => {
+            clamp(rng.gen_range(1, MAX_KILLED_INFANTRY + 1), 1, defender.count)
+        },
+        UnitClass::Vehicle => 1,
+    }
+}
+
+/// Probability that a roll in `[TEST_ROLL_MIN, TEST_ROLL_MAX)` is less than `needed`
+fn test_chance(needed: ZInt) -> ZFloat {
+    let rolls_count = TEST_ROLL_MAX - TEST_ROLL_MIN;
+    let good_rolls_count = clamp(needed - TEST_ROLL_MIN, 0, rolls_count);
+    good_rolls_count as ZFloat / rolls_count as ZFloat
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct AttackOdds {
+    pub hit: ZFloat,
+    pub pierce: ZFloat,
+    pub wound: ZFloat,
+    pub expected_killed: ZFloat,
+    pub expected_suppression: ZFloat,
+}
+
+/// Exact odds of the dice `Core` rolls for an active attack.
+/// Works with any state so players can use their own `PartialState`.
+pub fn get_attack_odds<S: GameState>(
+    db: &Db,
+    state: &S,
+    attacker: &Unit,
+    defender: &Unit,
+) -> AttackOdds {
+    let v = hit_test_values(db, state, attacker, defender);
+    let hit = test_chance(v.hit);
+    let pierce = test_chance(v.pierce);
+    let wound = test_chance(v.wound);
+    let killed_on_success = match db.unit_type(&defender.type_id).class {
+        UnitClass::Infantry => {
+            let mut sum = 0;
+            for killed in 1 .. MAX_KILLED_INFANTRY + 1 {
+                sum += cmp::min(killed, defender.count);
+            }
+            sum as ZFloat / MAX_KILLED_INFANTRY as ZFloat
+        },
+        UnitClass::Vehicle => 1.0,
+    };
+    let expected_killed = hit * pierce * wound * killed_on_success;
+    AttackOdds {
+        hit: hit,
+        pierce: pierce,
+        wound: wound,
+        expected_killed: expected_killed,
+        expected_suppression: BASE_SUPPRESSION as ZFloat
+            + PER_DEATH_SUPPRESSION as ZFloat * expected_killed,
+    }
+}
+
+pub fn los<S: GameState>(
+    state: &S,
+    unit_type: &UnitType,
+    from: &MapPos,
+    to: &MapPos,
+) -> bool {
+    state.fov().los(state.map(), from, to, unit_type.los_range)
+}
+
+pub fn get_free_slot_for_building<S: GameState>(
+    state: &S,
+    pos: &MapPos,
+) -> Option<SlotId> {
+    let objects_at = state.objects_at(pos);
+    let mut slots = [false, false, false];
+    for object in &objects_at {
+        if let SlotId::Id(slot_id) = object.pos.slot_id {
+            slots[slot_id as usize] = true;
+        } else {
+            return None;
+        }
+    }
+    for i in 0..MAX_GROUND_SLOTS_COUNT {
+        if !slots[i] {
+            return Some(SlotId::Id(i as u8));
+        }
+    }
+    None
+}
+
+pub fn get_free_exact_pos<S: GameState>(
+    db: &Db,
+    state: &S,
     type_id: &UnitTypeId,
     pos: &MapPos,
 ) -> Option<ExactPos> {
@@ -691,6 +1452,25 @@ pub fn get_free_slot_id<S: GameState>(
     None
 }
 
+/// Position is on the map and its slot fits the unit
+pub fn is_exact_pos_valid<S: GameState>(
+    db: &Db,
+    state: &S,
+    type_id: &UnitTypeId,
+    pos: &ExactPos,
+) -> bool {
+    if !state.map().is_inboard(&pos.map_pos) {
+        return false;
+    }
+    match pos.slot_id {
+        SlotId::WholeTile => db.unit_type(type_id).is_big,
+        SlotId::Id(id) => {
+            !db.unit_type(type_id).is_big
+                && (id as usize) < MAX_GROUND_SLOTS_COUNT
+        },
+    }
+}
+
 // TODO: join logic with get_free_slot_id
 pub fn is_exact_pos_free<S: GameState>(
     db: &Db,
@@ -716,52 +1496,126 @@ pub fn is_exact_pos_free<S: GameState>(
 }
 
 impl Core {
-    pub fn new(options: &Options) -> Core {
-        let map_size = Size2{w: 10, h: 8}; // TODO: read from config file
+    pub fn new(options: &Options) -> Result<Core, StartError> {
+        Core::with_clock(options, Box::new(RealClock::new()))
+    }
+
+    /// Tests use `clock::ManualClock` to run without the real time
+    pub fn with_clock(
+        options: &Options,
+        clock: Box<dyn Clock>,
+    ) -> Result<Core, StartError> {
+        let human_ids: Vec<_> = get_players_list(options).into_iter()
+            .filter(|player| player.class == PlayerClass::Human)
+            .map(|player| player.id)
+            .collect();
+        let timer = TurnTimer::new(&options.time_control, &human_ids, clock.now());
+        let db = Db::new();
+        let scenario = match options.scenario {
+            Some(ref scenario) => {
+                check_scenario(scenario).map_err(StartError::BadScenario)?;
+                scenario.clone()
+            },
+            None => Scenario::default_scenario(&db),
+        };
+        let map_size = scenario.size().clone();
         let mut core = Core {
-            state: InternalState::new(&map_size),
-            players: get_players_list(&options.game_type),
+            state: scenario.to_map_state(),
+            bots: create_bots(options, &scenario)?,
+            scenario: scenario,
+            players: get_players_list(options),
             current_player_id: PlayerId{id: 0},
-            db: Db::new(),
-            ai: Ai::new(&PlayerId{id:1}, &map_size),
+            db: db,
+            is_bot_turn: false,
+            rng: RefCell::new(seeded_rng(options.seed.unwrap_or_else(|| thread_rng().gen()))),
+            observer_events: get_observer_events(options),
             players_info: get_player_info_lists(&map_size),
             next_unit_id: UnitId{id: 0},
+            vision_sharing: options.vision_sharing.clone(),
+            turn_mode: options.turn_mode.clone(),
+            orders: HashMap::new(),
+            wego_turn: 0,
+            clock: clock,
+            timer: timer,
+            leaks: Vec::new(),
         };
-        core.get_units();
-        core
+        core.create_scenario_units();
+        Ok(core)
     }
 
     pub fn db(&self) -> &Db {
         &self.db
     }
 
-    // TODO: Move to scenario.json
-    fn get_units(&mut self) {
-        for &(player_id, (x, y), type_name) in &[
-            (0, (0, 1), "medium_tank"),
-            (0, (0, 4), "mammoth_tank"),
-            (0, (0, 5), "heavy_tank"),
-            (0, (0, 5), "medium_tank"),
-            (0, (1, 3), "truck"),
-            (0, (1, 3), "mortar"),
-            (0, (1, 4), "jeep"),
-            (0, (2, 2), "soldier"),
-            (0, (2, 2), "scout"),
-            (0, (2, 4), "smg"),
-            (0, (2, 4), "smg"),
-            (1, (9, 1), "medium_tank"),
-            (1, (9, 2), "soldier"),
-            (1, (9, 2), "soldier"),
-            (1, (9, 4), "soldier"),
-            (1, (9, 5), "light_tank"),
-            (1, (9, 5), "light_tank"),
-            (1, (9, 6), "light_spg"),
-            (1, (8, 2), "field_gun"),
-            (1, (8, 4), "field_gun"),
-        ] {
-            let pos = MapPos{v: Vector2{x: x, y: y}};
-            let unit_type_id = self.db.unit_type_id(type_name);
-            self.add_unit(&pos, &unit_type_id, &PlayerId{id: player_id});
+    /// Scenario the game was started from
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
+    }
+
+    pub fn vision_sharing(&self) -> &VisionSharing {
+        &self.vision_sharing
+    }
+
+    pub fn turn_mode(&self) -> &TurnMode {
+        &self.turn_mode
+    }
+
+    /// Orders that the player has planned for the current
+    /// simultaneous turn
+    pub fn orders(&self, player_id: &PlayerId) -> Option<&OrderQueue> {
+        self.orders.get(player_id)
+    }
+
+    pub fn time_control(&self) -> &TimeControl {
+        self.timer.control()
+    }
+
+    /// Time left to end the current turn for the current player
+    /// or for a whole next turn for anybody else,
+    /// `None` if the player is not timed
+    pub fn time_left(&self, player_id: &PlayerId) -> Option<Duration> {
+        if *player_id == self.current_player_id {
+            self.timer.time_left(player_id, self.clock.now())
+        } else {
+            self.timer.time_per_turn(player_id)
+        }
+    }
+
+    /// Time left in the player's bank for all the remaining turns
+    pub fn time_bank(&self, player_id: &PlayerId) -> Option<Duration> {
+        self.timer.bank(player_id)
+    }
+
+    pub fn is_out_of_time(&self) -> bool {
+        self.time_left(&self.current_player_id) == Some(Duration::from_secs(0))
+    }
+
+    /// Ends the turn of the current player if its time is out.
+    ///
+    /// Core has no thread of its own so frontends must call this
+    /// from time to time.
+    pub fn check_time(&mut self) {
+        if self.is_out_of_time() {
+            self.do_command(Command::EndTurn);
+        }
+    }
+
+    /// Units get ids in the order of `Scenario::units`
+    /// like in `Scenario::to_state`
+    fn create_scenario_units(&mut self) {
+        for unit in self.scenario.units.clone() {
+            let event = CoreEvent::CreateUnit {
+                unit_info: UnitInfo {
+                    unit_id: self.get_new_unit_id(),
+                    pos: unit.pos,
+                    type_id: unit.type_id,
+                    player_id: unit.player_id,
+                    passenger_ids: Vec::new(),
+                    towed_unit_id: None,
+                    is_contact: false,
+                },
+            };
+            self.do_core_event(&event);
         }
     }
 
@@ -771,6 +1625,8 @@ impl Core {
         new_unit_id
     }
 
+    /// Tests and the fuzzer add units to the free slots of the scenario
+    #[cfg(test)]
     fn add_unit(&mut self, pos: &MapPos, type_id: &UnitTypeId, player_id: &PlayerId) {
         let new_unit_id = self.get_new_unit_id();
         let pos = get_free_exact_pos(&self.db, &self.state, type_id, pos).unwrap();
@@ -780,7 +1636,9 @@ impl Core {
                 pos: pos,
                 type_id: type_id.clone(),
                 player_id: player_id.clone(),
-                passenger_id: None,
+                passenger_ids: Vec::new(),
+                towed_unit_id: None,
+                is_contact: false,
             },
         };
         self.do_core_event(&event);
@@ -791,40 +1649,8 @@ impl Core {
     }
 
     fn get_killed_count(&self, attacker: &Unit, defender: &Unit) -> ZInt {
-        let hit = self.hit_test(attacker, defender);
-        if !hit {
-            return 0;
-        }
-        let defender_type = self.db.unit_type(&defender.type_id);
-        match defender_type.class {
-            UnitClass::Infantry => {
-                clamp(thread_rng().gen_range(1, 5), 1, defender.count)
-            },
-            UnitClass::Vehicle => 1,
-        }
-    }
-
-    fn hit_test(&self, attacker: &Unit, defender: &Unit) -> bool {
-        fn test(needed: ZInt) -> bool {
-            thread_rng().gen_range(-5, 5) < needed
-        }
-        let attacker_type = self.db.unit_type(&attacker.type_id);
-        let defender_type = self.db.unit_type(&defender.type_id);
-        let weapon_type = self.db.weapon_type(&attacker_type.weapon_type_id);
-        let cover_bonus = if let UnitClass::Infantry = defender_type.class {
-            match self.state.map().tile(&defender.pos) {
-                &Terrain::Plain => 0,
-                &Terrain::Trees => 2,
-                &Terrain::City => 3,
-            }
-        } else {
-            0
-        };
-        let hit_test_v = -13 - cover_bonus + defender_type.size
-            + weapon_type.accuracy + attacker_type.weapon_skill;
-        let pierce_test_v = 5 + -defender_type.armor + weapon_type.ap;
-        let wound_test_v = -defender_type.toughness + weapon_type.damage;
-        test(hit_test_v) && test(pierce_test_v) && test(wound_test_v)
+        roll_killed_count(
+            &self.db, &self.state, attacker, defender, &mut *self.rng.borrow_mut())
     }
 
     pub fn player(&self) -> &Player {
@@ -835,9 +1661,19 @@ impl Core {
         &self.current_player_id
     }
 
+    pub fn players(&self) -> &[Player] {
+        &self.players
+    }
+
     pub fn get_event(&mut self) -> Option<CoreEvent> {
-        let mut i = self.players_info.get_mut(&self.current_player_id)
-            .expect("core: Can`t get current player`s info");
+        let player_id = self.current_player_id.clone();
+        self.get_player_event(&player_id)
+    }
+
+    /// Pops the next event that the player is allowed to see
+    pub fn get_player_event(&mut self, player_id: &PlayerId) -> Option<CoreEvent> {
+        let mut i = self.players_info.get_mut(player_id)
+            .expect("core: Can`t get player`s info");
         i.events.pop_front()
     }
 
@@ -868,22 +1704,87 @@ impl Core {
             &self.db, &self.state, attacker, &attacker.pos);
         let ambush_chance = 70;
         let is_ambush = !is_visible
-            && thread_rng().gen_range(1, 100) <= ambush_chance;
-        let base_suppression = 10;
-        let per_death_suppression = 20;
+            && self.rng.borrow_mut().gen_range(1, 100) <= ambush_chance;
         let attack_info = AttackInfo {
             attacker_id: Some(attacker_id.clone()),
             defender_id: defender_id.clone(),
             killed: killed,
             mode: fire_mode.clone(),
-            suppression: base_suppression + per_death_suppression * killed,
+            suppression: BASE_SUPPRESSION + PER_DEATH_SUPPRESSION * killed,
             remove_move_points: false,
             is_ambush: is_ambush,
             is_inderect: weapon_type.is_inderect.clone(),
+            is_assault: false,
         };
         Some(CoreEvent::AttackUnit{attack_info: attack_info})
     }
 
+    /// What `unit` knows about `target`
+    fn target_visibility(&self, unit: &Unit, target: &Unit) -> UnitVisibility {
+        match self.vision_sharing {
+            VisionSharing::Full => {
+                let fow = &self.players_info[&unit.player_id].fow;
+                fow.unit_visibility(&self.db, &self.state, target, &target.pos)
+            },
+            VisionSharing::CommandNet => {
+                command_net_visibility(&self.db, &self.state, unit, target)
+            },
+        }
+    }
+
+    /// Players can give orders only to their own units
+    fn check_unit_owner(&self, command: &Command) -> Result<(), CommandError> {
+        let unit_ids = match *command {
+            Command::Move{ref unit_id, ..}
+                | Command::SetReactionFireMode{ref unit_id, ..} => vec![unit_id],
+            Command::AttackUnit{ref attacker_id, ..}
+                | Command::Assault{ref attacker_id, ..} => vec![attacker_id],
+            Command::LoadUnit{ref transporter_id, ref passenger_id}
+                | Command::UnloadUnit{ref transporter_id, ref passenger_id, ..}
+                => vec![transporter_id, passenger_id],
+            Command::EndTurn | Command::CreateUnit{..} => vec![],
+        };
+        for unit_id in unit_ids {
+            // Bad ids are reported by `check_command`
+            if let Some(unit) = self.state.units().get(unit_id) {
+                if unit.player_id != self.current_player_id {
+                    return Err(CommandError::NotYourUnit);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Players can attack only enemies they know about
+    /// and indirect fire needs someone who spots the target
+    fn check_target_is_spotted(&self, command: &Command) -> Result<(), CommandError> {
+        if let Command::AttackUnit{ref attacker_id, ref defender_id}
+            | Command::Assault{ref attacker_id, ref defender_id} = *command
+        {
+            let player_id = &self.state.unit(attacker_id).player_id;
+            let defender = self.state.unit(defender_id);
+            let i = &self.players_info[player_id];
+            if defender.player_id != *player_id
+                && !i.visible_enemies.contains(defender_id)
+                && !i.enemy_contacts.contains(defender_id)
+            {
+                return Err(CommandError::TargetIsNotSpotted);
+            }
+        }
+        if let Command::AttackUnit{ref attacker_id, ref defender_id} = *command {
+            let attacker = self.state.unit(attacker_id);
+            let defender = self.state.unit(defender_id);
+            let attacker_type = self.db.unit_type(&attacker.type_id);
+            let weapon_type = self.db.weapon_type(&attacker_type.weapon_type_id);
+            if weapon_type.is_inderect
+                && self.target_visibility(attacker, defender) == UnitVisibility::No
+            {
+                return Err(CommandError::TargetIsNotSpotted);
+            }
+        }
+        Ok(())
+    }
+
     fn can_unit_make_reaction_attack(
         &self,
         defender: &Unit,
@@ -894,8 +1795,7 @@ impl Core {
             return false;
         }
         // TODO: move to `check_attack`
-        let fow = &self.players_info[&attacker.player_id].fow;
-        if !fow.is_visible(&self.db, &self.state, defender, &defender.pos) {
+        if self.target_visibility(attacker, defender) != UnitVisibility::Full {
             return false;
         }
         let check_attack_result = check_attack(
@@ -912,8 +1812,10 @@ impl Core {
     fn reaction_fire_internal<F>(&mut self, unit_id: &UnitId, f: F) -> ReactionFireResult
         where F: Fn(&mut AttackInfo)
     {
-        let unit_ids: Vec<_> = self.state.units().keys()
+        let mut unit_ids: Vec<_> = self.state.units().keys()
             .map(|id| id.clone()).collect();
+        // Order of shots must not depend on the hashing for replays
+        unit_ids.sort();
         let mut result = ReactionFireResult::None;
         for enemy_unit_id in unit_ids {
             let event = {
@@ -947,6 +1849,210 @@ impl Core {
         self.reaction_fire_internal(unit_id, |_| {});
     }
 
+    fn assault_strength(&self, unit: &Unit, is_defender: bool) -> ZInt {
+        let cover_bonus = if is_defender {
+            cover_bonus(&self.db, &self.state, unit)
+        } else {
+            0
+        };
+        unit.count + unit.morale / 25 + cover_bonus
+            + self.rng.borrow_mut().gen_range(0, 6)
+    }
+
+    fn get_retreat_pos(&self, unit: &Unit, enemy_pos: &MapPos) -> Option<ExactPos> {
+        let current_distance = distance(&unit.pos.map_pos, enemy_pos);
+        for dir in dirs() {
+            let pos = Dir::get_neighbour_pos(&unit.pos.map_pos, &dir);
+            if !self.state.map().is_inboard(&pos) {
+                continue;
+            }
+            if distance(&pos, enemy_pos) <= current_distance {
+                continue;
+            }
+            if let Some(exact_pos) = get_free_exact_pos(
+                &self.db, &self.state, &unit.type_id, &pos)
+            {
+                return Some(exact_pos);
+            }
+        }
+        None
+    }
+
+    fn assault_attack_info(
+        &self,
+        attacker: &Unit,
+        defender: &Unit,
+        killed: ZInt,
+        is_loser: bool,
+    ) -> AttackInfo {
+        let loser_suppression = 20;
+        let mode = if attacker.player_id == self.current_player_id {
+            FireMode::Active
+        } else {
+            FireMode::Reactive
+        };
+        AttackInfo {
+            attacker_id: Some(attacker.id.clone()),
+            defender_id: defender.id.clone(),
+            killed: killed,
+            mode: mode,
+            suppression: BASE_SUPPRESSION + PER_DEATH_SUPPRESSION * killed
+                + if is_loser { loser_suppression } else { 0 },
+            remove_move_points: is_loser,
+            is_ambush: false,
+            is_inderect: false,
+            is_assault: true,
+        }
+    }
+
+    /// Resolves close combat: the winner's losses are applied first
+    /// (it always survives), then the loser's, then the loser retreats
+    /// and a victorious attacker advances into the hex.
+    fn assault(&mut self, attacker_id: &UnitId, defender_id: &UnitId) {
+        let defender_pos = self.state.unit(defender_id).pos.clone();
+        let (winner_event, loser_event, winner_id, loser_id, loser_retreat_pos) = {
+            let attacker = self.state.unit(attacker_id);
+            let defender = self.state.unit(defender_id);
+            let attacker_strength = self.assault_strength(attacker, false);
+            let defender_strength = self.assault_strength(defender, true);
+            let (winner, loser, diff) = if attacker_strength > defender_strength {
+                (attacker, defender, attacker_strength - defender_strength)
+            } else {
+                (defender, attacker, defender_strength - attacker_strength)
+            };
+            let winner_killed = cmp::min(
+                self.rng.borrow_mut().gen_range(0, 2), winner.count - 1);
+            let retreat_pos = self.get_retreat_pos(loser, &winner.pos.map_pos);
+            let loser_killed = if retreat_pos.is_some() {
+                clamp(1 + diff / 2, 1, loser.count)
+            } else {
+                loser.count
+            };
+            let winner_event = CoreEvent::AttackUnit {
+                attack_info: self.assault_attack_info(
+                    loser, winner, winner_killed, false),
+            };
+            let loser_event = CoreEvent::AttackUnit {
+                attack_info: self.assault_attack_info(
+                    winner, loser, loser_killed, true),
+            };
+            (winner_event, loser_event, winner.id.clone(), loser.id.clone(), retreat_pos)
+        };
+        self.do_core_event(&winner_event);
+        self.do_core_event(&loser_event);
+        // a loser with no way back is expected to die but if it has
+        // somehow survived it just holds its position
+        let is_loser_alive = self.state.units().get(&loser_id).is_some();
+        if let (true, Some(retreat_pos)) = (is_loser_alive, loser_retreat_pos) {
+            let event = CoreEvent::Move {
+                unit_id: loser_id.clone(),
+                from: self.state.unit(&loser_id).pos.clone(),
+                to: retreat_pos,
+                mode: MoveMode::Fast,
+                cost: MovePoints{n: 0},
+            };
+            self.do_core_event(&event);
+        }
+        if winner_id != *attacker_id {
+            return;
+        }
+        let is_hex_clear = self.state.units_at(&defender_pos.map_pos).iter()
+            .all(|unit| unit.player_id == self.current_player_id);
+        if !is_hex_clear {
+            return;
+        }
+        let event = {
+            let attacker = self.state.unit(attacker_id);
+            let to = match get_free_exact_pos(
+                &self.db, &self.state, &attacker.type_id, &defender_pos.map_pos)
+            {
+                Some(pos) => pos,
+                None => return,
+            };
+            let cost = cmp::min(
+                tile_cost(&self.db, &self.state, attacker, &to).n,
+                attacker.move_points.n,
+            );
+            CoreEvent::Move {
+                unit_id: attacker_id.clone(),
+                from: attacker.pos.clone(),
+                to: to,
+                mode: MoveMode::Fast,
+                cost: MovePoints{n: cost},
+            }
+        };
+        self.do_core_event(&event);
+        self.reaction_fire(attacker_id);
+    }
+
+    fn get_bail_out_pos(&self, unit: &Unit) -> Option<ExactPos> {
+        let pos = &unit.pos.map_pos;
+        if let Some(exact_pos) = get_free_exact_pos(
+            &self.db, &self.state, &unit.type_id, pos)
+        {
+            return Some(exact_pos);
+        }
+        for dir in dirs() {
+            let neighbour_pos = Dir::get_neighbour_pos(pos, &dir);
+            if !self.state.map().is_inboard(&neighbour_pos) {
+                continue;
+            }
+            if let Some(exact_pos) = get_free_exact_pos(
+                &self.db, &self.state, &unit.type_id, &neighbour_pos)
+            {
+                return Some(exact_pos);
+            }
+        }
+        None
+    }
+
+    /// Passengers of a transporter that is going to be destroyed by this
+    /// attack bail out into free slots or die if there are none
+    fn bail_out_passengers(&mut self, attack_info: &AttackInfo) {
+        loop {
+            let event = {
+                let transporter = match self.state.units().get(&attack_info.defender_id) {
+                    Some(unit) => unit,
+                    None => return,
+                };
+                if transporter.count - attack_info.killed > 0 {
+                    return;
+                }
+                let passenger_id = match transporter.passenger_ids.first()
+                    .or(transporter.towed_unit_id.as_ref())
+                {
+                    Some(id) => id.clone(),
+                    None => return,
+                };
+                self.bail_out_event(transporter, &passenger_id)
+            };
+            self.do_core_event(&event);
+        }
+    }
+
+    fn bail_out_event(&self, transporter: &Unit, passenger_id: &UnitId) -> CoreEvent {
+        let passenger = self.state.unit(passenger_id);
+        let (to, killed) = match self.get_bail_out_pos(passenger) {
+            Some(pos) => {
+                let killed = cmp::min(
+                    self.rng.borrow_mut().gen_range(0, 3), passenger.count - 1);
+                (pos, killed)
+            },
+            None => (passenger.pos.clone(), passenger.count),
+        };
+        CoreEvent::BailOut {
+            unit_info: UnitInfo {
+                pos: to.clone(),
+                .. unit_to_info(passenger)
+            },
+            transporter_id: Some(transporter.id.clone()),
+            from: transporter.pos.clone(),
+            to: to,
+            killed: killed,
+            suppression: BASE_SUPPRESSION + PER_DEATH_SUPPRESSION * killed,
+        }
+    }
+
     pub fn next_player_id(&self, id: &PlayerId) -> PlayerId {
         let old_id = id.id;
         let max_id = self.players.len() as ZInt;
@@ -957,11 +2063,159 @@ impl Core {
         }}
     }
 
-    fn simulation_step(&mut self, command: Command) {
-        if let Err(err) = check_command(&self.db, &self.state, &command) {
-            println!("Bad command: {:?}", err);
-            return;
+    /// True if any of the observer views is open
+    pub fn is_observer_enabled(&self) -> bool {
+        !self.observer_events.is_empty()
+    }
+
+    /// View is closed if the observer is disabled or
+    /// if it has more than `MAX_OBSERVER_EVENTS` unread events
+    pub fn is_observer_view_open(&self, view: &ObserverView) -> bool {
+        self.observer_events.contains_key(view)
+    }
+
+    /// Pops the next event of the view, events of every open view are
+    /// kept until they are taken so views can be switched at any time.
+    /// Closed views have no events.
+    pub fn get_observer_event(&mut self, view: &ObserverView) -> Option<CoreEvent> {
+        self.observer_events.get_mut(view)
+            .and_then(|events| events.pop_front())
+    }
+
+    /// Checks that the player's view of the game, built from
+    /// the filtered events, holds nothing the player can`t see
+    pub fn check_partial_state(
+        &self,
+        player_id: &PlayerId,
+        state: &PartialState,
+    ) -> Result<(), Leak> {
+        let fow = &self.players_info[player_id].fow;
+        verify::check_partial_state(&self.db, &self.state, fow, state)
+    }
+
+    /// Leaks of the filtered events found since the last call.
+    /// Only debug builds look for them, tests panic instead.
+    pub fn take_leaks(&mut self) -> Vec<(PlayerId, Leak)> {
+        mem::replace(&mut self.leaks, Vec::new())
+    }
+
+    /// Same as `simulation_step` but for commands of bots and remote
+    /// players who can`t use debug commands like `Command::CreateUnit`
+    pub fn player_simulation_step(&mut self, command: Command) -> Result<(), CommandError> {
+        if let Command::CreateUnit{..} = command {
+            return Err(CommandError::DebugOnly);
+        }
+        self.simulation_step(command)
+    }
+
+    /// Checks the command of the current player and executes it
+    pub fn simulation_step(&mut self, command: Command) -> Result<(), CommandError> {
+        self.check_unit_owner(&command)?;
+        check_command(&self.db, &self.state, &command)?;
+        self.check_target_is_spotted(&command)?;
+        if self.turn_mode == TurnMode::Wego {
+            return self.plan(command);
         }
+        self.execute_command(command);
+        Ok(())
+    }
+
+    /// Moves and attacks of simultaneous turns are only queued.
+    /// Orders that change what is loaded into what are rejected because
+    /// the schedule of `execute_orders` knows only moves and attacks.
+    /// Reaction fire mode (and debug units) are set right away.
+    fn plan(&mut self, command: Command) -> Result<(), CommandError> {
+        match command {
+            Command::EndTurn => self.end_planning(),
+            Command::Move{..} | Command::AttackUnit{..} => {
+                let order = Order::from_command(&command).unwrap();
+                let queue = self.orders.entry(self.current_player_id.clone())
+                    .or_insert_with(OrderQueue::new);
+                if !queue.push(order) {
+                    return Err(CommandError::AlreadyHasOrder);
+                }
+            },
+            Command::Assault{..}
+                | Command::LoadUnit{..}
+                | Command::UnloadUnit{..} =>
+            {
+                return Err(CommandError::NotAvailableInWego);
+            },
+            Command::CreateUnit{..} | Command::SetReactionFireMode{..} => {
+                self.execute_command(command);
+            },
+        }
+        Ok(())
+    }
+
+    /// The turn ends when the last player has planned it
+    fn end_planning(&mut self) {
+        let old_id = self.current_player_id.clone();
+        let new_id = self.next_player_id(&old_id);
+        if new_id == self.players[0].id {
+            self.convert_ap_of_idle_units();
+            self.execute_orders();
+            self.do_core_event(&CoreEvent::EndTurn {
+                old_id: old_id,
+                new_id: new_id,
+            });
+        } else {
+            self.do_core_event(&CoreEvent::EndPlanning {
+                old_id: old_id,
+                new_id: new_id,
+            });
+        }
+    }
+
+    /// Planning is over for every player: units that have no attack to
+    /// make use their attack points for the reaction fire while
+    /// the orders are executed, like in the opponent's turn.
+    fn convert_ap_of_idle_units(&mut self) {
+        let mut unit_ids = Vec::new();
+        for (id, unit) in self.state.units() {
+            let unit_type = self.db.unit_type(&unit.type_id);
+            let weapon_type = self.db.weapon_type(&unit_type.weapon_type_id);
+            let has_attack_order = match self.orders.get(&unit.player_id) {
+                Some(queue) => queue.attack_order(id).is_some(),
+                None => false,
+            };
+            if weapon_type.reaction_fire
+                && unit.attack_points.n > 0
+                && !has_attack_order
+            {
+                unit_ids.push(id.clone());
+            }
+        }
+        // events must not depend on the order of the hash map
+        unit_ids.sort_by_key(|id| id.id);
+        for unit_id in unit_ids {
+            self.do_core_event(&CoreEvent::ConvertAp{unit_id: unit_id});
+        }
+    }
+
+    /// Orders are checked again because the situation has changed
+    /// since they were planned: units that can`t execute their
+    /// orders anymore just skip them.
+    fn execute_orders(&mut self) {
+        let mut player_ids: Vec<_> = self.players.iter()
+            .map(|player| player.id.clone())
+            .collect();
+        // nobody shoots first all the time
+        let shift = self.wego_turn % player_ids.len();
+        player_ids.rotate_left(shift);
+        self.wego_turn += 1;
+        let orders = mem::replace(&mut self.orders, HashMap::new());
+        for (_, order) in schedule(&player_ids, &orders) {
+            let command = order.to_command();
+            if check_command(&self.db, &self.state, &command).is_ok()
+                && self.check_target_is_spotted(&command).is_ok()
+            {
+                self.execute_command(command);
+            }
+        }
+    }
+
+    fn execute_command(&mut self, command: Command) {
         match command {
             Command::EndTurn => {
                 let old_id = self.current_player_id.clone();
@@ -979,7 +2233,9 @@ impl Core {
                         // type_id: self.db.unit_type_id("soldier"),
                         type_id: type_id,
                         player_id: self.current_player_id.clone(),
-                        passenger_id: None,
+                        passenger_ids: Vec::new(),
+                        towed_unit_id: None,
+                        is_contact: false,
                     },
                 };
                 self.do_core_event(&event);
@@ -1028,6 +2284,9 @@ impl Core {
                     self.reaction_fire(&attacker_id);
                 }
             },
+            Command::Assault{ref attacker_id, ref defender_id} => {
+                self.assault(attacker_id, defender_id);
+            },
             Command::LoadUnit{transporter_id, passenger_id} => {
                 let from = self.state.unit(&passenger_id).pos.clone();
                 let to = self.state.unit(&transporter_id).pos.clone();
@@ -1065,23 +2324,54 @@ impl Core {
     }
 
     pub fn do_command(&mut self, command: Command) {
-        self.simulation_step(command);
+        if let Err(err) = self.simulation_step(command) {
+            println!("Bad command: {:?}", err);
+        }
     }
 
-    fn do_ai(&mut self) {
+    /// Plays the whole turn of the current player who must be a bot.
+    ///
+    /// Turns of bots are played automatically after a human player
+    /// ends the turn. Games without humans must call this method.
+    pub fn play_bot_turn(&mut self) {
+        let player_id = self.current_player_id.clone();
+        let mut bot = self.bots.remove(&player_id)
+            .expect("core: Current player is not a bot");
+        self.is_bot_turn = true;
+        let mut commands_count = 0;
         loop {
             while let Some(event) = self.get_event() {
-                self.ai.apply_event(&self.db, &event);
+                bot.apply_event(&self.db, &event);
+            }
+            if commands_count >= MAX_BOT_COMMANDS_PER_TURN {
+                self.player_simulation_step(Command::EndTurn)
+                    .expect("core: Can`t end the turn of the bot");
+                break;
+            }
+            let command = bot.get_command(&self.db);
+            commands_count += 1;
+            if self.player_simulation_step(command.clone()).is_err() {
+                bot.command_rejected(&command);
             }
-            let command = self.ai.get_command(&self.db);
-            self.do_command(command.clone());
             if let Command::EndTurn = command {
-                return;
+                break;
             }
         }
+        self.is_bot_turn = false;
+        self.bots.insert(player_id, bot);
+    }
+
+    /// Why the bot of the player has failed to play its last turn
+    pub fn bot_error(&self, player_id: &PlayerId) -> Option<&BotError> {
+        self.bots.get(player_id).and_then(|bot| bot.error())
+    }
+
+    pub fn has_human_players(&self) -> bool {
+        self.players.iter().any(|player| player.class == PlayerClass::Human)
     }
 
     fn handle_end_turn_event(&mut self, old_id: &PlayerId, new_id: &PlayerId) {
+        self.timer.end_turn(old_id, self.clock.now());
         for player in &self.players {
             if player.id == *new_id {
                 if self.current_player_id == *old_id {
@@ -1090,15 +2380,95 @@ impl Core {
                 break;
             }
         }
-        if self.player().class == PlayerClass::Ai
-            && *new_id == *self.player_id()
+        if self.is_bot_turn
+            || !self.has_human_players()
+            || *new_id != *self.player_id()
         {
-            self.do_ai();
+            return;
+        }
+        while self.player().class == PlayerClass::Ai {
+            self.play_bot_turn();
+        }
+    }
+
+    fn get_noise(&self, event: &CoreEvent) -> Option<(UnitId, NoiseClass, ZInt)> {
+        match *event {
+            CoreEvent::Move{ref unit_id, ref mode, ..} => {
+                let unit = self.state.unit(unit_id);
+                if self.db.unit_type(&unit.type_id).class != UnitClass::Vehicle {
+                    return None;
+                }
+                Some((unit_id.clone(), NoiseClass::Engine, engine_noise_radius(mode)))
+            },
+            // players who see the attacker get no noise, see `make_noise`
+            CoreEvent::AttackUnit{ref attack_info} => {
+                attack_info.attacker_id.clone().map(|attacker_id| {
+                    (attacker_id, NoiseClass::Gunfire, GUNFIRE_NOISE_RADIUS)
+                })
+            },
+            _ => None,
+        }
+    }
+
+    fn get_noise_pos(&self, pos: &MapPos) -> MapPos {
+        let mut positions = vec![pos.clone()];
+        for pos in spiral_iter(pos, NOISE_POS_ERROR) {
+            if self.state.map().is_inboard(&pos) {
+                positions.push(pos);
+            }
+        }
+        self.rng.borrow_mut().choose(&positions).unwrap().clone()
+    }
+
+    /// Enemies that do not see a noisy unit still can hear it
+    fn make_noise(&mut self, event: &CoreEvent) {
+        let (unit_id, class, radius) = match self.get_noise(event) {
+            Some(noise) => noise,
+            None => return,
+        };
+        let mut events = Vec::new();
+        if let Some(unit) = self.state.units().get(&unit_id) {
+            let pos = &unit.pos.map_pos;
+            for player in &self.players {
+                if player.id == unit.player_id {
+                    continue;
+                }
+                let fow = &self.players_info[&player.id].fow;
+                if fow.unit_visibility(&self.db, &self.state, unit, &unit.pos)
+                    != UnitVisibility::No
+                {
+                    continue;
+                }
+                let can_hear = self.state.units().values().any(|listener| {
+                    listener.player_id == player.id
+                        && distance(&listener.pos.map_pos, pos) <= radius
+                });
+                if can_hear {
+                    events.push(CoreEvent::Noise {
+                        listener_id: player.id.clone(),
+                        pos: self.get_noise_pos(pos),
+                        class: class.clone(),
+                    });
+                }
+            }
+        }
+        for event in &events {
+            self.do_core_event(event);
         }
     }
 
     fn do_core_event(&mut self, event: &CoreEvent) {
+        if let CoreEvent::AttackUnit{ref attack_info} = *event {
+            self.bail_out_passengers(attack_info);
+        }
+        let defender = if let CoreEvent::AttackUnit{ref attack_info} = *event {
+            Some(self.state.unit(&attack_info.defender_id).clone())
+        } else {
+            None
+        };
         self.state.apply_event(&self.db, &event);
+        push_observer_events(
+            &mut self.observer_events, &ObserverView::God, Some(event.clone()).into_iter());
         for player in &self.players {
             let (filtered_events, active_unit_ids) = filter::filter_events(
                 &self.db,
@@ -1106,32 +2476,949 @@ impl Core {
                 &player.id,
                 &self.players_info[&player.id].fow,
                 &event,
+                defender.as_ref(),
             );
             let mut i = self.players_info.get_mut(&player.id)
                 .expect("core: Can`t get player`s info");
+            let old_events_count = i.events.len();
+            if filtered_events.is_empty() {
+                // enemy contacts may appear or disappear
+                // even if the player gets no events
+                update_passive_enemies(
+                    &self.db, &self.state, &player.id, &active_unit_ids, i);
+            }
             for event in filtered_events {
                 i.fow.apply_event(&self.db, &self.state, &event);
                 i.events.push_back(event);
-                let new_visible_enemies = filter::get_visible_enemies(
-                    &self.db,
-                    &self.state,
-                    &i.fow,
-                    &player.id,
-                );
-                let show_hide_events = filter::show_or_hide_passive_enemies(
-                    self.state.units(),
-                    &active_unit_ids,
-                    &i.visible_enemies,
-                    &new_visible_enemies,
-                );
-                i.events.extend(show_hide_events);
-                i.visible_enemies = new_visible_enemies;
-            }
-        }
-        if let CoreEvent::EndTurn{ref old_id, ref new_id} = *event {
+                update_passive_enemies(
+                    &self.db, &self.state, &player.id, &active_unit_ids, i);
+            }
+            // visibility changes during the command so the player's
+            // events can be checked against the real state only here
+            if cfg!(debug_assertions) {
+                for event in i.events.iter().skip(old_events_count) {
+                    let result = verify::check_filtered_event(
+                        &self.db, &self.state, &i.fow, &player.id, event);
+                    if let Err(leak) = result {
+                        if cfg!(test) {
+                            panic!("core: Leak to player {}: {}", player.id.id, leak);
+                        }
+                        eprintln!("core: Leak to player {}: {}", player.id.id, leak);
+                        self.leaks.push((player.id.clone(), leak));
+                    }
+                }
+            }
+            let view = ObserverView::Player(player.id.clone());
+            push_observer_events(
+                &mut self.observer_events,
+                &view,
+                i.events.iter().skip(old_events_count).cloned(),
+            );
+        }
+        self.make_noise(event);
+        if let CoreEvent::EndTurn{ref old_id, ref new_id}
+            | CoreEvent::EndPlanning{ref old_id, ref new_id} = *event
+        {
             self.handle_end_turn_event(old_id, new_id);
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{Size2};
+    use db::{Db};
+    use internal_state::{InternalState};
+    use fow::{UnitVisibility};
+    use game_state::{GameState, GameStateMut};
+    use partial_state::{PartialState};
+    use dir::{Dir, dirs};
+    use map::{distance};
+    use std::collections::{HashMap};
+    use super::{
+        test_chance,
+        command_net_visibility,
+        get_free_exact_pos,
+        check_command,
+        Core,
+        Options,
+        GameType,
+        AiDifficulty,
+        AiPosture,
+        AiBackend,
+        BotKind,
+        ObserverView,
+        Command,
+        CoreEvent,
+        CommandError,
+        NoiseClass,
+        AttackInfo,
+        FireMode,
+        UnitId,
+        PlayerId,
+        MapPos,
+        MoveMode,
+        MovePoints,
+        TurnMode,
+        HOOK_COST,
+        MAX_OBSERVER_EVENTS,
+        MAX_BOT_COMMANDS_PER_TURN,
+        TEST_ROLL_MIN,
+        TEST_ROLL_MAX,
+        StartError,
+        ai_posture,
+    };
+    use scenario::{Scenario, MAX_MAP_SIDE};
+    use protocol::{event_to_string};
+    use clock::{ManualClock, TimeControl};
+    use test_utils::{add_unit, create_unit};
+    use std::time::{Duration};
+    use std::io::{self, Cursor};
+    use bot::{BotError, ProcessBot};
+
+    fn load_unit(core: &mut Core, transporter_id: &UnitId, passenger_id: &UnitId) {
+        core.do_command(Command::LoadUnit {
+            transporter_id: transporter_id.clone(),
+            passenger_id: passenger_id.clone(),
+        });
+        assert!(core.state.unit(transporter_id).passenger_ids.contains(passenger_id));
+    }
+
+    fn destroy_unit(core: &mut Core, attacker_id: &UnitId, defender_id: &UnitId) {
+        let killed = core.state.unit(defender_id).count;
+        core.do_core_event(&CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(attacker_id.clone()),
+                defender_id: defender_id.clone(),
+                mode: FireMode::Active,
+                killed: killed,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: false,
+                is_assault: false,
+            },
+        });
+    }
+
+    /// Builds every player's view of the game from its filtered events
+    fn partial_states(core: &Core) -> Vec<PartialState> {
+        let mut states = Vec::new();
+        for player in &core.players {
+            let mut state = PartialState::from_scenario(core.scenario(), &player.id);
+            for event in &core.players_info[&player.id].events {
+                state.apply_event(&core.db, event);
+            }
+            states.push(state);
+        }
+        states
+    }
+
+    fn check_no_orphans<S: GameState>(state: &S, dead_transporter_id: &UnitId) {
+        assert!(state.units().get(dead_transporter_id).is_none());
+        for (_, unit) in state.units() {
+            for passenger_id in &unit.passenger_ids {
+                assert!(state.units().get(passenger_id).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_passenger_bails_out_of_destroyed_transporter() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let pos = MapPos{v: Vector2{x: 3, y: 0}};
+        let truck_id = add_unit(&mut core, &pos, "truck", 0);
+        let soldier_id = add_unit(&mut core, &pos, "soldier", 0);
+        load_unit(&mut core, &truck_id, &soldier_id);
+        let truck_pos = core.state.unit(&truck_id).pos.clone();
+        let enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 1}}, "medium_tank", 1);
+        destroy_unit(&mut core, &enemy_id, &truck_id);
+        check_no_orphans(&core.state, &truck_id);
+        {
+            let soldier = core.state.unit(&soldier_id);
+            assert!(soldier.pos != truck_pos);
+            assert!(soldier.count > 0);
+            assert!(soldier.morale < 100);
+        }
+        for state in &partial_states(&core) {
+            check_no_orphans(state, &truck_id);
+            if let Some(soldier) = state.units().get(&soldier_id) {
+                assert!(soldier.pos != truck_pos);
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_passengers_bail_out_of_destroyed_transporter() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let pos = MapPos{v: Vector2{x: 3, y: 0}};
+        let truck_id = add_unit(&mut core, &pos, "truck", 0);
+        let soldier_1_id = add_unit(&mut core, &pos, "soldier", 0);
+        let soldier_2_id = add_unit(&mut core, &pos, "soldier", 0);
+        load_unit(&mut core, &truck_id, &soldier_1_id);
+        load_unit(&mut core, &truck_id, &soldier_2_id);
+        let enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 1}}, "medium_tank", 1);
+        destroy_unit(&mut core, &enemy_id, &truck_id);
+        check_no_orphans(&core.state, &truck_id);
+        assert!(core.state.units().get(&soldier_1_id).is_some());
+        assert!(core.state.units().get(&soldier_2_id).is_some());
+        for state in &partial_states(&core) {
+            check_no_orphans(state, &truck_id);
+        }
+    }
+
+    #[test]
+    fn test_transporter_capacity() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let pos = MapPos{v: Vector2{x: 3, y: 3}};
+        let truck_id = add_unit(&mut core, &pos, "truck", 0);
+        let soldier_1_id = add_unit(&mut core, &pos, "soldier", 0);
+        let soldier_2_id = add_unit(&mut core, &pos, "soldier", 0);
+        let soldier_3_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 4}}, "soldier", 0);
+        load_unit(&mut core, &truck_id, &soldier_1_id);
+        load_unit(&mut core, &truck_id, &soldier_2_id);
+        let command = Command::LoadUnit {
+            transporter_id: truck_id.clone(),
+            passenger_id: soldier_3_id.clone(),
+        };
+        match check_command(&core.db, &core.state, &command) {
+            Err(CommandError::TransporterIsFull) => {},
+            _ => panic!("Transporter must be full"),
+        }
+        let unload_pos = get_free_exact_pos(
+            &core.db, &core.state, &core.state.unit(&soldier_1_id).type_id,
+            &MapPos{v: Vector2{x: 4, y: 3}}).unwrap();
+        core.do_command(Command::UnloadUnit {
+            transporter_id: truck_id.clone(),
+            passenger_id: soldier_1_id.clone(),
+            pos: unload_pos.clone(),
+        });
+        assert!(core.state.unit(&soldier_1_id).pos == unload_pos);
+        assert!(core.state.unit(&truck_id).passenger_ids == vec![soldier_2_id.clone()]);
+        load_unit(&mut core, &truck_id, &soldier_3_id);
+    }
+
+    #[test]
+    fn test_towed_gun_moves_with_tower() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let pos = MapPos{v: Vector2{x: 3, y: 3}};
+        let jeep_id = add_unit(&mut core, &pos, "jeep", 0);
+        let gun_id = add_unit(&mut core, &pos, "field_gun", 0);
+        let enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 5}}, "soldier", 1);
+        let move_points = core.state.unit(&jeep_id).move_points.n;
+        core.do_command(Command::LoadUnit {
+            transporter_id: jeep_id.clone(),
+            passenger_id: gun_id.clone(),
+        });
+        assert!(core.state.unit(&jeep_id).towed_unit_id == Some(gun_id.clone()));
+        assert_eq!(core.state.unit(&jeep_id).move_points.n, move_points - HOOK_COST);
+        let attack_command = Command::AttackUnit {
+            attacker_id: gun_id.clone(),
+            defender_id: enemy_id.clone(),
+        };
+        match check_command(&core.db, &core.state, &attack_command) {
+            Err(CommandError::GunIsLimbered) => {},
+            _ => panic!("Limbered gun must not fire"),
+        }
+        let jeep_type_id = core.state.unit(&jeep_id).type_id.clone();
+        let new_pos = get_free_exact_pos(
+            &core.db, &core.state, &jeep_type_id, &MapPos{v: Vector2{x: 4, y: 3}}).unwrap();
+        core.do_core_event(&CoreEvent::Move {
+            unit_id: jeep_id.clone(),
+            from: core.state.unit(&jeep_id).pos.clone(),
+            to: new_pos.clone(),
+            mode: MoveMode::Fast,
+            cost: MovePoints{n: 1},
+        });
+        assert!(core.state.unit(&gun_id).pos == new_pos);
+        let unhook_pos = get_free_exact_pos(
+            &core.db, &core.state, &core.state.unit(&gun_id).type_id,
+            &new_pos.map_pos).unwrap();
+        core.do_command(Command::UnloadUnit {
+            transporter_id: jeep_id.clone(),
+            passenger_id: gun_id.clone(),
+            pos: unhook_pos.clone(),
+        });
+        assert!(core.state.unit(&jeep_id).towed_unit_id.is_none());
+        assert!(core.state.unit(&gun_id).pos == unhook_pos);
+        assert_eq!(core.state.unit(&jeep_id).move_points.n, move_points - HOOK_COST * 2 - 1);
+    }
+
+    #[test]
+    fn test_passenger_dies_if_there_is_no_free_slot() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let pos = MapPos{v: Vector2{x: 0, y: 0}};
+        let truck_id = add_unit(&mut core, &pos, "truck", 0);
+        let soldier_id = add_unit(&mut core, &pos, "soldier", 0);
+        load_unit(&mut core, &truck_id, &soldier_id);
+        let mut positions = vec![pos.clone()];
+        for dir in dirs() {
+            let neighbour_pos = Dir::get_neighbour_pos(&pos, &dir);
+            if core.state.map().is_inboard(&neighbour_pos) {
+                positions.push(neighbour_pos);
+            }
+        }
+        let soldier_type_id = core.db.unit_type_id("soldier");
+        for p in &positions {
+            while get_free_exact_pos(&core.db, &core.state, &soldier_type_id, p).is_some() {
+                add_unit(&mut core, p, "soldier", 0);
+            }
+        }
+        let enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 1}}, "medium_tank", 1);
+        destroy_unit(&mut core, &enemy_id, &truck_id);
+        check_no_orphans(&core.state, &truck_id);
+        assert!(core.state.units().get(&soldier_id).is_none());
+        for state in &partial_states(&core) {
+            check_no_orphans(state, &truck_id);
+            assert!(state.units().get(&soldier_id).is_none());
+        }
+    }
+
+    /// A passenger that bails out where the enemy can`t see it
+    /// must stay unknown even if its transporter is visible
+    #[test]
+    fn test_unseen_passenger_bails_out_unknown() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let pos = MapPos{v: Vector2{x: 4, y: 4}};
+        let truck_id = add_unit(&mut core, &pos, "truck", 0);
+        let soldier_id = add_unit(&mut core, &pos, "soldier", 0);
+        load_unit(&mut core, &truck_id, &soldier_id);
+        let enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 2, y: 5}}, "medium_tank", 1);
+        let enemy_player_id = PlayerId{id: 1};
+        {
+            let truck = core.state.unit(&truck_id);
+            let fow = &core.players_info[&enemy_player_id].fow;
+            assert!(fow.is_visible(&core.db, &core.state, truck, &truck.pos));
+        }
+        destroy_unit(&mut core, &enemy_id, &truck_id);
+        {
+            let soldier = core.state.unit(&soldier_id);
+            assert_eq!(soldier.pos.map_pos, pos);
+            let fow = &core.players_info[&enemy_player_id].fow;
+            assert!(!fow.is_visible(&core.db, &core.state, soldier, &soldier.pos));
+        }
+        for event in &core.players_info[&enemy_player_id].events {
+            match *event {
+                CoreEvent::BailOut{..} => panic!("Unseen passenger is shown"),
+                CoreEvent::ShowUnit{ref unit_info} | CoreEvent::CreateUnit{ref unit_info}
+                    if unit_info.unit_id == soldier_id =>
+                {
+                    assert!(unit_info.is_contact);
+                },
+                _ => {},
+            }
+        }
+        let state = &partial_states(&core)[1];
+        if let Some(soldier) = state.units().get(&soldier_id) {
+            assert!(soldier.is_contact);
+        }
+        assert!(state.ghosts().get(&soldier_id).is_none());
+    }
+
+    #[test]
+    fn test_command_net_relays_spotting() {
+        let db = Db::new();
+        let mut state = InternalState::new(&Size2{w: 16, h: 8});
+        let mortar_id = create_unit(&db, &mut state, 0, "mortar", 0, (0, 0, 0));
+        create_unit(&db, &mut state, 1, "scout", 0, (9, 0, 0));
+        let target_id = create_unit(&db, &mut state, 2, "soldier", 1, (15, 0, 0));
+        let check = |state: &InternalState, expected: UnitVisibility| {
+            let mortar = state.unit(&mortar_id);
+            let target = state.unit(&target_id);
+            assert_eq!(command_net_visibility(&db, state, mortar, target), expected);
+        };
+        check(&state, UnitVisibility::No);
+        // the scout is out of radio range of this HQ
+        create_unit(&db, &mut state, 3, "jeep", 0, (3, 0, 0));
+        check(&state, UnitVisibility::No);
+        create_unit(&db, &mut state, 4, "jeep", 0, (4, 0, 0));
+        check(&state, UnitVisibility::Full);
+    }
+
+    fn heard_noises(core: &Core, player_id: &PlayerId) -> Vec<NoiseClass> {
+        let mut noises = Vec::new();
+        for event in &core.players_info[player_id].events {
+            if let CoreEvent::Noise{ref class, ..} = *event {
+                noises.push(class.clone());
+            }
+        }
+        noises
+    }
+
+    #[test]
+    fn test_unseen_vehicle_makes_noise() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        // the city hides the truck from this soldier
+        add_unit(&mut core, &MapPos{v: Vector2{x: 4, y: 4}}, "soldier", 0);
+        let truck_id = add_unit(&mut core, &MapPos{v: Vector2{x: 7, y: 6}}, "truck", 1);
+        let player_id = PlayerId{id: 0};
+        {
+            let truck = core.state.unit(&truck_id);
+            let fow = &core.players_info[&player_id].fow;
+            assert_eq!(fow.unit_visibility(&core.db, &core.state, truck, &truck.pos), UnitVisibility::No);
+        }
+        for &(x, y, ref mode) in &[(7, 7, MoveMode::Hunt), (8, 7, MoveMode::Fast)] {
+            let from = core.state.unit(&truck_id).pos.clone();
+            let to = get_free_exact_pos(
+                &core.db, &core.state, &core.state.unit(&truck_id).type_id,
+                &MapPos{v: Vector2{x: x, y: y}}).unwrap();
+            core.do_core_event(&CoreEvent::Move {
+                unit_id: truck_id.clone(),
+                from: from,
+                to: to,
+                mode: mode.clone(),
+                cost: MovePoints{n: 0},
+            });
+        }
+        // the hunting truck was too far away to be heard
+        assert_eq!(heard_noises(&core, &player_id), vec![NoiseClass::Engine]);
+        assert!(heard_noises(&core, &PlayerId{id: 1}).is_empty());
+    }
+
+    fn shoot(core: &mut Core, attacker_id: &UnitId, defender_id: &UnitId, is_ambush: bool) {
+        core.do_core_event(&CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(attacker_id.clone()),
+                defender_id: defender_id.clone(),
+                mode: FireMode::Active,
+                killed: 0,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: is_ambush,
+                is_inderect: false,
+                is_assault: false,
+            },
+        });
+    }
+
+    /// Every shot is heard by those who don`t see the shooter
+    #[test]
+    fn test_unseen_shooter_makes_noise() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let player_id = PlayerId{id: 0};
+        let soldier_id = add_unit(&mut core, &MapPos{v: Vector2{x: 4, y: 4}}, "soldier", 0);
+        let enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 7, y: 6}}, "soldier", 1);
+        shoot(&mut core, &enemy_id, &soldier_id, true);
+        assert_eq!(heard_noises(&core, &player_id), vec![NoiseClass::Gunfire]);
+        // the shooter is revealed, nothing to hear
+        shoot(&mut core, &enemy_id, &soldier_id, false);
+        assert_eq!(heard_noises(&core, &player_id), vec![NoiseClass::Gunfire]);
+        assert!(heard_noises(&core, &PlayerId{id: 1}).is_empty());
+    }
+
+    #[test]
+    fn test_ai_always_ends_its_turn() {
+        let difficulties = [
+            AiDifficulty::Easy,
+            AiDifficulty::Normal,
+            AiDifficulty::Hard,
+        ];
+        for difficulty in &difficulties {
+            let mut core = Core::new(&Options {
+                game_type: GameType::SingleVsAi,
+                ai_difficulty: difficulty.clone(),
+                .. Options::default()
+            }).unwrap();
+            for _ in 0 .. 8 {
+                core.do_command(Command::EndTurn);
+                assert_eq!(core.player_id().id, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mcts_ai_always_ends_its_turn() {
+        let mut core = Core::new(&Options {
+            game_type: GameType::SingleVsAi,
+            ai_backend: AiBackend::Mcts{max_iterations: 20, max_time_ms: 100},
+            .. Options::default()
+        }).unwrap();
+        for _ in 0 .. 4 {
+            core.do_command(Command::EndTurn);
+            assert_eq!(core.player_id().id, 0);
+        }
+    }
+
+    #[test]
+    fn test_defending_ai_holds_objectives() {
+        let mut core = Core::new(&Options {
+            game_type: GameType::SingleVsAi,
+            ai_posture: AiPosture::Defend,
+            .. Options::default()
+        }).unwrap();
+        for _ in 0 .. 4 {
+            core.do_command(Command::EndTurn);
+        }
+        let objectives = core.scenario().objectives.clone();
+        for (_, unit) in core.state.units() {
+            if unit.player_id.id != 1 {
+                continue;
+            }
+            let is_near_objective = objectives.iter()
+                .any(|pos| distance(pos, &unit.pos.map_pos) <= 1);
+            assert!(is_near_objective);
+        }
+    }
+
+    #[test]
+    fn test_scenario_ai_posture_wins_over_options() {
+        let mut scenario = Scenario::default_scenario(&Db::new());
+        scenario.ai_postures.insert(PlayerId{id: 1}, AiPosture::Defend);
+        let options = Options{ai_posture: AiPosture::Attack, .. Options::default()};
+        assert_eq!(*ai_posture(&options, &scenario, &PlayerId{id: 1}), AiPosture::Defend);
+        assert_eq!(*ai_posture(&options, &scenario, &PlayerId{id: 0}), AiPosture::Attack);
+    }
+
+    #[test]
+    fn test_core_starts_from_scenario() {
+        let db = Db::new();
+        let mut scenario = Scenario::new(&Size2{w: 12, h: 6});
+        let tank_id = db.unit_type_id("medium_tank");
+        let soldier_id = db.unit_type_id("soldier");
+        let tank_pos = MapPos{v: Vector2{x: 1, y: 1}};
+        let soldier_pos = MapPos{v: Vector2{x: 10, y: 4}};
+        assert!(scenario.add_unit(&db, &PlayerId{id: 0}, &tank_id, &tank_pos));
+        assert!(scenario.add_unit(&db, &PlayerId{id: 1}, &soldier_id, &soldier_pos));
+        scenario.toggle_objective(&MapPos{v: Vector2{x: 6, y: 3}});
+        let core = Core::new(&Options {
+            scenario: Some(scenario.clone()),
+            .. Options::default()
+        }).unwrap();
+        assert_eq!((core.map_size().w, core.map_size().h), (12, 6));
+        assert!(core.state.objects().is_empty());
+        assert_eq!(core.state.units().len(), 2);
+        let tank = core.state.unit(&UnitId{id: 0});
+        assert!(tank.type_id == tank_id);
+        assert_eq!(tank.pos, scenario.units[0].pos);
+        assert!(core.state.unit(&UnitId{id: 1}).player_id == PlayerId{id: 1});
+        assert_eq!(core.scenario().objectives, scenario.objectives);
+    }
+
+    #[test]
+    fn test_core_rejects_bad_scenarios() {
+        let db = Db::new();
+        let mut scenario = Scenario::new(&Size2{w: 6, h: 6});
+        let soldier_id = db.unit_type_id("soldier");
+        let pos = MapPos{v: Vector2{x: 1, y: 1}};
+        assert!(scenario.add_unit(&db, &PlayerId{id: 2}, &soldier_id, &pos));
+        let result = Core::new(&Options{scenario: Some(scenario), .. Options::default()});
+        match result {
+            Err(StartError::BadScenario(_)) => {},
+            _ => panic!("A scenario with a unit of a third player is accepted"),
+        }
+        let scenario = Scenario::new(&Size2{w: MAX_MAP_SIDE + 1, h: 6});
+        let result = Core::new(&Options{scenario: Some(scenario), .. Options::default()});
+        match result {
+            Err(StartError::BadScenario(_)) => {},
+            _ => panic!("A scenario with a huge map is accepted"),
+        }
+    }
+
+    #[test]
+    fn test_test_chance_matches_all_rolls() {
+        for needed in -8 .. 8 {
+            let rolls: Vec<_> = (TEST_ROLL_MIN .. TEST_ROLL_MAX).collect();
+            let good_rolls = rolls.iter().filter(|&&roll| roll < needed).count();
+            let expected = good_rolls as f32 / rolls.len() as f32;
+            assert_eq!(test_chance(needed), expected);
+        }
+    }
+
+    #[test]
+    fn test_bots_play_without_humans() {
+        let mut bots = HashMap::new();
+        bots.insert(PlayerId{id: 0}, BotKind::Builtin);
+        bots.insert(PlayerId{id: 1}, BotKind::Builtin);
+        let mut core = Core::new(&Options{bots: bots, .. Options::default()}).unwrap();
+        for i in 0 .. 4 {
+            assert_eq!(core.player_id().id, i % 2);
+            core.play_bot_turn();
+        }
+        assert_eq!(core.player_id().id, 0);
+    }
+
+    #[test]
+    fn test_wego_orders_are_executed_after_planning() {
+        let mut core = Core::new(&Options {
+            turn_mode: TurnMode::Wego,
+            seed: Some(0),
+            .. Options::default()
+        }).unwrap();
+        let unit_id = add_unit(&mut core, &MapPos{v: Vector2{x: 2, y: 3}}, "soldier", 0);
+        let from = core.state.unit(&unit_id).pos.clone();
+        let to = get_free_exact_pos(
+            &core.db, &core.state, &core.state.unit(&unit_id).type_id,
+            &MapPos{v: Vector2{x: 3, y: 3}}).unwrap();
+        let command = Command::Move {
+            unit_id: unit_id.clone(),
+            path: vec![to.clone()],
+            mode: MoveMode::Fast,
+        };
+        assert!(core.simulation_step(command.clone()).is_ok());
+        match core.simulation_step(command) {
+            Err(CommandError::AlreadyHasOrder) => {},
+            _ => panic!("Unit must have only one move order"),
+        }
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id().id, 1);
+        assert_eq!(core.state.unit(&unit_id).pos, from);
+        let mut is_planning_ended = false;
+        while let Some(event) = core.get_player_event(&PlayerId{id: 1}) {
+            match event {
+                CoreEvent::EndPlanning{..} => is_planning_ended = true,
+                CoreEvent::Move{..} => panic!("Orders must be hidden"),
+                _ => {},
+            }
+        }
+        assert!(is_planning_ended);
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id().id, 0);
+        assert_eq!(core.state.unit(&unit_id).pos, to);
+    }
+
+    #[test]
+    fn test_wego_idle_units_keep_ap_for_reaction_fire() {
+        let mut core = Core::new(&Options {
+            turn_mode: TurnMode::Wego,
+            seed: Some(0),
+            .. Options::default()
+        }).unwrap();
+        let target_id = add_unit(&mut core, &MapPos{v: Vector2{x: 6, y: 1}}, "soldier", 0);
+        let attacker_id = add_unit(&mut core, &MapPos{v: Vector2{x: 7, y: 1}}, "soldier", 1);
+        let idle_id = add_unit(&mut core, &MapPos{v: Vector2{x: 8, y: 7}}, "soldier", 1);
+        core.do_command(Command::EndTurn);
+        assert!(core.simulation_step(Command::AttackUnit {
+            attacker_id: attacker_id.clone(),
+            defender_id: target_id.clone(),
+        }).is_ok());
+        while core.get_player_event(&PlayerId{id: 1}).is_some() {}
+        core.do_command(Command::EndTurn);
+        let mut converted_ids = Vec::new();
+        while let Some(event) = core.get_player_event(&PlayerId{id: 1}) {
+            if let CoreEvent::ConvertAp{unit_id} = event {
+                converted_ids.push(unit_id);
+            }
+        }
+        assert!(converted_ids.contains(&idle_id));
+        assert!(!converted_ids.contains(&attacker_id));
+    }
+
+    #[test]
+    fn test_bots_play_wego() {
+        let mut bots = HashMap::new();
+        bots.insert(PlayerId{id: 0}, BotKind::Builtin);
+        bots.insert(PlayerId{id: 1}, BotKind::Builtin);
+        let mut core = Core::new(&Options {
+            bots: bots,
+            turn_mode: TurnMode::Wego,
+            .. Options::default()
+        }).unwrap();
+        for i in 0 .. 4 {
+            assert_eq!(core.player_id().id, i % 2);
+            core.play_bot_turn();
+        }
+        assert_eq!(core.player_id().id, 0);
+    }
+
+    #[test]
+    fn test_turn_ends_when_time_is_out() {
+        let clock = ManualClock::new();
+        let mut core = Core::with_clock(&Options {
+            time_control: TimeControl {
+                turn_limit: Some(Duration::from_secs(60)),
+                time_bank: Some(Duration::from_secs(90)),
+            },
+            .. Options::default()
+        }, Box::new(clock.clone())).unwrap();
+        let id_0 = PlayerId{id: 0};
+        let id_1 = PlayerId{id: 1};
+        clock.advance(Duration::from_secs(59));
+        core.check_time();
+        assert_eq!(core.player_id().id, 0);
+        assert_eq!(core.time_left(&id_0), Some(Duration::from_secs(1)));
+        clock.advance(Duration::from_secs(1));
+        core.check_time();
+        assert_eq!(core.player_id().id, 1);
+        assert_eq!(core.time_bank(&id_0), Some(Duration::from_secs(30)));
+        let is_end_turn = |event: &CoreEvent| matches!(*event, CoreEvent::EndTurn{..});
+        assert!(core.players_info[&id_0].events.iter().any(&is_end_turn));
+        // the next turn is limited by the rest of the bank
+        assert_eq!(core.time_left(&id_0), Some(Duration::from_secs(30)));
+        clock.advance(Duration::from_secs(10));
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.time_left(&id_0), Some(Duration::from_secs(30)));
+        assert_eq!(core.time_bank(&id_1), Some(Duration::from_secs(80)));
+    }
+
+    #[test]
+    fn test_bots_are_not_timed() {
+        let core = Core::with_clock(&Options {
+            game_type: GameType::SingleVsAi,
+            time_control: TimeControl {
+                turn_limit: Some(Duration::from_secs(60)),
+                time_bank: None,
+            },
+            .. Options::default()
+        }, Box::new(ManualClock::new())).unwrap();
+        assert!(core.time_left(&PlayerId{id: 0}).is_some());
+        assert!(core.time_left(&PlayerId{id: 1}).is_none());
+    }
+
+    /// Core where the second player is a process bot
+    /// that answers `get_command`s with the given lines
+    fn scripted_bot_core(lines: &[&str]) -> Core {
+        let id = PlayerId{id: 1};
+        let mut bots = HashMap::new();
+        bots.insert(id.clone(), BotKind::Builtin);
+        let mut core = Core::new(&Options{bots: bots, .. Options::default()}).unwrap();
+        let mut script = String::new();
+        for line in lines {
+            script.push_str(line);
+            script.push('\n');
+        }
+        let bot = ProcessBot::with_pipes(
+            Box::new(io::sink()), Cursor::new(script.into_bytes()), &id);
+        core.bots.insert(id, Box::new(bot));
+        core
+    }
+
+    #[test]
+    fn test_process_bot_ends_its_turn() {
+        let mut core = scripted_bot_core(&["end_turn", "end_turn", "end_turn"]);
+        for _ in 0 .. 3 {
+            core.do_command(Command::EndTurn);
+            assert_eq!(core.player_id().id, 0);
+        }
+        assert!(core.bot_error(&PlayerId{id: 1}).is_none());
+    }
+
+    #[test]
+    fn test_process_bot_with_bad_commands_ends_its_turn() {
+        // Unit 0 belongs to the first player so every attack is rejected
+        let attacks = vec!["attack 0 11"; 20];
+        let mut core = scripted_bot_core(&attacks);
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id().id, 0);
+        assert!(core.bot_error(&PlayerId{id: 1}).is_none());
+        let mut core = scripted_bot_core(&["bad_command"]);
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id().id, 0);
+        match core.bot_error(&PlayerId{id: 1}) {
+            Some(&BotError::BadCommand(..)) => {},
+            _ => panic!("Bad command must be reported"),
+        }
+    }
+
+    #[test]
+    fn test_process_bot_can_not_cheat() {
+        // Unit 0 belongs to the first player, unit 11 is a tank of the bot
+        let commands = [
+            "create_unit soldier 9 7 0",
+            "move 0 fast 1 1 0",
+            "move 11 fast 99 99 0",
+        ];
+        for command in &commands {
+            let mut core = scripted_bot_core(&[command, "end_turn"]);
+            let units_count = core.state.units().len();
+            let pos = core.state.unit(&UnitId{id: 0}).pos.clone();
+            core.do_command(Command::EndTurn);
+            assert_eq!(core.player_id().id, 0);
+            assert_eq!(core.state.units().len(), units_count);
+            assert_eq!(core.state.unit(&UnitId{id: 0}).pos, pos);
+            assert!(core.bot_error(&PlayerId{id: 1}).is_none());
+        }
+    }
+
+    #[test]
+    fn test_endless_bot_turn_is_ended() {
+        // unit 11 belongs to the bot so the command is always accepted
+        let lines = vec!["reaction_fire 11 normal"; MAX_BOT_COMMANDS_PER_TURN * 2];
+        let mut core = scripted_bot_core(&lines);
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id().id, 0);
+        // the bot was stopped before it ran out of lines
+        assert!(core.bot_error(&PlayerId{id: 1}).is_none());
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_rolls() {
+        let rolls = |seed| {
+            let core = Core::new(&Options{seed: Some(seed), .. Options::default()}).unwrap();
+            let tank = core.state.unit(&UnitId{id: 0});
+            let soldier = core.state.unit(&UnitId{id: 12});
+            (0 .. 20).map(|_| core.get_killed_count(tank, soldier)).collect::<Vec<_>>()
+        };
+        assert_eq!(rolls(1), rolls(1));
+        assert!(rolls(1) != rolls(2));
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_bot_game() {
+        let play = |seed| {
+            let mut bots = HashMap::new();
+            bots.insert(PlayerId{id: 0}, BotKind::Builtin);
+            bots.insert(PlayerId{id: 1}, BotKind::Builtin);
+            let mut core = Core::new(&Options {
+                bots: bots,
+                ai_difficulty: AiDifficulty::Easy,
+                observer: true,
+                seed: Some(seed),
+                .. Options::default()
+            }).unwrap();
+            for _ in 0 .. 4 {
+                core.play_bot_turn();
+            }
+            let mut events = Vec::new();
+            while let Some(event) = core.get_observer_event(&ObserverView::God) {
+                events.push(event_to_string(&core.db, &event));
+            }
+            events
+        };
+        assert_eq!(play(3), play(3));
+    }
+
+    #[test]
+    fn test_observer_views() {
+        let db = Db::new();
+        let mut core = Core::new(&Options{observer: true, .. Options::default()}).unwrap();
+        core.do_command(Command::EndTurn);
+        let scenario = core.scenario().clone();
+        let mut god_state = PartialState::new_god_view(&scenario);
+        while let Some(event) = core.get_observer_event(&ObserverView::God) {
+            god_state.apply_event(&db, &event);
+        }
+        assert_eq!(god_state.units().len(), core.state.units().len());
+        assert!(god_state.is_tile_visible(&MapPos{v: Vector2{x: 9, y: 7}}));
+        for player_id in &[PlayerId{id: 0}, PlayerId{id: 1}] {
+            let view = ObserverView::Player(player_id.clone());
+            let mut observer_state = PartialState::from_scenario(&scenario, player_id);
+            while let Some(event) = core.get_observer_event(&view) {
+                observer_state.apply_event(&db, &event);
+            }
+            let mut player_state = PartialState::from_scenario(&scenario, player_id);
+            while let Some(event) = core.get_player_event(player_id) {
+                player_state.apply_event(&db, &event);
+            }
+            assert!(observer_state.units().len() < god_state.units().len());
+            assert_eq!(observer_state.units().len(), player_state.units().len());
+        }
+    }
+
+    #[test]
+    fn test_unread_observer_view_is_closed() {
+        let mut core = Core::new(&Options{observer: true, .. Options::default()}).unwrap();
+        let view = ObserverView::Player(PlayerId{id: 0});
+        let mut turns = 0;
+        while core.is_observer_view_open(&view) {
+            assert!(turns <= MAX_OBSERVER_EVENTS, "Unread view is never closed");
+            core.do_command(Command::EndTurn);
+            while core.get_observer_event(&ObserverView::God).is_some() {}
+            turns += 1;
+        }
+        assert!(core.get_observer_event(&view).is_none());
+        assert!(core.is_observer_view_open(&ObserverView::God));
+        assert!(core.is_observer_enabled());
+    }
+
+    fn assault_command(attacker_id: &UnitId, defender_id: &UnitId) -> Command {
+        Command::Assault {
+            attacker_id: attacker_id.clone(),
+            defender_id: defender_id.clone(),
+        }
+    }
+
+    #[test]
+    fn test_bad_assaults() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let pos = MapPos{v: Vector2{x: 4, y: 0}};
+        let soldier_id = add_unit(&mut core, &pos, "soldier", 0);
+        let friend_id = add_unit(&mut core, &MapPos{v: Vector2{x: 5, y: 0}}, "soldier", 0);
+        let enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 4, y: 1}}, "soldier", 1);
+        let far_enemy_id = add_unit(&mut core, &MapPos{v: Vector2{x: 7, y: 0}}, "soldier", 1);
+        let tank_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 0}}, "medium_tank", 0);
+        let truck_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 1}}, "truck", 0);
+        let passenger_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 1}}, "soldier", 0);
+        load_unit(&mut core, &truck_id, &passenger_id);
+        assert!(check_command(
+            &core.db, &core.state, &assault_command(&soldier_id, &enemy_id)).is_ok());
+        let command = assault_command(&soldier_id, &friend_id);
+        match check_command(&core.db, &core.state, &command) {
+            Err(CommandError::FriendlyFire) => {},
+            _ => panic!("Can`t assault own units"),
+        }
+        let command = assault_command(&passenger_id, &enemy_id);
+        match check_command(&core.db, &core.state, &command) {
+            Err(CommandError::UnitIsLoaded) => {},
+            _ => panic!("Loaded units can`t assault"),
+        }
+        let command = assault_command(&tank_id, &enemy_id);
+        match check_command(&core.db, &core.state, &command) {
+            Err(CommandError::BadAttackerClass) => {},
+            _ => panic!("Only infantry can assault"),
+        }
+        let command = assault_command(&soldier_id, &far_enemy_id);
+        match check_command(&core.db, &core.state, &command) {
+            Err(CommandError::OutOfRange) => {},
+            _ => panic!("Only adjacent enemies can be assaulted"),
+        }
+    }
+
+    /// The loser retreats or dies and the winner holds or takes the hex
+    #[test]
+    fn test_assault() {
+        for seed in 0 .. 20 {
+            let mut core = Core::new(&Options{seed: Some(seed), .. Options::default()}).unwrap();
+            let attacker_pos = MapPos{v: Vector2{x: 4, y: 0}};
+            let defender_pos = MapPos{v: Vector2{x: 4, y: 1}};
+            let attacker_id = add_unit(&mut core, &attacker_pos, "soldier", 0);
+            let defender_id = add_unit(&mut core, &defender_pos, "soldier", 1);
+            core.simulation_step(assault_command(&attacker_id, &defender_id)).unwrap();
+            let is_defender_beaten = match core.state.units().get(&defender_id) {
+                Some(defender) => defender.pos.map_pos != defender_pos,
+                None => true,
+            };
+            let is_attacker_beaten = match core.state.units().get(&attacker_id) {
+                Some(attacker) => distance(&attacker.pos.map_pos, &defender_pos) > 1,
+                None => true,
+            };
+            assert!(is_defender_beaten != is_attacker_beaten);
+            if is_defender_beaten {
+                let attacker = core.state.unit(&attacker_id);
+                assert_eq!(attacker.pos.map_pos, defender_pos);
+            }
+        }
+    }
+
+    /// A cornered loser can`t retreat and is destroyed
+    #[test]
+    fn test_assault_without_retreat_pos() {
+        let corner_pos = MapPos{v: Vector2{x: 9, y: 7}};
+        let attacker_pos = MapPos{v: Vector2{x: 8, y: 7}};
+        let exit_pos = MapPos{v: Vector2{x: 9, y: 6}};
+        for seed in 0 .. 20 {
+            let mut core = Core::new(&Options{seed: Some(seed), .. Options::default()}).unwrap();
+            let soldier_type_id = core.db.unit_type_id("soldier");
+            while get_free_exact_pos(
+                &core.db, &core.state, &soldier_type_id, &exit_pos).is_some()
+            {
+                add_unit(&mut core, &exit_pos, "soldier", 1);
+            }
+            let attacker_id = add_unit(&mut core, &attacker_pos, "soldier", 0);
+            let defender_id = add_unit(&mut core, &corner_pos, "soldier", 1);
+            assert!(core.get_retreat_pos(
+                core.state.unit(&defender_id), &attacker_pos).is_none());
+            core.simulation_step(assault_command(&attacker_id, &defender_id)).unwrap();
+            let is_defender_removed = core.state.units().get(&defender_id).is_none();
+            let is_attacker_beaten = match core.state.units().get(&attacker_id) {
+                Some(attacker) => distance(&attacker.pos.map_pos, &corner_pos) > 1,
+                None => true,
+            };
+            let is_defender_holding = core.state.units().get(&defender_id)
+                .map_or(false, |defender| defender.pos.map_pos == corner_pos);
+            assert!(is_defender_removed != (is_attacker_beaten && is_defender_holding));
+        }
+    }
+}
+
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
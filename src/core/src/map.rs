@@ -5,9 +5,10 @@ use std::iter::{repeat};
 use cgmath::{Vector2, Array};
 use types::{Size2, ZInt};
 use dir::{Dir, DirIter, dirs};
+use hex;
 use ::{MapPos};
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Terrain {
     Plain,
     Trees,
@@ -18,6 +19,7 @@ impl Default for Terrain {
     fn default() -> Terrain { Terrain::Plain }
 }
 
+#[derive(Clone)]
 pub struct Map<T> {
     tiles: Vec<T>,
     size: Size2,
@@ -194,11 +196,7 @@ impl Iterator for SpiralIter {
 }
 
 pub fn distance<P: AsRef<MapPos>>(from: &P, to: &P) -> ZInt {
-    let to = to.as_ref().v;
-    let from = from.as_ref().v;
-    let dx = (to.x + to.y / 2) - (from.x + from.y / 2);
-    let dy = to.y - from.y;
-    (dx.abs() + dy.abs() + (dx - dy).abs()) / 2
+    hex::distance(from.as_ref(), to.as_ref())
 }
 
 #[cfg(test)]
@@ -0,0 +1,524 @@
+// See LICENSE file for copyright and license details.
+
+//! Random maps
+//!
+//! The map is built from `MapGenOptions::seed` so the same options
+//! always give the same map. Towns are placed first, then the forests
+//! grow on the rest of the plain tiles. The first player deploys
+//! at the west edge of the map, the second one at the east edge.
+//!
+//! Rows of `MapPos` are shifted by half a tile so symmetric layouts
+//! are built in cube coordinates, see `symmetric_pos`.
+//! A map is accepted only if a vehicle can drive from one deployment
+//! zone to the other one.
+
+use rand::{Rng, XorShiftRng};
+use cgmath::{Vector2};
+use types::{Size2, ZInt};
+use db::{Db};
+use map::{Map, Terrain, distance};
+use dir::{Dir};
+use hex::{Cube};
+use unit::{Unit};
+use internal_state::{InternalState};
+use game_state::{GameState};
+use pathfinder::{Pathfinder, max_cost};
+use ::{
+    MapPos,
+    ExactPos,
+    SlotId,
+    Object,
+    ObjectClass,
+    UnitId,
+    PlayerId,
+    ReactionFireMode,
+    MAX_GROUND_SLOTS_COUNT,
+    get_free_exact_pos,
+    seeded_rng,
+};
+
+/// Maps that fail the connectivity check are generated again
+const MAX_ATTEMPTS: ZInt = 10;
+
+/// One of this number of town tiles gets a big building
+const BIG_BUILDING_CHANCE: ZInt = 5;
+
+const OBJECTIVES_COUNT: usize = 3;
+
+/// Symmetric layouts give both players the same terrain
+#[derive(Clone, PartialEq, Debug)]
+pub enum Symmetry {
+    None,
+
+    /// West half is reflected to the east half
+    Mirror,
+
+    /// Map is rotated by 180 degrees around its center
+    Point,
+}
+
+#[derive(Clone)]
+pub struct MapGenOptions {
+    pub size: Size2,
+
+    /// Percent of plain tiles that are covered with trees
+    pub forest_density: ZInt,
+
+    /// Symmetric layouts place towns in pairs
+    /// so an odd number is rounded up
+    pub towns_count: ZInt,
+
+    /// Number of tiles of every town
+    pub town_size: ZInt,
+
+    pub symmetry: Symmetry,
+    pub seed: u64,
+}
+
+impl Default for MapGenOptions {
+    fn default() -> MapGenOptions {
+        MapGenOptions {
+            size: Size2{w: 10, h: 8},
+            forest_density: 15,
+            towns_count: 2,
+            town_size: 4,
+            symmetry: Symmetry::Point,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GeneratedMap {
+    pub map: Map<Terrain>,
+    pub objects: Vec<Object>,
+
+    /// Tiles where the units of the player can be placed,
+    /// the index is the id of the player
+    pub deployment_zones: Vec<Vec<MapPos>>,
+
+    pub objectives: Vec<MapPos>,
+}
+
+impl GeneratedMap {
+    pub fn to_state(&self) -> InternalState {
+        InternalState::with_map(self.map.clone(), &self.objects)
+    }
+}
+
+fn corner(x: ZInt, y: ZInt) -> Cube {
+    Cube::from_map_pos(&MapPos{v: Vector2{x: x, y: y}})
+}
+
+/// Tile that gets the same terrain as `pos`.
+///
+/// The board is reflected or rotated as a whole so distances between
+/// the tiles are kept. Rows are shifted by half a tile so in every
+/// other row one edge tile has its image off the board: it is `None`
+/// and that tile stays unpaired.
+pub fn symmetric_pos(size: &Size2, symmetry: &Symmetry, pos: &MapPos) -> Option<MapPos> {
+    let cube = Cube::from_map_pos(pos);
+    let origin = Cube{x: 0, y: 0, z: 0};
+    let image = match *symmetry {
+        Symmetry::None => return Some(pos.clone()),
+        // west end of the first row swaps places with its east end
+        Symmetry::Mirror => cube.reflect_east_west(&origin)
+            + corner(size.w - 1, 0),
+        // north-west corner swaps places with the south-east one
+        Symmetry::Point => cube.rotate(&origin, 3)
+            + corner(0, 0)
+            + corner(size.w - 1, size.h - 1),
+    }.to_map_pos();
+    let v = image.v;
+    if v.x >= 0 && v.y >= 0 && v.x < size.w && v.y < size.h {
+        Some(image)
+    } else {
+        None
+    }
+}
+
+fn deployment_depth(size: &Size2) -> ZInt {
+    (size.w / 5).max(1)
+}
+
+/// West zone of a symmetric layout is the image of the east one
+fn deployment_zones(size: &Size2, symmetry: &Symmetry) -> Vec<Vec<MapPos>> {
+    let depth = deployment_depth(size);
+    let mut west = Vec::new();
+    let mut east = Vec::new();
+    for y in 0 .. size.h {
+        for x in 0 .. depth {
+            west.push(MapPos{v: Vector2{x: x, y: y}});
+            east.push(MapPos{v: Vector2{x: size.w - 1 - x, y: y}});
+        }
+    }
+    if *symmetry != Symmetry::None {
+        west = east.iter()
+            .map(|pos| symmetric_pos(size, symmetry, pos)
+                .expect("Deployment zone must be symmetric"))
+            .collect();
+    }
+    vec![west, east]
+}
+
+struct Generator<'a> {
+    options: &'a MapGenOptions,
+    deployment_zones: &'a [Vec<MapPos>],
+    rng: &'a mut XorShiftRng,
+    map: Map<Terrain>,
+    objects: Vec<Object>,
+}
+
+impl<'a> Generator<'a> {
+    fn image(&self, pos: &MapPos) -> Option<MapPos> {
+        symmetric_pos(&self.options.size, &self.options.symmetry, pos)
+    }
+
+    /// Every pair of symmetric tiles is visited once,
+    /// unpaired tiles are never visited and stay plain
+    fn is_first_of_pair(&self, pos: &MapPos) -> bool {
+        match self.image(pos) {
+            Some(image) => (pos.v.y, pos.v.x) <= (image.v.y, image.v.x),
+            None => false,
+        }
+    }
+
+    fn is_deployment_zone(&self, pos: &MapPos) -> bool {
+        self.deployment_zones.iter().any(|zone| zone.contains(pos))
+    }
+
+    fn set_terrain(&mut self, pos: &MapPos, terrain: Terrain) {
+        if let Some(image) = self.image(pos) {
+            *self.map.tile_mut(&image) = terrain.clone();
+        }
+        *self.map.tile_mut(pos) = terrain;
+    }
+
+    fn random_pos(&mut self) -> MapPos {
+        let size = self.options.size.clone();
+        MapPos{v: Vector2 {
+            x: self.rng.gen_range(0, size.w),
+            y: self.rng.gen_range(0, size.h),
+        }}
+    }
+
+    fn can_build_town_at(&self, pos: &MapPos) -> bool {
+        self.map.is_inboard(pos)
+            && self.image(pos).is_some()
+            && !self.is_deployment_zone(pos)
+            && *self.map.tile(pos) != Terrain::City
+    }
+
+    fn add_town(&mut self) {
+        let max_tries = self.options.town_size * 10;
+        let mut tiles = Vec::new();
+        for _ in 0 .. max_tries {
+            let pos = self.random_pos();
+            if self.can_build_town_at(&pos) {
+                tiles.push(pos);
+                break;
+            }
+        }
+        for _ in 0 .. max_tries {
+            if tiles.is_empty() || tiles.len() as ZInt >= self.options.town_size {
+                break;
+            }
+            let pos = self.rng.choose(&tiles).unwrap().clone();
+            let dir = Dir::from_int(self.rng.gen_range(0, 6));
+            let pos = Dir::get_neighbour_pos(&pos, &dir);
+            if self.can_build_town_at(&pos) && !tiles.contains(&pos) {
+                tiles.push(pos);
+            }
+        }
+        for pos in &tiles {
+            self.set_terrain(pos, Terrain::City);
+        }
+    }
+
+    fn add_buildings(&mut self, pos: &MapPos) {
+        let slot_ids = if self.rng.gen_range(0, BIG_BUILDING_CHANCE) == 0 {
+            vec![SlotId::WholeTile]
+        } else {
+            let count = self.rng.gen_range(1, MAX_GROUND_SLOTS_COUNT + 1);
+            (0 .. count).map(|i| SlotId::Id(i as u8)).collect()
+        };
+        let positions = match self.image(pos) {
+            Some(ref image) if image != pos => vec![pos.clone(), image.clone()],
+            _ => vec![pos.clone()],
+        };
+        for map_pos in positions {
+            for slot_id in &slot_ids {
+                self.objects.push(Object {
+                    class: ObjectClass::Building,
+                    pos: ExactPos{map_pos: map_pos.clone(), slot_id: slot_id.clone()},
+                });
+            }
+        }
+    }
+
+    fn generate(&mut self) {
+        self.map = Map::new(&self.options.size);
+        self.objects.clear();
+        let towns_count = match self.options.symmetry {
+            Symmetry::None => self.options.towns_count,
+            Symmetry::Mirror | Symmetry::Point => (self.options.towns_count + 1) / 2,
+        };
+        for _ in 0 .. towns_count {
+            self.add_town();
+        }
+        for pos in self.map.get_iter() {
+            if !self.is_first_of_pair(&pos) {
+                continue;
+            }
+            if *self.map.tile(&pos) == Terrain::City {
+                self.add_buildings(&pos);
+            } else if self.rng.gen_range(0, 100) < self.options.forest_density {
+                self.set_terrain(&pos, Terrain::Trees);
+            }
+        }
+    }
+
+    /// Town tiles nearest to the center of the map
+    /// or just the center if there are no towns
+    fn objectives(&self) -> Vec<MapPos> {
+        let size = &self.options.size;
+        let center = MapPos{v: Vector2{x: size.w / 2, y: size.h / 2}};
+        let mut candidates: Vec<_> = self.map.get_iter()
+            .filter(|pos| *self.map.tile(pos) == Terrain::City)
+            .collect();
+        if candidates.is_empty() {
+            candidates.push(center.clone());
+        }
+        candidates.sort_by_key(|pos| (distance(pos, &center), pos.v.y, pos.v.x));
+        let mut objectives = Vec::new();
+        for pos in candidates {
+            if objectives.len() >= OBJECTIVES_COUNT {
+                break;
+            }
+            let image = self.image(&pos);
+            for pos in Some(pos).into_iter().chain(image) {
+                if !objectives.contains(&pos) {
+                    objectives.push(pos);
+                }
+            }
+        }
+        objectives
+    }
+}
+
+/// Unit that is only used to ask the pathfinder
+fn probe_unit(db: &Db, pos: ExactPos) -> Unit {
+    let type_id = db.unit_type_id("medium_tank");
+    let unit_type = db.unit_type(&type_id);
+    Unit {
+        id: UnitId{id: 0},
+        pos: pos,
+        player_id: PlayerId{id: 0},
+        type_id: type_id.clone(),
+        move_points: unit_type.move_points.clone(),
+        attack_points: unit_type.attack_points.clone(),
+        reactive_attack_points: None,
+        reaction_fire_mode: ReactionFireMode::Normal,
+        count: unit_type.count,
+        morale: 100,
+        passenger_ids: Vec::new(),
+        towed_unit_id: None,
+        is_contact: false,
+    }
+}
+
+/// Can a vehicle get from any deployment zone to all the other ones?
+pub fn is_connected(db: &Db, state: &InternalState, zones: &[Vec<MapPos>]) -> bool {
+    let type_id = db.unit_type_id("medium_tank");
+    let mut pathfinder = Pathfinder::new(state.map().size());
+    for zone in zones {
+        let start_pos = zone.iter()
+            .filter_map(|pos| get_free_exact_pos(db, state, &type_id, pos))
+            .next();
+        let start_pos = match start_pos {
+            Some(pos) => pos,
+            None => return false,
+        };
+        pathfinder.fill_map(db, state, &probe_unit(db, start_pos));
+        for other_zone in zones {
+            let is_reachable = other_zone.iter().any(|pos| {
+                pathfinder.get_map().tile(pos).cost().n != max_cost().n
+            });
+            if !is_reachable {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// `None` if no connected map is found in a few attempts
+pub fn generate(db: &Db, options: &MapGenOptions) -> Option<GeneratedMap> {
+    let mut rng = seeded_rng(options.seed);
+    let zones = deployment_zones(&options.size, &options.symmetry);
+    let mut generator = Generator {
+        options: options,
+        deployment_zones: &zones,
+        rng: &mut rng,
+        map: Map::new(&options.size),
+        objects: Vec::new(),
+    };
+    for _ in 0 .. MAX_ATTEMPTS {
+        generator.generate();
+        let map = GeneratedMap {
+            map: generator.map.clone(),
+            objects: generator.objects.clone(),
+            deployment_zones: zones.clone(),
+            objectives: generator.objectives(),
+        };
+        if is_connected(db, &map.to_state(), &zones) {
+            return Some(map);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{Size2};
+    use db::{Db};
+    use map::{Map, Terrain, distance};
+    use game_state::{GameState};
+    use ::{MapPos, SlotId};
+    use super::{MapGenOptions, GeneratedMap, Symmetry, generate, symmetric_pos};
+
+    fn generate_ok(db: &Db, options: &MapGenOptions) -> GeneratedMap {
+        generate(db, options).expect("Can`t generate a connected map")
+    }
+
+    #[test]
+    fn test_same_seed_same_map() {
+        let db = Db::new();
+        let options = MapGenOptions{seed: 42, .. MapGenOptions::default()};
+        let a = generate_ok(&db, &options);
+        let b = generate_ok(&db, &options);
+        for pos in a.map.get_iter() {
+            assert!(a.map.tile(&pos) == b.map.tile(&pos));
+        }
+        assert_eq!(a.objects.len(), b.objects.len());
+        assert_eq!(a.objectives, b.objectives);
+    }
+
+    fn check_symmetric_pos(size: &Size2, symmetry: &Symmetry) {
+        let pairs: Vec<_> = Map::<Terrain>::new(size).get_iter()
+            .filter_map(|pos| {
+                symmetric_pos(size, symmetry, &pos).map(|image| (pos, image))
+            })
+            .collect();
+        for &(ref a, ref image_a) in &pairs {
+            assert_eq!(symmetric_pos(size, symmetry, image_a).as_ref(), Some(a));
+            for &(ref b, ref image_b) in &pairs {
+                assert_eq!(distance(a, b), distance(image_a, image_b));
+            }
+        }
+        let tiles_count = (size.w * size.h) as usize;
+        let unpaired_count = tiles_count - pairs.len();
+        if *symmetry == Symmetry::Point && size.h % 2 == 0 {
+            assert_eq!(unpaired_count, 0);
+        } else {
+            // one edge tile of every odd row
+            assert_eq!(unpaired_count, size.h as usize / 2);
+        }
+    }
+
+    #[test]
+    fn test_symmetric_layouts() {
+        let db = Db::new();
+        let sizes = [Size2{w: 16, h: 12}, Size2{w: 15, h: 11}];
+        for symmetry in &[Symmetry::Mirror, Symmetry::Point] {
+            for size in &sizes {
+                check_symmetric_pos(size, symmetry);
+            }
+            for seed in 0 .. 20 {
+                let options = MapGenOptions {
+                    size: sizes[seed as usize % sizes.len()].clone(),
+                    towns_count: 3,
+                    symmetry: symmetry.clone(),
+                    seed: seed,
+                    .. MapGenOptions::default()
+                };
+                let generated = generate_ok(&db, &options);
+                let state = generated.to_state();
+                for pos in generated.map.get_iter() {
+                    match symmetric_pos(&options.size, symmetry, &pos) {
+                        Some(image) => {
+                            assert!(generated.map.tile(&pos) == generated.map.tile(&image));
+                            assert_eq!(
+                                state.objects_at(&pos).len(),
+                                state.objects_at(&image).len());
+                        },
+                        None => {
+                            assert!(*generated.map.tile(&pos) == Terrain::Plain);
+                            assert!(state.objects_at(&pos).is_empty());
+                        },
+                    }
+                }
+                for pos in &generated.objectives {
+                    let image = symmetric_pos(&options.size, symmetry, pos)
+                        .expect("Objective must have a pair");
+                    assert!(generated.objectives.contains(&image));
+                }
+                let zones = &generated.deployment_zones;
+                assert_eq!(zones[0].len(), zones[1].len());
+                for pos in &zones[0] {
+                    let image = symmetric_pos(&options.size, symmetry, pos).unwrap();
+                    assert!(zones[1].contains(&image));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generated_maps_are_playable() {
+        let db = Db::new();
+        for seed in 0 .. 30 {
+            let options = MapGenOptions {
+                size: Size2{w: 20, h: 14},
+                forest_density: 40,
+                towns_count: 5,
+                town_size: 8,
+                symmetry: Symmetry::None,
+                seed: seed,
+            };
+            let generated = generate_ok(&db, &options);
+            let state = generated.to_state();
+            assert_eq!(generated.deployment_zones.len(), 2);
+            for zone in &generated.deployment_zones {
+                assert!(!zone.is_empty());
+                for pos in zone {
+                    assert!(*generated.map.tile(pos) != Terrain::City);
+                    assert!(state.objects_at(pos).is_empty());
+                }
+            }
+            assert!(!generated.objectives.is_empty());
+            for object in state.objects().values() {
+                assert!(*generated.map.tile(&object.pos) == Terrain::City);
+                if object.pos.slot_id == SlotId::WholeTile {
+                    assert_eq!(state.objects_at(&object.pos.map_pos).len(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_towns() {
+        let db = Db::new();
+        let options = MapGenOptions {
+            towns_count: 0,
+            forest_density: 0,
+            symmetry: Symmetry::None,
+            .. MapGenOptions::default()
+        };
+        let generated = generate_ok(&db, &options);
+        assert!(generated.objects.is_empty());
+        assert_eq!(generated.objectives, vec![MapPos{v: Vector2{x: 5, y: 4}}]);
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -0,0 +1,690 @@
+// See LICENSE file for copyright and license details.
+
+/// Monte-Carlo tree search AI
+///
+/// Every `get_command` call searches through sequences of commands for
+/// the current turn. Each iteration replays a branch of the tree on
+/// a new `Sample` of the state with newly rolled dice so the same node
+/// collects rewards of different outcomes of its attacks and of
+/// different guesses about enemies that are out of sight.
+
+use std::collections::{HashSet};
+use std::time::{Duration, Instant};
+use rand::{Rng, XorShiftRng};
+use types::{ZInt, ZFloat};
+use game_state::{GameState, GameStateMut};
+use internal_state::{InternalState};
+use partial_state::{PartialState};
+use map::{distance};
+use pathfinder::{Pathfinder, tile_cost};
+use unit::{Unit};
+use db::{Db};
+use bot::{Bot};
+use ai::{Ai};
+use scenario::{Scenario};
+use ::{
+    CoreEvent,
+    Command,
+    AttackInfo,
+    FireMode,
+    MoveMode,
+    MovePoints,
+    PlayerId,
+    UnitId,
+    UnitInfo,
+    ExactPos,
+    VisionSharing,
+    BASE_SUPPRESSION,
+    PER_DEATH_SUPPRESSION,
+    check_attack,
+    check_command,
+    cover_bonus,
+    get_attack_odds,
+    get_free_exact_pos,
+    is_target_spotted,
+    is_towed,
+    los,
+    move_cost_modifier,
+    roll_killed_count,
+    seeded_rng,
+};
+
+/// Exploration constant of UCT
+const EXPLORATION: ZFloat = 1.0;
+
+/// Only the most promising destinations of every unit are tried
+const MOVES_PER_UNIT: usize = 3;
+
+const MAX_TREE_DEPTH: ZInt = 8;
+
+/// Max number of random attacks after a new node of the tree
+const ROLLOUT_LENGTH: ZInt = 4;
+
+const THREAT_WEIGHT: ZFloat = 0.5;
+const COVER_WEIGHT: ZFloat = 0.05;
+
+struct Node {
+    /// Command that leads to this node, `None` for the root
+    command: Option<Command>,
+    visits: ZInt,
+    total_reward: ZFloat,
+    is_expanded: bool,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(command: Option<Command>) -> Node {
+        Node {
+            command: command,
+            visits: 0,
+            total_reward: 0.0,
+            is_expanded: false,
+            children: Vec::new(),
+        }
+    }
+
+    fn is_terminal(&self) -> bool {
+        matches!(self.command, Some(Command::EndTurn))
+    }
+
+    fn uct(&self, parent_visits: ZInt) -> ZFloat {
+        let mean = self.total_reward / self.visits as ZFloat;
+        let ln = (parent_visits as ZFloat).ln();
+        mean + EXPLORATION * (ln / self.visits as ZFloat).sqrt()
+    }
+}
+
+/// Copy of the known state with a guess about the unknown
+struct Sample {
+    state: InternalState,
+
+    /// Reactive attack points of enemies are unknown
+    /// so every enemy is supposed to have one reaction shot
+    reacted_ids: HashSet<UnitId>,
+}
+
+/// Searches are reproducible with the same seed
+/// if they are stopped by `max_iterations` and not by `max_time`
+pub struct MctsAi {
+    id: PlayerId,
+    state: PartialState,
+    pathfinder: Pathfinder,
+    vision_sharing: VisionSharing,
+    max_iterations: ZInt,
+    max_time: Duration,
+    rng: XorShiftRng,
+
+    /// Units that got a rejected command during this turn
+    stuck_unit_ids: HashSet<UnitId>,
+
+    /// Search is only good at fights so
+    /// this AI decides what to do when no enemies are visible
+    fallback: Ai,
+}
+
+impl MctsAi {
+    pub fn new(
+        id: &PlayerId,
+        scenario: &Scenario,
+        vision_sharing: &VisionSharing,
+        max_iterations: ZInt,
+        max_time_ms: u64,
+        seed: u64,
+        fallback: Ai,
+    ) -> MctsAi {
+        MctsAi {
+            id: id.clone(),
+            state: PartialState::from_scenario(scenario, id),
+            pathfinder: Pathfinder::new(scenario.size()),
+            vision_sharing: vision_sharing.clone(),
+            max_iterations: max_iterations,
+            max_time: Duration::from_millis(max_time_ms),
+            rng: seeded_rng(seed),
+            stuck_unit_ids: HashSet::new(),
+            fallback: fallback,
+        }
+    }
+
+    fn is_enemy_visible(&self) -> bool {
+        self.state.units().values().any(|unit| unit.player_id != self.id)
+    }
+
+    fn own_unit_ids(&self, state: &InternalState) -> Vec<UnitId> {
+        let mut ids = Vec::new();
+        for (id, unit) in state.units() {
+            if unit.player_id != self.id || self.stuck_unit_ids.contains(id) {
+                continue;
+            }
+            let is_passenger = state.units().values()
+                .any(|other| other.passenger_ids.contains(id));
+            if !is_passenger && !is_towed(state, id) {
+                ids.push(id.clone());
+            }
+        }
+        // `HashMap` order is random and the tree must be reproducible
+        ids.sort();
+        ids
+    }
+
+    fn is_target_spotted(&self, db: &Db, state: &InternalState, unit: &Unit, target: &Unit) -> bool {
+        let unit_type = db.unit_type(&unit.type_id);
+        if !db.weapon_type(&unit_type.weapon_type_id).is_inderect {
+            return true;
+        }
+        match self.vision_sharing {
+            VisionSharing::Full => true,
+            VisionSharing::CommandNet => is_target_spotted(db, state, unit, target),
+        }
+    }
+
+    fn get_attack_commands(&self, db: &Db, state: &InternalState) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for unit_id in self.own_unit_ids(state) {
+            let unit = state.unit(&unit_id);
+            if unit.attack_points.n <= 0 {
+                continue;
+            }
+            let mut target_ids: Vec<_> = state.units().values()
+                .filter(|target| target.player_id != self.id)
+                .filter(|target| self.is_target_spotted(db, state, unit, target))
+                .map(|target| target.id.clone())
+                .collect();
+            target_ids.sort();
+            for target_id in target_ids {
+                let command = Command::AttackUnit {
+                    attacker_id: unit_id.clone(),
+                    defender_id: target_id,
+                };
+                if check_command(db, state, &command).is_ok() {
+                    commands.push(command);
+                }
+            }
+        }
+        commands
+    }
+
+    fn distance_to_nearest_enemy(&self, state: &InternalState, pos: &ExactPos) -> ZInt {
+        state.units().values()
+            .filter(|unit| unit.player_id != self.id)
+            .map(|unit| distance(&unit.pos.map_pos, &pos.map_pos))
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Closest to enemies destinations and the one with the best cover
+    fn get_move_commands(&mut self, db: &Db, state: &InternalState) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for unit_id in self.own_unit_ids(state) {
+            let unit = state.unit(&unit_id);
+            if unit.move_points.n <= 0 {
+                continue;
+            }
+            self.pathfinder.fill_map(db, state, unit);
+            let mut destinations = Vec::new();
+            for map_pos in state.map().get_iter() {
+                let tile = self.pathfinder.get_map().tile(&map_pos);
+                if tile.cost().n == 0 || tile.cost().n > unit.move_points.n {
+                    continue;
+                }
+                destinations.push(ExactPos {
+                    map_pos: map_pos.clone(),
+                    slot_id: tile.slot_id().clone(),
+                });
+            }
+            destinations.sort_by_key(|pos| self.distance_to_nearest_enemy(state, pos));
+            let best_cover_pos = destinations.iter().max_by_key(|pos| {
+                let mut stub = unit.clone();
+                stub.pos = (*pos).clone();
+                cover_bonus(db, state, &stub)
+            }).cloned();
+            destinations.truncate(MOVES_PER_UNIT);
+            if let Some(pos) = best_cover_pos {
+                if !destinations.contains(&pos) {
+                    destinations.push(pos);
+                }
+            }
+            for destination in &destinations {
+                let path = match self.pathfinder.get_path(destination) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let command = Command::Move {
+                    unit_id: unit_id.clone(),
+                    path: path,
+                    mode: MoveMode::Fast,
+                };
+                if check_command(db, state, &command).is_ok() {
+                    commands.push(command);
+                }
+            }
+        }
+        commands
+    }
+
+    fn get_commands(&mut self, db: &Db, state: &InternalState) -> Vec<Command> {
+        let mut commands = self.get_attack_commands(db, state);
+        commands.extend(self.get_move_commands(db, state));
+        commands.push(Command::EndTurn);
+        commands
+    }
+
+    /// New copy of the known state with some of the recently seen
+    /// enemies put back to their last known positions: the older
+    /// the memory the less likely the enemy is still there
+    fn sample(&mut self, db: &Db) -> Sample {
+        let mut state = self.state.internal_state().clone();
+        let mut ghost_ids: Vec<_> = self.state.ghosts().keys().cloned().collect();
+        ghost_ids.sort();
+        for ghost_id in ghost_ids {
+            let ghost = &self.state.ghosts()[&ghost_id];
+            let age = self.state.turn() - ghost.turn;
+            if !self.rng.gen_weighted_bool(age as u32 + 1) {
+                continue;
+            }
+            let pos = match get_free_exact_pos(
+                db, &state, &ghost.type_id, &ghost.pos.map_pos)
+            {
+                Some(pos) => pos,
+                None => continue,
+            };
+            state.apply_event(db, &CoreEvent::ShowUnit {
+                unit_info: UnitInfo {
+                    unit_id: ghost_id.clone(),
+                    pos: pos,
+                    type_id: ghost.type_id.clone(),
+                    player_id: ghost.player_id.clone(),
+                    passenger_ids: Vec::new(),
+                    towed_unit_id: None,
+                    is_contact: ghost.is_contact,
+                },
+            });
+        }
+        Sample {
+            state: state,
+            reacted_ids: HashSet::new(),
+        }
+    }
+
+    /// Rolls the dice of an attack
+    fn attack_event(
+        &mut self,
+        db: &Db,
+        state: &InternalState,
+        attacker: &Unit,
+        defender: &Unit,
+        mode: &FireMode,
+    ) -> CoreEvent {
+        let mut killed = roll_killed_count(
+            db, state, attacker, defender, &mut self.rng);
+        let has_cargo = !defender.passenger_ids.is_empty()
+            || defender.towed_unit_id.is_some();
+        if killed >= defender.count && has_cargo {
+            // bailing out is not simulated
+            killed = defender.count - 1;
+        }
+        let attacker_type = db.unit_type(&attacker.type_id);
+        let weapon_type = db.weapon_type(&attacker_type.weapon_type_id);
+        CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(attacker.id.clone()),
+                defender_id: defender.id.clone(),
+                mode: mode.clone(),
+                killed: killed,
+                suppression: BASE_SUPPRESSION + PER_DEATH_SUPPRESSION * killed,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: weapon_type.is_inderect,
+                is_assault: false,
+            },
+        }
+    }
+
+    /// Enemies that see the unit shoot at it,
+    /// returns `false` if nobody has fired
+    fn simulate_reaction_fire(
+        &mut self,
+        db: &Db,
+        sample: &mut Sample,
+        unit_id: &UnitId,
+    ) -> bool {
+        let mut enemy_ids: Vec<_> = sample.state.units().values()
+            .filter(|enemy| enemy.player_id != self.id)
+            .filter(|enemy| !sample.reacted_ids.contains(&enemy.id))
+            .map(|enemy| enemy.id.clone())
+            .collect();
+        enemy_ids.sort();
+        let mut has_fired = false;
+        for enemy_id in enemy_ids {
+            let event = {
+                let unit = match sample.state.units().get(unit_id) {
+                    Some(unit) => unit,
+                    None => break,
+                };
+                let enemy = sample.state.unit(&enemy_id);
+                let enemy_type = db.unit_type(&enemy.type_id);
+                let weapon_type = db.weapon_type(&enemy_type.weapon_type_id);
+                let mut stub = enemy.clone();
+                stub.reactive_attack_points = Some(
+                    enemy_type.reactive_attack_points.clone());
+                if !weapon_type.reaction_fire
+                    || check_attack(
+                        db, &sample.state, &stub, unit, &FireMode::Reactive).is_err()
+                    || !los(&sample.state, enemy_type,
+                        &enemy.pos.map_pos, &unit.pos.map_pos)
+                {
+                    continue;
+                }
+                self.attack_event(db, &sample.state, enemy, unit, &FireMode::Reactive)
+            };
+            sample.reacted_ids.insert(enemy_id);
+            sample.state.apply_event(db, &event);
+            has_fired = true;
+        }
+        has_fired
+    }
+
+    /// Applies the command to the sample rolling the dice for attacks
+    /// and for reaction fire of the enemy
+    fn simulate(&mut self, db: &Db, sample: &mut Sample, command: &Command) {
+        match *command {
+            Command::Move{ref unit_id, ref path, ref mode} => {
+                for pos in path {
+                    let event = {
+                        let unit = sample.state.unit(unit_id);
+                        let cost = tile_cost(db, &sample.state, unit, pos).n
+                            * move_cost_modifier(mode);
+                        CoreEvent::Move {
+                            unit_id: unit_id.clone(),
+                            from: unit.pos.clone(),
+                            to: pos.clone(),
+                            mode: mode.clone(),
+                            cost: MovePoints{n: cost},
+                        }
+                    };
+                    sample.state.apply_event(db, &event);
+                    // `Core` stops a unit that is shot at
+                    if self.simulate_reaction_fire(db, sample, unit_id) {
+                        break;
+                    }
+                }
+            },
+            Command::AttackUnit{ref attacker_id, ref defender_id} => {
+                let event = {
+                    let attacker = sample.state.unit(attacker_id);
+                    let defender = sample.state.unit(defender_id);
+                    self.attack_event(
+                        db, &sample.state, attacker, defender, &FireMode::Active)
+                };
+                sample.state.apply_event(db, &event);
+                self.simulate_reaction_fire(db, sample, attacker_id);
+            },
+            _ => {},
+        }
+    }
+
+    /// Expected fraction of the unit that visible enemies destroy next turn
+    fn threat(&self, db: &Db, state: &InternalState, unit: &Unit) -> ZFloat {
+        let mut threat = 0.0;
+        for enemy in state.units().values() {
+            if enemy.player_id == self.id {
+                continue;
+            }
+            let enemy_type = db.unit_type(&enemy.type_id);
+            let weapon_type = db.weapon_type(&enemy_type.weapon_type_id);
+            let dist = distance(&enemy.pos.map_pos, &unit.pos.map_pos);
+            if dist > weapon_type.max_distance || dist < weapon_type.min_distance {
+                continue;
+            }
+            if !weapon_type.is_inderect
+                && !los(state, enemy_type, &enemy.pos.map_pos, &unit.pos.map_pos)
+            {
+                continue;
+            }
+            let odds = get_attack_odds(db, state, enemy, unit);
+            threat += odds.expected_killed / unit.count as ZFloat;
+        }
+        threat
+    }
+
+    /// Own strength minus strength of visible enemies
+    /// with penalties for exposed positions
+    fn evaluate(&self, db: &Db, state: &InternalState) -> ZFloat {
+        let mut score = 0.0;
+        for unit in state.units().values() {
+            let unit_type = db.unit_type(&unit.type_id);
+            let strength = unit.count as ZFloat / unit_type.count as ZFloat;
+            if unit.player_id == self.id {
+                score += strength;
+                score -= THREAT_WEIGHT * self.threat(db, state, unit);
+                score += COVER_WEIGHT * cover_bonus(db, state, unit) as ZFloat;
+            } else {
+                score -= strength;
+            }
+        }
+        score
+    }
+
+    fn rollout(&mut self, db: &Db, sample: &mut Sample) -> ZFloat {
+        for _ in 0 .. ROLLOUT_LENGTH {
+            let commands = self.get_attack_commands(db, &sample.state);
+            let command = match self.rng.choose(&commands) {
+                Some(command) => command.clone(),
+                None => break,
+            };
+            self.simulate(db, sample, &command);
+        }
+        self.evaluate(db, &sample.state)
+    }
+
+    /// Children that are possible in this sample of the state,
+    /// not yet visited ones go first
+    fn select_child(&mut self, db: &Db, node: &Node, state: &InternalState) -> Option<usize> {
+        let mut best = None;
+        let mut best_uct = 0.0;
+        for (i, child) in node.children.iter().enumerate() {
+            let command = child.command.as_ref().unwrap();
+            if check_command(db, state, command).is_err() {
+                continue;
+            }
+            if child.visits == 0 {
+                return Some(i);
+            }
+            let uct = child.uct(node.visits);
+            if best.is_none() || uct > best_uct {
+                best = Some(i);
+                best_uct = uct;
+            }
+        }
+        best
+    }
+
+    fn search_step(
+        &mut self,
+        db: &Db,
+        node: &mut Node,
+        sample: &mut Sample,
+        depth: ZInt,
+    ) -> ZFloat {
+        let reward = if node.is_terminal() || depth >= MAX_TREE_DEPTH {
+            self.evaluate(db, &sample.state)
+        } else if !node.is_expanded {
+            node.children = self.get_commands(db, &sample.state).into_iter()
+                .map(|command| Node::new(Some(command)))
+                .collect();
+            node.is_expanded = true;
+            self.rollout(db, sample)
+        } else {
+            match self.select_child(db, node, &sample.state) {
+                Some(i) => {
+                    let child = &mut node.children[i];
+                    let command = child.command.clone().unwrap();
+                    self.simulate(db, sample, &command);
+                    self.search_step(db, child, sample, depth + 1)
+                },
+                None => self.evaluate(db, &sample.state),
+            }
+        };
+        node.visits += 1;
+        node.total_reward += reward;
+        reward
+    }
+}
+
+impl Bot for MctsAi {
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
+        if let CoreEvent::EndTurn{ref new_id, ..}
+            | CoreEvent::EndPlanning{ref new_id, ..} = *event
+        {
+            if *new_id == self.id {
+                self.stuck_unit_ids.clear();
+            }
+        }
+        self.state.apply_event(db, event);
+        self.fallback.apply_event(db, event);
+    }
+
+    fn command_rejected(&mut self, command: &Command) {
+        let unit_id = match *command {
+            Command::Move{ref unit_id, ..} => unit_id,
+            Command::AttackUnit{ref attacker_id, ..} => attacker_id,
+            _ => return self.fallback.command_rejected(command),
+        };
+        self.stuck_unit_ids.insert(unit_id.clone());
+        self.fallback.command_rejected(command);
+    }
+
+    fn get_command(&mut self, db: &Db) -> Command {
+        if !self.is_enemy_visible() {
+            return self.fallback.get_command(db);
+        }
+        let start_time = Instant::now();
+        let mut root = Node::new(None);
+        for _ in 0 .. self.max_iterations {
+            if start_time.elapsed() > self.max_time {
+                break;
+            }
+            let mut sample = self.sample(db);
+            self.search_step(db, &mut root, &mut sample, 0);
+        }
+        let best_child = root.children.iter()
+            .filter(|child| child.visits > 0)
+            .max_by_key(|child| child.visits);
+        match best_child {
+            Some(child) => child.command.clone().unwrap(),
+            None => Command::EndTurn,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{ZInt};
+    use db::{Db};
+    use bot::{Bot};
+    use ai::{Ai};
+    use scenario::{Scenario};
+    use protocol::{command_to_string};
+    use ::{
+        CoreEvent,
+        Command,
+        MoveMode,
+        MovePoints,
+        UnitInfo,
+        PlayerId,
+        UnitId,
+        MapPos,
+        ExactPos,
+        SlotId,
+        AiDifficulty,
+        AiPosture,
+        VisionSharing,
+    };
+    use super::{MctsAi};
+
+    fn unit_info(db: &Db, id: ZInt, player_id: ZInt, x: ZInt, type_name: &str) -> UnitInfo {
+        UnitInfo {
+            unit_id: UnitId{id: id},
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: x, y: 1}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: db.unit_type_id(type_name),
+            player_id: PlayerId{id: player_id},
+            passenger_ids: Vec::new(),
+            towed_unit_id: None,
+            is_contact: false,
+        }
+    }
+
+    /// Player 1 has a tank that has already moved this turn
+    /// and sees a soldier of player 0
+    fn create_ai(db: &Db, seed: u64) -> MctsAi {
+        let player_id = PlayerId{id: 1};
+        let scenario = Scenario::default_scenario(db);
+        let fallback = Ai::new(
+            &player_id,
+            &scenario,
+            &VisionSharing::Full,
+            &AiDifficulty::Normal,
+            &AiPosture::Attack,
+            seed,
+        );
+        let mut ai = MctsAi::new(
+            &player_id, &scenario, &VisionSharing::Full, 2000, 10_000, seed, fallback);
+        ai.apply_event(db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 0},
+            new_id: player_id.clone(),
+        });
+        ai.apply_event(db, &CoreEvent::CreateUnit {
+            unit_info: unit_info(db, 0, 1, 1, "medium_tank"),
+        });
+        ai.apply_event(db, &CoreEvent::ShowUnit {
+            unit_info: unit_info(db, 1, 0, 3, "soldier"),
+        });
+        ai.apply_event(db, &CoreEvent::Move {
+            unit_id: UnitId{id: 0},
+            from: unit_info(db, 0, 1, 1, "medium_tank").pos,
+            to: unit_info(db, 0, 1, 2, "medium_tank").pos,
+            mode: MoveMode::Fast,
+            cost: MovePoints{n: 3},
+        });
+        ai
+    }
+
+    #[test]
+    fn test_mcts_attacks_visible_enemy() {
+        let db = Db::new();
+        let mut ai = create_ai(&db, 0);
+        match ai.get_command(&db) {
+            Command::AttackUnit{ref defender_id, ..} => {
+                assert_eq!(defender_id.id, 1);
+            },
+            _ => panic!("MCTS must attack"),
+        }
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_search() {
+        let db = Db::new();
+        let mut ai_1 = create_ai(&db, 42);
+        let mut ai_2 = create_ai(&db, 42);
+        // both forget the soldier that might still be there
+        for ai in &mut [&mut ai_1, &mut ai_2] {
+            ai.apply_event(&db, &CoreEvent::HideUnit{unit_id: UnitId{id: 1}});
+            ai.apply_event(&db, &CoreEvent::ShowUnit {
+                unit_info: unit_info(&db, 2, 0, 6, "soldier"),
+            });
+        }
+        for _ in 0 .. 3 {
+            let command_1 = ai_1.get_command(&db);
+            let command_2 = ai_2.get_command(&db);
+            assert_eq!(
+                command_to_string(&db, &command_1),
+                command_to_string(&db, &command_2),
+            );
+        }
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
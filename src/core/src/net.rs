@@ -0,0 +1,643 @@
+// See LICENSE file for copyright and license details.
+
+//! Client/server multiplayer over TCP
+//!
+//! The server owns the only `Core`, every client keeps just
+//! a `PartialState` built from the events that its player may see.
+//! Messages are lines of text, events and commands are written
+//! as described in `protocol`:
+//!
+//! ```text
+//! client -> server: hello <protocol_version>
+//! server -> client: welcome <player_id> <map_w> <map_h> full|command_net <turn_limit> <time_bank>
+//! server -> client: error <text>
+//! server -> client: event <event>
+//! server -> client: time <player_id> <time_left> <time_bank>
+//! client -> server: command <command>
+//! server -> client: rejected <text>
+//! ```
+//!
+//! Server answers `hello` with `welcome` or, if the version
+//! is not the same as its own, with `error` and closes the connection.
+//! Times are whole seconds or `none` if there is no limit. `welcome`
+//! tells the time control of the game, `time` is sent when the turn
+//! of a player starts if the game is timed.
+//! A `command` is rejected if it is not the turn of the client's
+//! player or if `Core` can't execute it. Clients can't use debug
+//! commands like `create_unit`. The server ends the turn
+//! of a player whose time is out, see `Options::time_control`.
+//! The game is over when any client disconnects.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, SocketAddr, ToSocketAddrs, Shutdown};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError, RecvTimeoutError};
+use std::collections::{HashMap};
+use std::thread;
+use std::time::{Duration, Instant};
+use types::{Size2};
+use db::{Db};
+use clock::{TimeControl, saturating_sub};
+use protocol::{
+    PROTOCOL_VERSION,
+    command_to_string,
+    event_to_string,
+    parse_command,
+    parse_event,
+};
+use ::{
+    Core,
+    Options,
+    Command,
+    CoreEvent,
+    PlayerClass,
+    PlayerId,
+    VisionSharing,
+};
+
+pub const DEFAULT_PORT: u16 = 7777;
+
+fn bad_data(text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text)
+}
+
+fn send(stream: &mut TcpStream, line: &str) -> io::Result<()> {
+    writeln!(stream, "{}", line)?;
+    stream.flush()
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    Ok(line.trim().to_owned())
+}
+
+/// Splits the line into a tag and the rest of the message
+fn split_tag(line: &str) -> (&str, &str) {
+    match line.find(' ') {
+        Some(i) => (&line[.. i], &line[i + 1 ..]),
+        None => (line, ""),
+    }
+}
+
+/// Forwards lines from the stream to the channel,
+/// `None` means that the connection is closed
+fn spawn_reader<T: Clone + Send + 'static>(
+    mut reader: BufReader<TcpStream>,
+    tag: T,
+    tx: Sender<(T, Option<String>)>,
+) {
+    thread::spawn(move || {
+        loop {
+            let line = read_line(&mut reader).ok();
+            let is_closed = line.is_none();
+            if tx.send((tag.clone(), line)).is_err() || is_closed {
+                return;
+            }
+        }
+    });
+}
+
+fn time_to_string(time: Option<Duration>) -> String {
+    match time {
+        Some(time) => time.as_secs().to_string(),
+        None => "none".to_owned(),
+    }
+}
+
+/// `None` if the token is bad, `Some(None)` if there is no limit
+fn parse_time(token: &str) -> Option<Option<Duration>> {
+    if token == "none" {
+        return Some(None);
+    }
+    token.parse().ok().map(|secs| Some(Duration::from_secs(secs)))
+}
+
+fn vision_sharing_to_str(vision_sharing: &VisionSharing) -> &'static str {
+    match *vision_sharing {
+        VisionSharing::Full => "full",
+        VisionSharing::CommandNet => "command_net",
+    }
+}
+
+pub struct Server {
+    core: Core,
+    listener: TcpListener,
+    streams: HashMap<PlayerId, TcpStream>,
+    rx: Receiver<(PlayerId, Option<String>)>,
+    tx: Sender<(PlayerId, Option<String>)>,
+}
+
+impl Server {
+    /// Clients know only the size of the map so the server
+    /// can`t play `Options::scenario`, the default one is used
+    pub fn new<A: ToSocketAddrs>(options: &Options, address: A) -> io::Result<Server> {
+        if options.scenario.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput, "Remote games can`t use custom scenarios"));
+        }
+        let (tx, rx) = channel();
+        let core = Core::new(options)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        Ok(Server {
+            core: core,
+            listener: TcpListener::bind(address)?,
+            streams: HashMap::new(),
+            rx: rx,
+            tx: tx,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Waits for a client for every human player,
+    /// players get ids in the order of connection
+    pub fn accept_clients(&mut self) -> io::Result<()> {
+        let player_ids: Vec<_> = self.core.players().iter()
+            .filter(|player| player.class == PlayerClass::Human)
+            .map(|player| player.id.clone())
+            .collect();
+        for player_id in player_ids {
+            loop {
+                let (stream, _) = self.listener.accept()?;
+                if let Ok(stream) = self.greet(stream, &player_id) {
+                    self.streams.insert(player_id, stream);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn greet(&self, mut stream: TcpStream, player_id: &PlayerId) -> io::Result<TcpStream> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let line = read_line(&mut reader)?;
+        if line != format!("hello {}", PROTOCOL_VERSION) {
+            let text = format!("error expected protocol version {}", PROTOCOL_VERSION);
+            send(&mut stream, &text)?;
+            return Err(bad_data(&line));
+        }
+        let map_size = self.core.map_size();
+        let time_control = self.core.time_control();
+        let welcome = format!("welcome {} {} {} {} {} {}",
+            player_id.id,
+            map_size.w,
+            map_size.h,
+            vision_sharing_to_str(self.core.vision_sharing()),
+            time_to_string(time_control.turn_limit),
+            time_to_string(time_control.time_bank),
+        );
+        send(&mut stream, &welcome)?;
+        spawn_reader(reader, player_id.clone(), self.tx.clone());
+        Ok(stream)
+    }
+
+    fn send_events(&mut self) -> io::Result<()> {
+        for (player_id, stream) in &mut self.streams {
+            while let Some(event) = self.core.get_player_event(player_id) {
+                let line = event_to_string(self.core.db(), &event);
+                send(stream, &format!("event {}", line))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Clients count the time down by themselves, the server
+    /// only tells them how much is left when a new turn starts
+    fn send_time(&mut self) -> io::Result<()> {
+        if !self.core.time_control().is_enabled() {
+            return Ok(());
+        }
+        let player_id = self.core.player_id().clone();
+        let line = format!("time {} {} {}",
+            player_id.id,
+            time_to_string(self.core.time_left(&player_id)),
+            time_to_string(self.core.time_bank(&player_id)),
+        );
+        for stream in self.streams.values_mut() {
+            send(stream, &line)?;
+        }
+        Ok(())
+    }
+
+    fn reject(&mut self, player_id: &PlayerId, text: &str) -> io::Result<()> {
+        let stream = self.streams.get_mut(player_id).unwrap();
+        send(stream, &format!("rejected {}", text))
+    }
+
+    fn handle_line(&mut self, player_id: &PlayerId, line: &str) -> io::Result<()> {
+        let command = match split_tag(line) {
+            ("command", command) => parse_command(self.core.db(), command),
+            _ => return self.reject(player_id, &format!("unknown message: {}", line)),
+        };
+        let command = match command {
+            Ok(command) => command,
+            Err(err) => return self.reject(player_id, &err.to_string()),
+        };
+        if *player_id != *self.core.player_id() {
+            return self.reject(player_id, "not your turn");
+        }
+        if let Err(err) = self.core.player_simulation_step(command) {
+            return self.reject(player_id, &err.to_string());
+        }
+        Ok(())
+    }
+
+    /// Plays the game until any client disconnects
+    pub fn run(&mut self) -> io::Result<()> {
+        while self.core.player().class == PlayerClass::Ai {
+            self.core.play_bot_turn();
+        }
+        let mut turn_player_id = None;
+        loop {
+            self.send_events()?;
+            if turn_player_id.as_ref() != Some(self.core.player_id()) {
+                turn_player_id = Some(self.core.player_id().clone());
+                self.send_time()?;
+            }
+            let message = match self.core.time_left(self.core.player_id()) {
+                Some(time_left) => self.rx.recv_timeout(time_left),
+                None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let (player_id, line) = match message {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.core.check_time();
+                    continue;
+                },
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+            match line {
+                Some(line) => self.handle_line(&player_id, &line)?,
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Reader threads have their own handles of the sockets
+/// so they must be closed explicitly
+impl Drop for Server {
+    fn drop(&mut self) {
+        for stream in self.streams.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+pub enum ServerMessage {
+    Event(CoreEvent),
+    Rejected(String),
+}
+
+/// Time of the current turn as the server has sent it
+struct TurnTime {
+    player_id: PlayerId,
+    time_left: Option<Duration>,
+    received: Instant,
+}
+
+pub struct Client {
+    db: Db,
+    player_id: PlayerId,
+    map_size: Size2,
+    vision_sharing: VisionSharing,
+    time_control: TimeControl,
+    turn_time: Option<TurnTime>,
+    banks: HashMap<PlayerId, Duration>,
+    stream: TcpStream,
+    rx: Receiver<((), Option<String>)>,
+}
+
+impl Client {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Client> {
+        let mut stream = TcpStream::connect(address)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        send(&mut stream, &format!("hello {}", PROTOCOL_VERSION))?;
+        let line = read_line(&mut reader)?;
+        let (player_id, map_size, vision_sharing, time_control) = match split_tag(&line) {
+            ("welcome", welcome) => parse_welcome(welcome)
+                .ok_or_else(|| bad_data(&line))?,
+            ("error", text) => return Err(bad_data(text)),
+            _ => return Err(bad_data(&line)),
+        };
+        let (tx, rx) = channel();
+        spawn_reader(reader, (), tx);
+        Ok(Client {
+            db: Db::new(),
+            player_id: player_id,
+            map_size: map_size,
+            vision_sharing: vision_sharing,
+            time_control: time_control,
+            turn_time: None,
+            banks: HashMap::new(),
+            stream: stream,
+            rx: rx,
+        })
+    }
+
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    /// Player of this client, not the current player
+    pub fn player_id(&self) -> &PlayerId {
+        &self.player_id
+    }
+
+    pub fn map_size(&self) -> &Size2 {
+        &self.map_size
+    }
+
+    pub fn vision_sharing(&self) -> &VisionSharing {
+        &self.vision_sharing
+    }
+
+    pub fn time_control(&self) -> &TimeControl {
+        &self.time_control
+    }
+
+    /// Same as `Core::time_left` but counted by the client's own clock
+    pub fn time_left(&self, player_id: &PlayerId) -> Option<Duration> {
+        if !self.time_control.is_enabled() {
+            return None;
+        }
+        match self.turn_time {
+            Some(ref turn_time) if turn_time.player_id == *player_id => {
+                turn_time.time_left.map(|time_left| {
+                    saturating_sub(time_left, turn_time.received.elapsed())
+                })
+            },
+            _ => self.time_control.time_per_turn(self.time_bank(player_id)),
+        }
+    }
+
+    /// Bank as it was at the start of the player's last turn
+    pub fn time_bank(&self, player_id: &PlayerId) -> Option<Duration> {
+        self.banks.get(player_id).cloned().or(self.time_control.time_bank)
+    }
+
+    pub fn do_command(&mut self, command: Command) -> io::Result<()> {
+        let line = format!("command {}", command_to_string(&self.db, &command));
+        send(&mut self.stream, &line)
+    }
+
+    fn set_turn_time(&mut self, time: &str) -> io::Result<()> {
+        let tokens: Vec<_> = time.split_whitespace().collect();
+        if tokens.len() != 3 {
+            return Err(bad_data(time));
+        }
+        let player_id = PlayerId{id: tokens[0].parse().map_err(|_| bad_data(time))?};
+        let time_left = parse_time(tokens[1]).ok_or_else(|| bad_data(time))?;
+        let bank = parse_time(tokens[2]).ok_or_else(|| bad_data(time))?;
+        if let Some(bank) = bank {
+            self.banks.insert(player_id.clone(), bank);
+        }
+        self.turn_time = Some(TurnTime {
+            player_id: player_id,
+            time_left: time_left,
+            received: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// `time` messages are kept by the client itself, so `None`
+    fn parse_message(&mut self, line: &str) -> io::Result<Option<ServerMessage>> {
+        match split_tag(line) {
+            ("event", event) => {
+                parse_event(&self.db, event)
+                    .map(|event| Some(ServerMessage::Event(event)))
+                    .map_err(|err| bad_data(&err.to_string()))
+            },
+            ("rejected", text) => Ok(Some(ServerMessage::Rejected(text.to_owned()))),
+            ("time", time) => self.set_turn_time(time).map(|_| None),
+            _ => Err(bad_data(line)),
+        }
+    }
+
+    /// Waits for the next message from the server,
+    /// `None` means that the connection is closed
+    pub fn wait_message(&mut self) -> io::Result<Option<ServerMessage>> {
+        loop {
+            let line = match self.rx.recv() {
+                Ok((_, Some(line))) => line,
+                Ok((_, None)) | Err(_) => return Ok(None),
+            };
+            if let Some(message) = self.parse_message(&line)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Returns the next message if it has already arrived,
+    /// a closed connection is an error
+    pub fn try_message(&mut self) -> io::Result<Option<ServerMessage>> {
+        loop {
+            let line = match self.rx.try_recv() {
+                Ok((_, Some(line))) => line,
+                Ok((_, None)) | Err(TryRecvError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof, "connection closed"));
+                },
+                Err(TryRecvError::Empty) => return Ok(None),
+            };
+            if let Some(message) = self.parse_message(&line)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+fn parse_welcome(
+    welcome: &str,
+) -> Option<(PlayerId, Size2, VisionSharing, TimeControl)> {
+    let tokens: Vec<_> = welcome.split_whitespace().collect();
+    if tokens.len() != 6 {
+        return None;
+    }
+    let player_id = PlayerId{id: tokens[0].parse().ok()?};
+    let map_size = Size2{w: tokens[1].parse().ok()?, h: tokens[2].parse().ok()?};
+    let vision_sharing = match tokens[3] {
+        "full" => VisionSharing::Full,
+        "command_net" => VisionSharing::CommandNet,
+        _ => return None,
+    };
+    let time_control = TimeControl {
+        turn_limit: parse_time(tokens[4])?,
+        time_bank: parse_time(tokens[5])?,
+    };
+    Some((player_id, map_size, vision_sharing, time_control))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration};
+    use cgmath::{Vector2};
+    use std::sync::mpsc::{channel};
+    use db::{Db};
+    use clock::{TimeControl};
+    use protocol::{event_to_string};
+    use ::{Core, Options, Command, PlayerId, UnitId, ExactPos, MapPos, MoveMode, SlotId};
+    use super::{Server, Client, ServerMessage};
+
+    fn expected_events(db: &Db, player_id: &PlayerId) -> Vec<String> {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = core.get_player_event(player_id) {
+            events.push(event_to_string(db, &event));
+        }
+        events
+    }
+
+    fn receive_events(client: &mut Client, count: usize) -> Vec<String> {
+        let mut events = Vec::new();
+        while events.len() < count {
+            match client.wait_message().unwrap() {
+                Some(ServerMessage::Event(event)) => {
+                    events.push(event_to_string(client.db(), &event));
+                },
+                Some(ServerMessage::Rejected(text)) => panic!("Rejected: {}", text),
+                None => panic!("Connection closed"),
+            }
+        }
+        events
+    }
+
+    fn receive_rejection(client: &mut Client) -> String {
+        match client.wait_message().unwrap() {
+            Some(ServerMessage::Rejected(text)) => text,
+            _ => panic!("Expected rejection"),
+        }
+    }
+
+    #[test]
+    fn test_server_with_two_clients() {
+        // Core is not `Send` so the server is created in its own thread
+        let (tx, rx) = channel();
+        let server_thread = thread::spawn(move || {
+            let mut server = Server::new(&Options::default(), "127.0.0.1:0").unwrap();
+            tx.send(server.local_addr().unwrap()).unwrap();
+            server.accept_clients().unwrap();
+            server.run().unwrap();
+        });
+        let address = rx.recv().unwrap();
+        let mut clients = vec![
+            Client::connect(address).unwrap(),
+            Client::connect(address).unwrap(),
+        ];
+        clients.sort_by_key(|client| client.player_id().id);
+        assert_eq!(clients[0].player_id().id, 0);
+        assert_eq!(clients[1].player_id().id, 1);
+        // Units that are seen at once are shown in random order
+        for client in &mut clients {
+            let mut expected = expected_events(client.db(), client.player_id());
+            assert!(!expected.is_empty());
+            let mut events = receive_events(client, expected.len());
+            expected.sort();
+            events.sort();
+            assert_eq!(events, expected);
+        }
+
+        clients[1].do_command(Command::EndTurn).unwrap();
+        assert_eq!(receive_rejection(&mut clients[1]), "not your turn");
+
+        // Unit 11 belongs to the second player
+        clients[0].do_command(Command::AttackUnit {
+            attacker_id: UnitId{id: 11},
+            defender_id: UnitId{id: 0},
+        }).unwrap();
+        assert_eq!(receive_rejection(&mut clients[0]), "Not your unit");
+
+        // Bad positions must be rejected and not crash the server
+        let off_map_pos = ExactPos {
+            map_pos: MapPos{v: Vector2{x: 99, y: 99}},
+            slot_id: SlotId::Id(0),
+        };
+        clients[0].do_command(Command::Move {
+            unit_id: UnitId{id: 0},
+            path: vec![off_map_pos],
+            mode: MoveMode::Fast,
+        }).unwrap();
+        assert_eq!(receive_rejection(&mut clients[0]), "Bad path");
+
+        let type_id = clients[0].db().unit_type_id("soldier");
+        clients[0].do_command(Command::CreateUnit {
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: 0, y: 0}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: type_id,
+        }).unwrap();
+        assert_eq!(receive_rejection(&mut clients[0]), "Only available in debug mode");
+
+        clients[0].do_command(Command::EndTurn).unwrap();
+        let end_turn = "end_turn 0 1".to_owned();
+        for client in &mut clients {
+            assert_eq!(receive_events(client, 1), vec![end_turn.clone()]);
+        }
+        clients[1].do_command(Command::EndTurn).unwrap();
+        let end_turn = "end_turn 1 0".to_owned();
+        for client in &mut clients {
+            assert_eq!(receive_events(client, 1), vec![end_turn.clone()]);
+        }
+
+        clients.clear();
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_clients_know_the_time() {
+        let (tx, rx) = channel();
+        let server_thread = thread::spawn(move || {
+            let options = Options {
+                time_control: TimeControl {
+                    turn_limit: Some(Duration::from_secs(60)),
+                    time_bank: Some(Duration::from_secs(600)),
+                },
+                .. Options::default()
+            };
+            let mut server = Server::new(&options, "127.0.0.1:0").unwrap();
+            tx.send(server.local_addr().unwrap()).unwrap();
+            server.accept_clients().unwrap();
+            server.run().unwrap();
+        });
+        let address = rx.recv().unwrap();
+        let mut clients = vec![
+            Client::connect(address).unwrap(),
+            Client::connect(address).unwrap(),
+        ];
+        clients.sort_by_key(|client| client.player_id().id);
+        for client in &mut clients {
+            assert_eq!(client.time_control().turn_limit, Some(Duration::from_secs(60)));
+            let count = expected_events(client.db(), client.player_id()).len();
+            receive_events(client, count);
+        }
+        clients[0].do_command(Command::EndTurn).unwrap();
+        assert_eq!(receive_events(&mut clients[0], 1), vec!["end_turn 0 1".to_owned()]);
+        // `time` of the new turn is sent before the answer to this command
+        clients[0].do_command(Command::EndTurn).unwrap();
+        assert_eq!(receive_rejection(&mut clients[0]), "not your turn");
+        let time_left = clients[0].time_left(&PlayerId{id: 1}).unwrap();
+        assert!(time_left <= Duration::from_secs(60));
+        assert!(time_left > Duration::from_secs(50));
+        let bank = clients[0].time_bank(&PlayerId{id: 0}).unwrap();
+        assert!(bank <= Duration::from_secs(600));
+        assert!(bank > Duration::from_secs(590));
+        assert_eq!(clients[0].time_left(&PlayerId{id: 0}), Some(Duration::from_secs(60)));
+        clients.clear();
+        server_thread.join().unwrap();
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -0,0 +1,265 @@
+// See LICENSE file for copyright and license details.
+
+//! Orders of simultaneous (WEGO) turns
+//!
+//! Players plan their moves and attacks one after another without
+//! seeing the orders of each other. Nothing happens until the last
+//! player ends the planning, then `Core` executes all the orders
+//! in the interleaved order of `schedule`.
+
+use std::collections::{HashMap};
+use ::{Command, MoveMode, UnitId, PlayerId, ExactPos};
+
+#[derive(Clone)]
+pub enum Order {
+    Move{unit_id: UnitId, path: Vec<ExactPos>, mode: MoveMode},
+    Attack{attacker_id: UnitId, defender_id: UnitId},
+}
+
+impl Order {
+    pub fn unit_id(&self) -> &UnitId {
+        match *self {
+            Order::Move{ref unit_id, ..} => unit_id,
+            Order::Attack{ref attacker_id, ..} => attacker_id,
+        }
+    }
+
+    /// Only moves and attacks can be planned
+    pub fn from_command(command: &Command) -> Option<Order> {
+        match *command {
+            Command::Move{ref unit_id, ref path, ref mode} => Some(Order::Move {
+                unit_id: unit_id.clone(),
+                path: path.clone(),
+                mode: mode.clone(),
+            }),
+            Command::AttackUnit{ref attacker_id, ref defender_id} => Some(Order::Attack {
+                attacker_id: attacker_id.clone(),
+                defender_id: defender_id.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_command(&self) -> Command {
+        match *self {
+            Order::Move{ref unit_id, ref path, ref mode} => Command::Move {
+                unit_id: unit_id.clone(),
+                path: path.clone(),
+                mode: mode.clone(),
+            },
+            Order::Attack{ref attacker_id, ref defender_id} => Command::AttackUnit {
+                attacker_id: attacker_id.clone(),
+                defender_id: defender_id.clone(),
+            },
+        }
+    }
+
+    fn is_move(&self) -> bool {
+        match *self {
+            Order::Move{..} => true,
+            Order::Attack{..} => false,
+        }
+    }
+}
+
+/// Planned orders of one player: at most one move
+/// and one attack for every unit
+#[derive(Clone)]
+pub struct OrderQueue {
+    orders: Vec<Order>,
+}
+
+impl OrderQueue {
+    pub fn new() -> OrderQueue {
+        OrderQueue{orders: Vec::new()}
+    }
+
+    pub fn orders(&self) -> &[Order] {
+        &self.orders
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    fn find(&self, unit_id: &UnitId, is_move: bool) -> Option<&Order> {
+        self.orders.iter().find(|order| {
+            order.unit_id() == unit_id && order.is_move() == is_move
+        })
+    }
+
+    pub fn move_order(&self, unit_id: &UnitId) -> Option<&Order> {
+        self.find(unit_id, true)
+    }
+
+    pub fn attack_order(&self, unit_id: &UnitId) -> Option<&Order> {
+        self.find(unit_id, false)
+    }
+
+    /// Returns `false` if the unit already has an order of this kind
+    pub fn push(&mut self, order: Order) -> bool {
+        if self.find(order.unit_id(), order.is_move()).is_some() {
+            return false;
+        }
+        self.orders.push(order);
+        true
+    }
+
+    pub fn remove(&mut self, unit_id: &UnitId) {
+        self.orders.retain(|order| order.unit_id() != unit_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.orders.clear();
+    }
+}
+
+impl Default for OrderQueue {
+    fn default() -> Self {
+        OrderQueue::new()
+    }
+}
+
+/// Takes the first element of every list, then the second one and so on
+fn interleave<T: Clone>(lists: &[Vec<T>]) -> Vec<T> {
+    let max_len = lists.iter().map(|list| list.len()).max().unwrap_or(0);
+    let mut result = Vec::new();
+    for i in 0 .. max_len {
+        for list in lists {
+            if let Some(item) = list.get(i) {
+                result.push(item.clone());
+            }
+        }
+    }
+    result
+}
+
+/// Order of execution of the planned orders.
+///
+/// All attacks are made first, one attack of every player in turn.
+/// Then the units move one tile at a time: the first step of every
+/// unit, then the second one and so on. Within one phase players
+/// take turns unit by unit starting from the first player
+/// of `player_ids`.
+pub fn schedule(
+    player_ids: &[PlayerId],
+    queues: &HashMap<PlayerId, OrderQueue>,
+) -> Vec<(PlayerId, Order)> {
+    let empty = OrderQueue::new();
+    let queues: Vec<_> = player_ids.iter()
+        .map(|id| (id, queues.get(id).unwrap_or(&empty)))
+        .collect();
+    let attacks: Vec<Vec<_>> = queues.iter()
+        .map(|&(id, queue)| queue.orders().iter()
+            .filter(|order| !order.is_move())
+            .map(|order| (id.clone(), order.clone()))
+            .collect())
+        .collect();
+    let mut result = interleave(&attacks);
+    let max_path_len = queues.iter()
+        .flat_map(|&(_, queue)| queue.orders())
+        .map(|order| match *order {
+            Order::Move{ref path, ..} => path.len(),
+            Order::Attack{..} => 0,
+        })
+        .max()
+        .unwrap_or(0);
+    for step in 0 .. max_path_len {
+        let steps: Vec<Vec<_>> = queues.iter()
+            .map(|&(id, queue)| queue.orders().iter()
+                .filter_map(|order| match *order {
+                    Order::Move{ref unit_id, ref path, ref mode} => {
+                        path.get(step).map(|pos| (id.clone(), Order::Move {
+                            unit_id: unit_id.clone(),
+                            path: vec![pos.clone()],
+                            mode: mode.clone(),
+                        }))
+                    },
+                    Order::Attack{..} => None,
+                })
+                .collect())
+            .collect();
+        result.extend(interleave(&steps));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap};
+    use cgmath::{Vector2};
+    use ::{MoveMode, UnitId, PlayerId, MapPos, ExactPos, SlotId};
+    use super::{Order, OrderQueue, schedule};
+
+    fn pos(x: i32) -> ExactPos {
+        ExactPos{map_pos: MapPos{v: Vector2{x: x, y: 0}}, slot_id: SlotId::Id(0)}
+    }
+
+    fn move_order(id: i32, path: Vec<ExactPos>) -> Order {
+        Order::Move{unit_id: UnitId{id: id}, path: path, mode: MoveMode::Fast}
+    }
+
+    fn attack_order(attacker_id: i32, defender_id: i32) -> Order {
+        Order::Attack {
+            attacker_id: UnitId{id: attacker_id},
+            defender_id: UnitId{id: defender_id},
+        }
+    }
+
+    fn describe(order: &Order) -> String {
+        match *order {
+            Order::Move{ref unit_id, ref path, ..} => {
+                format!("move {} {}", unit_id.id, path[0].map_pos.v.x)
+            },
+            Order::Attack{ref attacker_id, ref defender_id} => {
+                format!("attack {} {}", attacker_id.id, defender_id.id)
+            },
+        }
+    }
+
+    #[test]
+    fn test_one_order_of_each_kind() {
+        let mut queue = OrderQueue::new();
+        assert!(queue.push(move_order(1, vec![pos(1)])));
+        assert!(queue.push(attack_order(1, 5)));
+        assert!(!queue.push(move_order(1, vec![pos(2)])));
+        assert!(!queue.push(attack_order(1, 6)));
+        assert!(queue.push(move_order(2, vec![pos(2)])));
+        assert!(queue.attack_order(&UnitId{id: 2}).is_none());
+        queue.remove(&UnitId{id: 1});
+        assert!(queue.move_order(&UnitId{id: 1}).is_none());
+        assert_eq!(queue.orders().len(), 1);
+    }
+
+    #[test]
+    fn test_schedule_interleaves_players() {
+        let player_ids = [PlayerId{id: 0}, PlayerId{id: 1}];
+        let mut queues = HashMap::new();
+        let mut queue = OrderQueue::new();
+        queue.push(move_order(1, vec![pos(1), pos(2), pos(3)]));
+        queue.push(attack_order(2, 10));
+        queue.push(attack_order(3, 11));
+        queues.insert(PlayerId{id: 0}, queue);
+        let mut queue = OrderQueue::new();
+        queue.push(move_order(10, vec![pos(7), pos(6)]));
+        queue.push(move_order(11, vec![pos(8)]));
+        queue.push(attack_order(12, 2));
+        queues.insert(PlayerId{id: 1}, queue);
+        let result: Vec<_> = schedule(&player_ids, &queues).iter()
+            .map(|&(ref id, ref order)| format!("{}: {}", id.id, describe(order)))
+            .collect();
+        assert_eq!(result, vec![
+            "0: attack 2 10",
+            "1: attack 12 2",
+            "0: attack 3 11",
+            "0: move 1 1",
+            "1: move 10 7",
+            "1: move 11 8",
+            "0: move 1 2",
+            "1: move 10 6",
+            "0: move 1 3",
+        ]);
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -1,30 +1,154 @@
 // See LICENSE file for copyright and license details.
 
 use std::collections::{HashMap};
-use types::{Size2};
-use unit::{Unit};
+use types::{Size2, ZInt};
+use unit::{Unit, UnitTypeId};
 use db::{Db};
 use map::{Map, Terrain};
 use internal_state::{InternalState};
 use game_state::{GameState, GameStateMut};
 use fow::{Fow};
-use ::{CoreEvent, PlayerId, UnitId, ObjectId, Object, MapPos};
+use fov::{Fov};
+use scenario::{Scenario};
+use ::{CoreEvent, PlayerId, UnitId, ObjectId, Object, MapPos, ExactPos, NoiseClass};
 
+/// Last known position of an enemy unit that is out of sight now
+#[derive(Clone)]
+pub struct Ghost {
+    pub unit_id: UnitId,
+    pub type_id: UnitTypeId,
+    pub player_id: PlayerId,
+    pub pos: ExactPos,
+    pub turn: ZInt,
+    pub is_contact: bool,
+}
+
+/// Approximate position of something that was heard but not seen
+#[derive(Clone)]
+pub struct Noise {
+    pub pos: MapPos,
+    pub class: NoiseClass,
+    pub turn: ZInt,
+}
+
+/// Noises are forgotten after this number of turns
+const NOISE_LIFETIME: ZInt = 1;
+
+/// Ghosts are forgotten after this number of turns even if nobody
+/// has seen their tiles empty: a unit that was killed while
+/// it was only a contact leaves a ghost that nothing else removes
+pub const GHOST_LIFETIME: ZInt = 4;
+
+#[derive(Clone)]
 pub struct PartialState {
     state: InternalState,
     fow: Fow,
+    player_id: PlayerId,
+    ghosts: HashMap<UnitId, Ghost>,
+    noises: Vec<Noise>,
+    turn: ZInt,
+    is_god_view: bool,
 }
 
 impl PartialState {
+    /// State of a game on the default map, see `Scenario::default_scenario`
     pub fn new(map_size: &Size2, player_id: &PlayerId) -> PartialState {
+        PartialState::with_state(InternalState::new(map_size), player_id)
+    }
+
+    /// Map and buildings of the scenario, units come with events
+    pub fn from_scenario(scenario: &Scenario, player_id: &PlayerId) -> PartialState {
+        PartialState::with_state(scenario.to_map_state(), player_id)
+    }
+
+    fn with_state(state: InternalState, player_id: &PlayerId) -> PartialState {
+        let fow = Fow::new(state.map().size(), player_id);
+        PartialState {
+            state: state,
+            fow: fow,
+            player_id: player_id.clone(),
+            ghosts: HashMap::new(),
+            noises: Vec::new(),
+            turn: 0,
+            is_god_view: false,
+        }
+    }
+
+    /// State of an observer who sees everything,
+    /// it must get unfiltered events (`ObserverView::God`)
+    pub fn new_god_view(scenario: &Scenario) -> PartialState {
         PartialState {
-            state: InternalState::new(map_size),
-            fow: Fow::new(map_size, player_id),
+            is_god_view: true,
+            // Nobody plays for this id, fow is ignored
+            .. PartialState::from_scenario(scenario, &PlayerId{id: -1})
         }
     }
 
+    pub fn player_id(&self) -> &PlayerId {
+        &self.player_id
+    }
+
+    pub fn is_god_view(&self) -> bool {
+        self.is_god_view
+    }
+
     pub fn is_tile_visible(&self, pos: &MapPos) -> bool {
-        self.fow.is_tile_visible(pos)
+        self.is_god_view || self.fow.is_tile_visible(pos)
+    }
+
+    /// Number of turns this player has started
+    pub fn turn(&self) -> ZInt {
+        self.turn
+    }
+
+    /// Known units, objects and the map without the memory of the player
+    pub fn internal_state(&self) -> &InternalState {
+        &self.state
+    }
+
+    pub fn ghosts(&self) -> &HashMap<UnitId, Ghost> {
+        &self.ghosts
+    }
+
+    pub fn noises(&self) -> &[Noise] {
+        &self.noises
+    }
+
+    pub fn ghosts_at(&self, pos: &MapPos) -> Vec<&Ghost> {
+        let mut ghosts = Vec::new();
+        for (_, ghost) in &self.ghosts {
+            if ghost.pos.map_pos == *pos {
+                ghosts.push(ghost);
+            }
+        }
+        ghosts
+    }
+
+    fn add_ghost(&mut self, unit_id: &UnitId) {
+        let ghost = {
+            let unit = self.state.unit(unit_id);
+            Ghost {
+                unit_id: unit.id.clone(),
+                type_id: unit.type_id.clone(),
+                player_id: unit.player_id.clone(),
+                pos: unit.pos.clone(),
+                turn: self.turn,
+                is_contact: unit.is_contact,
+            }
+        };
+        self.ghosts.insert(unit_id.clone(), ghost);
+    }
+
+    /// Forgets ghosts of units that are visible again and
+    /// ghosts in tiles that are seen empty now
+    fn update_ghosts(&mut self, db: &Db) {
+        let units = self.state.units();
+        let fow = &self.fow;
+        self.ghosts.retain(|id, ghost| {
+            let unit_type = db.unit_type(&ghost.type_id);
+            !units.contains_key(id)
+                && !fow.check_terrain_visibility(unit_type, &ghost.pos.map_pos)
+        });
     }
 }
 
@@ -40,12 +164,121 @@ impl GameState for PartialState {
     fn map(&self) -> &Map<Terrain> {
         &self.state.map()
     }
+
+    fn fov(&self) -> &Fov {
+        self.state.fov()
+    }
 }
 
 impl GameStateMut for PartialState {
     fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
+        match *event {
+            CoreEvent::HideUnit{ref unit_id} => {
+                self.add_ghost(unit_id);
+            },
+            CoreEvent::EndTurn{ref new_id, ..}
+                | CoreEvent::EndPlanning{ref new_id, ..} =>
+            {
+                if *new_id == self.player_id || self.is_god_view {
+                    self.turn += 1;
+                    let turn = self.turn;
+                    self.noises.retain(|noise| turn - noise.turn <= NOISE_LIFETIME);
+                    self.ghosts.retain(|_, ghost| turn - ghost.turn <= GHOST_LIFETIME);
+                }
+            },
+            CoreEvent::Noise{ref pos, ref class, ..} => {
+                self.noises.push(Noise {
+                    pos: pos.clone(),
+                    class: class.clone(),
+                    turn: self.turn,
+                });
+            },
+            _ => {},
+        }
         self.state.apply_event(db, event);
         self.fow.apply_event(db, &self.state, event);
+        self.update_ghosts(db);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{Size2};
+    use db::{Db};
+    use unit::{UnitClass};
+    use game_state::{GameState, GameStateMut};
+    use ::{CoreEvent, UnitInfo, PlayerId, UnitId, MapPos, ExactPos, SlotId};
+    use super::{PartialState, GHOST_LIFETIME};
+
+    #[test]
+    fn test_ghost_of_hidden_enemy() {
+        let db = Db::new();
+        let mut state = PartialState::new(&Size2{w: 10, h: 12}, &PlayerId{id: 0});
+        let unit_id = UnitId{id: 0};
+        let pos = ExactPos {
+            map_pos: MapPos{v: Vector2{x: 2, y: 2}},
+            slot_id: SlotId::Id(0),
+        };
+        let unit_info = UnitInfo {
+            unit_id: unit_id.clone(),
+            pos: pos.clone(),
+            type_id: db.unit_type_id("soldier"),
+            player_id: PlayerId{id: 1},
+            passenger_ids: Vec::new(),
+            towed_unit_id: None,
+            is_contact: false,
+        };
+        state.apply_event(&db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 1},
+            new_id: PlayerId{id: 0},
+        });
+        state.apply_event(&db, &CoreEvent::ShowUnit{unit_info: unit_info.clone()});
+        assert!(state.ghosts().is_empty());
+        state.apply_event(&db, &CoreEvent::HideUnit{unit_id: unit_id.clone()});
+        assert!(state.units().get(&unit_id).is_none());
+        {
+            let ghost = &state.ghosts()[&unit_id];
+            assert!(ghost.pos == pos);
+            assert_eq!(ghost.turn, 1);
+        }
+        assert_eq!(state.ghosts_at(&pos.map_pos).len(), 1);
+        state.apply_event(&db, &CoreEvent::ShowUnit{unit_info: unit_info});
+        assert!(state.ghosts().is_empty());
+    }
+
+    #[test]
+    fn test_ghost_is_forgotten() {
+        let db = Db::new();
+        let mut state = PartialState::new(&Size2{w: 10, h: 12}, &PlayerId{id: 0});
+        let unit_id = UnitId{id: 0};
+        state.apply_event(&db, &CoreEvent::ShowUnit {
+            unit_info: UnitInfo {
+                unit_id: unit_id.clone(),
+                pos: ExactPos {
+                    map_pos: MapPos{v: Vector2{x: 2, y: 2}},
+                    slot_id: SlotId::Id(0),
+                },
+                type_id: db.contact_type_id(&UnitClass::Infantry),
+                player_id: PlayerId{id: 1},
+                passenger_ids: Vec::new(),
+                towed_unit_id: None,
+                is_contact: true,
+            },
+        });
+        state.apply_event(&db, &CoreEvent::HideUnit{unit_id: unit_id.clone()});
+        for _ in 0 .. GHOST_LIFETIME {
+            state.apply_event(&db, &CoreEvent::EndTurn {
+                old_id: PlayerId{id: 1},
+                new_id: PlayerId{id: 0},
+            });
+        }
+        assert!(state.ghosts().get(&unit_id).is_some());
+        state.apply_event(&db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 1},
+            new_id: PlayerId{id: 0},
+        });
+        assert!(state.ghosts().is_empty());
     }
 }
 
@@ -116,10 +116,10 @@ impl Pathfinder {
         &self.map
     }
 
-    fn process_neighbour_pos(
+    fn process_neighbour_pos<S: GameState>(
         &mut self,
         db: &Db,
-        state: &PartialState,
+        state: &S,
         unit: &Unit,
         original_pos: &ExactPos,
         neighbour_pos: &ExactPos
@@ -146,10 +146,10 @@ impl Pathfinder {
         }
     }
 
-    fn try_to_push_neighbours(
+    fn try_to_push_neighbours<S: GameState>(
         &mut self,
         db: &Db,
-        state: &PartialState,
+        state: &S,
         unit: &Unit,
         pos: ExactPos,
     ) {
@@ -177,7 +177,7 @@ impl Pathfinder {
         self.queue.push(start_pos);
     }
 
-    pub fn fill_map(&mut self, db: &Db, state: &PartialState, unit: &Unit) {
+    pub fn fill_map<S: GameState>(&mut self, db: &Db, state: &S, unit: &Unit) {
         assert!(self.queue.len() == 0);
         self.clean_map();
         self.push_start_pos_to_queue(unit.pos.clone());
@@ -0,0 +1,539 @@
+// See LICENSE file for copyright and license details.
+
+//! Play-by-email
+//!
+//! Every player runs the whole game on its own machine. All players
+//! use the same `Options` with the same `seed` so the dice roll
+//! the same numbers everywhere. When the turn is over the commands
+//! of the player are written to a turn file that is sent to the next
+//! player. Its client replays the commands and shows only the events
+//! that its player is allowed to see. To continue the game later
+//! all turn files, including player's own, are loaded again in order.
+//!
+//! The turn file is:
+//!
+//! ```text
+//! zoc_turn <protocol_version> <turn>
+//! <encrypted payload in hex>
+//! <signature in hex>
+//! ```
+//!
+//! The payload is `player <player_id>` line followed by
+//! `command <command>` lines written as described in `protocol`.
+//! The last turn file of the game, see `PbemGame::end_game`,
+//! ends with `game_over` line. Only finished games can be replayed
+//! with `Options::observer` by the frontends.
+//!
+//! The game key must be shared by the players in advance.
+//! The payload is XORed with a XorShift keystream and signed with
+//! a keyed FNV-1a hash. This is NOT a real cryptography: it only keeps
+//! the enemy's commands away from the eyes of a player who just opens
+//! the file and catches careless editing. A player who wants to cheat
+//! still can inspect the memory of its client.
+//!
+//! Bots are not supported because they don't record their commands.
+//! Debug commands like `create_unit` are not allowed.
+
+use std::fmt;
+use rand::{Rng};
+use types::{ZInt};
+use db::{Db};
+use protocol::{PROTOCOL_VERSION, ParseError, command_to_string, parse_command};
+use ::{
+    Core,
+    Options,
+    Command,
+    CoreEvent,
+    CommandError,
+    PlayerId,
+    ObserverView,
+    StartError,
+    seeded_rng,
+};
+
+const MAGIC: &'static str = "zoc_turn";
+
+#[derive(Debug)]
+pub enum PbemError {
+    /// All players must roll the same dice, see `Options::seed`
+    NoSeed,
+
+    /// Bots don't record their commands
+    BotsAreNotSupported,
+
+    StartFailed(StartError),
+
+    /// Turn is ended by `PbemGame::end_turn`
+    EndTurnCommand,
+
+    /// Nothing can be done after `PbemGame::end_game`
+    GameIsOver,
+
+    BadFormat,
+    BadVersion,
+    BadSignature,
+    WrongTurn,
+    WrongPlayer,
+    NotYourTurn,
+    BadCommand(ParseError),
+    RejectedCommand(CommandError),
+}
+
+impl fmt::Display for PbemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PbemError::NoSeed => write!(f, "Play-by-email game needs a seed"),
+            PbemError::BotsAreNotSupported => {
+                write!(f, "Bots are not supported in play-by-email games")
+            },
+            PbemError::StartFailed(ref err) => write!(f, "{}", err),
+            PbemError::EndTurnCommand => write!(f, "Turn file must be written to end the turn"),
+            PbemError::GameIsOver => write!(f, "Game is over"),
+            PbemError::BadFormat => write!(f, "Bad turn file format"),
+            PbemError::BadVersion => write!(f, "Bad protocol version"),
+            PbemError::BadSignature => write!(f, "Bad signature or wrong key"),
+            PbemError::WrongTurn => write!(f, "Turn file is out of order"),
+            PbemError::WrongPlayer => write!(f, "Turn file of the wrong player"),
+            PbemError::NotYourTurn => write!(f, "Not your turn"),
+            PbemError::BadCommand(ref err) => write!(f, "Bad command: {}", err),
+            PbemError::RejectedCommand(ref err) => write!(f, "Rejected command: {}", err),
+        }
+    }
+}
+
+/// FNV-1a with the key mixed in before and after the data.
+/// It is not a MAC: anybody who has seen a few signed files can
+/// forge a signature, it only catches careless editing.
+fn keyed_hash(key: &str, data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let key = key.as_bytes();
+    for &byte in key.iter().chain(&[0]).chain(data).chain(&[0]).chain(key) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// XOR is symmetric so this both encrypts and decrypts.
+/// XorShift is not a cipher, the key can be recovered from a known
+/// payload: this only obfuscates the commands.
+fn apply_keystream(key: &str, turn: ZInt, data: &mut [u8]) {
+    let nonce = format!("{}", turn);
+    let mut rng = seeded_rng(keyed_hash(key, nonce.as_bytes()));
+    for byte in data {
+        *byte ^= rng.gen::<u8>();
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    let mut data = Vec::new();
+    for i in 0 .. text.len() / 2 {
+        let byte = text.get(i * 2 .. i * 2 + 2)?;
+        data.push(u8::from_str_radix(byte, 16).ok()?);
+    }
+    Some(data)
+}
+
+struct TurnFile {
+    turn: ZInt,
+    player_id: PlayerId,
+    commands: Vec<Command>,
+    is_game_over: bool,
+}
+
+fn write_turn_file(db: &Db, key: &str, turn_file: &TurnFile) -> String {
+    let mut payload = format!("player {}\n", turn_file.player_id.id);
+    for command in &turn_file.commands {
+        payload += &format!("command {}\n", command_to_string(db, command));
+    }
+    if turn_file.is_game_over {
+        payload += "game_over\n";
+    }
+    let mut payload = payload.into_bytes();
+    apply_keystream(key, turn_file.turn, &mut payload);
+    let header = format!("{} {} {}", MAGIC, PROTOCOL_VERSION, turn_file.turn);
+    let payload = to_hex(&payload);
+    let signature = keyed_hash(key, (header.clone() + &payload).as_bytes());
+    format!("{}\n{}\n{:016x}\n", header, payload, signature)
+}
+
+fn read_turn_file(db: &Db, key: &str, text: &str) -> Result<TurnFile, PbemError> {
+    let lines: Vec<_> = text.lines().collect();
+    if lines.len() != 3 {
+        return Err(PbemError::BadFormat);
+    }
+    let (header, payload, signature) = (lines[0], lines[1], lines[2]);
+    let header_tokens: Vec<_> = header.split_whitespace().collect();
+    if header_tokens.len() != 3 || header_tokens[0] != MAGIC {
+        return Err(PbemError::BadFormat);
+    }
+    if header_tokens[1] != PROTOCOL_VERSION.to_string() {
+        return Err(PbemError::BadVersion);
+    }
+    let turn = header_tokens[2].parse().map_err(|_| PbemError::BadFormat)?;
+    let expected_signature = keyed_hash(key, (header.to_owned() + payload).as_bytes());
+    if signature != format!("{:016x}", expected_signature) {
+        return Err(PbemError::BadSignature);
+    }
+    let mut payload = from_hex(payload).ok_or(PbemError::BadFormat)?;
+    apply_keystream(key, turn, &mut payload);
+    let payload = String::from_utf8(payload).map_err(|_| PbemError::BadFormat)?;
+    let mut player_id = None;
+    let mut commands = Vec::new();
+    let mut is_game_over = false;
+    for line in payload.lines() {
+        if is_game_over {
+            return Err(PbemError::BadFormat);
+        }
+        if line == "game_over" {
+            is_game_over = true;
+        } else if line.starts_with("player ") {
+            let id = line["player ".len() ..].parse()
+                .map_err(|_| PbemError::BadFormat)?;
+            player_id = Some(PlayerId{id: id});
+        } else if line.starts_with("command ") {
+            let command = parse_command(db, &line["command ".len() ..])
+                .map_err(PbemError::BadCommand)?;
+            commands.push(command);
+        } else {
+            return Err(PbemError::BadFormat);
+        }
+    }
+    Ok(TurnFile {
+        turn: turn,
+        player_id: player_id.ok_or(PbemError::BadFormat)?,
+        commands: commands,
+        is_game_over: is_game_over,
+    })
+}
+
+pub struct PbemGame {
+    core: Core,
+    key: String,
+
+    /// Player who sits in front of this client
+    player_id: PlayerId,
+
+    /// Number of turns that are already over
+    turn: ZInt,
+
+    /// Commands of the current turn of the local player
+    commands: Vec<Command>,
+
+    is_over: bool,
+}
+
+impl PbemGame {
+    pub fn new(
+        options: &Options,
+        player_id: &PlayerId,
+        key: &str,
+    ) -> Result<PbemGame, PbemError> {
+        if options.seed.is_none() {
+            return Err(PbemError::NoSeed);
+        }
+        if !options.bots.is_empty() {
+            return Err(PbemError::BotsAreNotSupported);
+        }
+        let mut game = PbemGame {
+            core: Core::new(options).map_err(PbemError::StartFailed)?,
+            key: key.to_owned(),
+            player_id: player_id.clone(),
+            turn: 0,
+            commands: Vec::new(),
+            is_over: false,
+        };
+        game.drop_foreign_events();
+        Ok(game)
+    }
+
+    pub fn db(&self) -> &Db {
+        self.core.db()
+    }
+
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    pub fn player_id(&self) -> &PlayerId {
+        &self.player_id
+    }
+
+    pub fn turn(&self) -> ZInt {
+        self.turn
+    }
+
+    pub fn is_my_turn(&self) -> bool {
+        !self.is_over && *self.core.player_id() == self.player_id
+    }
+
+    /// Some player has ended the game with `end_game`
+    pub fn is_over(&self) -> bool {
+        self.is_over
+    }
+
+    /// Next event of the local player
+    pub fn get_event(&mut self) -> Option<CoreEvent> {
+        self.core.get_player_event(&self.player_id)
+    }
+
+    /// Replays are watched with `Options::observer`
+    pub fn get_observer_event(&mut self, view: &ObserverView) -> Option<CoreEvent> {
+        self.core.get_observer_event(view)
+    }
+
+    /// Nobody looks at the events of other players
+    fn drop_foreign_events(&mut self) {
+        let player_ids: Vec<_> = self.core.players().iter()
+            .map(|player| player.id.clone())
+            .filter(|id| *id != self.player_id)
+            .collect();
+        for player_id in &player_ids {
+            while self.core.get_player_event(player_id).is_some() {}
+        }
+    }
+
+    /// Executes the command of the local player,
+    /// use `end_turn` for `Command::EndTurn`
+    pub fn do_command(&mut self, command: Command) -> Result<(), PbemError> {
+        if self.is_over {
+            return Err(PbemError::GameIsOver);
+        }
+        if !self.is_my_turn() {
+            return Err(PbemError::NotYourTurn);
+        }
+        if let Command::EndTurn = command {
+            return Err(PbemError::EndTurnCommand);
+        }
+        let result = self.core.player_simulation_step(command.clone());
+        self.drop_foreign_events();
+        result.map_err(PbemError::RejectedCommand)?;
+        self.commands.push(command);
+        Ok(())
+    }
+
+    /// Ends the turn of the local player and returns
+    /// the turn file for the next player
+    pub fn end_turn(&mut self) -> Result<String, PbemError> {
+        let turn_file = self.take_turn_file(false)?;
+        self.core.simulation_step(Command::EndTurn)
+            .map_err(PbemError::RejectedCommand)?;
+        self.drop_foreign_events();
+        self.turn += 1;
+        Ok(write_turn_file(self.core.db(), &self.key, &turn_file))
+    }
+
+    /// Ends the whole game on the local player's turn, e.g. when
+    /// the player gives up, and returns the last turn file
+    /// that must be sent to all other players
+    pub fn end_game(&mut self) -> Result<String, PbemError> {
+        let turn_file = self.take_turn_file(true)?;
+        self.is_over = true;
+        self.turn += 1;
+        Ok(write_turn_file(self.core.db(), &self.key, &turn_file))
+    }
+
+    fn take_turn_file(&mut self, is_game_over: bool) -> Result<TurnFile, PbemError> {
+        if self.is_over {
+            return Err(PbemError::GameIsOver);
+        }
+        if !self.is_my_turn() {
+            return Err(PbemError::NotYourTurn);
+        }
+        Ok(TurnFile {
+            turn: self.turn,
+            player_id: self.player_id.clone(),
+            commands: self.commands.drain(..).collect(),
+            is_game_over: is_game_over,
+        })
+    }
+
+    /// Replays the turn of the current player.
+    /// Turn files must be loaded in the order they were written.
+    pub fn load_turn(&mut self, text: &str) -> Result<(), PbemError> {
+        if self.is_over {
+            return Err(PbemError::GameIsOver);
+        }
+        let turn_file = read_turn_file(self.core.db(), &self.key, text)?;
+        if turn_file.turn != self.turn {
+            return Err(PbemError::WrongTurn);
+        }
+        if turn_file.player_id != *self.core.player_id() {
+            return Err(PbemError::WrongPlayer);
+        }
+        if !self.commands.is_empty() {
+            return Err(PbemError::WrongTurn);
+        }
+        let end_turn = if turn_file.is_game_over { None } else { Some(Command::EndTurn) };
+        for command in turn_file.commands.into_iter().chain(end_turn) {
+            let result = self.core.player_simulation_step(command);
+            self.drop_foreign_events();
+            result.map_err(PbemError::RejectedCommand)?;
+        }
+        self.is_over = turn_file.is_game_over;
+        self.turn += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap};
+    use cgmath::{Vector2};
+    use types::{ZInt};
+    use protocol::{event_to_string};
+    use game_state::{GameState};
+    use ::{
+        Options,
+        BotKind,
+        Command,
+        MoveMode,
+        PlayerId,
+        UnitId,
+        MapPos,
+        ExactPos,
+        SlotId,
+        ReactionFireMode,
+    };
+    use super::{PbemGame, PbemError};
+
+    const KEY: &'static str = "secret";
+
+    fn new_game(player_id: ZInt, key: &str) -> PbemGame {
+        let options = Options{seed: Some(42), .. Options::default()};
+        PbemGame::new(&options, &PlayerId{id: player_id}, key).unwrap()
+    }
+
+    fn events(game: &mut PbemGame) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Some(event) = game.get_event() {
+            events.push(event_to_string(game.db(), &event));
+        }
+        events
+    }
+
+    fn pos(x: ZInt, y: ZInt) -> ExactPos {
+        ExactPos{map_pos: MapPos{v: Vector2{x: x, y: y}}, slot_id: SlotId::Id(0)}
+    }
+
+    /// Moves the infantry of the first player that is hidden from the second one
+    fn move_command() -> Command {
+        Command::Move {
+            unit_id: UnitId{id: 9},
+            path: vec![pos(2, 5)],
+            mode: MoveMode::Fast,
+        }
+    }
+
+    #[test]
+    fn test_turn_files() {
+        let mut game_0 = new_game(0, KEY);
+        let mut game_1 = new_game(1, KEY);
+        events(&mut game_0);
+        events(&mut game_1);
+        match game_1.do_command(move_command()) {
+            Err(PbemError::NotYourTurn) => {},
+            _ => panic!("Command out of turn is executed"),
+        }
+        match game_0.do_command(Command::EndTurn) {
+            Err(PbemError::EndTurnCommand) => {},
+            _ => panic!("Turn is ended without a turn file"),
+        }
+        assert!(game_0.is_my_turn());
+        game_0.do_command(move_command()).unwrap();
+        let turn_file_0 = game_0.end_turn().unwrap();
+
+        let mut tampered = turn_file_0.clone().into_bytes();
+        tampered[30] = if tampered[30] == b'0' { b'1' } else { b'0' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        match game_1.load_turn(&tampered) {
+            Err(PbemError::BadSignature) => {},
+            _ => panic!("Tampered file is loaded"),
+        }
+        match new_game(1, "other").load_turn(&turn_file_0) {
+            Err(PbemError::BadSignature) => {},
+            _ => panic!("File is loaded with a wrong key"),
+        }
+
+        game_1.load_turn(&turn_file_0).unwrap();
+        assert!(game_1.is_my_turn());
+        // Nothing but the end of the turn is seen by the second player
+        assert_eq!(events(&mut game_1), vec!["end_turn 0 1".to_owned()]);
+        match game_1.load_turn(&turn_file_0) {
+            Err(PbemError::WrongTurn) => {},
+            _ => panic!("File is loaded twice"),
+        }
+
+        let turn_file_1 = game_1.end_turn().unwrap();
+        game_0.load_turn(&turn_file_1).unwrap();
+        assert!(game_0.is_my_turn());
+
+        let mut restored_0 = new_game(0, KEY);
+        restored_0.load_turn(&turn_file_0).unwrap();
+        restored_0.load_turn(&turn_file_1).unwrap();
+        assert!(restored_0.is_my_turn());
+        assert!(events(&mut restored_0).iter().any(|e| e.starts_with("move 9 ")));
+    }
+
+    #[test]
+    fn test_end_game() {
+        let mut game_0 = new_game(0, KEY);
+        let mut game_1 = new_game(1, KEY);
+        let turn_file_0 = game_0.end_turn().unwrap();
+        game_1.load_turn(&turn_file_0).unwrap();
+        assert!(!game_1.is_over());
+        game_1.do_command(Command::SetReactionFireMode {
+            unit_id: UnitId{id: 11},
+            mode: ReactionFireMode::HoldFire,
+        }).unwrap();
+        let turn_file_1 = game_1.end_game().unwrap();
+        assert!(game_1.is_over());
+        assert!(!game_1.is_my_turn());
+        game_0.load_turn(&turn_file_1).unwrap();
+        assert!(game_0.is_over());
+        assert!(!game_0.is_my_turn());
+        match game_0.do_command(move_command()) {
+            Err(PbemError::GameIsOver) => {},
+            _ => panic!("Command is executed after the game"),
+        }
+        match game_0.end_turn() {
+            Err(PbemError::GameIsOver) => {},
+            _ => panic!("Turn is ended after the game"),
+        }
+        match game_0.load_turn(&turn_file_1) {
+            Err(PbemError::GameIsOver) => {},
+            _ => panic!("Turn is loaded after the game"),
+        }
+        // commands of the last turn are replayed too
+        let unit = game_0.core.state.unit(&UnitId{id: 11});
+        match unit.reaction_fire_mode {
+            ReactionFireMode::HoldFire => {},
+            _ => panic!("Last turn is lost"),
+        }
+    }
+
+    #[test]
+    fn test_bad_options() {
+        let player_id = PlayerId{id: 0};
+        match PbemGame::new(&Options::default(), &player_id, KEY) {
+            Err(PbemError::NoSeed) => {},
+            _ => panic!("Game without a seed is started"),
+        }
+        let mut bots = HashMap::new();
+        bots.insert(PlayerId{id: 1}, BotKind::Builtin);
+        let options = Options{seed: Some(42), bots: bots, .. Options::default()};
+        match PbemGame::new(&options, &player_id, KEY) {
+            Err(PbemError::BotsAreNotSupported) => {},
+            _ => panic!("Game with a bot is started"),
+        }
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
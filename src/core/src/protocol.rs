@@ -0,0 +1,742 @@
+// See LICENSE file for copyright and license details.
+
+//! Text representation of `Command`s and `CoreEvent`s
+//!
+//! Every command and event is one line of space separated tokens.
+//! The first token is a tag, the rest are fields in the order
+//! of declaration. Notation:
+//!
+//! - `<pos>` is `x y slot` where `slot` is `0`, `1`, `2` or `w` (whole tile);
+//! - `<id?>` is a unit id or `-` for none;
+//! - `<bool>` is `0` or `1`;
+//! - unit types are names from `Db` like `soldier` or `medium_tank`;
+//! - `<unit_info>` is `unit_id type player_id <pos> is_contact towed_unit_id?
+//!   passengers_count passenger_id...`.
+//!
+//! Commands:
+//!
+//! ```text
+//! end_turn
+//! move unit_id fast|hunt <pos> <pos>...
+//! create_unit type <pos>
+//! attack attacker_id defender_id
+//! assault attacker_id defender_id
+//! load transporter_id passenger_id
+//! unload transporter_id passenger_id <pos>
+//! reaction_fire unit_id normal|hold
+//! ```
+//!
+//! Events:
+//!
+//! ```text
+//! move unit_id <pos:from> <pos:to> fast|hunt cost
+//! end_turn old_player_id new_player_id
+//! end_planning old_player_id new_player_id
+//! create_unit <unit_info>
+//! attack attacker_id? defender_id active|reactive killed suppression
+//!        remove_move_points ambush inderect assault
+//! show_unit <unit_info>
+//! hide_unit unit_id
+//! load transporter_id? passenger_id <pos:from> <pos:to>
+//! unload transporter_id? <pos:from> <pos:to> <unit_info>
+//! bail_out transporter_id? <pos:from> <pos:to> killed suppression <unit_info>
+//! reaction_fire unit_id normal|hold
+//! convert_ap unit_id
+//! noise listener_player_id x y engine|gunfire
+//! ```
+
+use std::fmt;
+use std::str::{SplitWhitespace};
+use cgmath::{Vector2};
+use types::{ZInt};
+use unit::{UnitTypeId};
+use db::{Db};
+use ::{
+    Command,
+    CoreEvent,
+    UnitInfo,
+    AttackInfo,
+    FireMode,
+    MoveMode,
+    MovePoints,
+    NoiseClass,
+    ReactionFireMode,
+    PlayerId,
+    UnitId,
+    MapPos,
+    ExactPos,
+    SlotId,
+};
+
+/// Must be increased after every incompatible change of the format
+pub const PROTOCOL_VERSION: ZInt = 1;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ParseError {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    BadTag(String),
+    BadNumber(String),
+    BadUnitType(String),
+    BadPos(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnexpectedEnd => write!(f, "Unexpected end of line"),
+            ParseError::UnexpectedToken(ref s) => write!(f, "Unexpected token: {}", s),
+            ParseError::BadTag(ref s) => write!(f, "Unknown tag: {}", s),
+            ParseError::BadNumber(ref s) => write!(f, "Bad number: {}", s),
+            ParseError::BadUnitType(ref s) => write!(f, "Unknown unit type: {}", s),
+            ParseError::BadPos(ref s) => write!(f, "Bad position: {}", s),
+        }
+    }
+}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+struct Writer<'a> {
+    db: &'a Db,
+    tokens: Vec<String>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(db: &'a Db, tag: &str) -> Writer<'a> {
+        Writer {
+            db: db,
+            tokens: vec![tag.to_owned()],
+        }
+    }
+
+    fn token<T: ToString>(&mut self, token: T) {
+        self.tokens.push(token.to_string());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.token(if value { 1 } else { 0 });
+    }
+
+    fn unit_id(&mut self, id: &UnitId) {
+        self.token(id.id);
+    }
+
+    fn opt_unit_id(&mut self, id: &Option<UnitId>) {
+        match *id {
+            Some(ref id) => self.unit_id(id),
+            None => self.token("-"),
+        }
+    }
+
+    fn unit_type(&mut self, type_id: &UnitTypeId) {
+        let name = self.db.unit_type(type_id).name.clone();
+        self.token(name);
+    }
+
+    fn pos(&mut self, pos: &ExactPos) {
+        self.token(pos.map_pos.v.x);
+        self.token(pos.map_pos.v.y);
+        match pos.slot_id {
+            SlotId::Id(id) => self.token(id),
+            SlotId::WholeTile => self.token("w"),
+        }
+    }
+
+    fn move_mode(&mut self, mode: &MoveMode) {
+        self.token(match *mode {
+            MoveMode::Fast => "fast",
+            MoveMode::Hunt => "hunt",
+        });
+    }
+
+    fn reaction_fire_mode(&mut self, mode: &ReactionFireMode) {
+        self.token(match *mode {
+            ReactionFireMode::Normal => "normal",
+            ReactionFireMode::HoldFire => "hold",
+        });
+    }
+
+    fn unit_info(&mut self, info: &UnitInfo) {
+        self.unit_id(&info.unit_id);
+        self.unit_type(&info.type_id);
+        self.token(info.player_id.id);
+        self.pos(&info.pos);
+        self.bool(info.is_contact);
+        self.opt_unit_id(&info.towed_unit_id);
+        self.token(info.passenger_ids.len());
+        for id in &info.passenger_ids {
+            self.unit_id(id);
+        }
+    }
+
+    fn finish(self) -> String {
+        self.tokens.join(" ")
+    }
+}
+
+struct Reader<'a> {
+    db: &'a Db,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(db: &'a Db, line: &'a str) -> Reader<'a> {
+        Reader {
+            db: db,
+            tokens: line.split_whitespace(),
+        }
+    }
+
+    fn token(&mut self) -> ParseResult<&'a str> {
+        self.tokens.next().ok_or(ParseError::UnexpectedEnd)
+    }
+
+    fn int(&mut self) -> ParseResult<ZInt> {
+        let token = self.token()?;
+        token.parse().map_err(|_| ParseError::BadNumber(token.to_owned()))
+    }
+
+    fn bool(&mut self) -> ParseResult<bool> {
+        match self.token()? {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            token => Err(ParseError::UnexpectedToken(token.to_owned())),
+        }
+    }
+
+    fn unit_id(&mut self) -> ParseResult<UnitId> {
+        Ok(UnitId{id: self.int()?})
+    }
+
+    fn opt_unit_id(&mut self) -> ParseResult<Option<UnitId>> {
+        let token = self.token()?;
+        if token == "-" {
+            return Ok(None);
+        }
+        let id = token.parse().map_err(|_| ParseError::BadNumber(token.to_owned()))?;
+        Ok(Some(UnitId{id: id}))
+    }
+
+    fn player_id(&mut self) -> ParseResult<PlayerId> {
+        Ok(PlayerId{id: self.int()?})
+    }
+
+    fn unit_type(&mut self) -> ParseResult<UnitTypeId> {
+        let token = self.token()?;
+        self.db.unit_type_id_opt(token)
+            .ok_or_else(|| ParseError::BadUnitType(token.to_owned()))
+    }
+
+    /// Contact types are valid only in events
+    fn real_unit_type(&mut self) -> ParseResult<UnitTypeId> {
+        let token = self.token()?;
+        self.db.real_unit_type_id_opt(token)
+            .ok_or_else(|| ParseError::BadUnitType(token.to_owned()))
+    }
+
+    fn map_pos(&mut self) -> ParseResult<MapPos> {
+        let x = self.int()?;
+        let y = self.int()?;
+        Ok(MapPos{v: Vector2{x: x, y: y}})
+    }
+
+    fn pos(&mut self) -> ParseResult<ExactPos> {
+        let map_pos = self.map_pos()?;
+        let slot_id = match self.token()? {
+            "w" => SlotId::WholeTile,
+            token => {
+                let id = token.parse()
+                    .map_err(|_| ParseError::BadNumber(token.to_owned()))?;
+                SlotId::Id(id)
+            },
+        };
+        Ok(ExactPos{map_pos: map_pos, slot_id: slot_id})
+    }
+
+    fn move_mode(&mut self) -> ParseResult<MoveMode> {
+        match self.token()? {
+            "fast" => Ok(MoveMode::Fast),
+            "hunt" => Ok(MoveMode::Hunt),
+            token => Err(ParseError::UnexpectedToken(token.to_owned())),
+        }
+    }
+
+    fn reaction_fire_mode(&mut self) -> ParseResult<ReactionFireMode> {
+        match self.token()? {
+            "normal" => Ok(ReactionFireMode::Normal),
+            "hold" => Ok(ReactionFireMode::HoldFire),
+            token => Err(ParseError::UnexpectedToken(token.to_owned())),
+        }
+    }
+
+    fn fire_mode(&mut self) -> ParseResult<FireMode> {
+        match self.token()? {
+            "active" => Ok(FireMode::Active),
+            "reactive" => Ok(FireMode::Reactive),
+            token => Err(ParseError::UnexpectedToken(token.to_owned())),
+        }
+    }
+
+    fn noise_class(&mut self) -> ParseResult<NoiseClass> {
+        match self.token()? {
+            "engine" => Ok(NoiseClass::Engine),
+            "gunfire" => Ok(NoiseClass::Gunfire),
+            token => Err(ParseError::UnexpectedToken(token.to_owned())),
+        }
+    }
+
+    fn unit_info(&mut self) -> ParseResult<UnitInfo> {
+        let unit_id = self.unit_id()?;
+        let type_id = self.unit_type()?;
+        let player_id = self.player_id()?;
+        let pos = self.pos()?;
+        let is_contact = self.bool()?;
+        let towed_unit_id = self.opt_unit_id()?;
+        let passengers_count = self.int()?;
+        let mut passenger_ids = Vec::new();
+        for _ in 0 .. passengers_count {
+            passenger_ids.push(self.unit_id()?);
+        }
+        Ok(UnitInfo {
+            unit_id: unit_id,
+            pos: pos,
+            type_id: type_id,
+            player_id: player_id,
+            passenger_ids: passenger_ids,
+            towed_unit_id: towed_unit_id,
+            is_contact: is_contact,
+        })
+    }
+
+    /// Checks that there is nothing left in the line
+    fn finish<T>(mut self, value: T) -> ParseResult<T> {
+        match self.tokens.next() {
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_owned())),
+            None => Ok(value),
+        }
+    }
+}
+
+pub fn command_to_string(db: &Db, command: &Command) -> String {
+    match *command {
+        Command::EndTurn => Writer::new(db, "end_turn").finish(),
+        Command::Move{ref unit_id, ref path, ref mode} => {
+            let mut w = Writer::new(db, "move");
+            w.unit_id(unit_id);
+            w.move_mode(mode);
+            for pos in path {
+                w.pos(pos);
+            }
+            w.finish()
+        },
+        Command::CreateUnit{ref pos, ref type_id} => {
+            let mut w = Writer::new(db, "create_unit");
+            w.unit_type(type_id);
+            w.pos(pos);
+            w.finish()
+        },
+        Command::AttackUnit{ref attacker_id, ref defender_id} => {
+            let mut w = Writer::new(db, "attack");
+            w.unit_id(attacker_id);
+            w.unit_id(defender_id);
+            w.finish()
+        },
+        Command::Assault{ref attacker_id, ref defender_id} => {
+            let mut w = Writer::new(db, "assault");
+            w.unit_id(attacker_id);
+            w.unit_id(defender_id);
+            w.finish()
+        },
+        Command::LoadUnit{ref transporter_id, ref passenger_id} => {
+            let mut w = Writer::new(db, "load");
+            w.unit_id(transporter_id);
+            w.unit_id(passenger_id);
+            w.finish()
+        },
+        Command::UnloadUnit{ref transporter_id, ref passenger_id, ref pos} => {
+            let mut w = Writer::new(db, "unload");
+            w.unit_id(transporter_id);
+            w.unit_id(passenger_id);
+            w.pos(pos);
+            w.finish()
+        },
+        Command::SetReactionFireMode{ref unit_id, ref mode} => {
+            let mut w = Writer::new(db, "reaction_fire");
+            w.unit_id(unit_id);
+            w.reaction_fire_mode(mode);
+            w.finish()
+        },
+    }
+}
+
+pub fn parse_command(db: &Db, line: &str) -> ParseResult<Command> {
+    let mut r = Reader::new(db, line);
+    let command = match r.token()? {
+        "end_turn" => Command::EndTurn,
+        "move" => {
+            let unit_id = r.unit_id()?;
+            let mode = r.move_mode()?;
+            let mut path = Vec::new();
+            while r.tokens.clone().next().is_some() {
+                path.push(r.pos()?);
+            }
+            Command::Move{unit_id: unit_id, path: path, mode: mode}
+        },
+        "create_unit" => {
+            let type_id = r.real_unit_type()?;
+            let pos = r.pos()?;
+            Command::CreateUnit{pos: pos, type_id: type_id}
+        },
+        "attack" => Command::AttackUnit {
+            attacker_id: r.unit_id()?,
+            defender_id: r.unit_id()?,
+        },
+        "assault" => Command::Assault {
+            attacker_id: r.unit_id()?,
+            defender_id: r.unit_id()?,
+        },
+        "load" => Command::LoadUnit {
+            transporter_id: r.unit_id()?,
+            passenger_id: r.unit_id()?,
+        },
+        "unload" => Command::UnloadUnit {
+            transporter_id: r.unit_id()?,
+            passenger_id: r.unit_id()?,
+            pos: r.pos()?,
+        },
+        "reaction_fire" => Command::SetReactionFireMode {
+            unit_id: r.unit_id()?,
+            mode: r.reaction_fire_mode()?,
+        },
+        tag => return Err(ParseError::BadTag(tag.to_owned())),
+    };
+    r.finish(command)
+}
+
+pub fn event_to_string(db: &Db, event: &CoreEvent) -> String {
+    match *event {
+        CoreEvent::Move{ref unit_id, ref from, ref to, ref mode, ref cost} => {
+            let mut w = Writer::new(db, "move");
+            w.unit_id(unit_id);
+            w.pos(from);
+            w.pos(to);
+            w.move_mode(mode);
+            w.token(cost.n);
+            w.finish()
+        },
+        CoreEvent::EndTurn{ref old_id, ref new_id} => {
+            let mut w = Writer::new(db, "end_turn");
+            w.token(old_id.id);
+            w.token(new_id.id);
+            w.finish()
+        },
+        CoreEvent::EndPlanning{ref old_id, ref new_id} => {
+            let mut w = Writer::new(db, "end_planning");
+            w.token(old_id.id);
+            w.token(new_id.id);
+            w.finish()
+        },
+        CoreEvent::CreateUnit{ref unit_info} => {
+            let mut w = Writer::new(db, "create_unit");
+            w.unit_info(unit_info);
+            w.finish()
+        },
+        CoreEvent::AttackUnit{ref attack_info} => {
+            let mut w = Writer::new(db, "attack");
+            w.opt_unit_id(&attack_info.attacker_id);
+            w.unit_id(&attack_info.defender_id);
+            w.token(match attack_info.mode {
+                FireMode::Active => "active",
+                FireMode::Reactive => "reactive",
+            });
+            w.token(attack_info.killed);
+            w.token(attack_info.suppression);
+            w.bool(attack_info.remove_move_points);
+            w.bool(attack_info.is_ambush);
+            w.bool(attack_info.is_inderect);
+            w.bool(attack_info.is_assault);
+            w.finish()
+        },
+        CoreEvent::ShowUnit{ref unit_info} => {
+            let mut w = Writer::new(db, "show_unit");
+            w.unit_info(unit_info);
+            w.finish()
+        },
+        CoreEvent::HideUnit{ref unit_id} => {
+            let mut w = Writer::new(db, "hide_unit");
+            w.unit_id(unit_id);
+            w.finish()
+        },
+        CoreEvent::LoadUnit{ref transporter_id, ref passenger_id, ref from, ref to} => {
+            let mut w = Writer::new(db, "load");
+            w.opt_unit_id(transporter_id);
+            w.unit_id(passenger_id);
+            w.pos(from);
+            w.pos(to);
+            w.finish()
+        },
+        CoreEvent::UnloadUnit{ref unit_info, ref transporter_id, ref from, ref to} => {
+            let mut w = Writer::new(db, "unload");
+            w.opt_unit_id(transporter_id);
+            w.pos(from);
+            w.pos(to);
+            w.unit_info(unit_info);
+            w.finish()
+        },
+        CoreEvent::BailOut {
+            ref unit_info,
+            ref transporter_id,
+            ref from,
+            ref to,
+            killed,
+            suppression,
+        } => {
+            let mut w = Writer::new(db, "bail_out");
+            w.opt_unit_id(transporter_id);
+            w.pos(from);
+            w.pos(to);
+            w.token(killed);
+            w.token(suppression);
+            w.unit_info(unit_info);
+            w.finish()
+        },
+        CoreEvent::SetReactionFireMode{ref unit_id, ref mode} => {
+            let mut w = Writer::new(db, "reaction_fire");
+            w.unit_id(unit_id);
+            w.reaction_fire_mode(mode);
+            w.finish()
+        },
+        CoreEvent::ConvertAp{ref unit_id} => {
+            let mut w = Writer::new(db, "convert_ap");
+            w.unit_id(unit_id);
+            w.finish()
+        },
+        CoreEvent::Noise{ref listener_id, ref pos, ref class} => {
+            let mut w = Writer::new(db, "noise");
+            w.token(listener_id.id);
+            w.token(pos.v.x);
+            w.token(pos.v.y);
+            w.token(match *class {
+                NoiseClass::Engine => "engine",
+                NoiseClass::Gunfire => "gunfire",
+            });
+            w.finish()
+        },
+    }
+}
+
+pub fn parse_event(db: &Db, line: &str) -> ParseResult<CoreEvent> {
+    let mut r = Reader::new(db, line);
+    let event = match r.token()? {
+        "move" => CoreEvent::Move {
+            unit_id: r.unit_id()?,
+            from: r.pos()?,
+            to: r.pos()?,
+            mode: r.move_mode()?,
+            cost: MovePoints{n: r.int()?},
+        },
+        "end_turn" => CoreEvent::EndTurn {
+            old_id: r.player_id()?,
+            new_id: r.player_id()?,
+        },
+        "end_planning" => CoreEvent::EndPlanning {
+            old_id: r.player_id()?,
+            new_id: r.player_id()?,
+        },
+        "create_unit" => CoreEvent::CreateUnit{unit_info: r.unit_info()?},
+        "attack" => CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: r.opt_unit_id()?,
+                defender_id: r.unit_id()?,
+                mode: r.fire_mode()?,
+                killed: r.int()?,
+                suppression: r.int()?,
+                remove_move_points: r.bool()?,
+                is_ambush: r.bool()?,
+                is_inderect: r.bool()?,
+                is_assault: r.bool()?,
+            },
+        },
+        "show_unit" => CoreEvent::ShowUnit{unit_info: r.unit_info()?},
+        "hide_unit" => CoreEvent::HideUnit{unit_id: r.unit_id()?},
+        "load" => CoreEvent::LoadUnit {
+            transporter_id: r.opt_unit_id()?,
+            passenger_id: r.unit_id()?,
+            from: r.pos()?,
+            to: r.pos()?,
+        },
+        "unload" => {
+            let transporter_id = r.opt_unit_id()?;
+            let from = r.pos()?;
+            let to = r.pos()?;
+            CoreEvent::UnloadUnit {
+                unit_info: r.unit_info()?,
+                transporter_id: transporter_id,
+                from: from,
+                to: to,
+            }
+        },
+        "bail_out" => {
+            let transporter_id = r.opt_unit_id()?;
+            let from = r.pos()?;
+            let to = r.pos()?;
+            let killed = r.int()?;
+            let suppression = r.int()?;
+            CoreEvent::BailOut {
+                unit_info: r.unit_info()?,
+                transporter_id: transporter_id,
+                from: from,
+                to: to,
+                killed: killed,
+                suppression: suppression,
+            }
+        },
+        "reaction_fire" => CoreEvent::SetReactionFireMode {
+            unit_id: r.unit_id()?,
+            mode: r.reaction_fire_mode()?,
+        },
+        "convert_ap" => CoreEvent::ConvertAp{unit_id: r.unit_id()?},
+        "noise" => CoreEvent::Noise {
+            listener_id: r.player_id()?,
+            pos: r.map_pos()?,
+            class: r.noise_class()?,
+        },
+        tag => return Err(ParseError::BadTag(tag.to_owned())),
+    };
+    r.finish(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use db::{Db};
+    use ::{
+        Command,
+        CoreEvent,
+        UnitInfo,
+        AttackInfo,
+        FireMode,
+        MoveMode,
+        NoiseClass,
+        PlayerId,
+        UnitId,
+        MapPos,
+        ExactPos,
+        SlotId,
+    };
+    use super::{
+        ParseError,
+        command_to_string,
+        event_to_string,
+        parse_command,
+        parse_event,
+    };
+
+    fn pos(x: i32, y: i32, slot_id: SlotId) -> ExactPos {
+        ExactPos{map_pos: MapPos{v: Vector2{x: x, y: y}}, slot_id: slot_id}
+    }
+
+    #[test]
+    fn test_commands_round_trip() {
+        let db = Db::new();
+        let commands = [
+            Command::EndTurn,
+            Command::Move {
+                unit_id: UnitId{id: 3},
+                path: vec![pos(1, 2, SlotId::Id(0)), pos(2, 2, SlotId::WholeTile)],
+                mode: MoveMode::Hunt,
+            },
+            Command::UnloadUnit {
+                transporter_id: UnitId{id: 1},
+                passenger_id: UnitId{id: 2},
+                pos: pos(0, 5, SlotId::Id(2)),
+            },
+        ];
+        for command in &commands {
+            let line = command_to_string(&db, command);
+            let parsed = parse_command(&db, &line).unwrap();
+            assert_eq!(command_to_string(&db, &parsed), line);
+        }
+        assert_eq!(
+            command_to_string(&db, &commands[1]),
+            "move 3 hunt 1 2 0 2 2 w",
+        );
+    }
+
+    #[test]
+    fn test_events_round_trip() {
+        let db = Db::new();
+        let unit_info = UnitInfo {
+            unit_id: UnitId{id: 7},
+            pos: pos(4, 4, SlotId::Id(1)),
+            type_id: db.unit_type_id("truck"),
+            player_id: PlayerId{id: 1},
+            passenger_ids: vec![UnitId{id: 8}, UnitId{id: 9}],
+            towed_unit_id: None,
+            is_contact: false,
+        };
+        let events = [
+            CoreEvent::ShowUnit{unit_info: unit_info.clone()},
+            CoreEvent::AttackUnit {
+                attack_info: AttackInfo {
+                    attacker_id: None,
+                    defender_id: UnitId{id: 7},
+                    mode: FireMode::Reactive,
+                    killed: 1,
+                    suppression: 30,
+                    remove_move_points: true,
+                    is_ambush: true,
+                    is_inderect: false,
+                    is_assault: false,
+                },
+            },
+            CoreEvent::BailOut {
+                unit_info: unit_info,
+                transporter_id: Some(UnitId{id: 5}),
+                from: pos(4, 4, SlotId::Id(1)),
+                to: pos(4, 5, SlotId::Id(0)),
+                killed: 2,
+                suppression: 50,
+            },
+            CoreEvent::EndPlanning {
+                old_id: PlayerId{id: 0},
+                new_id: PlayerId{id: 1},
+            },
+            CoreEvent::ConvertAp{unit_id: UnitId{id: 7}},
+            CoreEvent::Noise {
+                listener_id: PlayerId{id: 0},
+                pos: MapPos{v: Vector2{x: 3, y: 6}},
+                class: NoiseClass::Engine,
+            },
+        ];
+        for event in &events {
+            let line = event_to_string(&db, event);
+            let parsed = parse_event(&db, &line).unwrap();
+            assert_eq!(event_to_string(&db, &parsed), line);
+        }
+    }
+
+    #[test]
+    fn test_bad_lines() {
+        let db = Db::new();
+        assert_eq!(
+            parse_command(&db, "fly 1 2").err(),
+            Some(ParseError::BadTag("fly".to_owned())),
+        );
+        assert_eq!(parse_command(&db, "attack 1").err(), Some(ParseError::UnexpectedEnd));
+        assert_eq!(
+            parse_command(&db, "end_turn now").err(),
+            Some(ParseError::UnexpectedToken("now".to_owned())),
+        );
+        assert_eq!(
+            parse_command(&db, "create_unit dragon 1 1 w").err(),
+            Some(ParseError::BadUnitType("dragon".to_owned())),
+        );
+        assert_eq!(
+            parse_command(&db, "create_unit vehicle_contact 1 1 w").err(),
+            Some(ParseError::BadUnitType("vehicle_contact".to_owned())),
+        );
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
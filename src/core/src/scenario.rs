@@ -0,0 +1,672 @@
+// See LICENSE file for copyright and license details.
+
+//! Scenarios: map, buildings, units and objectives
+//!
+//! A scenario is saved as lines of space separated tokens:
+//!
+//! ```text
+//! zoc_scenario <version>
+//! size w h
+//! terrain x y trees|city
+//! building x y 0|1|2|w
+//! unit player_id type x y 0|1|2|w
+//! objective x y
+//! ai_posture player_id attack|defend
+//! ```
+//!
+//! `size` must be the first line after the header, plain tiles
+//! are not listed. `ai_posture` is optional, see `Scenario::ai_postures`. Unit types are names from `Db` as in `protocol`.
+
+use std::fmt::{self, Write};
+use std::collections::{HashMap};
+use std::fs::{File};
+use std::io::{self, Read};
+use std::path::{Path};
+use std::str::{SplitWhitespace};
+use cgmath::{Vector2};
+use types::{Size2, ZInt};
+use db::{Db};
+use map::{Map, Terrain};
+use unit::{UnitTypeId, UnitClass};
+use internal_state::{InternalState};
+use game_state::{GameState, GameStateMut};
+use mapgen::{GeneratedMap};
+use protocol::{ParseError, ParseResult};
+use ::{
+    CoreEvent,
+    AiPosture,
+    UnitInfo,
+    UnitId,
+    PlayerId,
+    MapPos,
+    ExactPos,
+    SlotId,
+    Object,
+    ObjectClass,
+    MAX_GROUND_SLOTS_COUNT,
+    get_free_exact_pos,
+    is_exact_pos_valid,
+    is_exact_pos_free,
+};
+
+/// Must be increased after every incompatible change of the format
+pub const SCENARIO_VERSION: ZInt = 1;
+
+const MAGIC: &'static str = "zoc_scenario";
+
+/// Players of every game, `player_id` of units must be less than this
+pub const PLAYERS_COUNT: ZInt = 2;
+
+pub const MIN_MAP_SIDE: ZInt = 3;
+pub const MAX_MAP_SIDE: ZInt = 40;
+
+/// Why a scenario can`t be loaded
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScenarioError::Io(ref err) => write!(f, "Can`t read scenario: {}", err),
+            ScenarioError::Parse(ref err) => write!(f, "Bad scenario: {}", err),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ScenarioUnit {
+    pub player_id: PlayerId,
+    pub type_id: UnitTypeId,
+    pub pos: ExactPos,
+}
+
+#[derive(Clone)]
+pub struct Scenario {
+    pub map: Map<Terrain>,
+    pub objects: Vec<Object>,
+    pub units: Vec<ScenarioUnit>,
+    pub objectives: Vec<MapPos>,
+
+    /// Postures of the builtin AI that win over `Options::ai_posture`
+    pub ai_postures: HashMap<PlayerId, AiPosture>,
+}
+
+/// Buildings keep the unit out of their slots unless it is infantry
+fn can_place_unit<S: GameState>(db: &Db, state: &S, type_id: &UnitTypeId, pos: &ExactPos) -> bool {
+    if !is_exact_pos_valid(db, state, type_id, pos)
+        || !is_exact_pos_free(db, state, type_id, pos)
+    {
+        return false;
+    }
+    let unit_type = db.unit_type(type_id);
+    if unit_type.class == UnitClass::Infantry && !unit_type.is_big {
+        return true;
+    }
+    state.objects_at(&pos.map_pos).iter().all(|object| {
+        !unit_type.is_big
+            && object.pos.slot_id != SlotId::WholeTile
+            && object.pos.slot_id != pos.slot_id
+    })
+}
+
+impl Scenario {
+    /// Plain map without anything on it
+    pub fn new(size: &Size2) -> Scenario {
+        Scenario {
+            map: Map::new(size),
+            objects: Vec::new(),
+            units: Vec::new(),
+            objectives: Vec::new(),
+            ai_postures: HashMap::new(),
+        }
+    }
+
+    pub fn from_generated_map(generated: &GeneratedMap) -> Scenario {
+        Scenario {
+            map: generated.map.clone(),
+            objects: generated.objects.clone(),
+            units: Vec::new(),
+            objectives: generated.objectives.clone(),
+            ai_postures: HashMap::new(),
+        }
+    }
+
+    /// Map, units and objectives that `Core` uses if no scenario is given
+    pub fn default_scenario(db: &Db) -> Scenario {
+        let state = InternalState::new(&Size2{w: 10, h: 8});
+        let mut objects: Vec<_> = state.objects().iter().collect();
+        objects.sort_by_key(|&(id, _)| id.id);
+        let mut scenario = Scenario {
+            map: state.map().clone(),
+            objects: objects.into_iter().map(|(_, object)| object.clone()).collect(),
+            units: Vec::new(),
+            objectives: vec![
+                MapPos{v: Vector2{x: 5, y: 4}},
+                MapPos{v: Vector2{x: 5, y: 5}},
+                MapPos{v: Vector2{x: 6, y: 5}},
+            ],
+            ai_postures: HashMap::new(),
+        };
+        for &(player_id, (x, y), type_name) in &[
+            (0, (0, 1), "medium_tank"),
+            (0, (0, 4), "mammoth_tank"),
+            (0, (0, 5), "heavy_tank"),
+            (0, (0, 5), "medium_tank"),
+            (0, (1, 3), "truck"),
+            (0, (1, 3), "mortar"),
+            (0, (1, 4), "jeep"),
+            (0, (2, 2), "soldier"),
+            (0, (2, 2), "scout"),
+            (0, (2, 4), "smg"),
+            (0, (2, 4), "smg"),
+            (1, (9, 1), "medium_tank"),
+            (1, (9, 2), "soldier"),
+            (1, (9, 2), "soldier"),
+            (1, (9, 4), "soldier"),
+            (1, (9, 5), "light_tank"),
+            (1, (9, 5), "light_tank"),
+            (1, (9, 6), "light_spg"),
+            (1, (8, 2), "field_gun"),
+            (1, (8, 4), "field_gun"),
+        ] {
+            let pos = MapPos{v: Vector2{x: x, y: y}};
+            let type_id = db.unit_type_id(type_name);
+            assert!(scenario.add_unit(db, &PlayerId{id: player_id}, &type_id, &pos));
+        }
+        scenario
+    }
+
+    pub fn size(&self) -> &Size2 {
+        self.map.size()
+    }
+
+    /// Map and buildings without units, they are created by events
+    pub fn to_map_state(&self) -> InternalState {
+        InternalState::with_map(self.map.clone(), &self.objects)
+    }
+
+    /// Units get ids in the order of `units`
+    pub fn to_state(&self, db: &Db) -> InternalState {
+        let mut state = self.to_map_state();
+        for (i, unit) in self.units.iter().enumerate() {
+            state.apply_event(db, &CoreEvent::CreateUnit {
+                unit_info: UnitInfo {
+                    unit_id: UnitId{id: i as ZInt},
+                    pos: unit.pos.clone(),
+                    type_id: unit.type_id.clone(),
+                    player_id: unit.player_id.clone(),
+                    passenger_ids: Vec::new(),
+                    towed_unit_id: None,
+                    is_contact: false,
+                },
+            });
+        }
+        state
+    }
+
+    /// Keeps everything that still fits on the map
+    pub fn resize(&mut self, size: &Size2) {
+        let mut map = Map::new(size);
+        for pos in self.map.get_iter() {
+            if map.is_inboard(&pos) {
+                *map.tile_mut(&pos) = self.map.tile(&pos).clone();
+            }
+        }
+        self.map = map;
+        let map = &self.map;
+        self.objects.retain(|object| map.is_inboard(&object.pos));
+        self.units.retain(|unit| map.is_inboard(&unit.pos));
+        self.objectives.retain(|pos| map.is_inboard(pos));
+    }
+
+    /// Buildings are removed from the tiles that are not `City` anymore
+    pub fn set_terrain(&mut self, pos: &MapPos, terrain: Terrain) {
+        if terrain != Terrain::City {
+            self.remove_buildings(pos);
+        }
+        *self.map.tile_mut(pos) = terrain;
+    }
+
+    /// Adds a small building to the first free slot of the tile
+    /// and turns it into `City`. Returns `false` if there is no room.
+    pub fn add_building(&mut self, db: &Db, pos: &MapPos) -> bool {
+        let slot_id = {
+            let state = self.to_state(db);
+            let objects = state.objects_at(pos);
+            let units = state.units_at(pos);
+            // big units take the whole tile, see `can_place_unit`
+            if objects.iter().any(|object| object.pos.slot_id == SlotId::WholeTile)
+                || units.iter().any(|unit| unit.pos.slot_id == SlotId::WholeTile)
+            {
+                return false;
+            }
+            let is_slot_free = |i: u8| {
+                objects.iter().all(|object| object.pos.slot_id != SlotId::Id(i))
+                    && units.iter().all(|unit| {
+                        unit.pos.slot_id != SlotId::Id(i)
+                            || db.unit_type(&unit.type_id).class == UnitClass::Infantry
+                    })
+            };
+            match (0 .. MAX_GROUND_SLOTS_COUNT as u8).find(|i| is_slot_free(*i)) {
+                Some(i) => SlotId::Id(i),
+                None => return false,
+            }
+        };
+        self.add_object(pos, slot_id);
+        true
+    }
+
+    /// Big building takes the whole tile so it must be empty
+    pub fn add_big_building(&mut self, pos: &MapPos) -> bool {
+        let is_empty = self.objects.iter().all(|object| object.pos.map_pos != *pos)
+            && self.units.iter().all(|unit| unit.pos.map_pos != *pos);
+        if !is_empty {
+            return false;
+        }
+        self.add_object(pos, SlotId::WholeTile);
+        true
+    }
+
+    fn add_object(&mut self, pos: &MapPos, slot_id: SlotId) {
+        *self.map.tile_mut(pos) = Terrain::City;
+        self.objects.push(Object {
+            class: ObjectClass::Building,
+            pos: ExactPos{map_pos: pos.clone(), slot_id: slot_id},
+        });
+    }
+
+    pub fn remove_buildings(&mut self, pos: &MapPos) {
+        self.objects.retain(|object| object.pos.map_pos != *pos);
+    }
+
+    /// Returns `false` if there is no room for the unit
+    pub fn add_unit(&mut self, db: &Db, player_id: &PlayerId, type_id: &UnitTypeId, pos: &MapPos) -> bool {
+        let exact_pos = match get_free_exact_pos(db, &self.to_state(db), type_id, pos) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        self.units.push(ScenarioUnit {
+            player_id: player_id.clone(),
+            type_id: type_id.clone(),
+            pos: exact_pos,
+        });
+        true
+    }
+
+    pub fn remove_units(&mut self, pos: &MapPos) {
+        self.units.retain(|unit| unit.pos.map_pos != *pos);
+    }
+
+    /// Adds the objective or removes it if it is already there
+    pub fn toggle_objective(&mut self, pos: &MapPos) {
+        if self.objectives.contains(pos) {
+            self.objectives.retain(|objective| objective != pos);
+        } else {
+            self.objectives.push(pos.clone());
+        }
+    }
+}
+
+fn ai_posture_to_str(posture: &AiPosture) -> &'static str {
+    match *posture {
+        AiPosture::Attack => "attack",
+        AiPosture::Defend => "defend",
+    }
+}
+
+fn slot_to_str(slot_id: &SlotId) -> String {
+    match *slot_id {
+        SlotId::Id(id) => id.to_string(),
+        SlotId::WholeTile => "w".to_owned(),
+    }
+}
+
+pub fn scenario_to_string(db: &Db, scenario: &Scenario) -> String {
+    let mut s = String::new();
+    let size = scenario.size();
+    writeln!(s, "{} {}", MAGIC, SCENARIO_VERSION).unwrap();
+    writeln!(s, "size {} {}", size.w, size.h).unwrap();
+    for pos in scenario.map.get_iter() {
+        let name = match *scenario.map.tile(&pos) {
+            Terrain::Plain => continue,
+            Terrain::Trees => "trees",
+            Terrain::City => "city",
+        };
+        writeln!(s, "terrain {} {} {}", pos.v.x, pos.v.y, name).unwrap();
+    }
+    for object in &scenario.objects {
+        let pos = &object.pos;
+        writeln!(s, "building {} {} {}",
+            pos.map_pos.v.x, pos.map_pos.v.y, slot_to_str(&pos.slot_id)).unwrap();
+    }
+    for unit in &scenario.units {
+        let pos = &unit.pos;
+        writeln!(s, "unit {} {} {} {} {}",
+            unit.player_id.id,
+            db.unit_type(&unit.type_id).name,
+            pos.map_pos.v.x,
+            pos.map_pos.v.y,
+            slot_to_str(&pos.slot_id),
+        ).unwrap();
+    }
+    for pos in &scenario.objectives {
+        writeln!(s, "objective {} {}", pos.v.x, pos.v.y).unwrap();
+    }
+    let mut postures: Vec<_> = scenario.ai_postures.iter().collect();
+    postures.sort_by_key(|&(player_id, _)| player_id.id);
+    for (player_id, posture) in postures {
+        writeln!(s, "ai_posture {} {}", player_id.id, ai_posture_to_str(posture)).unwrap();
+    }
+    s
+}
+
+struct Reader<'a> {
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn token(&mut self) -> ParseResult<&'a str> {
+        self.tokens.next().ok_or(ParseError::UnexpectedEnd)
+    }
+
+    fn int(&mut self) -> ParseResult<ZInt> {
+        let token = self.token()?;
+        token.parse().map_err(|_| ParseError::BadNumber(token.to_owned()))
+    }
+
+    fn map_pos(&mut self, map: &Map<Terrain>) -> ParseResult<MapPos> {
+        let x = self.int()?;
+        let y = self.int()?;
+        let pos = MapPos{v: Vector2{x: x, y: y}};
+        if map.is_inboard(&pos) {
+            Ok(pos)
+        } else {
+            Err(ParseError::BadPos(format!("{} {}", x, y)))
+        }
+    }
+
+    fn pos(&mut self, map: &Map<Terrain>) -> ParseResult<ExactPos> {
+        let map_pos = self.map_pos(map)?;
+        let slot_id = match self.token()? {
+            "w" => SlotId::WholeTile,
+            token => match token.parse() {
+                Ok(id) if (id as usize) < MAX_GROUND_SLOTS_COUNT => SlotId::Id(id),
+                _ => return Err(ParseError::BadNumber(token.to_owned())),
+            },
+        };
+        Ok(ExactPos{map_pos: map_pos, slot_id: slot_id})
+    }
+
+    fn finish(mut self) -> ParseResult<()> {
+        match self.tokens.next() {
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_owned())),
+            None => Ok(()),
+        }
+    }
+}
+
+fn check_size(size: &Size2) -> ParseResult<()> {
+    if size.w < MIN_MAP_SIDE || size.h < MIN_MAP_SIDE
+        || size.w > MAX_MAP_SIDE || size.h > MAX_MAP_SIDE
+    {
+        return Err(ParseError::BadNumber(format!("{} {}", size.w, size.h)));
+    }
+    Ok(())
+}
+
+fn check_player_id(player_id: &PlayerId) -> ParseResult<()> {
+    if player_id.id < 0 || player_id.id >= PLAYERS_COUNT {
+        return Err(ParseError::BadNumber(player_id.id.to_string()));
+    }
+    Ok(())
+}
+
+/// Checks the limits that `parse_scenario` checks for the scenarios
+/// that were built in code
+pub fn check_scenario(scenario: &Scenario) -> ParseResult<()> {
+    check_size(scenario.size())?;
+    for unit in &scenario.units {
+        check_player_id(&unit.player_id)?;
+    }
+    for player_id in scenario.ai_postures.keys() {
+        check_player_id(player_id)?;
+    }
+    Ok(())
+}
+
+pub fn parse_scenario(db: &Db, text: &str) -> ParseResult<Scenario> {
+    let mut lines = text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty());
+    let header = lines.next().ok_or(ParseError::UnexpectedEnd)?;
+    if header != format!("{} {}", MAGIC, SCENARIO_VERSION) {
+        return Err(ParseError::UnexpectedToken(header.to_owned()));
+    }
+    let mut scenario = {
+        let line = lines.next().ok_or(ParseError::UnexpectedEnd)?;
+        let mut r = Reader{tokens: line.split_whitespace()};
+        if r.token()? != "size" {
+            return Err(ParseError::UnexpectedToken(line.to_owned()));
+        }
+        let w = r.int()?;
+        let h = r.int()?;
+        r.finish()?;
+        check_size(&Size2{w: w, h: h})?;
+        Scenario::new(&Size2{w: w, h: h})
+    };
+    for line in lines {
+        let mut r = Reader{tokens: line.split_whitespace()};
+        match r.token()? {
+            "terrain" => {
+                let pos = r.map_pos(&scenario.map)?;
+                let terrain = match r.token()? {
+                    "trees" => Terrain::Trees,
+                    "city" => Terrain::City,
+                    token => return Err(ParseError::UnexpectedToken(token.to_owned())),
+                };
+                *scenario.map.tile_mut(&pos) = terrain;
+            },
+            "building" => {
+                let pos = r.pos(&scenario.map)?;
+                let is_taken = scenario.objects.iter().any(|object| {
+                    object.pos.map_pos == pos.map_pos
+                        && (object.pos.slot_id == pos.slot_id
+                            || object.pos.slot_id == SlotId::WholeTile
+                            || pos.slot_id == SlotId::WholeTile)
+                });
+                if is_taken {
+                    return Err(ParseError::BadPos(line.to_owned()));
+                }
+                scenario.add_object(&pos.map_pos, pos.slot_id);
+            },
+            "unit" => {
+                let player_id = PlayerId{id: r.int()?};
+                check_player_id(&player_id)?;
+                let token = r.token()?;
+                let type_id = db.real_unit_type_id_opt(token)
+                    .ok_or_else(|| ParseError::BadUnitType(token.to_owned()))?;
+                let pos = r.pos(&scenario.map)?;
+                if !can_place_unit(db, &scenario.to_state(db), &type_id, &pos) {
+                    return Err(ParseError::BadPos(line.to_owned()));
+                }
+                scenario.units.push(ScenarioUnit {
+                    player_id: player_id,
+                    type_id: type_id,
+                    pos: pos,
+                });
+            },
+            "objective" => {
+                let pos = r.map_pos(&scenario.map)?;
+                scenario.objectives.push(pos);
+            },
+            "ai_posture" => {
+                let player_id = PlayerId{id: r.int()?};
+                check_player_id(&player_id)?;
+                let posture = match r.token()? {
+                    "attack" => AiPosture::Attack,
+                    "defend" => AiPosture::Defend,
+                    token => return Err(ParseError::UnexpectedToken(token.to_owned())),
+                };
+                scenario.ai_postures.insert(player_id, posture);
+            },
+            tag => return Err(ParseError::BadTag(tag.to_owned())),
+        }
+        r.finish()?;
+    }
+    Ok(scenario)
+}
+
+pub fn load_scenario<P: AsRef<Path>>(db: &Db, path: P) -> Result<Scenario, ScenarioError> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(ScenarioError::Io)?;
+    parse_scenario(db, &text).map_err(ScenarioError::Parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use types::{Size2};
+    use db::{Db};
+    use map::{Terrain};
+    use game_state::{GameState};
+    use protocol::{ParseError};
+    use ::{PlayerId, MapPos, SlotId, AiPosture};
+    use super::{Scenario, scenario_to_string, parse_scenario, check_scenario};
+
+    fn pos(x: i32, y: i32) -> MapPos {
+        MapPos{v: Vector2{x: x, y: y}}
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let db = Db::new();
+        let mut scenario = Scenario::new(&Size2{w: 6, h: 5});
+        scenario.set_terrain(&pos(1, 1), Terrain::Trees);
+        assert!(scenario.add_building(&db, &pos(2, 2)));
+        assert!(scenario.add_building(&db, &pos(2, 2)));
+        assert!(scenario.add_big_building(&pos(3, 2)));
+        let soldier_id = db.unit_type_id("soldier");
+        let tank_id = db.unit_type_id("medium_tank");
+        assert!(scenario.add_unit(&db, &PlayerId{id: 0}, &soldier_id, &pos(2, 2)));
+        assert!(scenario.add_unit(&db, &PlayerId{id: 1}, &tank_id, &pos(5, 4)));
+        scenario.toggle_objective(&pos(2, 2));
+        scenario.ai_postures.insert(PlayerId{id: 1}, AiPosture::Defend);
+        let text = scenario_to_string(&db, &scenario);
+        let loaded = parse_scenario(&db, &text).unwrap();
+        assert_eq!(scenario_to_string(&db, &loaded), text);
+        let state = loaded.to_state(&db);
+        assert!(*state.map().tile(&pos(2, 2)) == Terrain::City);
+        assert_eq!(state.objects_at(&pos(2, 2)).len(), 2);
+        assert_eq!(state.units_at(&pos(5, 4)).len(), 1);
+        assert_eq!(loaded.objectives, vec![pos(2, 2)]);
+        assert_eq!(loaded.ai_postures[&PlayerId{id: 1}], AiPosture::Defend);
+        assert!(!loaded.ai_postures.contains_key(&PlayerId{id: 0}));
+    }
+
+    #[test]
+    fn test_default_scenario_round_trip() {
+        let db = Db::new();
+        let scenario = Scenario::default_scenario(&db);
+        assert!(check_scenario(&scenario).is_ok());
+        let text = scenario_to_string(&db, &scenario);
+        let loaded = parse_scenario(&db, &text).unwrap();
+        assert_eq!(scenario_to_string(&db, &loaded), text);
+        assert_eq!(loaded.units.len(), 20);
+    }
+
+    #[test]
+    fn test_no_room() {
+        let db = Db::new();
+        let mut scenario = Scenario::new(&Size2{w: 4, h: 4});
+        assert!(scenario.add_big_building(&pos(1, 1)));
+        assert!(!scenario.add_building(&db, &pos(1, 1)));
+        assert!(!scenario.add_big_building(&pos(1, 1)));
+        let tank_id = db.unit_type_id("medium_tank");
+        assert!(!scenario.add_unit(&db, &PlayerId{id: 0}, &tank_id, &pos(1, 1)));
+        for _ in 0 .. 3 {
+            assert!(scenario.add_building(&db, &pos(2, 2)));
+        }
+        assert!(!scenario.add_building(&db, &pos(2, 2)));
+        scenario.set_terrain(&pos(2, 2), Terrain::Plain);
+        assert!(scenario.objects.iter().all(|object| object.pos.map_pos != pos(2, 2)));
+    }
+
+    #[test]
+    fn test_no_building_under_big_unit() {
+        let db = Db::new();
+        let mut scenario = Scenario::new(&Size2{w: 4, h: 4});
+        let mammoth_id = db.unit_type_id("mammoth_tank");
+        assert!(scenario.add_unit(&db, &PlayerId{id: 0}, &mammoth_id, &pos(1, 1)));
+        assert!(scenario.units[0].pos.slot_id == SlotId::WholeTile);
+        assert!(!scenario.add_building(&db, &pos(1, 1)));
+        assert!(!scenario.add_big_building(&pos(1, 1)));
+        let text = scenario_to_string(&db, &scenario);
+        assert!(parse_scenario(&db, &text).is_ok());
+    }
+
+    #[test]
+    fn test_resize_drops_what_does_not_fit() {
+        let db = Db::new();
+        let mut scenario = Scenario::new(&Size2{w: 6, h: 6});
+        assert!(scenario.add_building(&db, &pos(5, 5)));
+        let soldier_id = db.unit_type_id("soldier");
+        assert!(scenario.add_unit(&db, &PlayerId{id: 0}, &soldier_id, &pos(5, 1)));
+        scenario.toggle_objective(&pos(0, 5));
+        scenario.resize(&Size2{w: 5, h: 8});
+        assert!(scenario.objects.is_empty());
+        assert!(scenario.units.is_empty());
+        assert_eq!(scenario.objectives.len(), 1);
+        assert!(*scenario.map.tile(&pos(4, 7)) == Terrain::Plain);
+    }
+
+    #[test]
+    fn test_bad_scenarios() {
+        let db = Db::new();
+        let header = "zoc_scenario 1\n";
+        assert_eq!(
+            parse_scenario(&db, "zoc_scenario 999\nsize 2 2").err(),
+            Some(ParseError::UnexpectedToken("zoc_scenario 999".to_owned())));
+        assert_eq!(
+            parse_scenario(&db, &format!("{}terrain 0 0 trees", header)).err(),
+            Some(ParseError::UnexpectedToken("terrain 0 0 trees".to_owned())));
+        let text = format!("{}size 3 3\nterrain 3 0 trees", header);
+        assert_eq!(
+            parse_scenario(&db, &text).err(),
+            Some(ParseError::BadPos("3 0".to_owned())));
+        let text = format!("{}size 3 3\nunit 0 dragon 0 0 0", header);
+        assert_eq!(
+            parse_scenario(&db, &text).err(),
+            Some(ParseError::BadUnitType("dragon".to_owned())));
+        let text = format!("{}size 3 3\nunit 0 infantry_contact 0 0 0", header);
+        assert_eq!(
+            parse_scenario(&db, &text).err(),
+            Some(ParseError::BadUnitType("infantry_contact".to_owned())));
+        let text = format!("{}size 3 3\nunit 2 soldier 0 0 0", header);
+        assert_eq!(
+            parse_scenario(&db, &text).err(),
+            Some(ParseError::BadNumber("2".to_owned())));
+        let text = format!("{}size 41 3", header);
+        assert_eq!(
+            parse_scenario(&db, &text).err(),
+            Some(ParseError::BadNumber("41 3".to_owned())));
+        assert!(parse_scenario(&db, &format!("{}size 2 3", header)).is_err());
+        let text = format!("{}size 3 3\nai_posture 1 hide", header);
+        assert_eq!(
+            parse_scenario(&db, &text).err(),
+            Some(ParseError::UnexpectedToken("hide".to_owned())));
+        let text = format!("{}size 3 3\nbuilding 1 1 w\nunit 0 medium_tank 1 1 0", header);
+        assert!(parse_scenario(&db, &text).is_err());
+        let text = format!("{}size 3 3\nbuilding 1 1 0\nbuilding 1 1 0", header);
+        assert!(parse_scenario(&db, &text).is_err());
+        let text = format!("{}size 3 3\nbuilding 1 1 w\nunit 0 soldier 1 1 1", header);
+        let scenario = parse_scenario(&db, &text).unwrap();
+        assert!(scenario.units[0].pos.slot_id == SlotId::Id(1));
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -0,0 +1,59 @@
+// See LICENSE file for copyright and license details.
+
+//! Fixtures shared by the tests of different modules
+
+use cgmath::{Vector2};
+use types::{ZInt};
+use db::{Db};
+use internal_state::{InternalState};
+use game_state::{GameStateMut};
+use ::{Core, CoreEvent, UnitInfo, UnitId, PlayerId, MapPos, ExactPos, SlotId};
+
+/// Adds a unit to the first free slot of `pos` the way
+/// a debug command does and returns its id
+pub fn add_unit(core: &mut Core, pos: &MapPos, type_name: &str, player_id: ZInt) -> UnitId {
+    let type_id = core.db.unit_type_id(type_name);
+    let unit_id = core.next_unit_id.clone();
+    core.add_unit(pos, &type_id, &PlayerId{id: player_id});
+    unit_id
+}
+
+/// Creates a unit in slot `pos.2` of tile (`pos.0`, `pos.1`),
+/// nobody checks that the slot is free
+pub fn create_unit_event(
+    db: &Db,
+    id: ZInt,
+    type_name: &str,
+    player_id: ZInt,
+    pos: (ZInt, ZInt, u8),
+) -> CoreEvent {
+    CoreEvent::CreateUnit {
+        unit_info: UnitInfo {
+            unit_id: UnitId{id: id},
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: pos.0, y: pos.1}},
+                slot_id: SlotId::Id(pos.2),
+            },
+            type_id: db.unit_type_id(type_name),
+            player_id: PlayerId{id: player_id},
+            passenger_ids: Vec::new(),
+            towed_unit_id: None,
+            is_contact: false,
+        },
+    }
+}
+
+/// Same as `create_unit_event` but the event is applied to the `state` right away
+pub fn create_unit(
+    db: &Db,
+    state: &mut InternalState,
+    id: ZInt,
+    type_name: &str,
+    player_id: ZInt,
+    pos: (ZInt, ZInt, u8),
+) -> UnitId {
+    state.apply_event(db, &create_unit_event(db, id, type_name, player_id, pos));
+    UnitId{id: id}
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
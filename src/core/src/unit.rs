@@ -12,6 +12,7 @@ pub enum UnitClass {
     Vehicle,
 }
 
+#[derive(Clone)]
 pub struct Unit {
     pub id: UnitId,
     pub pos: ExactPos,
@@ -23,7 +24,9 @@ pub struct Unit {
     pub reaction_fire_mode: ReactionFireMode,
     pub count: ZInt,
     pub morale: ZInt,
-    pub passenger_id: Option<UnitId>,
+    pub passenger_ids: Vec<UnitId>,
+    pub towed_unit_id: Option<UnitId>,
+    pub is_contact: bool,
 }
 
 pub struct WeaponType {
@@ -55,8 +58,11 @@ pub struct UnitType {
     pub reactive_attack_points: AttackPoints,
     pub los_range: ZInt,
     pub cover_los_range: ZInt,
-    pub is_transporter: bool,
+    pub transport_capacity: ZInt,
+    pub is_towable: bool,
+    pub can_tow: bool,
     pub is_big: bool,
+    pub is_hq: bool,
 }
 
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -0,0 +1,303 @@
+// See LICENSE file for copyright and license details.
+
+//! Anti-cheat checks of the filtered event streams
+//!
+//! `filter_events` is the only thing that stops a player from learning
+//! about enemy units that player can`t see. These checks compare what
+//! a player knows (`PartialState` built from the filtered events,
+//! with its ghosts and noises) with the real state of the game
+//! and with the player's fog of war.
+//!
+//! `check_event` must be called before the event is applied to
+//! the player's state and `Core::check_partial_state` after that.
+//! `check_filtered_event` is called by `Core` itself in debug builds,
+//! see `Core::take_leaks`.
+
+use std::fmt;
+use internal_state::{InternalState};
+use partial_state::{PartialState};
+use game_state::{GameState};
+use fow::{Fow, UnitVisibility};
+use db::{Db};
+use ::{CoreEvent, PlayerId, UnitId, MapPos};
+
+/// Something that the player must not know
+#[derive(Clone, PartialEq)]
+pub enum Leak {
+    /// Enemy unit that the player can`t see
+    HiddenUnit(UnitId),
+
+    /// Type or size of a unit that the player sees only as a contact
+    ContactInfo(UnitId),
+
+    /// Passengers or towed unit of an enemy transporter
+    Cargo(UnitId),
+
+    /// Attack on a unit the player knows nothing about
+    UnknownDefender(UnitId),
+
+    /// Noise that the player can`t hear
+    Noise(MapPos),
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Leak::HiddenUnit(ref id) => write!(f, "Hidden unit {} is known", id.id),
+            Leak::ContactInfo(ref id) => write!(f, "Contact {} is identified", id.id),
+            Leak::Cargo(ref id) => write!(f, "Cargo of unit {} is known", id.id),
+            Leak::UnknownDefender(ref id) => write!(f, "Attack on unknown unit {}", id.id),
+            Leak::Noise(ref pos) => {
+                write!(f, "Noise at ({}, {}) is heard", pos.v.x, pos.v.y)
+            },
+        }
+    }
+}
+
+/// Checks the enemy unit that the event shows against the real one:
+/// the player must see it unless it has already moved away
+/// and must know nothing about its cargo.
+///
+/// Visibility changes all the time so `Core` calls this as soon
+/// as the event is filtered and the player's fog of war is updated.
+pub fn check_filtered_event(
+    db: &Db,
+    state: &InternalState,
+    fow: &Fow,
+    player_id: &PlayerId,
+    event: &CoreEvent,
+) -> Result<(), Leak> {
+    let unit_info = match *event {
+        CoreEvent::ShowUnit{ref unit_info}
+            | CoreEvent::CreateUnit{ref unit_info}
+            | CoreEvent::UnloadUnit{ref unit_info, ..}
+            | CoreEvent::BailOut{ref unit_info, ..} => unit_info,
+        _ => return Ok(()),
+    };
+    if unit_info.player_id == *player_id {
+        return Ok(());
+    }
+    let id = &unit_info.unit_id;
+    if !unit_info.passenger_ids.is_empty() || unit_info.towed_unit_id.is_some() {
+        return Err(Leak::Cargo(id.clone()));
+    }
+    let unit = match state.units().get(id) {
+        Some(unit) if unit.pos == unit_info.pos => unit,
+        _ => return Ok(()),
+    };
+    match fow.unit_visibility(db, state, unit, &unit.pos) {
+        UnitVisibility::No => Err(Leak::HiddenUnit(id.clone())),
+        UnitVisibility::Contact if !unit_info.is_contact => {
+            Err(Leak::ContactInfo(id.clone()))
+        },
+        UnitVisibility::Contact | UnitVisibility::Full => Ok(()),
+    }
+}
+
+/// Checks the event against what the player already knows
+pub fn check_event(known: &PartialState, event: &CoreEvent) -> Result<(), Leak> {
+    match *event {
+        CoreEvent::AttackUnit{ref attack_info} => {
+            let defender_id = &attack_info.defender_id;
+            let defender = match known.units().get(defender_id) {
+                Some(defender) => defender,
+                None => return Err(Leak::UnknownDefender(defender_id.clone())),
+            };
+            if defender.is_contact && attack_info.killed != 0 {
+                return Err(Leak::ContactInfo(defender_id.clone()));
+            }
+        },
+        // it would leave a ghost of a unit that was never seen
+        CoreEvent::HideUnit{ref unit_id} if known.units().get(unit_id).is_none() => {
+            return Err(Leak::HiddenUnit(unit_id.clone()));
+        },
+        CoreEvent::Noise{ref listener_id, ref pos, ..}
+            if *listener_id != *known.player_id() =>
+        {
+            return Err(Leak::Noise(pos.clone()));
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Checks every enemy unit, ghost and noise of the player's state
+/// against the real ones.
+///
+/// Outdated info is not a leak: enemy unit that was seen some time
+/// ago may have lost some soldiers or may be already dead.
+pub fn check_partial_state(
+    db: &Db,
+    state: &InternalState,
+    fow: &Fow,
+    known: &PartialState,
+) -> Result<(), Leak> {
+    for (id, known_unit) in known.units() {
+        if known_unit.player_id == *known.player_id() {
+            continue;
+        }
+        if !known_unit.passenger_ids.is_empty() || known_unit.towed_unit_id.is_some() {
+            return Err(Leak::Cargo(id.clone()));
+        }
+        let unit = match state.units().get(id) {
+            Some(unit) => unit,
+            None => continue,
+        };
+        match fow.unit_visibility(db, state, unit, &unit.pos) {
+            UnitVisibility::No => return Err(Leak::HiddenUnit(id.clone())),
+            UnitVisibility::Contact => {
+                let class = &db.unit_type(&unit.type_id).class;
+                if !known_unit.is_contact
+                    || known_unit.type_id != db.contact_type_id(class)
+                {
+                    return Err(Leak::ContactInfo(id.clone()));
+                }
+            },
+            UnitVisibility::Full => {},
+        }
+    }
+    for (id, ghost) in known.ghosts() {
+        if ghost.is_contact && ghost.type_id != db.contact_type_id(
+            &db.unit_type(&ghost.type_id).class)
+        {
+            return Err(Leak::ContactInfo(id.clone()));
+        }
+    }
+    for noise in known.noises() {
+        if !state.map().is_inboard(&noise.pos) {
+            return Err(Leak::Noise(noise.pos.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use game_state::{GameState, GameStateMut};
+    use partial_state::{PartialState};
+    use fuzz::{random_core, random_order};
+    use test_utils::{add_unit};
+    use ::{
+        Core,
+        Options,
+        Command,
+        CoreEvent,
+        AttackInfo,
+        FireMode,
+        TurnMode,
+        PlayerId,
+        MapPos,
+        seeded_rng,
+    };
+    use super::{check_event};
+
+    /// Applies the new filtered events to the player's view with all the checks
+    fn update_known(core: &mut Core, known: &mut PartialState) {
+        let player_id = known.player_id().clone();
+        while let Some(event) = core.get_player_event(&player_id) {
+            if let Err(leak) = check_event(known, &event) {
+                panic!("{}", leak);
+            }
+            known.apply_event(&core.db, &event);
+        }
+        if let Err(leak) = core.check_partial_state(&player_id, known) {
+            panic!("{}", leak);
+        }
+    }
+
+    #[test]
+    fn test_unseen_passenger_does_not_leak() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let mut known = PartialState::from_scenario(core.scenario(), &PlayerId{id: 1});
+        // the truck is seen but its passenger bails out into the trees
+        let truck_id = add_unit(&mut core, &MapPos{v: Vector2{x: 4, y: 4}}, "truck", 0);
+        let soldier_id = add_unit(&mut core, &MapPos{v: Vector2{x: 4, y: 4}}, "soldier", 0);
+        core.do_command(Command::LoadUnit {
+            transporter_id: truck_id.clone(),
+            passenger_id: soldier_id.clone(),
+        });
+        let tank_id = add_unit(&mut core, &MapPos{v: Vector2{x: 2, y: 5}}, "medium_tank", 1);
+        update_known(&mut core, &mut known);
+        assert!(known.units().get(&truck_id).is_some());
+        let killed = core.state.unit(&truck_id).count;
+        core.do_core_event(&CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(tank_id),
+                defender_id: truck_id,
+                mode: FireMode::Active,
+                killed: killed,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: false,
+                is_assault: false,
+            },
+        });
+        assert!(core.state.units().get(&soldier_id).is_some());
+        update_known(&mut core, &mut known);
+        assert!(known.ghosts().get(&soldier_id).is_none());
+        if let Some(soldier) = known.units().get(&soldier_id) {
+            assert!(soldier.is_contact);
+        }
+    }
+
+    #[test]
+    fn test_passenger_that_gets_in_leaves_no_ghost() {
+        let mut core = Core::new(&Options::default()).unwrap();
+        let mut known = PartialState::from_scenario(core.scenario(), &PlayerId{id: 1});
+        // the soldier is seen in the open but not in the trees
+        let truck_id = add_unit(&mut core, &MapPos{v: Vector2{x: 4, y: 5}}, "truck", 0);
+        let soldier_id = add_unit(&mut core, &MapPos{v: Vector2{x: 3, y: 5}}, "soldier", 0);
+        add_unit(&mut core, &MapPos{v: Vector2{x: 2, y: 6}}, "medium_tank", 1);
+        update_known(&mut core, &mut known);
+        assert!(!known.units()[&soldier_id].is_contact);
+        core.do_command(Command::LoadUnit {
+            transporter_id: truck_id.clone(),
+            passenger_id: soldier_id.clone(),
+        });
+        assert!(core.state.unit(&truck_id).passenger_ids.contains(&soldier_id));
+        update_known(&mut core, &mut known);
+        assert!(known.units().get(&soldier_id).is_none());
+        assert!(known.ghosts().get(&soldier_id).is_none());
+        assert!(known.units()[&truck_id].passenger_ids.is_empty());
+    }
+
+    /// Plays a random game and checks every player's view of it
+    /// after every command
+    fn check_random_game(seed: u64, turn_mode: &TurnMode) {
+        let mut rng = seeded_rng(seed);
+        let mut core = random_core(&mut rng, seed, turn_mode);
+        let player_ids: Vec<_> = core.players().iter()
+            .map(|player| player.id.clone())
+            .collect();
+        let mut states: Vec<_> = player_ids.iter()
+            .map(|id| PartialState::from_scenario(core.scenario(), id))
+            .collect();
+        for _ in 0 .. 500 {
+            let command = random_order(&mut rng, &core);
+            let _ = core.simulation_step(command);
+            for (player_id, state) in player_ids.iter().zip(states.iter_mut()) {
+                while let Some(event) = core.get_player_event(player_id) {
+                    if let Err(leak) = check_event(state, &event) {
+                        panic!("seed {}, player {}: {}", seed, player_id.id, leak);
+                    }
+                    state.apply_event(&core.db, &event);
+                }
+                if let Err(leak) = core.check_partial_state(player_id, state) {
+                    panic!("seed {}, player {}: {}", seed, player_id.id, leak);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_games_do_not_leak() {
+        for seed in 0 .. 50 {
+            check_random_game(seed, &TurnMode::Igougo);
+            check_random_game(seed, &TurnMode::Wego);
+        }
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
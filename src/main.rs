@@ -1,11 +1,141 @@
 // See LICENSE file for copyright and license details.
 
 extern crate visualizer;
+extern crate core;
 
+use std::env;
+use std::process;
+use std::fs::{File};
+use std::io::{Read};
+use std::default::{Default};
+use std::time::{Duration};
 use visualizer::{Visualizer};
+use core::net::{Server, DEFAULT_PORT};
+use core::pbem::{PbemGame};
+use core::clock::{TimeControl};
+use core::db::{Db};
+use core::scenario::{Scenario, load_scenario};
+
+/// Value of `--<name> <seconds>` argument
+fn time_arg(args: &[String], name: &str) -> Option<Duration> {
+    let position = args.iter().position(|arg| *arg == format!("--{}", name));
+    position.map(|i| {
+        let secs = args.get(i + 1)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or_else(|| panic!("--{} needs a number of seconds", name));
+        Duration::from_secs(secs)
+    })
+}
+
+/// Value of `--<name> <value>` argument
+fn string_arg(args: &[String], name: &str) -> Option<String> {
+    let position = args.iter().position(|arg| *arg == format!("--{}", name));
+    position.map(|i| {
+        args.get(i + 1)
+            .cloned()
+            .unwrap_or_else(|| panic!("--{} needs a value", name))
+    })
+}
+
+/// Removes `--<name> <value>` from `args` so it does not
+/// break the positional arguments and returns the value
+fn take_string_arg(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let value = string_arg(args, name);
+    if value.is_some() {
+        let i = args.iter().position(|arg| *arg == format!("--{}", name)).unwrap();
+        args.drain(i .. i + 2);
+    }
+    value
+}
+
+fn run_server(args: &[String]) {
+    let options = core::Options {
+        time_control: TimeControl {
+            turn_limit: time_arg(args, "turn-limit"),
+            time_bank: time_arg(args, "time-bank"),
+        },
+        .. Default::default()
+    };
+    let address = string_arg(args, "listen")
+        .unwrap_or_else(|| format!("0.0.0.0:{}", DEFAULT_PORT));
+    let mut server = Server::new(&options, address.as_str())
+        .unwrap_or_else(|err| {
+            exit_with_error(&format!("Can`t listen on {}: {}", address, err))
+        });
+    println!("Waiting for players on {}", address);
+    server.accept_clients().expect("Can`t accept clients");
+    if let Err(err) = server.run() {
+        println!("Server error: {}", err);
+    }
+}
+
+/// Args are `<player_id> <key> <seed> <turn_file>...`
+fn load_pbem_game(
+    args: &[String],
+    observer: bool,
+    scenario: Option<Scenario>,
+) -> Result<PbemGame, String> {
+    if args.len() < 3 {
+        return Err("Usage: zoc --pbem <player_id> <key> <seed> <turn_file>...".to_owned());
+    }
+    let player_id = core::PlayerId {
+        id: args[0].parse().map_err(|_| format!("Bad player id: {}", args[0]))?,
+    };
+    let options = core::Options {
+        seed: Some(args[2].parse().map_err(|_| format!("Bad seed: {}", args[2]))?),
+        observer: observer,
+        scenario: scenario,
+        .. Default::default()
+    };
+    let mut game = PbemGame::new(&options, &player_id, &args[1])
+        .map_err(|err| err.to_string())?;
+    for path in &args[3..] {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|err| format!("Can`t read {}: {}", path, err))?;
+        game.load_turn(&text)
+            .map_err(|err| format!("Can`t load {}: {}", path, err))?;
+    }
+    Ok(game)
+}
+
+fn exit_with_error(text: &str) -> ! {
+    println!("{}", text);
+    process::exit(1);
+}
 
 pub fn main() {
-    let mut visualizer = Visualizer::new();
+    let mut args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "--server") {
+        run_server(&args);
+        return;
+    }
+    let scenario = take_string_arg(&mut args, "scenario").map(|path| {
+        load_scenario(&Db::new(), &path)
+            .unwrap_or_else(|err| exit_with_error(&format!("{}: {}", path, err)))
+    });
+    let server_address = string_arg(&args, "connect")
+        .unwrap_or_else(|| format!("127.0.0.1:{}", DEFAULT_PORT));
+    let mut visualizer = Visualizer::new(&server_address, scenario.clone());
+    if args.len() > 1 && args[1] == "--pbem" {
+        match load_pbem_game(&args[2..], false, scenario) {
+            Ok(game) => visualizer.start_pbem_game(game),
+            Err(err) => exit_with_error(&err),
+        }
+    } else if args.len() > 1 && args[1] == "--replay" {
+        // Turn files of all players are needed, so the replay
+        // is loaded as if the first player watches it
+        let mut replay_args = vec!["0".to_owned()];
+        replay_args.extend_from_slice(&args[2..]);
+        match load_pbem_game(&replay_args, true, scenario) {
+            Ok(ref game) if !game.is_over() => {
+                exit_with_error("Only finished games can be replayed")
+            },
+            Ok(game) => visualizer.start_replay(game),
+            Err(err) => exit_with_error(&err),
+        }
+    }
     while visualizer.is_running() {
         visualizer.tick();
     }
@@ -4,7 +4,7 @@ use std::sync::mpsc::{Sender};
 use std::collections::{HashMap};
 use glutin::{self, Event, MouseButton, VirtualKeyCode};
 use glutin::ElementState::{Released};
-use core::{UnitId, ExactPos};
+use core::{UnitId, ExactPos, AttackOdds};
 use types::{ZInt, Time, ScreenPos};
 use screen::{Screen, ScreenCommand, EventStatus};
 use context::{Context};
@@ -16,8 +16,9 @@ pub enum Command {
     Move{pos: ExactPos},
     Hunt{pos: ExactPos},
     Attack{id: UnitId},
+    Assault{id: UnitId},
     LoadUnit{passenger_id: UnitId},
-    UnloadUnit{pos: ExactPos},
+    UnloadUnit{passenger_id: UnitId, pos: ExactPos},
     EnableReactionFire{id: UnitId},
     DisableReactionFire{id: UnitId},
 }
@@ -27,11 +28,14 @@ pub enum Command {
 pub struct Options {
     // TODO: display unit name and/or type, not just IDs
     pub selects: Vec<UnitId>,
-    pub attacks: Vec<UnitId>,
+    pub attacks: Vec<(UnitId, AttackOdds)>,
+    pub assaults: Vec<UnitId>,
     pub loads: Vec<UnitId>,
+    pub unloads: Vec<(UnitId, ExactPos)>,
+    pub hooks: Vec<UnitId>,
+    pub unhooks: Vec<(UnitId, ExactPos)>,
     pub move_pos: Option<ExactPos>,
     pub hunt_pos: Option<ExactPos>,
-    pub unload_pos: Option<ExactPos>,
     pub enable_reaction_fire: Option<UnitId>,
     pub disable_reaction_fire: Option<UnitId>,
 }
@@ -41,10 +45,13 @@ impl Options {
         Options {
             selects: Vec::new(),
             attacks: Vec::new(),
+            assaults: Vec::new(),
             loads: Vec::new(),
+            unloads: Vec::new(),
+            hooks: Vec::new(),
+            unhooks: Vec::new(),
             move_pos: None,
             hunt_pos: None,
-            unload_pos: None,
             enable_reaction_fire: None,
             disable_reaction_fire: None,
         }
@@ -57,10 +64,11 @@ pub struct ContextMenuPopup {
     options: Options,
     select_button_ids: HashMap<ButtonId, UnitId>,
     attack_button_ids: HashMap<ButtonId, UnitId>,
+    assault_button_ids: HashMap<ButtonId, UnitId>,
     load_button_ids: HashMap<ButtonId, UnitId>,
+    unload_button_ids: HashMap<ButtonId, (UnitId, ExactPos)>,
     move_button_id: Option<ButtonId>,
     hunt_button_id: Option<ButtonId>,
-    unload_unit_button_id: Option<ButtonId>,
     enable_reaction_fire_button_id: Option<ButtonId>,
     disable_reaction_fire_button_id: Option<ButtonId>,
 }
@@ -75,10 +83,11 @@ impl ContextMenuPopup {
         let mut button_manager = ButtonManager::new();
         let mut select_button_ids = HashMap::new();
         let mut attack_button_ids = HashMap::new();
+        let mut assault_button_ids = HashMap::new();
         let mut load_button_ids = HashMap::new();
+        let mut unload_button_ids = HashMap::new();
         let mut move_button_id = None;
         let mut hunt_button_id = None;
-        let mut unload_unit_button_id = None;
         let mut enable_reaction_fire_button_id = None;
         let mut disable_reaction_fire_button_id = None;
         let mut pos = pos.clone();
@@ -92,18 +101,39 @@ impl ContextMenuPopup {
             select_button_ids.insert(button_id, unit_id.clone());
             pos.v.y -= vstep;
         }
-        for unit_id in &options.attacks {
+        for &(ref unit_id, ref odds) in &options.attacks {
+            let label = format!(
+                "attack <{}> (hit {:.0}%, pierce {:.0}%, wound {:.0}%, kills {:.1}, suppression {:.0})",
+                unit_id.id,
+                odds.hit * 100.0,
+                odds.pierce * 100.0,
+                odds.wound * 100.0,
+                odds.expected_killed,
+                odds.expected_suppression,
+            );
             let button_id = button_manager.add_button(
-                Button::new(context, &format!("attack <{}>", unit_id.id), &pos));
+                Button::new(context, &label, &pos));
             attack_button_ids.insert(button_id, unit_id.clone());
             pos.v.y -= vstep;
         }
+        for unit_id in &options.assaults {
+            let button_id = button_manager.add_button(
+                Button::new(context, &format!("assault <{}>", unit_id.id), &pos));
+            assault_button_ids.insert(button_id, unit_id.clone());
+            pos.v.y -= vstep;
+        }
         for unit_id in &options.loads {
             let button_id = button_manager.add_button(
                 Button::new(context, &format!("load <{}>", unit_id.id), &pos));
             load_button_ids.insert(button_id, unit_id.clone());
             pos.v.y -= vstep;
         }
+        for unit_id in &options.hooks {
+            let button_id = button_manager.add_button(
+                Button::new(context, &format!("hook up <{}>", unit_id.id), &pos));
+            load_button_ids.insert(button_id, unit_id.clone());
+            pos.v.y -= vstep;
+        }
         if options.move_pos.is_some() {
             move_button_id = Some(button_manager.add_button(
                 Button::new(context, "move", &pos)));
@@ -124,9 +154,16 @@ impl ContextMenuPopup {
                 Button::new(context, "disable reaction fire", &pos)));
             pos.v.y -= vstep;
         }
-        if options.unload_pos.is_some() {
-            unload_unit_button_id = Some(button_manager.add_button(
-                Button::new(context, "unload", &pos)));
+        for &(ref unit_id, ref unload_pos) in &options.unloads {
+            let button_id = button_manager.add_button(
+                Button::new(context, &format!("unload <{}>", unit_id.id), &pos));
+            unload_button_ids.insert(button_id, (unit_id.clone(), unload_pos.clone()));
+            pos.v.y -= vstep;
+        }
+        for &(ref unit_id, ref unhook_pos) in &options.unhooks {
+            let button_id = button_manager.add_button(
+                Button::new(context, &format!("unhook <{}>", unit_id.id), &pos));
+            unload_button_ids.insert(button_id, (unit_id.clone(), unhook_pos.clone()));
             pos.v.y -= vstep;
         }
         let popup = ContextMenuPopup {
@@ -134,10 +171,11 @@ impl ContextMenuPopup {
             button_manager: button_manager,
             select_button_ids: select_button_ids,
             attack_button_ids: attack_button_ids,
+            assault_button_ids: assault_button_ids,
             load_button_ids: load_button_ids,
+            unload_button_ids: unload_button_ids,
             move_button_id: move_button_id,
             hunt_button_id: hunt_button_id,
-            unload_unit_button_id: unload_unit_button_id,
             enable_reaction_fire_button_id: enable_reaction_fire_button_id,
             disable_reaction_fire_button_id: disable_reaction_fire_button_id,
             options: options,
@@ -178,12 +216,25 @@ impl ContextMenuPopup {
             });
             return;
         }
+        if let Some(unit_id) = self.assault_button_ids.get(button_id) {
+            self.return_command(context, Command::Assault {
+                id: unit_id.clone(),
+            });
+            return;
+        }
         if let Some(unit_id) = self.load_button_ids.get(button_id) {
             self.return_command(context, Command::LoadUnit {
                 passenger_id: unit_id.clone(),
             });
             return;
         }
+        if let Some(&(ref unit_id, ref pos)) = self.unload_button_ids.get(button_id) {
+            self.return_command(context, Command::UnloadUnit {
+                passenger_id: unit_id.clone(),
+                pos: pos.clone(),
+            });
+            return;
+        }
         let id = Some(button_id.clone());
         if id == self.move_button_id {
             self.return_command(context, Command::Move {
@@ -193,10 +244,6 @@ impl ContextMenuPopup {
             self.return_command(context, Command::Hunt {
                 pos: self.options.move_pos.clone().unwrap(),
             });
-        } else if id == self.unload_unit_button_id {
-            self.return_command(context, Command::UnloadUnit {
-                pos: self.options.unload_pos.clone().unwrap(),
-            });
         } else if id == self.enable_reaction_fire_button_id {
             self.return_command(context, Command::EnableReactionFire {
                 id: self.options.enable_reaction_fire.clone().unwrap(),
@@ -0,0 +1,635 @@
+// See LICENSE file for copyright and license details.
+
+//! Map and scenario editor
+
+use std::fs::{File};
+use std::io::{ErrorKind, Write};
+use std::f32::consts::{PI};
+use cgmath::{Vector2, Vector3, Matrix4, rad};
+use glutin::{self, VirtualKeyCode, Event, MouseButton};
+use glutin::ElementState::{Released};
+use types::{Size2, ZInt, ZFloat, Time, ScreenPos};
+use core::map::{Terrain};
+use core::db::{Db};
+use core::mapgen::{self, MapGenOptions};
+use core::scenario::{
+    Scenario,
+    ScenarioError,
+    scenario_to_string,
+    load_scenario,
+    PLAYERS_COUNT,
+    MIN_MAP_SIDE,
+    MAX_MAP_SIDE,
+};
+use core::{PlayerId, MapPos, SlotId, AiPosture};
+use core::unit::{UnitTypeId};
+use camera::Camera;
+use gui::{ButtonManager, Button, ButtonId, is_tap};
+use unit_type_visual_info::{UnitTypeVisualInfoManager};
+use selection::{get_selection_mesh};
+use context::{Context};
+use texture::{Texture, load_texture};
+use mesh::{Mesh, MeshId};
+use fs;
+use geom;
+use screen::{Screen, ScreenCommand, EventStatus};
+use tactical_screen::{
+    gen_tiles,
+    pick_tile,
+    move_camera,
+    rotate_camera,
+    add_mesh,
+    load_object_mesh,
+    get_marker,
+    get_unit_type_visual_info,
+    get_initial_camera_pos,
+    get_max_camera_pos,
+};
+
+const SCENARIO_PATH: &'static str = "scenario.txt";
+
+const PLAIN_COLOR: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const TREES_COLOR: [f32; 4] = [0.6, 0.8, 0.6, 1.0];
+const CITY_COLOR: [f32; 4] = [0.7, 0.7, 0.8, 1.0];
+const OBJECTIVE_COLOR: [f32; 4] = [1.0, 0.8, 0.0, 1.0];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tool {
+    Plain,
+    Trees,
+    City,
+    Building,
+    BigBuilding,
+    RemoveBuildings,
+    Unit,
+    RemoveUnits,
+    Objective,
+}
+
+const TOOLS: [(Tool, &'static str); 9] = [
+    (Tool::Plain, "tool: plain"),
+    (Tool::Trees, "tool: trees"),
+    (Tool::City, "tool: city"),
+    (Tool::Building, "tool: building"),
+    (Tool::BigBuilding, "tool: big building"),
+    (Tool::RemoveBuildings, "tool: remove buildings"),
+    (Tool::Unit, "tool: unit"),
+    (Tool::RemoveUnits, "tool: remove units"),
+    (Tool::Objective, "tool: objective"),
+];
+
+/// Missing file is not an error, the editor starts with a new map then
+fn load_saved_scenario(db: &Db) -> (Scenario, Option<String>) {
+    let new_scenario = || Scenario::new(&Size2{w: 10, h: 8});
+    match load_scenario(db, SCENARIO_PATH) {
+        Ok(scenario) => (scenario, None),
+        Err(ScenarioError::Io(ref err)) if err.kind() == ErrorKind::NotFound => {
+            (new_scenario(), None)
+        },
+        Err(err) => (new_scenario(), Some(format!("{}: {}", SCENARIO_PATH, err))),
+    }
+}
+
+fn size_label(size: &Size2) -> String {
+    format!("{}x{}", size.w, size.h)
+}
+
+fn player_label(player_id: &PlayerId) -> String {
+    format!("player: {}", player_id.id)
+}
+
+fn ai_posture_label(posture: Option<&AiPosture>) -> &'static str {
+    match posture {
+        None => "ai: any",
+        Some(&AiPosture::Attack) => "ai: attack",
+        Some(&AiPosture::Defend) => "ai: defend",
+    }
+}
+
+fn unit_label(db: &Db, type_id: &UnitTypeId) -> String {
+    format!("unit: {}", db.unit_type(type_id).name)
+}
+
+struct MeshIdManager {
+    big_building_mesh_w_id: MeshId,
+    building_mesh_w_id: MeshId,
+    trees_mesh_id: MeshId,
+    marker_1_mesh_id: MeshId,
+    marker_2_mesh_id: MeshId,
+    objective_mesh_id: MeshId,
+}
+
+/// One mesh per terrain type, they are painted with `basic_color`
+struct MapMeshes {
+    plain: Mesh,
+    trees: Mesh,
+    city: Mesh,
+}
+
+pub struct EditorScreen {
+    camera: Camera,
+    button_manager: ButtonManager,
+    button_tool_id: ButtonId,
+    button_unit_id: ButtonId,
+    button_player_id: ButtonId,
+    button_ai_posture_id: ButtonId,
+    button_width_less_id: ButtonId,
+    button_width_more_id: ButtonId,
+    button_height_less_id: ButtonId,
+    button_height_more_id: ButtonId,
+    button_size_id: ButtonId,
+    button_random_map_id: ButtonId,
+    button_save_id: ButtonId,
+
+    /// Label with the result of the last action, created by the first message
+    button_message_id: Option<ButtonId>,
+
+    message_pos: ScreenPos,
+    db: Db,
+    scenario: Scenario,
+
+    /// Index in `TOOLS`
+    tool_index: usize,
+
+    /// Types that can be placed, taken from the `db`
+    unit_type_ids: Vec<UnitTypeId>,
+
+    /// Index in `unit_type_ids`
+    unit_type_index: usize,
+
+    player_id: PlayerId,
+
+    /// Seed of the next random map
+    seed: u64,
+
+    mesh_ids: MeshIdManager,
+    meshes: Vec<Mesh>,
+    unit_type_visual_info: UnitTypeVisualInfoManager,
+    map_meshes: MapMeshes,
+    floor_tex: Texture,
+}
+
+impl EditorScreen {
+    pub fn new(context: &mut Context) -> EditorScreen {
+        let db = Db::new();
+        let (scenario, load_error) = load_saved_scenario(&db);
+        let floor_tex = load_texture(&mut context.factory, &fs::load("hex.png").into_inner());
+        let map_meshes = gen_map_meshes(context, &scenario, &floor_tex);
+        let mut meshes = Vec::new();
+        let big_building_mesh_w_id = add_mesh(
+            &mut meshes, load_object_mesh(context, "big_building_wire"));
+        let building_mesh_w_id = add_mesh(
+            &mut meshes, load_object_mesh(context, "building_wire"));
+        let trees_mesh_id = add_mesh(
+            &mut meshes, load_object_mesh(context, "trees"));
+        let marker_1_mesh_id = add_mesh(
+            &mut meshes, get_marker(context, "flag1.png"));
+        let marker_2_mesh_id = add_mesh(
+            &mut meshes, get_marker(context, "flag2.png"));
+        let objective_mesh_id = add_mesh(
+            &mut meshes, get_selection_mesh(context));
+        let unit_type_visual_info
+            = get_unit_type_visual_info(&db, context, &mut meshes);
+        let mut camera = Camera::new(&context.win_size);
+        camera.set_max_pos(get_max_camera_pos(scenario.size()));
+        camera.set_pos(get_initial_camera_pos(scenario.size()));
+        let tool_index = 0;
+        let unit_type_ids = db.real_unit_type_ids();
+        let unit_type_index = 0;
+        let player_id = PlayerId{id: 0};
+        let mut button_manager = ButtonManager::new();
+        let mut pos = ScreenPos{v: Vector2{x: 10, y: 10}};
+        let button_tool_id = button_manager.add_button(
+            Button::new(context, TOOLS[tool_index].1, &pos));
+        pos.v.y += button_manager.buttons()[&button_tool_id].size().h;
+        let button_unit_id = button_manager.add_button(
+            Button::new(context, &unit_label(&db, &unit_type_ids[unit_type_index]), &pos));
+        pos.v.y += button_manager.buttons()[&button_unit_id].size().h;
+        let button_player_id = button_manager.add_button(
+            Button::new(context, &player_label(&player_id), &pos));
+        pos.v.x += button_manager.buttons()[&button_player_id].size().w;
+        let button_ai_posture_id = button_manager.add_button(Button::new(
+            context, ai_posture_label(scenario.ai_postures.get(&player_id)), &pos));
+        pos.v.x = 10;
+        pos.v.y += button_manager.buttons()[&button_player_id].size().h;
+        let button_width_less_id = button_manager.add_button(
+            Button::new(context, "[w-]", &pos));
+        pos.v.x += button_manager.buttons()[&button_width_less_id].size().w;
+        let button_width_more_id = button_manager.add_button(
+            Button::new(context, "[w+]", &pos));
+        pos.v.x += button_manager.buttons()[&button_width_more_id].size().w;
+        let button_height_less_id = button_manager.add_button(
+            Button::new(context, "[h-]", &pos));
+        pos.v.x += button_manager.buttons()[&button_height_less_id].size().w;
+        let button_height_more_id = button_manager.add_button(
+            Button::new(context, "[h+]", &pos));
+        pos.v.x += button_manager.buttons()[&button_height_more_id].size().w;
+        let button_size_id = button_manager.add_button(
+            Button::new(context, &size_label(scenario.size()), &pos));
+        pos.v.x = 10;
+        pos.v.y += button_manager.buttons()[&button_size_id].size().h;
+        let button_random_map_id = button_manager.add_button(
+            Button::new(context, "[random map]", &pos));
+        pos.v.x += button_manager.buttons()[&button_random_map_id].size().w;
+        let button_save_id = button_manager.add_button(
+            Button::new(context, "[save]", &pos));
+        pos.v.x = 10;
+        pos.v.y += button_manager.buttons()[&button_save_id].size().h;
+        let mesh_ids = MeshIdManager {
+            big_building_mesh_w_id: big_building_mesh_w_id,
+            building_mesh_w_id: building_mesh_w_id,
+            trees_mesh_id: trees_mesh_id,
+            marker_1_mesh_id: marker_1_mesh_id,
+            marker_2_mesh_id: marker_2_mesh_id,
+            objective_mesh_id: objective_mesh_id,
+        };
+        let mut screen = EditorScreen {
+            camera: camera,
+            button_manager: button_manager,
+            button_tool_id: button_tool_id,
+            button_unit_id: button_unit_id,
+            button_player_id: button_player_id,
+            button_ai_posture_id: button_ai_posture_id,
+            button_width_less_id: button_width_less_id,
+            button_width_more_id: button_width_more_id,
+            button_height_less_id: button_height_less_id,
+            button_height_more_id: button_height_more_id,
+            button_size_id: button_size_id,
+            button_random_map_id: button_random_map_id,
+            button_save_id: button_save_id,
+            button_message_id: None,
+            message_pos: pos,
+            db: db,
+            scenario: scenario,
+            tool_index: tool_index,
+            unit_type_ids: unit_type_ids,
+            unit_type_index: unit_type_index,
+            player_id: player_id,
+            seed: 0,
+            mesh_ids: mesh_ids,
+            meshes: meshes,
+            unit_type_visual_info: unit_type_visual_info,
+            map_meshes: map_meshes,
+            floor_tex: floor_tex,
+        };
+        if let Some(text) = load_error {
+            screen.show_message(context, &text);
+        }
+        screen
+    }
+
+    fn set_label(&mut self, context: &mut Context, id: &ButtonId, label: &str) {
+        let pos = self.button_manager.buttons()[id].pos().clone();
+        let button = Button::new(context, label, &pos);
+        self.button_manager.buttons_mut().insert(id.clone(), button);
+    }
+
+    fn show_message(&mut self, context: &mut Context, text: &str) {
+        match self.button_message_id.clone() {
+            Some(id) => self.set_label(context, &id, text),
+            None => {
+                let button = Button::new(context, text, &self.message_pos);
+                self.button_message_id = Some(self.button_manager.add_button(button));
+            },
+        }
+    }
+
+    fn tool(&self) -> Tool {
+        TOOLS[self.tool_index].0
+    }
+
+    fn switch_tool(&mut self, context: &mut Context) {
+        self.tool_index = (self.tool_index + 1) % TOOLS.len();
+        let id = self.button_tool_id.clone();
+        self.set_label(context, &id, TOOLS[self.tool_index].1);
+    }
+
+    fn switch_unit_type(&mut self, context: &mut Context) {
+        self.unit_type_index
+            = (self.unit_type_index + 1) % self.unit_type_ids.len();
+        let id = self.button_unit_id.clone();
+        let label = unit_label(
+            &self.db, &self.unit_type_ids[self.unit_type_index]);
+        self.set_label(context, &id, &label);
+    }
+
+    fn switch_player(&mut self, context: &mut Context) {
+        self.player_id = PlayerId{id: (self.player_id.id + 1) % PLAYERS_COUNT};
+        let id = self.button_player_id.clone();
+        let label = player_label(&self.player_id);
+        self.set_label(context, &id, &label);
+        self.update_ai_posture_label(context);
+    }
+
+    /// Posture of the selected player: options of the game, attack or defend
+    fn switch_ai_posture(&mut self, context: &mut Context) {
+        let posture = match self.scenario.ai_postures.get(&self.player_id) {
+            None => Some(AiPosture::Attack),
+            Some(&AiPosture::Attack) => Some(AiPosture::Defend),
+            Some(&AiPosture::Defend) => None,
+        };
+        match posture {
+            Some(posture) => {
+                self.scenario.ai_postures.insert(self.player_id.clone(), posture);
+            },
+            None => {
+                self.scenario.ai_postures.remove(&self.player_id);
+            },
+        }
+        self.update_ai_posture_label(context);
+    }
+
+    fn update_ai_posture_label(&mut self, context: &mut Context) {
+        let id = self.button_ai_posture_id.clone();
+        let label = ai_posture_label(self.scenario.ai_postures.get(&self.player_id));
+        self.set_label(context, &id, label);
+    }
+
+    /// Must be called after every change of the map's size or terrain
+    fn update_map(&mut self, context: &mut Context) {
+        self.map_meshes = gen_map_meshes(context, &self.scenario, &self.floor_tex);
+        let size = self.scenario.size().clone();
+        self.camera.set_max_pos(get_max_camera_pos(&size));
+        let id = self.button_size_id.clone();
+        self.set_label(context, &id, &size_label(&size));
+    }
+
+    fn resize(&mut self, context: &mut Context, dw: ZInt, dh: ZInt) {
+        let size = {
+            let size = self.scenario.size();
+            Size2{w: size.w + dw, h: size.h + dh}
+        };
+        if size.w < MIN_MAP_SIDE || size.h < MIN_MAP_SIDE
+            || size.w > MAX_MAP_SIDE || size.h > MAX_MAP_SIDE
+        {
+            return;
+        }
+        self.scenario.resize(&size);
+        self.update_map(context);
+    }
+
+    fn generate_random_map(&mut self, context: &mut Context) {
+        let options = MapGenOptions {
+            size: self.scenario.size().clone(),
+            seed: self.seed,
+            .. Default::default()
+        };
+        self.seed += 1;
+        match mapgen::generate(&self.db, &options) {
+            Some(generated) => {
+                self.scenario = Scenario::from_generated_map(&generated);
+                self.update_map(context);
+                self.update_ai_posture_label(context);
+            },
+            None => {
+                let text = format!("Can`t generate a map with seed {}", options.seed);
+                self.show_message(context, &text);
+            },
+        }
+    }
+
+    fn save(&mut self, context: &mut Context) {
+        let text = scenario_to_string(&self.db, &self.scenario);
+        let message = match File::create(SCENARIO_PATH)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+        {
+            Ok(()) => format!("Saved {}", SCENARIO_PATH),
+            Err(err) => format!("Can`t write {}: {}", SCENARIO_PATH, err),
+        };
+        self.show_message(context, &message);
+    }
+
+    fn apply_tool(&mut self, context: &mut Context, pos: &MapPos) {
+        match self.tool() {
+            Tool::Plain => self.scenario.set_terrain(pos, Terrain::Plain),
+            Tool::Trees => self.scenario.set_terrain(pos, Terrain::Trees),
+            Tool::City => self.scenario.set_terrain(pos, Terrain::City),
+            Tool::Building => {
+                if !self.scenario.add_building(&self.db, pos) {
+                    self.show_message(context, "No room for a building");
+                }
+            },
+            Tool::BigBuilding => {
+                if !self.scenario.add_big_building(pos) {
+                    self.show_message(context, "No room for a big building");
+                }
+            },
+            Tool::RemoveBuildings => self.scenario.remove_buildings(pos),
+            Tool::Unit => {
+                let type_id = self.unit_type_ids[self.unit_type_index].clone();
+                if !self.scenario.add_unit(&self.db, &self.player_id, &type_id, pos) {
+                    self.show_message(context, "No room for a unit");
+                }
+            },
+            Tool::RemoveUnits => self.scenario.remove_units(pos),
+            Tool::Objective => self.scenario.toggle_objective(pos),
+        }
+        self.update_map(context);
+    }
+
+    fn handle_event_button_press(&mut self, context: &mut Context, button_id: &ButtonId) {
+        if *button_id == self.button_tool_id {
+            self.switch_tool(context);
+        } else if *button_id == self.button_unit_id {
+            self.switch_unit_type(context);
+        } else if *button_id == self.button_player_id {
+            self.switch_player(context);
+        } else if *button_id == self.button_ai_posture_id {
+            self.switch_ai_posture(context);
+        } else if *button_id == self.button_width_less_id {
+            self.resize(context, -1, 0);
+        } else if *button_id == self.button_width_more_id {
+            self.resize(context, 1, 0);
+        } else if *button_id == self.button_height_less_id {
+            self.resize(context, 0, -1);
+        } else if *button_id == self.button_height_more_id {
+            self.resize(context, 0, 1);
+        } else if *button_id == self.button_size_id {
+            // just a label
+        } else if *button_id == self.button_random_map_id {
+            self.generate_random_map(context);
+        } else if *button_id == self.button_save_id {
+            self.save(context);
+        } else if self.button_message_id.as_ref() == Some(button_id) {
+            // just a label
+        } else {
+            panic!("Bad button id: {}", button_id.id);
+        }
+    }
+
+    fn handle_event_lmb_release(&mut self, context: &mut Context) {
+        if !is_tap(context) {
+            return;
+        }
+        if let Some(button_id) = self.button_manager.get_clicked_button_id(context) {
+            self.handle_event_button_press(context, &button_id);
+        } else if let Some(pos) = pick_tile(context, &self.camera, self.scenario.size()) {
+            self.apply_tool(context, &pos);
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn handle_event_mouse_move(&mut self, context: &Context, pos: &ScreenPos) {
+        if context.mouse().is_left_button_pressed {
+            move_camera(context, &mut self.camera, pos);
+        } else if context.mouse().is_right_button_pressed {
+            rotate_camera(context, &mut self.camera, pos);
+        }
+    }
+
+    #[cfg(target_os = "android")]
+    fn handle_event_mouse_move(&mut self, context: &Context, pos: &ScreenPos) {
+        if context.mouse().is_left_button_pressed {
+            move_camera(context, &mut self.camera, pos);
+        }
+    }
+
+    fn handle_event_key_press(&mut self, context: &mut Context, key: VirtualKeyCode) {
+        let s = geom::HEX_EX_RADIUS;
+        match key {
+            VirtualKeyCode::Q | VirtualKeyCode::Escape => {
+                context.add_command(ScreenCommand::PopScreen);
+            },
+            VirtualKeyCode::W | VirtualKeyCode::Up => {
+                self.camera.move_camera(rad(PI * 1.5), s);
+            },
+            VirtualKeyCode::S | VirtualKeyCode::Down => {
+                self.camera.move_camera(rad(PI * 0.5), s);
+            },
+            VirtualKeyCode::D | VirtualKeyCode::Right => {
+                self.camera.move_camera(rad(PI * 0.0), s);
+            },
+            VirtualKeyCode::A | VirtualKeyCode::Left => {
+                self.camera.move_camera(rad(PI * 1.0), s);
+            },
+            VirtualKeyCode::Subtract | VirtualKeyCode::Key1 => {
+                self.camera.change_zoom(1.3);
+            },
+            VirtualKeyCode::Equals | VirtualKeyCode::Key2 => {
+                self.camera.change_zoom(0.7);
+            },
+            _ => {},
+        }
+    }
+
+    fn draw_mesh_at(&self, context: &mut Context, mesh_id: &MeshId, pos: Vector3<ZFloat>) {
+        let mesh = &self.meshes[mesh_id.id as usize];
+        context.data.mvp = (self.camera.mat() * Matrix4::from_translation(pos)).into();
+        if mesh.is_wire() {
+            context.draw_mesh_with_color([0.0, 0.0, 0.0, 1.0], mesh);
+        } else {
+            context.draw_mesh(mesh);
+        }
+    }
+
+    fn draw_map(&self, context: &mut Context) {
+        context.data.mvp = self.camera.mat().into();
+        context.draw_mesh_with_color(PLAIN_COLOR, &self.map_meshes.plain);
+        context.draw_mesh_with_color(TREES_COLOR, &self.map_meshes.trees);
+        context.draw_mesh_with_color(CITY_COLOR, &self.map_meshes.city);
+        let map = &self.scenario.map;
+        for pos in map.get_iter() {
+            if *map.tile(&pos) == Terrain::Trees {
+                let world_pos = geom::map_pos_to_world_pos(&pos);
+                self.draw_mesh_at(context, &self.mesh_ids.trees_mesh_id, world_pos.v);
+            }
+        }
+    }
+
+    fn draw_objects(&self, context: &mut Context) {
+        for object in &self.scenario.objects {
+            let mesh_id = match object.pos.slot_id {
+                SlotId::Id(_) => &self.mesh_ids.building_mesh_w_id,
+                SlotId::WholeTile => &self.mesh_ids.big_building_mesh_w_id,
+            };
+            let pos = geom::exact_pos_to_world_pos(&object.pos);
+            self.draw_mesh_at(context, mesh_id, pos.v);
+        }
+    }
+
+    fn draw_units(&self, context: &mut Context) {
+        for unit in &self.scenario.units {
+            let pos = geom::exact_pos_to_world_pos(&unit.pos);
+            let mesh_id = &self.unit_type_visual_info.get(&unit.type_id).mesh_id;
+            self.draw_mesh_at(context, mesh_id, pos.v);
+            let marker_mesh_id = match unit.player_id.id {
+                0 => &self.mesh_ids.marker_1_mesh_id,
+                _ => &self.mesh_ids.marker_2_mesh_id,
+            };
+            let marker_pos = Vector3{x: 0.0, y: 0.0, z: geom::HEX_EX_RADIUS / 2.0};
+            self.draw_mesh_at(context, marker_mesh_id, pos.v + marker_pos);
+        }
+    }
+
+    fn draw_objectives(&self, context: &mut Context) {
+        let mesh = &self.meshes[self.mesh_ids.objective_mesh_id.id as usize];
+        for pos in &self.scenario.objectives {
+            let pos = geom::map_pos_to_world_pos(pos);
+            let m = self.camera.mat() * Matrix4::from_translation(geom::lift(pos.v));
+            context.data.mvp = m.into();
+            context.draw_mesh_with_color(OBJECTIVE_COLOR, mesh);
+        }
+    }
+
+    fn draw(&self, context: &mut Context) {
+        context.clear_color = [0.7, 0.7, 0.7, 1.0];
+        context.encoder.clear(&context.data.out, context.clear_color);
+        context.data.basic_color = [1.0, 1.0, 1.0, 1.0];
+        self.draw_map(context);
+        self.draw_objects(context);
+        self.draw_units(context);
+        self.draw_objectives(context);
+        context.data.basic_color = [0.0, 0.0, 0.0, 1.0];
+        self.button_manager.draw(context);
+    }
+}
+
+fn gen_map_meshes(context: &mut Context, scenario: &Scenario, tex: &Texture) -> MapMeshes {
+    let map = &scenario.map;
+    MapMeshes {
+        plain: gen_tiles(context, map, tex.clone(), |pos| *map.tile(pos) == Terrain::Plain),
+        trees: gen_tiles(context, map, tex.clone(), |pos| *map.tile(pos) == Terrain::Trees),
+        city: gen_tiles(context, map, tex.clone(), |pos| *map.tile(pos) == Terrain::City),
+    }
+}
+
+impl Screen for EditorScreen {
+    fn tick(&mut self, context: &mut Context, _: &Time) {
+        self.draw(context);
+    }
+
+    fn handle_event(&mut self, context: &mut Context, event: &Event) -> EventStatus {
+        match *event {
+            Event::Resized(..) => {
+                self.camera.regenerate_projection_mat(&context.win_size);
+            },
+            Event::MouseMoved(x, y) => {
+                let pos = ScreenPos{v: Vector2{x: x as ZInt, y: y as ZInt}};
+                self.handle_event_mouse_move(context, &pos);
+            },
+            Event::MouseInput(Released, MouseButton::Left) => {
+                self.handle_event_lmb_release(context);
+            },
+            Event::KeyboardInput(Released, _, Some(key)) => {
+                self.handle_event_key_press(context, key);
+            },
+            Event::Touch(glutin::Touch{location: (x, y), phase, ..}) => {
+                let pos = ScreenPos{v: Vector2{x: x as ZInt, y: y as ZInt}};
+                match phase {
+                    glutin::TouchPhase::Moved | glutin::TouchPhase::Started => {
+                        self.handle_event_mouse_move(context, &pos);
+                    },
+                    glutin::TouchPhase::Ended => {
+                        self.handle_event_mouse_move(context, &pos);
+                        self.handle_event_lmb_release(context);
+                    },
+                    glutin::TouchPhase::Cancelled => {},
+                }
+            },
+            _ => {},
+        }
+        EventStatus::Handled
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
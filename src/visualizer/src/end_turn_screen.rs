@@ -1,5 +1,6 @@
 // See LICENSE file for copyright and license details.
 
+use std::time::{Duration};
 use cgmath::{Vector2};
 use glutin::{self, Event, MouseButton, VirtualKeyCode};
 use glutin::ElementState::{Released};
@@ -7,6 +8,7 @@ use screen::{Screen, ScreenCommand, EventStatus};
 use context::{Context};
 use gui::{ButtonManager, Button, is_tap};
 use core::{PlayerId};
+use core::clock::{duration_to_str};
 use types::{ScreenPos, Time};
 
 pub struct EndTurnScreen {
@@ -17,13 +19,28 @@ impl EndTurnScreen {
     pub fn new(
         context: &mut Context,
         player_id: &PlayerId,
+        time_left: Option<Duration>,
+        time_bank: Option<Duration>,
     ) -> EndTurnScreen {
         let mut button_manager = ButtonManager::new();
-        let pos = ScreenPos{v: Vector2{x: 10, y: 10}};
+        let mut pos = ScreenPos{v: Vector2{x: 10, y: 10}};
         let str = format!("Pass the device to Player {}", player_id.id);
         // TODO: button -> label + center on screen
-        let _ = button_manager.add_button(Button::new(
+        let id = button_manager.add_button(Button::new(
             context, &str, &pos));
+        let mut lines = Vec::new();
+        if let Some(time_left) = time_left {
+            lines.push(format!("Time for the turn: {}", duration_to_str(&time_left)));
+        }
+        if let Some(time_bank) = time_bank {
+            lines.push(format!("Time bank: {}", duration_to_str(&time_bank)));
+        }
+        let mut h = button_manager.buttons()[&id].size().h;
+        for line in &lines {
+            pos.v.y += h;
+            let id = button_manager.add_button(Button::new(context, line, &pos));
+            h = button_manager.buttons()[&id].size().h;
+        }
         EndTurnScreen {
             button_manager: button_manager,
         }
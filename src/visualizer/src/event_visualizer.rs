@@ -5,8 +5,8 @@ use rand::{thread_rng, Rng};
 use cgmath::{Vector3, rad};
 use core::partial_state::{PartialState};
 use core::game_state::{GameState};
-use core::{self, UnitInfo, AttackInfo, ReactionFireMode, UnitId, ExactPos};
-use core::unit::{UnitTypeId};
+use core::{self, UnitInfo, AttackInfo, ReactionFireMode, UnitId, ExactPos, MapPos, NoiseClass};
+use core::unit::{UnitTypeId, UnitClass};
 use core::db::{Db};
 use types::{ZFloat, ZInt, WorldPos, Time};
 use mesh::{MeshId};
@@ -92,7 +92,12 @@ fn show_unit_at(
     let world_pos = geom::exact_pos_to_world_pos(&unit_info.pos);
     let to = world_pos;
     let rot = rad(thread_rng().gen_range(0.0, PI * 2.0));
-    let mut children = get_unit_scene_nodes(db, &unit_info.type_id, mesh_id);
+    // unidentified contacts are shown only by their markers
+    let mut children = if unit_info.is_contact {
+        Vec::new()
+    } else {
+        get_unit_scene_nodes(db, &unit_info.type_id, mesh_id)
+    };
     children.push(SceneNode {
         pos: WorldPos{v: vec3_z(geom::HEX_EX_RADIUS / 2.0)},
         rot: rad(0.0),
@@ -209,24 +214,32 @@ impl EventAttackUnitVisualizer {
             let attacker_node_id = scene.unit_id_to_node_id(&attacker_id);
             let attacker_pos = scene.node(&attacker_node_id).pos.clone();
             let attacker_map_pos = state.unit(&attacker_id).pos.clone();
-            if let core::FireMode::Reactive = attack_info.mode {
-                map_text.add_text(&attacker_map_pos, "reaction fire");
+            if attack_info.is_assault {
+                map_text.add_text(&attacker_map_pos, "assault");
+            } else {
+                if let core::FireMode::Reactive = attack_info.mode {
+                    map_text.add_text(&attacker_map_pos, "reaction fire");
+                }
+                shell_node_id = Some(scene.add_node(SceneNode {
+                    pos: from.clone(),
+                    rot: geom::get_rot_angle(&attacker_pos, &defender_pos),
+                    mesh_id: Some(shell_mesh_id.clone()),
+                    children: Vec::new(),
+                }));
+                let shell_speed = 10.0;
+                shell_move = Some(MoveHelper::new(
+                    &attacker_pos, &defender_pos, shell_speed));
             }
-            shell_node_id = Some(scene.add_node(SceneNode {
-                pos: from.clone(),
-                rot: geom::get_rot_angle(&attacker_pos, &defender_pos),
-                mesh_id: Some(shell_mesh_id.clone()),
-                children: Vec::new(),
-            }));
-            let shell_speed = 10.0;
-            shell_move = Some(MoveHelper::new(
-                &attacker_pos, &defender_pos, shell_speed));
         }
         if attack_info.is_ambush {
             map_text.add_text(&defender.pos, "Ambushed");
         };
-        let is_target_destroyed = defender.count - attack_info.killed <= 0;
-        if attack_info.killed > 0 {
+        // contacts are hidden by a separate event when destroyed
+        let killed = if defender.is_contact { 0 } else { attack_info.killed };
+        let is_target_destroyed = defender.count - killed <= 0;
+        if defender.is_contact && attack_info.killed > 0 {
+            map_text.add_text(&defender.pos, "hit");
+        } else if attack_info.killed > 0 {
             map_text.add_text(&defender.pos, &format!("-{}", attack_info.killed));
         } else {
             map_text.add_text(&defender.pos, "miss");
@@ -244,7 +257,7 @@ impl EventAttackUnitVisualizer {
         }
         Box::new(EventAttackUnitVisualizer {
             defender_node_id: defender_node_id,
-            killed: attack_info.killed.clone(),
+            killed: killed,
             is_inderect: attack_info.is_inderect.clone(),
             is_target_destroyed: is_target_destroyed,
             move_helper: move_helper,
@@ -319,12 +332,27 @@ impl EventShowUnitVisualizer {
     pub fn new(
         db: &Db,
         scene: &mut Scene,
+        state: &PartialState,
         unit_info: &UnitInfo,
         mesh_id: &MeshId,
         marker_mesh_id: &MeshId,
         map_text: &mut MapTextManager,
     ) -> Box<EventVisualizer> {
-        map_text.add_text(&unit_info.pos, "spotted");
+        let is_known = state.units().get(&unit_info.unit_id).is_some();
+        if unit_info.is_contact {
+            let text = match db.unit_type(&unit_info.type_id).class {
+                UnitClass::Infantry => "infantry contact",
+                UnitClass::Vehicle => "vehicle contact",
+            };
+            map_text.add_text(&unit_info.pos, text);
+        } else if is_known {
+            map_text.add_text(&unit_info.pos, "identified");
+        } else {
+            map_text.add_text(&unit_info.pos, "spotted");
+        }
+        if scene.has_unit(&unit_info.unit_id) {
+            scene.remove_unit(&unit_info.unit_id);
+        }
         show_unit_at(db, scene, unit_info, mesh_id, marker_mesh_id);
         Box::new(EventShowUnitVisualizer)
     }
@@ -382,7 +410,11 @@ impl EventUnloadUnitVisualizer {
         unit_type_visual_info: &UnitTypeVisualInfo,
         map_text: &mut MapTextManager,
     ) -> Box<EventVisualizer> {
-        map_text.add_text(&unit_info.pos, "unloaded");
+        if db.unit_type(&unit_info.type_id).is_towable {
+            map_text.add_text(&unit_info.pos, "unhooked");
+        } else {
+            map_text.add_text(&unit_info.pos, "unloaded");
+        }
         let to = geom::exact_pos_to_world_pos(&unit_info.pos);
         let from = geom::exact_pos_to_world_pos(transporter_pos);
         show_unit_at(db, scene, unit_info, mesh_id, marker_mesh_id);
@@ -411,6 +443,85 @@ impl EventVisualizer for EventUnloadUnitVisualizer {
     fn end(&mut self, _: &mut Scene, _: &PartialState) {}
 }
 
+pub struct EventBailOutVisualizer {
+    node_id: Option<NodeId>,
+    move_helper: Option<MoveHelper>,
+    killed: ZInt,
+}
+
+impl EventBailOutVisualizer {
+    pub fn new(
+        db: &Db,
+        state: &PartialState,
+        scene: &mut Scene,
+        unit_info: &UnitInfo,
+        killed: ZInt,
+        mesh_id: &MeshId,
+        marker_mesh_id: &MeshId,
+        transporter_pos: &ExactPos,
+        unit_type_visual_info: &UnitTypeVisualInfo,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        let is_dead = match state.units().get(&unit_info.unit_id) {
+            Some(unit) => unit.count - killed <= 0,
+            None => false,
+        };
+        if is_dead {
+            map_text.add_text(transporter_pos, "passengers killed");
+            return Box::new(EventBailOutVisualizer {
+                node_id: None,
+                move_helper: None,
+                killed: 0,
+            });
+        }
+        map_text.add_text(&unit_info.pos, "bailed out");
+        if killed > 0 {
+            map_text.add_text(&unit_info.pos, &format!("-{}", killed));
+        }
+        let to = geom::exact_pos_to_world_pos(&unit_info.pos);
+        let from = geom::exact_pos_to_world_pos(transporter_pos);
+        show_unit_at(db, scene, unit_info, mesh_id, marker_mesh_id);
+        let node_id = scene.unit_id_to_node_id(&unit_info.unit_id);
+        {
+            let unit_node = scene.node_mut(&node_id);
+            unit_node.pos = from.clone();
+            unit_node.rot = geom::get_rot_angle(&from, &to);
+        }
+        let move_speed = unit_type_visual_info.move_speed;
+        Box::new(EventBailOutVisualizer {
+            node_id: Some(node_id),
+            move_helper: Some(MoveHelper::new(&from, &to, move_speed)),
+            killed: killed,
+        })
+    }
+}
+
+impl EventVisualizer for EventBailOutVisualizer {
+    fn is_finished(&self) -> bool {
+        match self.move_helper {
+            Some(ref move_helper) => move_helper.is_finished(),
+            None => true,
+        }
+    }
+
+    fn draw(&mut self, scene: &mut Scene, dtime: &Time) {
+        if let (Some(ref node_id), Some(ref mut move_helper))
+            = (self.node_id.clone(), self.move_helper.as_mut())
+        {
+            scene.node_mut(node_id).pos = move_helper.step(dtime);
+        }
+    }
+
+    fn end(&mut self, scene: &mut Scene, _: &PartialState) {
+        if let Some(ref node_id) = self.node_id {
+            let children = &mut scene.node_mut(node_id).children;
+            for _ in 0 .. self.killed {
+                let _ = children.remove(0);
+            }
+        }
+    }
+}
+
 pub struct EventLoadUnitVisualizer {
     passenger_id: UnitId,
     move_helper: MoveHelper,
@@ -418,6 +529,7 @@ pub struct EventLoadUnitVisualizer {
 
 impl EventLoadUnitVisualizer {
     pub fn new(
+        db: &Db,
         scene: &mut Scene,
         state: &PartialState,
         unit_id: &UnitId,
@@ -425,8 +537,13 @@ impl EventLoadUnitVisualizer {
         unit_type_visual_info: &UnitTypeVisualInfo,
         map_text: &mut MapTextManager,
     ) -> Box<EventVisualizer> {
-        let unit_pos = &state.unit(unit_id).pos;
-        map_text.add_text(unit_pos, "loaded");
+        let unit = state.unit(unit_id);
+        let unit_pos = &unit.pos;
+        if db.unit_type(&unit.type_id).is_towable {
+            map_text.add_text(unit_pos, "hooked up");
+        } else {
+            map_text.add_text(unit_pos, "loaded");
+        }
         let from = geom::exact_pos_to_world_pos(unit_pos);
         let to = geom::exact_pos_to_world_pos(transporter_pos);
         let passenger_node_id = scene.unit_id_to_node_id(unit_id);
@@ -488,4 +605,30 @@ impl EventVisualizer for EventSetReactionFireModeVisualizer {
     fn end(&mut self, _: &mut Scene, _: &PartialState) {}
 }
 
+pub struct EventNoiseVisualizer;
+
+impl EventNoiseVisualizer {
+    pub fn new(
+        pos: &MapPos,
+        class: &NoiseClass,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        match *class {
+            NoiseClass::Engine => map_text.add_text(pos, "heard engine"),
+            NoiseClass::Gunfire => map_text.add_text(pos, "heard gunfire"),
+        }
+        Box::new(EventNoiseVisualizer)
+    }
+}
+
+impl EventVisualizer for EventNoiseVisualizer {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn draw(&mut self, _: &mut Scene, _: &Time) {}
+
+    fn end(&mut self, _: &mut Scene, _: &PartialState) {}
+}
+
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -97,6 +97,10 @@ impl ButtonManager {
         &self.buttons
     }
 
+    pub fn buttons_mut(&mut self) -> &mut HashMap<ButtonId, Button> {
+        &mut self.buttons
+    }
+
     pub fn add_button(&mut self, button: Button) -> ButtonId {
         let id = self.last_id.clone();
         self.buttons.insert(id.clone(), button);
@@ -33,6 +33,7 @@ mod geom;
 mod screen;
 mod texture;
 mod tactical_screen;
+mod editor_screen;
 mod context_menu_popup;
 mod main_menu_screen;
 mod end_turn_screen;
@@ -46,6 +47,9 @@ use gfx::traits::{Device};
 use screen::{Screen, ScreenCommand, EventStatus};
 use context::{Context};
 use main_menu_screen::{MainMenuScreen};
+use tactical_screen::{TacticalScreen};
+use core::pbem::{PbemGame};
+use core::scenario::{Scenario};
 use types::{Time};
 
 pub struct Visualizer {
@@ -58,11 +62,14 @@ pub struct Visualizer {
 }
 
 impl Visualizer {
-    pub fn new() -> Visualizer {
+    /// `server_address` is `host:port` of the server that
+    /// the "join server" button connects to, local games
+    /// are started from `scenario` if it is given
+    pub fn new(server_address: &str, scenario: Option<Scenario>) -> Visualizer {
         let (tx, rx) = channel();
         let mut context = Context::new(tx);
         let screens = vec![
-            Box::new(MainMenuScreen::new(&mut context)) as Box<Screen>,
+            Box::new(MainMenuScreen::new(&mut context, server_address, scenario)) as Box<Screen>,
         ];
         Visualizer {
             screens: screens,
@@ -74,6 +81,18 @@ impl Visualizer {
         }
     }
 
+    /// Skips the main menu
+    pub fn start_pbem_game(&mut self, game: PbemGame) {
+        let screen = Box::new(TacticalScreen::new_pbem(&mut self.context, game));
+        self.screens.push(screen);
+    }
+
+    /// Skips the main menu
+    pub fn start_replay(&mut self, game: PbemGame) {
+        let screen = Box::new(TacticalScreen::new_replay(&mut self.context, game));
+        self.screens.push(screen);
+    }
+
     pub fn tick(&mut self) {
         self.draw();
         self.handle_events();
@@ -1,24 +1,114 @@
 // See LICENSE file for copyright and license details.
 
 use std::default::{Default};
+use std::collections::{HashMap};
+use std::time::{Duration};
 use cgmath::{Vector2};
 use glutin::{self, Event, MouseButton, VirtualKeyCode};
 use glutin::ElementState::{Released};
 use screen::{Screen, ScreenCommand, EventStatus};
 use tactical_screen::{TacticalScreen};
+use editor_screen::{EditorScreen};
 use core;
+use core::net::{Client};
+use core::clock::{TimeControl};
+use core::scenario::{Scenario};
 use context::{Context};
 use gui::{ButtonManager, Button, ButtonId, is_tap};
-use types::{ScreenPos, Time};
+use types::{ZInt, ScreenPos, Time};
+
+/// Search budget of the MCTS AI, it must not make the player wait too long
+const MCTS_MAX_ITERATIONS: ZInt = 2000;
+const MCTS_MAX_TIME_MS: u64 = 1000;
+
+/// Label, turn limit and time bank in seconds
+const TIME_CONTROLS: [(&'static str, Option<u64>, Option<u64>); 4] = [
+    ("time: unlimited", None, None),
+    ("time: 1 min per turn", Some(60), None),
+    ("time: 10 min bank", None, Some(600)),
+    ("time: 1 min per turn, 10 min bank", Some(60), Some(600)),
+];
+
+fn time_control(index: usize) -> TimeControl {
+    let (_, turn_limit, time_bank) = TIME_CONTROLS[index];
+    TimeControl {
+        turn_limit: turn_limit.map(Duration::from_secs),
+        time_bank: time_bank.map(Duration::from_secs),
+    }
+}
+
+fn vision_sharing_label(vision_sharing: &core::VisionSharing) -> &'static str {
+    match *vision_sharing {
+        core::VisionSharing::Full => "vision: shared",
+        core::VisionSharing::CommandNet => "vision: command net",
+    }
+}
+
+fn turn_mode_label(turn_mode: &core::TurnMode) -> &'static str {
+    match *turn_mode {
+        core::TurnMode::Igougo => "turns: alternate",
+        core::TurnMode::Wego => "turns: simultaneous",
+    }
+}
+
+fn ai_difficulty_label(difficulty: &core::AiDifficulty) -> &'static str {
+    match *difficulty {
+        core::AiDifficulty::Easy => "ai: easy",
+        core::AiDifficulty::Normal => "ai: normal",
+        core::AiDifficulty::Hard => "ai: hard",
+    }
+}
+
+fn ai_posture_label(posture: &core::AiPosture) -> &'static str {
+    match *posture {
+        core::AiPosture::Attack => "ai: attack",
+        core::AiPosture::Defend => "ai: defend",
+    }
+}
+
+fn ai_backend_label(backend: &core::AiBackend) -> &'static str {
+    match *backend {
+        core::AiBackend::Utility => "ai: utility",
+        core::AiBackend::Mcts{..} => "ai: mcts",
+    }
+}
 
 pub struct MainMenuScreen {
     button_start_hotseat_id: ButtonId,
     button_start_vs_ai_id: ButtonId,
+    button_start_ai_vs_ai_id: ButtonId,
+    button_join_server_id: ButtonId,
+    button_editor_id: ButtonId,
+    button_vision_sharing_id: ButtonId,
+    button_turn_mode_id: ButtonId,
+    button_time_control_id: ButtonId,
+    button_ai_difficulty_id: ButtonId,
+    button_ai_posture_id: ButtonId,
+    button_ai_backend_id: ButtonId,
     button_manager: ButtonManager,
+    vision_sharing: core::VisionSharing,
+    turn_mode: core::TurnMode,
+
+    /// Index in `TIME_CONTROLS`
+    time_control_index: usize,
+
+    ai_difficulty: core::AiDifficulty,
+    ai_posture: core::AiPosture,
+    ai_backend: core::AiBackend,
+
+    /// `host:port` of the server to join
+    server_address: String,
+
+    /// Scenario of local games, `--scenario` argument
+    scenario: Option<Scenario>,
 }
 
 impl MainMenuScreen {
-    pub fn new(context: &mut Context) -> MainMenuScreen {
+    pub fn new(
+        context: &mut Context,
+        server_address: &str,
+        scenario: Option<Scenario>,
+    ) -> MainMenuScreen {
         let mut button_manager = ButtonManager::new();
         // TODO: Use relative coords in ScreenPos - x: [0.0, 1.0], y: [0.0, 1.0]
         // TODO: Add analog of Qt::Alignment
@@ -36,10 +126,191 @@ impl MainMenuScreen {
             "start human vs ai",
             &button_pos,
         ));
+        button_pos.v.y += button_manager.buttons()[&button_start_vs_ai_id]
+            .size().h;
+        let button_start_ai_vs_ai_id = button_manager.add_button(Button::new(
+            context,
+            "watch ai vs ai",
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_start_ai_vs_ai_id]
+            .size().h;
+        let button_join_server_id = button_manager.add_button(Button::new(
+            context,
+            "join server",
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_join_server_id]
+            .size().h;
+        let button_editor_id = button_manager.add_button(Button::new(
+            context,
+            "map editor",
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_editor_id]
+            .size().h;
+        let vision_sharing = core::VisionSharing::Full;
+        let button_vision_sharing_id = button_manager.add_button(Button::new(
+            context,
+            vision_sharing_label(&vision_sharing),
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_vision_sharing_id]
+            .size().h;
+        let turn_mode = core::TurnMode::Igougo;
+        let button_turn_mode_id = button_manager.add_button(Button::new(
+            context,
+            turn_mode_label(&turn_mode),
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_turn_mode_id]
+            .size().h;
+        let time_control_index = 0;
+        let button_time_control_id = button_manager.add_button(Button::new(
+            context,
+            TIME_CONTROLS[time_control_index].0,
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_time_control_id]
+            .size().h;
+        let ai_difficulty = core::AiDifficulty::Normal;
+        let button_ai_difficulty_id = button_manager.add_button(Button::new(
+            context,
+            ai_difficulty_label(&ai_difficulty),
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_ai_difficulty_id]
+            .size().h;
+        let ai_posture = core::AiPosture::Attack;
+        let button_ai_posture_id = button_manager.add_button(Button::new(
+            context,
+            ai_posture_label(&ai_posture),
+            &button_pos,
+        ));
+        button_pos.v.y += button_manager.buttons()[&button_ai_posture_id]
+            .size().h;
+        let ai_backend = core::AiBackend::Utility;
+        let button_ai_backend_id = button_manager.add_button(Button::new(
+            context,
+            ai_backend_label(&ai_backend),
+            &button_pos,
+        ));
         MainMenuScreen {
             button_manager: button_manager,
             button_start_hotseat_id: button_start_hotseat_id,
             button_start_vs_ai_id: button_start_vs_ai_id,
+            button_start_ai_vs_ai_id: button_start_ai_vs_ai_id,
+            button_join_server_id: button_join_server_id,
+            button_editor_id: button_editor_id,
+            button_vision_sharing_id: button_vision_sharing_id,
+            button_turn_mode_id: button_turn_mode_id,
+            button_time_control_id: button_time_control_id,
+            button_ai_difficulty_id: button_ai_difficulty_id,
+            button_ai_posture_id: button_ai_posture_id,
+            button_ai_backend_id: button_ai_backend_id,
+            vision_sharing: vision_sharing,
+            turn_mode: turn_mode,
+            time_control_index: time_control_index,
+            ai_difficulty: ai_difficulty,
+            ai_posture: ai_posture,
+            ai_backend: ai_backend,
+            server_address: server_address.to_owned(),
+            scenario: scenario,
+        }
+    }
+
+    fn switch_vision_sharing(&mut self, context: &mut Context) {
+        self.vision_sharing = match self.vision_sharing {
+            core::VisionSharing::Full => core::VisionSharing::CommandNet,
+            core::VisionSharing::CommandNet => core::VisionSharing::Full,
+        };
+        let id = &self.button_vision_sharing_id;
+        let pos = self.button_manager.buttons()[id].pos().clone();
+        let label = vision_sharing_label(&self.vision_sharing);
+        let button = Button::new(context, label, &pos);
+        self.button_manager.buttons_mut().insert(id.clone(), button);
+    }
+
+    fn switch_turn_mode(&mut self, context: &mut Context) {
+        self.turn_mode = match self.turn_mode {
+            core::TurnMode::Igougo => core::TurnMode::Wego,
+            core::TurnMode::Wego => core::TurnMode::Igougo,
+        };
+        let id = &self.button_turn_mode_id;
+        let pos = self.button_manager.buttons()[id].pos().clone();
+        let label = turn_mode_label(&self.turn_mode);
+        let button = Button::new(context, label, &pos);
+        self.button_manager.buttons_mut().insert(id.clone(), button);
+    }
+
+    fn switch_time_control(&mut self, context: &mut Context) {
+        self.time_control_index = (self.time_control_index + 1) % TIME_CONTROLS.len();
+        let id = &self.button_time_control_id;
+        let pos = self.button_manager.buttons()[id].pos().clone();
+        let label = TIME_CONTROLS[self.time_control_index].0;
+        let button = Button::new(context, label, &pos);
+        self.button_manager.buttons_mut().insert(id.clone(), button);
+    }
+
+    fn switch_ai_difficulty(&mut self, context: &mut Context) {
+        self.ai_difficulty = match self.ai_difficulty {
+            core::AiDifficulty::Easy => core::AiDifficulty::Normal,
+            core::AiDifficulty::Normal => core::AiDifficulty::Hard,
+            core::AiDifficulty::Hard => core::AiDifficulty::Easy,
+        };
+        let id = &self.button_ai_difficulty_id;
+        let pos = self.button_manager.buttons()[id].pos().clone();
+        let label = ai_difficulty_label(&self.ai_difficulty);
+        let button = Button::new(context, label, &pos);
+        self.button_manager.buttons_mut().insert(id.clone(), button);
+    }
+
+    fn switch_ai_posture(&mut self, context: &mut Context) {
+        self.ai_posture = match self.ai_posture {
+            core::AiPosture::Attack => core::AiPosture::Defend,
+            core::AiPosture::Defend => core::AiPosture::Attack,
+        };
+        let id = &self.button_ai_posture_id;
+        let pos = self.button_manager.buttons()[id].pos().clone();
+        let label = ai_posture_label(&self.ai_posture);
+        let button = Button::new(context, label, &pos);
+        self.button_manager.buttons_mut().insert(id.clone(), button);
+    }
+
+    fn switch_ai_backend(&mut self, context: &mut Context) {
+        self.ai_backend = match self.ai_backend {
+            core::AiBackend::Utility => core::AiBackend::Mcts {
+                max_iterations: MCTS_MAX_ITERATIONS,
+                max_time_ms: MCTS_MAX_TIME_MS,
+            },
+            core::AiBackend::Mcts{..} => core::AiBackend::Utility,
+        };
+        let id = &self.button_ai_backend_id;
+        let pos = self.button_manager.buttons()[id].pos().clone();
+        let label = ai_backend_label(&self.ai_backend);
+        let button = Button::new(context, label, &pos);
+        self.button_manager.buttons_mut().insert(id.clone(), button);
+    }
+
+    fn join_server(&mut self, context: &mut Context) {
+        match Client::connect(self.server_address.as_str()) {
+            Ok(client) => {
+                let tactical_screen = Box::new(TacticalScreen::new_client(context, client));
+                context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            },
+            Err(err) => {
+                println!("Can`t connect to {}: {}", self.server_address, err);
+            },
+        }
+    }
+
+    fn start_game(&mut self, context: &mut Context, core_options: &core::Options) {
+        match TacticalScreen::new(context, core_options) {
+            Ok(tactical_screen) => {
+                let tactical_screen = Box::new(tactical_screen);
+                context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            },
+            Err(err) => println!("Can`t start the game: {}", err),
         }
     }
 
@@ -58,16 +329,60 @@ impl MainMenuScreen {
         button_id: &ButtonId
     ) {
         if *button_id == self.button_start_hotseat_id {
-            let core_options = Default::default();
-            let tactical_screen = Box::new(TacticalScreen::new(context, &core_options));
-            context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            let core_options = core::Options {
+                vision_sharing: self.vision_sharing.clone(),
+                turn_mode: self.turn_mode.clone(),
+                time_control: time_control(self.time_control_index),
+                scenario: self.scenario.clone(),
+                .. Default::default()
+            };
+            self.start_game(context, &core_options);
         } else if *button_id == self.button_start_vs_ai_id {
             let core_options = core::Options {
                 game_type: core::GameType::SingleVsAi,
+                vision_sharing: self.vision_sharing.clone(),
+                turn_mode: self.turn_mode.clone(),
+                time_control: time_control(self.time_control_index),
+                ai_difficulty: self.ai_difficulty.clone(),
+                ai_posture: self.ai_posture.clone(),
+                ai_backend: self.ai_backend.clone(),
+                scenario: self.scenario.clone(),
+                .. Default::default()
+            };
+            self.start_game(context, &core_options);
+        } else if *button_id == self.button_start_ai_vs_ai_id {
+            let mut bots = HashMap::new();
+            bots.insert(core::PlayerId{id: 0}, core::BotKind::Builtin);
+            bots.insert(core::PlayerId{id: 1}, core::BotKind::Builtin);
+            let core_options = core::Options {
+                vision_sharing: self.vision_sharing.clone(),
+                turn_mode: self.turn_mode.clone(),
+                ai_difficulty: self.ai_difficulty.clone(),
+                ai_posture: self.ai_posture.clone(),
+                ai_backend: self.ai_backend.clone(),
+                bots: bots,
+                observer: true,
+                scenario: self.scenario.clone(),
                 .. Default::default()
             };
-            let tactical_screen = Box::new(TacticalScreen::new(context, &core_options));
-            context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            self.start_game(context, &core_options);
+        } else if *button_id == self.button_join_server_id {
+            self.join_server(context);
+        } else if *button_id == self.button_editor_id {
+            let editor_screen = Box::new(EditorScreen::new(context));
+            context.add_command(ScreenCommand::PushScreen(editor_screen));
+        } else if *button_id == self.button_vision_sharing_id {
+            self.switch_vision_sharing(context);
+        } else if *button_id == self.button_turn_mode_id {
+            self.switch_turn_mode(context);
+        } else if *button_id == self.button_time_control_id {
+            self.switch_time_control(context);
+        } else if *button_id == self.button_ai_difficulty_id {
+            self.switch_ai_difficulty(context);
+        } else if *button_id == self.button_ai_posture_id {
+            self.switch_ai_posture(context);
+        } else if *button_id == self.button_ai_backend_id {
+            self.switch_ai_backend(context);
         } else {
             panic!("Bad button id: {}", button_id.id);
         }
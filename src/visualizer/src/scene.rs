@@ -54,6 +54,10 @@ impl Scene {
         self.unit_id_to_node_id_map.remove(unit_id).unwrap();
     }
 
+    pub fn has_unit(&self, unit_id: &UnitId) -> bool {
+        self.unit_id_to_node_id_map.contains_key(unit_id)
+    }
+
     pub fn add_unit(&mut self, unit_id: &UnitId, node: SceneNode) -> NodeId {
         let node_id = self.add_node(node);
         assert!(!self.unit_id_to_node_id_map.contains_key(unit_id));
@@ -5,6 +5,9 @@ use std::f32::consts::{PI};
 use rand::{thread_rng, Rng};
 use std::path::{Path};
 use std::collections::{HashMap};
+use std::fs::{File};
+use std::io::{self, Write};
+use std::time::{Duration};
 use cgmath::{
     Vector2,
     Vector3,
@@ -23,7 +26,7 @@ use glutin::ElementState::{Released};
 use types::{Size2, ZInt, ZFloat, Time};
 use core::map::{Map, Terrain, spiral_iter};
 use core::dir::{Dir, dirs};
-use core::partial_state::{PartialState};
+use core::partial_state::{PartialState, GHOST_LIFETIME};
 use core::game_state::{GameState, GameStateMut};
 use core::pathfinder::{Pathfinder};
 use core::{
@@ -36,16 +39,27 @@ use core::{
     MovePoints,
     UnitId,
     PlayerId,
+    ObserverView,
     MapPos,
     ExactPos,
     SlotId,
+    NoiseClass,
+    VisionSharing,
+    TurnMode,
     check_command,
+    is_target_spotted,
     get_unit_ids_at,
     find_next_player_unit_id,
     find_prev_player_unit_id,
     get_free_exact_pos,
+    get_attack_odds,
 };
 use core::db::{Db};
+use core::net::{Client, ServerMessage};
+use core::pbem::{PbemGame};
+use core::orders::{Order, OrderQueue};
+use core::clock::{duration_to_str};
+use core::scenario::{Scenario};
 use obj;
 use camera::Camera;
 use gui::{ButtonManager, Button, ButtonId, is_tap};
@@ -57,10 +71,12 @@ use event_visualizer::{
     EventCreateUnitVisualizer,
     EventUnloadUnitVisualizer,
     EventLoadUnitVisualizer,
+    EventBailOutVisualizer,
     EventAttackUnitVisualizer,
     EventShowUnitVisualizer,
     EventHideUnitVisualizer,
     EventSetReactionFireModeVisualizer,
+    EventNoiseVisualizer,
 };
 use unit_type_visual_info::{
     UnitTypeVisualInfo,
@@ -79,29 +95,39 @@ use context_menu_popup::{self, ContextMenuPopup};
 use end_turn_screen::{EndTurnScreen};
 use types::{ScreenPos, WorldPos};
 
-fn get_initial_camera_pos(map_size: &Size2) -> WorldPos {
+/// Opacity of a fresh last-known-position marker
+const GHOST_ALPHA: ZFloat = 0.5;
+
+const NOISE_ENGINE_COLOR: [f32; 4] = [1.0, 0.6, 0.0, 1.0];
+
+const NOISE_GUNFIRE_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+const ORDER_MOVE_COLOR: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+const ORDER_ATTACK_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+pub fn get_initial_camera_pos(map_size: &Size2) -> WorldPos {
     let pos = get_max_camera_pos(map_size);
     WorldPos{v: Vector3{x: pos.v.x / 2.0, y: pos.v.y / 2.0, z: 0.0}}
 }
 
-fn get_max_camera_pos(map_size: &Size2) -> WorldPos {
+pub fn get_max_camera_pos(map_size: &Size2) -> WorldPos {
     let map_pos = MapPos{v: Vector2{x: map_size.w, y: map_size.h - 1}};
     let pos = geom::map_pos_to_world_pos(&map_pos);
     WorldPos{v: Vector3{x: -pos.v.x, y: -pos.v.y, z: 0.0}}
 }
 
-// TODO: `cond: F` -> `enum NameMe{Visible, Fogged}`
-fn gen_tiles<F: Fn(bool) -> bool>(
+/// Mesh of the tiles for which `cond` is true
+pub fn gen_tiles<T: Clone + Default, F: Fn(&MapPos) -> bool>(
     context: &mut Context,
-    state: &PartialState,
+    map: &Map<T>,
     tex: Texture,
     cond: F,
 ) -> Mesh {
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
     let mut i = 0;
-    for tile_pos in state.map().get_iter() {
-        if !cond(state.is_tile_visible(&tile_pos)) {
+    for tile_pos in map.get_iter() {
+        if !cond(&tile_pos) {
             continue;
         }
         let pos = geom::map_pos_to_world_pos(&tile_pos);
@@ -126,11 +152,11 @@ fn gen_tiles<F: Fn(bool) -> bool>(
 }
 
 fn generate_visible_tiles_mesh(context: &mut Context, state: &PartialState, tex: Texture) -> Mesh {
-    gen_tiles(context, state, tex, |vis| vis)
+    gen_tiles(context, state.map(), tex, |pos| state.is_tile_visible(pos))
 }
 
 fn generate_fogged_tiles_mesh(context: &mut Context, state: &PartialState, tex: Texture) -> Mesh {
-    gen_tiles(context, state, tex, |vis| !vis)
+    gen_tiles(context, state.map(), tex, |pos| !state.is_tile_visible(pos))
 }
 
 fn build_walkable_mesh(
@@ -173,7 +199,41 @@ fn build_walkable_mesh(
     Mesh::new_wireframe(context, &vertices, &indices)
 }
 
-fn build_targets_mesh(db: &Db, context: &mut Context, state: &PartialState, unit_id: &UnitId) -> Mesh {
+/// Indirect fire in command net mode needs a spotter in the net
+fn can_attack(
+    db: &Db,
+    state: &PartialState,
+    vision_sharing: &VisionSharing,
+    attacker_id: &UnitId,
+    defender_id: &UnitId,
+) -> bool {
+    let command = Command::AttackUnit {
+        attacker_id: attacker_id.clone(),
+        defender_id: defender_id.clone(),
+    };
+    if !check_command(db, state, &command).is_ok() {
+        return false;
+    }
+    let attacker = state.unit(attacker_id);
+    let attacker_type = db.unit_type(&attacker.type_id);
+    if !db.weapon_type(&attacker_type.weapon_type_id).is_inderect {
+        return true;
+    }
+    match *vision_sharing {
+        VisionSharing::Full => true,
+        VisionSharing::CommandNet => {
+            is_target_spotted(db, state, attacker, state.unit(defender_id))
+        },
+    }
+}
+
+fn build_targets_mesh(
+    db: &Db,
+    context: &mut Context,
+    state: &PartialState,
+    vision_sharing: &VisionSharing,
+    unit_id: &UnitId,
+) -> Mesh {
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
     let unit = state.unit(unit_id);
@@ -182,11 +242,7 @@ fn build_targets_mesh(db: &Db, context: &mut Context, state: &PartialState, unit
         if unit.player_id == enemy.player_id {
             continue;
         }
-        let command = Command::AttackUnit {
-            attacker_id: unit_id.clone(),
-            defender_id: enemy_id.clone(),
-        };
-        if !check_command(db, state, &command).is_ok() {
+        if !can_attack(db, state, vision_sharing, unit_id, enemy_id) {
             continue;
         }
         let world_pos_from = geom::exact_pos_to_world_pos(&unit.pos);
@@ -220,7 +276,7 @@ fn get_shell_mesh(context: &mut Context) -> Mesh {
     Mesh::new(context, &vertices, &indices, texture)
 }
 
-fn get_marker<P: AsRef<Path>>(context: &mut Context, tex_path: P) -> Mesh {
+pub fn get_marker<P: AsRef<Path>>(context: &mut Context, tex_path: P) -> Mesh {
     let n = 0.2;
     let vertices = [
         Vertex{pos: [-n, 0.0, 0.1], uv: [0.0, 0.0]},
@@ -233,7 +289,7 @@ fn get_marker<P: AsRef<Path>>(context: &mut Context, tex_path: P) -> Mesh {
     Mesh::new(context, &vertices, &indices, texture)
 }
 
-fn load_object_mesh(context: &mut Context, name: &str) -> Mesh {
+pub fn load_object_mesh(context: &mut Context, name: &str) -> Mesh {
     let model = obj::Model::new(&format!("{}.obj", name));
     let (vertices, indices) = obj::build(&model);
     if model.is_wire() {
@@ -245,6 +301,75 @@ fn load_object_mesh(context: &mut Context, name: &str) -> Mesh {
     }
 }
 
+pub fn pick_world_pos(context: &Context, camera: &Camera) -> WorldPos {
+    let im = camera.mat().invert()
+        .expect("Can`t invert camera matrix");
+    let w = context.win_size.w as ZFloat;
+    let h = context.win_size.h as ZFloat;
+    let x = context.mouse().pos.v.x as ZFloat;
+    let y = context.mouse().pos.v.y as ZFloat;
+    let x = (2.0 * x) / w - 1.0;
+    let y = 1.0 - (2.0 * y) / h;
+    let p0_raw = im * Vector4{x: x, y: y, z: 0.0, w: 1.0};
+    let p0 = (p0_raw / p0_raw.w).truncate();
+    let p1_raw = im * Vector4{x: x, y: y, z: 1.0, w: 1.0};
+    let p1 = (p1_raw / p1_raw.w).truncate();
+    let plane = Plane::from_abcd(0.0, 0.0, 1.0, 0.0);
+    let ray = Ray::new(Point3::from_vec(p0), p1 - p0);
+    let p = (plane, ray).intersection()
+        .expect("Can`t find mouse ray/plane intersection");
+    WorldPos{v: p.to_vec()}
+}
+
+/// Tile under the mouse cursor
+pub fn pick_tile(context: &Context, camera: &Camera, map_size: &Size2) -> Option<MapPos> {
+    let p = pick_world_pos(context, camera);
+    let origin = MapPos{v: Vector2 {
+        x: (p.v.x / (geom::HEX_IN_RADIUS * 2.0)) as ZInt,
+        y: (p.v.y / (geom::HEX_EX_RADIUS * 1.5)) as ZInt,
+    }};
+    let origin_world_pos = geom::map_pos_to_world_pos(&origin);
+    let mut closest_map_pos = origin.clone();
+    let mut min_dist = (origin_world_pos.v - p.v).magnitude();
+    for map_pos in spiral_iter(&origin, 1) {
+        let pos = geom::map_pos_to_world_pos(&map_pos);
+        let d = (pos.v - p.v).magnitude();
+        if d < min_dist {
+            min_dist = d;
+            closest_map_pos = map_pos;
+        }
+    }
+    let pos = closest_map_pos;
+    if pos.v.x >= 0 && pos.v.y >= 0 && pos.v.x < map_size.w && pos.v.y < map_size.h {
+        Some(pos)
+    } else {
+        None
+    }
+}
+
+/// Drags the camera after the mouse
+pub fn move_camera(context: &Context, camera: &mut Camera, pos: &ScreenPos) {
+    let diff = pos.v - context.mouse().pos.v;
+    let camera_move_speed = geom::HEX_EX_RADIUS * 12.0;
+    let per_x_pixel = camera_move_speed / (context.win_size.w as ZFloat);
+    let per_y_pixel = camera_move_speed / (context.win_size.h as ZFloat);
+    camera.move_camera(
+        rad(PI), diff.x as ZFloat * per_x_pixel);
+    camera.move_camera(
+        rad(PI * 1.5), diff.y as ZFloat * per_y_pixel);
+}
+
+pub fn rotate_camera(context: &Context, camera: &mut Camera, pos: &ScreenPos) {
+    let diff = pos.v - context.mouse().pos.v;
+    let per_x_pixel = PI / (context.win_size.w as ZFloat);
+    // TODO: get max angles from camera
+    let per_y_pixel = (PI / 4.0) / (context.win_size.h as ZFloat);
+    camera.add_horizontal_angle(
+        rad(diff.x as ZFloat * per_x_pixel));
+    camera.add_vertical_angle(
+        rad(diff.y as ZFloat * per_y_pixel));
+}
+
 fn get_marker_mesh_id<'a>(mesh_ids: &'a MeshIdManager, player_id: &PlayerId) -> &'a MeshId {
     match player_id.id {
         0 => &mesh_ids.marker_1_mesh_id,
@@ -260,14 +385,16 @@ struct MeshIdManager {
     shell_mesh_id: MeshId,
     marker_1_mesh_id: MeshId,
     marker_2_mesh_id: MeshId,
+    noise_marker_mesh_id: MeshId,
+    order_marker_mesh_id: MeshId,
 }
 
-fn add_mesh(meshes: &mut Vec<Mesh>, mesh: Mesh) -> MeshId {
+pub fn add_mesh(meshes: &mut Vec<Mesh>, mesh: Mesh) -> MeshId {
     meshes.push(mesh);
     MeshId{id: (meshes.len() as ZInt) - 1}
 }
 
-fn get_unit_type_visual_info(
+pub fn get_unit_type_visual_info(
     db: &Db,
     context: &mut Context,
     meshes: &mut Vec<Mesh>,
@@ -292,6 +419,17 @@ fn get_unit_type_visual_info(
             move_speed: move_speed,
         });
     }
+    // contacts are drawn without meshes so these are only placeholders
+    for &(contact_name, unit_name, move_speed) in &[
+        ("infantry_contact", "soldier", 2.0),
+        ("vehicle_contact", "truck", 3.0),
+    ] {
+        let mesh_id = manager.get(&db.unit_type_id(unit_name)).mesh_id.clone();
+        manager.add_info(&db.unit_type_id(contact_name), UnitTypeVisualInfo {
+            mesh_id: mesh_id,
+            move_speed: move_speed,
+        });
+    }
     manager
 }
 
@@ -301,36 +439,267 @@ struct PlayerInfo {
     scene: Scene,
 }
 
+/// Whose eyes the screen looks through
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ViewId {
+    Player(PlayerId),
+    Observer(ObserverView),
+}
+
 struct PlayerInfoManager {
-    info: HashMap<PlayerId, PlayerInfo>,
+    info: HashMap<ViewId, PlayerInfo>,
 }
 
 impl PlayerInfoManager {
-    fn new(map_size: &Size2, options: &core::Options) -> PlayerInfoManager {
+    fn new(scenario: &Scenario, view_ids: &[ViewId]) -> PlayerInfoManager {
         let mut m = HashMap::new();
-        m.insert(PlayerId{id: 0}, PlayerInfo {
-            game_state: PartialState::new(map_size, &PlayerId{id: 0}),
-            pathfinder: Pathfinder::new(map_size),
-            scene: Scene::new(),
-        });
-        if options.game_type == core::GameType::Hotseat {
-            m.insert(PlayerId{id: 1}, PlayerInfo {
-                game_state: PartialState::new(map_size, &PlayerId{id: 1}),
-                pathfinder: Pathfinder::new(map_size),
+        for view_id in view_ids {
+            let game_state = match *view_id {
+                ViewId::Player(ref player_id)
+                    | ViewId::Observer(ObserverView::Player(ref player_id))
+                    => PartialState::from_scenario(scenario, player_id),
+                ViewId::Observer(ObserverView::God)
+                    => PartialState::new_god_view(scenario),
+            };
+            m.insert(view_id.clone(), PlayerInfo {
+                game_state: game_state,
+                pathfinder: Pathfinder::new(scenario.size()),
                 scene: Scene::new(),
             });
         }
         PlayerInfoManager{info: m}
     }
 
-    fn get(&self, player_id: &PlayerId) -> &PlayerInfo {
-        &self.info[player_id]
+    fn get(&self, view_id: &ViewId) -> &PlayerInfo {
+        &self.info[view_id]
     }
 
-    fn get_mut(&mut self, player_id: &PlayerId) -> &mut PlayerInfo {
-        match self.info.get_mut(player_id) {
+    fn get_mut(&mut self, view_id: &ViewId) -> &mut PlayerInfo {
+        match self.info.get_mut(view_id) {
             Some(i) => i,
-            None => panic!("Can`t find player_info for id={}", player_id.id),
+            None => panic!("Can`t find player_info"),
+        }
+    }
+
+    fn players_count(&self) -> usize {
+        self.info.keys()
+            .filter(|view_id| if let ViewId::Player(_) = **view_id { true } else { false })
+            .count()
+    }
+}
+
+/// Local game, a connection to the server or a play-by-email game
+enum Game {
+    Local(Core),
+    Remote(Client),
+    Pbem(PbemGame),
+}
+
+/// The last turn file of the game must be sent to all players
+fn save_turn_file(game: &mut PbemGame, is_game_over: bool) {
+    let turn = game.turn();
+    let result = if is_game_over { game.end_game() } else { game.end_turn() };
+    let text = match result {
+        Ok(text) => text,
+        Err(err) => {
+            println!("Can`t end turn: {}", err);
+            return;
+        },
+    };
+    let path = format!("turn_{}.zoc", turn);
+    match File::create(&path).and_then(|mut file| file.write_all(text.as_bytes())) {
+        Ok(()) if is_game_over => println!("Send {} to all players", path),
+        Ok(()) => println!("Send {} to the next player", path),
+        Err(err) => println!("Can`t write {}: {}", path, err),
+    }
+}
+
+/// Empty if the player is not timed
+fn time_label(game: &Game, player_id: &PlayerId) -> String {
+    let time_left = match game.time_left(player_id) {
+        Some(time_left) => time_left,
+        None => return String::new(),
+    };
+    match game.time_bank(player_id) {
+        Some(bank) => format!("time: {} (bank {})",
+            duration_to_str(&time_left), duration_to_str(&bank)),
+        None => format!("time: {}", duration_to_str(&time_left)),
+    }
+}
+
+impl Game {
+    fn db(&self) -> &Db {
+        match *self {
+            Game::Local(ref core) => core.db(),
+            Game::Remote(ref client) => client.db(),
+            Game::Pbem(ref game) => game.db(),
+        }
+    }
+
+    /// Remote games are always played on the default scenario,
+    /// see `net::Server::new`
+    fn scenario(&self) -> Scenario {
+        match *self {
+            Game::Local(ref core) => core.scenario().clone(),
+            Game::Remote(ref client) => Scenario::default_scenario(client.db()),
+            Game::Pbem(ref game) => game.core().scenario().clone(),
+        }
+    }
+
+    fn map_size(&self) -> &Size2 {
+        match *self {
+            Game::Local(ref core) => core.map_size(),
+            Game::Remote(ref client) => client.map_size(),
+            Game::Pbem(ref game) => game.core().map_size(),
+        }
+    }
+
+    /// Player whose units are shown
+    fn player_id(&self) -> &PlayerId {
+        match *self {
+            Game::Local(ref core) => core.player_id(),
+            Game::Remote(ref client) => client.player_id(),
+            Game::Pbem(ref game) => game.player_id(),
+        }
+    }
+
+    fn vision_sharing(&self) -> &VisionSharing {
+        match *self {
+            Game::Local(ref core) => core.vision_sharing(),
+            Game::Remote(ref client) => client.vision_sharing(),
+            Game::Pbem(ref game) => game.core().vision_sharing(),
+        }
+    }
+
+    /// Server does not tell about simultaneous turns yet
+    fn turn_mode(&self) -> TurnMode {
+        match *self {
+            Game::Local(ref core) => core.turn_mode().clone(),
+            Game::Remote(_) => TurnMode::Igougo,
+            Game::Pbem(ref game) => game.core().turn_mode().clone(),
+        }
+    }
+
+    /// Planned orders of the player whose units are shown
+    fn orders(&self) -> Option<&OrderQueue> {
+        match *self {
+            Game::Local(ref core) => core.orders(core.player_id()),
+            Game::Remote(_) => None,
+            Game::Pbem(ref game) => game.core().orders(game.player_id()),
+        }
+    }
+
+    /// The server ends the turns of the network games itself,
+    /// its clients only show the time
+    fn time_left(&self, player_id: &PlayerId) -> Option<Duration> {
+        match *self {
+            Game::Local(ref core) => core.time_left(player_id),
+            Game::Remote(ref client) => client.time_left(player_id),
+            Game::Pbem(_) => None,
+        }
+    }
+
+    fn time_bank(&self, player_id: &PlayerId) -> Option<Duration> {
+        match *self {
+            Game::Local(ref core) => core.time_bank(player_id),
+            Game::Remote(ref client) => client.time_bank(player_id),
+            Game::Pbem(_) => None,
+        }
+    }
+
+    fn is_out_of_time(&self) -> bool {
+        match *self {
+            Game::Local(ref core) => core.is_out_of_time(),
+            Game::Remote(_) | Game::Pbem(_) => false,
+        }
+    }
+
+    fn next_player_id(&self, id: &PlayerId) -> PlayerId {
+        match *self {
+            Game::Local(ref core) => core.next_player_id(id),
+            Game::Remote(ref client) => client.player_id().clone(),
+            Game::Pbem(ref game) => game.player_id().clone(),
+        }
+    }
+
+    fn do_command(&mut self, command: Command) {
+        match *self {
+            Game::Local(ref mut core) => core.do_command(command),
+            Game::Remote(ref mut client) => {
+                if let Err(err) = client.do_command(command) {
+                    println!("Can`t send command: {}", err);
+                }
+            },
+            Game::Pbem(ref mut game) => {
+                if let Command::EndTurn = command {
+                    save_turn_file(game, false);
+                } else if let Err(err) = game.do_command(command) {
+                    println!("Bad command: {}", err);
+                }
+            },
+        }
+    }
+
+    fn get_event(&mut self) -> Option<CoreEvent> {
+        match *self {
+            Game::Local(ref mut core) => core.get_event(),
+            Game::Remote(ref mut client) => loop {
+                match client.try_message() {
+                    Ok(Some(ServerMessage::Event(event))) => return Some(event),
+                    Ok(Some(ServerMessage::Rejected(text))) => {
+                        println!("Command rejected: {}", text);
+                    },
+                    Ok(None) => return None,
+                    Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                        return None;
+                    },
+                    Err(err) => println!("Bad message from server: {}", err),
+                }
+            },
+            Game::Pbem(ref mut game) => game.get_event(),
+        }
+    }
+
+    /// Server does not send the events of other players
+    fn observer_views(&self) -> Vec<ObserverView> {
+        let core = match *self {
+            Game::Local(ref core) => core,
+            Game::Remote(_) => return Vec::new(),
+            Game::Pbem(ref game) => game.core(),
+        };
+        if !core.is_observer_enabled() {
+            return Vec::new();
+        }
+        let mut views = vec![ObserverView::God];
+        for player in core.players() {
+            views.push(ObserverView::Player(player.id.clone()));
+        }
+        views
+    }
+
+    /// Views that nobody has watched for too long are closed
+    fn is_observer_view_open(&self, view: &ObserverView) -> bool {
+        match *self {
+            Game::Local(ref core) => core.is_observer_view_open(view),
+            Game::Remote(_) => false,
+            Game::Pbem(ref game) => game.core().is_observer_view_open(view),
+        }
+    }
+
+    fn get_observer_event(&mut self, view: &ObserverView) -> Option<CoreEvent> {
+        match *self {
+            Game::Local(ref mut core) => core.get_observer_event(view),
+            Game::Remote(_) => None,
+            Game::Pbem(ref mut game) => game.get_observer_event(view),
+        }
+    }
+
+    /// Bots of a local game without humans are not started by anybody
+    fn play_bots(&mut self) {
+        if let Game::Local(ref mut core) = *self {
+            if !core.has_human_players() {
+                core.play_bot_turn();
+            }
         }
     }
 }
@@ -344,8 +713,17 @@ pub struct TacticalScreen {
     button_deselect_unit_id: ButtonId,
     button_next_unit_id: ButtonId,
     button_prev_unit_id: ButtonId,
+
+    /// `None` if the game is not timed
+    button_time_id: Option<ButtonId>,
+    time_label: String,
+
     player_info: PlayerInfoManager,
-    core: Core,
+    core: Game,
+
+    /// `None` if the screen shows the current player's view
+    observer_view: Option<ObserverView>,
+    observer_views: Vec<ObserverView>,
     event: Option<CoreEvent>,
     event_visualizer: Option<Box<EventVisualizer>>,
     mesh_ids: MeshIdManager,
@@ -364,16 +742,69 @@ pub struct TacticalScreen {
 }
 
 impl TacticalScreen {
-    pub fn new(context: &mut Context, core_options: &core::Options) -> TacticalScreen {
-        let core = Core::new(core_options);
+    pub fn new(
+        context: &mut Context,
+        core_options: &core::Options,
+    ) -> Result<TacticalScreen, core::StartError> {
+        let core = Core::new(core_options)?;
+        let player_ids: Vec<_> = core.players().iter()
+            .filter(|player| player.class == core::PlayerClass::Human)
+            .map(|player| player.id.clone())
+            .collect();
+        // Bots play against each other and somebody watches them
+        let observer_view = if player_ids.is_empty() {
+            assert!(core_options.observer, "Nobody can see this game");
+            Some(ObserverView::God)
+        } else {
+            None
+        };
+        Ok(TacticalScreen::with_game(
+            context, Game::Local(core), &player_ids, observer_view))
+    }
+
+    pub fn new_client(context: &mut Context, client: Client) -> TacticalScreen {
+        let player_ids = [client.player_id().clone()];
+        TacticalScreen::with_game(context, Game::Remote(client), &player_ids, None)
+    }
+
+    pub fn new_pbem(context: &mut Context, game: PbemGame) -> TacticalScreen {
+        let player_ids = [game.player_id().clone()];
+        TacticalScreen::with_game(context, Game::Pbem(game), &player_ids, None)
+    }
+
+    /// Watch the loaded turn files, the game must be created
+    /// with `Options::observer` and be over
+    pub fn new_replay(context: &mut Context, game: PbemGame) -> TacticalScreen {
+        assert!(game.is_over(), "Unfinished games can`t be replayed");
+        TacticalScreen::with_game(
+            context, Game::Pbem(game), &[], Some(ObserverView::God))
+    }
+
+    fn with_game(
+        context: &mut Context,
+        core: Game,
+        player_ids: &[PlayerId],
+        observer_view: Option<ObserverView>,
+    ) -> TacticalScreen {
         let map_size = core.map_size().clone();
-        let player_info = PlayerInfoManager::new(&map_size, core_options);
+        let observer_views = core.observer_views();
+        let mut view_ids: Vec<_> = player_ids.iter()
+            .map(|id| ViewId::Player(id.clone()))
+            .collect();
+        for view in &observer_views {
+            view_ids.push(ViewId::Observer(view.clone()));
+        }
+        let player_info = PlayerInfoManager::new(&core.scenario(), &view_ids);
+        let view_id = match observer_view {
+            Some(ref view) => ViewId::Observer(view.clone()),
+            None => ViewId::Player(core.player_id().clone()),
+        };
         let floor_tex = load_texture(&mut context.factory, &fs::load("hex.png").into_inner());
         let mut meshes = Vec::new();
         let visible_map_mesh = generate_visible_tiles_mesh(
-            context, &player_info.get(core.player_id()).game_state, floor_tex.clone());
+            context, &player_info.get(&view_id).game_state, floor_tex.clone());
         let fow_map_mesh = generate_fogged_tiles_mesh(
-            context, &player_info.get(core.player_id()).game_state, floor_tex.clone());
+            context, &player_info.get(&view_id).game_state, floor_tex.clone());
         let selection_marker_mesh_id = add_mesh(
             &mut meshes, get_selection_mesh(context));
         let big_building_mesh_w_id = add_mesh(
@@ -388,6 +819,10 @@ impl TacticalScreen {
             &mut meshes, get_marker(context, "flag1.png"));
         let marker_2_mesh_id = add_mesh(
             &mut meshes, get_marker(context, "flag2.png"));
+        let noise_marker_mesh_id = add_mesh(
+            &mut meshes, get_selection_mesh(context));
+        let order_marker_mesh_id = add_mesh(
+            &mut meshes, get_selection_mesh(context));
         let unit_type_visual_info
             = get_unit_type_visual_info(core.db(), context, &mut meshes);
         let mut camera = Camera::new(&context.win_size);
@@ -395,8 +830,12 @@ impl TacticalScreen {
         camera.set_pos(get_initial_camera_pos(&map_size));
         let mut button_manager = ButtonManager::new();
         let mut pos = ScreenPos{v: Vector2{x: 10, y: 10}};
+        let end_turn_label = match core.turn_mode() {
+            TurnMode::Igougo => "end turn",
+            TurnMode::Wego => "end planning",
+        };
         let button_end_turn_id = button_manager.add_button(
-            Button::new(context, "end turn", &pos));
+            Button::new(context, end_turn_label, &pos));
         pos.v.y += (button_manager.buttons()[&button_end_turn_id].size().h as ZFloat * 1.2) as ZInt; // TODO
         let button_deselect_unit_id = button_manager.add_button(
             Button::new(context, "[X]", &pos));
@@ -406,6 +845,14 @@ impl TacticalScreen {
         pos.v.x += button_manager.buttons()[&button_prev_unit_id].size().w;
         let button_next_unit_id = button_manager.add_button(
             Button::new(context, "[>]", &pos));
+        let time_label = time_label(&core, core.player_id());
+        let button_time_id = if time_label.is_empty() {
+            None
+        } else {
+            pos.v.x = 10;
+            pos.v.y += (button_manager.buttons()[&button_next_unit_id].size().h as ZFloat * 1.2) as ZInt; // TODO
+            Some(button_manager.add_button(Button::new(context, &time_label, &pos)))
+        };
         let mesh_ids = MeshIdManager {
             big_building_mesh_w_id: big_building_mesh_w_id,
             building_mesh_w_id: building_mesh_w_id,
@@ -413,6 +860,8 @@ impl TacticalScreen {
             shell_mesh_id: shell_mesh_id,
             marker_1_mesh_id: marker_1_mesh_id,
             marker_2_mesh_id: marker_2_mesh_id,
+            noise_marker_mesh_id: noise_marker_mesh_id,
+            order_marker_mesh_id: order_marker_mesh_id,
         };
         let map_text_manager = MapTextManager::new();
         let (tx, rx) = channel();
@@ -423,8 +872,12 @@ impl TacticalScreen {
             button_deselect_unit_id: button_deselect_unit_id,
             button_prev_unit_id: button_prev_unit_id,
             button_next_unit_id: button_next_unit_id,
+            button_time_id: button_time_id,
+            time_label: time_label,
             player_info: player_info,
             core: core,
+            observer_view: observer_view,
+            observer_views: observer_views,
             event: None,
             event_visualizer: None,
             mesh_ids: mesh_ids,
@@ -446,23 +899,7 @@ impl TacticalScreen {
     }
 
     fn pick_world_pos(&self, context: &Context) -> WorldPos {
-        let im = self.camera.mat().invert()
-            .expect("Can`t invert camera matrix");
-        let w = context.win_size.w as ZFloat;
-        let h = context.win_size.h as ZFloat;
-        let x = context.mouse().pos.v.x as ZFloat;
-        let y = context.mouse().pos.v.y as ZFloat;
-        let x = (2.0 * x) / w - 1.0;
-        let y = 1.0 - (2.0 * y) / h;
-        let p0_raw = im * Vector4{x: x, y: y, z: 0.0, w: 1.0};
-        let p0 = (p0_raw / p0_raw.w).truncate();
-        let p1_raw = im * Vector4{x: x, y: y, z: 1.0, w: 1.0};
-        let p1 = (p1_raw / p1_raw.w).truncate();
-        let plane = Plane::from_abcd(0.0, 0.0, 1.0, 0.0);
-        let ray = Ray::new(Point3::from_vec(p0), p1 - p0);
-        let p = (plane, ray).intersection()
-            .expect("Can`t find mouse ray/plane intersection");
-        WorldPos{v: p.to_vec()}
+        pick_world_pos(context, &self.camera)
     }
 
     fn add_marker(&mut self, pos: &WorldPos) {
@@ -511,39 +948,122 @@ impl TacticalScreen {
         }
     }
 
+    fn view_id(&self) -> ViewId {
+        match self.observer_view {
+            Some(ref view) => ViewId::Observer(view.clone()),
+            None => ViewId::Player(self.core.player_id().clone()),
+        }
+    }
+
+    fn is_observing(&self) -> bool {
+        self.observer_view.is_some()
+    }
+
+    /// Moves and attacks are only planned and nothing
+    /// happens until all players end the planning
+    fn is_planning(&self) -> bool {
+        !self.is_observing() && self.core.turn_mode() == TurnMode::Wego
+    }
+
+    fn has_move_order(&self, unit_id: &UnitId) -> bool {
+        self.core.orders()
+            .and_then(|orders| orders.move_order(unit_id))
+            .is_some()
+    }
+
+    fn has_attack_order(&self, unit_id: &UnitId) -> bool {
+        self.core.orders()
+            .and_then(|orders| orders.attack_order(unit_id))
+            .is_some()
+    }
+
+    /// Cycles through the observer views and the player's own view
+    fn switch_view(&mut self, context: &mut Context) {
+        if self.event_visualizer.is_some() {
+            return;
+        }
+        let mut views: Vec<_> = self.observer_views.iter()
+            .filter(|view| self.core.is_observer_view_open(view))
+            .map(|view| Some(view.clone()))
+            .collect();
+        if self.player_info.players_count() > 0 {
+            views.push(None);
+        }
+        if views.is_empty() {
+            return;
+        }
+        let index = match views.iter().position(|view| *view == self.observer_view) {
+            Some(index) => (index + 1) % views.len(),
+            None => 0,
+        };
+        self.deselect_unit();
+        self.observer_view = views[index].clone();
+        let view_id = self.view_id();
+        let state = &self.player_info.get(&view_id).game_state;
+        self.visible_map_mesh = generate_visible_tiles_mesh(context, state, self.floor_tex.clone());
+        self.fow_map_mesh = generate_fogged_tiles_mesh(context, state, self.floor_tex.clone());
+    }
+
+    fn get_event(&mut self) -> Option<CoreEvent> {
+        match self.observer_view {
+            Some(ref view) => self.core.get_observer_event(view),
+            None => self.core.get_event(),
+        }
+    }
+
     fn end_turn(&mut self, context: &mut Context) {
-        if self.player_info.info.len() > 1 {
+        if self.is_observing() {
+            return;
+        }
+        if self.player_info.players_count() > 1 {
             let next_id = self.core.next_player_id(self.core.player_id());
-            let screen = Box::new(EndTurnScreen::new(context, &next_id));
+            let screen = Box::new(EndTurnScreen::new(
+                context,
+                &next_id,
+                self.core.time_left(&next_id),
+                self.core.time_bank(&next_id),
+            ));
             context.add_command(ScreenCommand::PushScreen(screen));
         }
         self.deselect_unit();
         self.core.do_command(Command::EndTurn);
     }
 
+    /// Only play-by-email games can be ended by a player
+    fn end_game(&mut self) {
+        if self.is_observing() {
+            return;
+        }
+        self.deselect_unit();
+        if let Game::Pbem(ref mut game) = self.core {
+            save_turn_file(game, true);
+        }
+    }
+
     fn deselect_unit(&mut self) {
         self.selected_unit_id = None;
-        let i = self.player_info.get_mut(self.core.player_id());
+        let view_id = self.view_id();
+        let i = self.player_info.get_mut(&view_id);
         self.selection_manager.deselect(&mut i.scene);
         self.walkable_mesh = None;
         self.targets_mesh = None;
     }
 
     fn current_state(&self) -> &PartialState {
-        &self.player_info.get(self.core.player_id()).game_state
+        &self.player_info.get(&self.view_id()).game_state
     }
 
-    fn can_unload_unit(&self, transporter_id: &UnitId, pos: &MapPos) -> Option<ExactPos> {
+    fn can_unload_unit(
+        &self,
+        transporter_id: &UnitId,
+        passenger_id: &UnitId,
+        pos: &MapPos,
+    ) -> Option<ExactPos> {
         let state = self.current_state();
-        let transporter = state.unit(&transporter_id);
-        let passenger_id = match transporter.passenger_id {
-            Some(ref id) => id.clone(),
-            None => return None,
-        };
         let exact_pos = match get_free_exact_pos(
             self.core.db(),
             state,
-            &state.unit(&passenger_id).type_id,
+            &state.unit(passenger_id).type_id,
             pos,
         ) {
             Some(pos) => pos,
@@ -560,7 +1080,6 @@ impl TacticalScreen {
         }
     }
 
-    // TODO: show commands preview
     fn try_create_context_menu_popup(
         &mut self,
         context: &mut Context,
@@ -581,7 +1100,7 @@ impl TacticalScreen {
         &mut self,
         pos: &MapPos,
     ) -> context_menu_popup::Options {
-        let i = self.player_info.get(self.core.player_id());
+        let i = self.player_info.get(&self.view_id());
         let state = &i.game_state;
         let db = self.core.db();
         let mut options = context_menu_popup::Options::new();
@@ -603,43 +1122,57 @@ impl TacticalScreen {
                             transporter_id: selected_unit_id.clone(),
                             passenger_id: unit_id.clone(),
                         };
-                        if check_command(db, state, &load_command).is_ok() {
-                            options.loads.push(unit_id.clone());
+                        if !self.is_planning()
+                            && check_command(db, state, &load_command).is_ok()
+                        {
+                            if db.unit_type(&unit.type_id).is_towable {
+                                options.hooks.push(unit_id.clone());
+                            } else {
+                                options.loads.push(unit_id.clone());
+                            }
                         }
                     }
                 } else {
-                    let attack_command = Command::AttackUnit {
+                    let vision_sharing = self.core.vision_sharing();
+                    if !self.has_attack_order(&selected_unit_id)
+                        && can_attack(db, state, vision_sharing, &selected_unit_id, unit_id)
+                    {
+                        let odds = get_attack_odds(
+                            db, state, state.unit(&selected_unit_id), unit);
+                        options.attacks.push((unit_id.clone(), odds));
+                    }
+                    let assault_command = Command::Assault {
                         attacker_id: selected_unit_id.clone(),
                         defender_id: unit_id.clone(),
                     };
-                    if check_command(db, state, &attack_command).is_ok() {
-                        options.attacks.push(unit_id.clone());
+                    if !self.is_planning()
+                        && check_command(db, state, &assault_command).is_ok()
+                    {
+                        options.assaults.push(unit_id.clone());
                     }
                 }
             }
-            if let Some(pos) = self.can_unload_unit(&selected_unit_id, pos) {
-                options.unload_pos = Some(pos);
+            if self.is_planning() {
+                if !self.has_move_order(&selected_unit_id) {
+                    self.add_move_options(&mut options, &selected_unit_id, pos);
+                }
+                return options;
             }
-            if let Some(destination) = get_free_exact_pos(
-                db, state, &state.unit(&selected_unit_id).type_id, pos,
-            ) {
-                if let Some(path) = i.pathfinder.get_path(&destination) {
-                    if check_command(db, state, &Command::Move {
-                        unit_id: selected_unit_id.clone(),
-                        path: path.clone(),
-                        mode: MoveMode::Fast,
-                    }).is_ok() {
-                        options.move_pos = Some(destination.clone());
-                    }
-                    if check_command(db, state, &Command::Move {
-                        unit_id: selected_unit_id.clone(),
-                        path: path.clone(),
-                        mode: MoveMode::Hunt,
-                    }).is_ok() {
-                        options.hunt_pos = Some(destination.clone());
-                    }
+            for passenger_id in &state.unit(&selected_unit_id).passenger_ids {
+                if let Some(pos) = self.can_unload_unit(
+                    &selected_unit_id, passenger_id, pos)
+                {
+                    options.unloads.push((passenger_id.clone(), pos));
+                }
+            }
+            if let Some(ref towed_unit_id) = state.unit(&selected_unit_id).towed_unit_id {
+                if let Some(pos) = self.can_unload_unit(
+                    &selected_unit_id, towed_unit_id, pos)
+                {
+                    options.unhooks.push((towed_unit_id.clone(), pos));
                 }
             }
+            self.add_move_options(&mut options, &selected_unit_id, pos);
         } else {
             for unit_id in &unit_ids {
                 let unit = state.unit(&unit_id);
@@ -651,7 +1184,43 @@ impl TacticalScreen {
         options
     }
 
+    fn add_move_options(
+        &self,
+        options: &mut context_menu_popup::Options,
+        unit_id: &UnitId,
+        pos: &MapPos,
+    ) {
+        let i = self.player_info.get(&self.view_id());
+        let state = &i.game_state;
+        let db = self.core.db();
+        let destination = match get_free_exact_pos(
+            db, state, &state.unit(unit_id).type_id, pos,
+        ) {
+            Some(destination) => destination,
+            None => return,
+        };
+        if let Some(path) = i.pathfinder.get_path(&destination) {
+            if check_command(db, state, &Command::Move {
+                unit_id: unit_id.clone(),
+                path: path.clone(),
+                mode: MoveMode::Fast,
+            }).is_ok() {
+                options.move_pos = Some(destination.clone());
+            }
+            if check_command(db, state, &Command::Move {
+                unit_id: unit_id.clone(),
+                path: path.clone(),
+                mode: MoveMode::Hunt,
+            }).is_ok() {
+                options.hunt_pos = Some(destination.clone());
+            }
+        }
+    }
+
     fn create_unit(&mut self, context: &Context) {
+        if self.is_observing() {
+            return;
+        }
         let pick_result = self.pick_tile(context);
         if let Some(ref pos) = pick_result {
             let type_id = self.core.db().unit_type_id("soldier");
@@ -669,14 +1238,15 @@ impl TacticalScreen {
     // TODO: add ability to select enemy units
     fn select_unit(&mut self, context: &mut Context, unit_id: &UnitId) {
         self.selected_unit_id = Some(unit_id.clone());
-        let mut i = self.player_info.get_mut(self.core.player_id());
+        let view_id = self.view_id();
+        let mut i = self.player_info.get_mut(&view_id);
         let state = &i.game_state;
         let pf = &mut i.pathfinder;
         pf.fill_map(self.core.db(), state, state.unit(unit_id));
         self.walkable_mesh = Some(build_walkable_mesh(
             context, pf, state.map(), &state.unit(unit_id).move_points));
         self.targets_mesh = Some(build_targets_mesh(
-            self.core.db(), context, state, unit_id));
+            self.core.db(), context, state, self.core.vision_sharing(), unit_id));
         let scene = &mut i.scene;
         self.selection_manager.create_selection_marker(
             state, scene, unit_id);
@@ -684,7 +1254,8 @@ impl TacticalScreen {
 
     fn move_unit(&mut self, pos: &ExactPos, move_mode: &MoveMode) {
         let unit_id = self.selected_unit_id.as_ref().unwrap();
-        let i = self.player_info.get_mut(self.core.player_id());
+        let view_id = self.view_id();
+        let i = self.player_info.get_mut(&view_id);
         // TODO: duplicated get_path =\
         let path = i.pathfinder.get_path(pos).unwrap();
         self.core.do_command(Command::Move {
@@ -695,25 +1266,11 @@ impl TacticalScreen {
     }
 
     fn handle_camera_move(&mut self, context: &Context, pos: &ScreenPos) {
-        let diff = pos.v - context.mouse().pos.v;
-        let camera_move_speed = geom::HEX_EX_RADIUS * 12.0;
-        let per_x_pixel = camera_move_speed / (context.win_size.w as ZFloat);
-        let per_y_pixel = camera_move_speed / (context.win_size.h as ZFloat);
-        self.camera.move_camera(
-            rad(PI), diff.x as ZFloat * per_x_pixel);
-        self.camera.move_camera(
-            rad(PI * 1.5), diff.y as ZFloat * per_y_pixel);
+        move_camera(context, &mut self.camera, pos);
     }
 
     fn handle_camera_rotate(&mut self, context: &Context, pos: &ScreenPos) {
-        let diff = pos.v - context.mouse().pos.v;
-        let per_x_pixel = PI / (context.win_size.w as ZFloat);
-        // TODO: get max angles from camera
-        let per_y_pixel = (PI / 4.0) / (context.win_size.h as ZFloat);
-        self.camera.add_horizontal_angle(
-            rad(diff.x as ZFloat * per_x_pixel));
-        self.camera.add_vertical_angle(
-            rad(diff.y as ZFloat * per_y_pixel));
+        rotate_camera(context, &mut self.camera, pos);
     }
 
     fn handle_event_mouse_move(&mut self, context: &Context, pos: &ScreenPos) {
@@ -798,6 +1355,12 @@ impl TacticalScreen {
             VirtualKeyCode::Equals | VirtualKeyCode::Key2 => {
                 self.camera.change_zoom(0.7);
             },
+            VirtualKeyCode::V => {
+                self.switch_view(context);
+            },
+            VirtualKeyCode::G => {
+                self.end_game();
+            },
             _ => println!("Unknown key pressed"),
         }
     }
@@ -840,7 +1403,7 @@ impl TacticalScreen {
     }
 
     fn scene(&self) -> &Scene {
-        &self.player_info.get(self.core.player_id()).scene
+        &self.player_info.get(&self.view_id()).scene
     }
 
     fn draw_scene_node(
@@ -874,6 +1437,76 @@ impl TacticalScreen {
         }
     }
 
+    fn draw_ghosts(&self, context: &mut Context) {
+        let state = self.current_state();
+        for (_, ghost) in state.ghosts() {
+            let age = state.turn() - ghost.turn;
+            // fades out completely when the ghost is forgotten
+            let alpha = GHOST_ALPHA
+                * (1.0 - age as ZFloat / (GHOST_LIFETIME + 1) as ZFloat);
+            if alpha <= 0.0 {
+                continue;
+            }
+            let color = [1.0, 1.0, 1.0, alpha];
+            let pos = geom::exact_pos_to_world_pos(&ghost.pos);
+            let m = self.camera.mat() * Matrix4::from_translation(pos.v);
+            if !ghost.is_contact {
+                let mesh_id = &self.unit_type_visual_info.get(&ghost.type_id).mesh_id;
+                context.data.mvp = m.into();
+                context.draw_mesh_with_color(color, &self.meshes[mesh_id.id as usize]);
+            }
+            let marker_mesh_id = get_marker_mesh_id(&self.mesh_ids, &ghost.player_id);
+            let marker_pos = Vector3{x: 0.0, y: 0.0, z: geom::HEX_EX_RADIUS / 2.0};
+            context.data.mvp = (m * Matrix4::from_translation(marker_pos)).into();
+            context.draw_mesh_with_color(color, &self.meshes[marker_mesh_id.id as usize]);
+        }
+    }
+
+    fn draw_noises(&self, context: &mut Context) {
+        let state = self.current_state();
+        let mesh = &self.meshes[self.mesh_ids.noise_marker_mesh_id.id as usize];
+        for noise in state.noises() {
+            let color = match noise.class {
+                NoiseClass::Engine => NOISE_ENGINE_COLOR,
+                NoiseClass::Gunfire => NOISE_GUNFIRE_COLOR,
+            };
+            let pos = geom::map_pos_to_world_pos(&noise.pos);
+            let m = self.camera.mat() * Matrix4::from_translation(geom::lift(pos.v));
+            context.data.mvp = m.into();
+            context.draw_mesh_with_color(color, mesh);
+        }
+    }
+
+    /// Planned orders of the current player: path tiles and targets
+    fn draw_orders(&self, context: &mut Context) {
+        if !self.is_planning() {
+            return;
+        }
+        let orders = match self.core.orders() {
+            Some(orders) => orders,
+            None => return,
+        };
+        let state = self.current_state();
+        let mesh = &self.meshes[self.mesh_ids.order_marker_mesh_id.id as usize];
+        for order in orders.orders() {
+            let (positions, color) = match *order {
+                Order::Move{ref path, ..} => (path.clone(), ORDER_MOVE_COLOR),
+                Order::Attack{ref defender_id, ..} => {
+                    match state.units().get(defender_id) {
+                        Some(defender) => (vec![defender.pos.clone()], ORDER_ATTACK_COLOR),
+                        None => continue,
+                    }
+                },
+            };
+            for pos in &positions {
+                let pos = geom::exact_pos_to_world_pos(pos);
+                let m = self.camera.mat() * Matrix4::from_translation(geom::lift(pos.v));
+                context.data.mvp = m.into();
+                context.draw_mesh_with_color(color, mesh);
+            }
+        }
+    }
+
     fn draw_map(&mut self, context: &mut Context) {
         context.data.mvp = self.camera.mat().into();
         context.data.basic_color = [0.85, 0.85, 0.85, 1.0];
@@ -886,6 +1519,9 @@ impl TacticalScreen {
         context.data.basic_color = [1.0, 1.0, 1.0, 1.0];
         self.draw_scene_nodes(context);
         self.draw_map(context);
+        self.draw_ghosts(context);
+        self.draw_noises(context);
+        self.draw_orders(context);
         if let Some(ref walkable_mesh) = self.walkable_mesh {
             context.data.basic_color = [0.0, 0.0, 1.0, 1.0];
             context.draw_mesh(walkable_mesh);
@@ -895,7 +1531,8 @@ impl TacticalScreen {
             context.draw_mesh(targets_mesh);
         }
         if let Some(ref mut event_visualizer) = self.event_visualizer {
-            let i = self.player_info.get_mut(self.core.player_id());
+            let view_id = self.view_id();
+            let i = self.player_info.get_mut(&view_id);
             event_visualizer.draw(&mut i.scene, dtime);
         }
     }
@@ -910,37 +1547,15 @@ impl TacticalScreen {
     }
 
     fn pick_tile(&mut self, context: &Context) -> Option<MapPos> {
-        let p = self.pick_world_pos(context);
-        let origin = MapPos{v: Vector2 {
-            x: (p.v.x / (geom::HEX_IN_RADIUS * 2.0)) as ZInt,
-            y: (p.v.y / (geom::HEX_EX_RADIUS * 1.5)) as ZInt,
-        }};
-        let origin_world_pos = geom::map_pos_to_world_pos(&origin);
-        let mut closest_map_pos = origin.clone();
-        let mut min_dist = (origin_world_pos.v - p.v).magnitude();
-        for map_pos in spiral_iter(&origin, 1) {
-            let pos = geom::map_pos_to_world_pos(&map_pos);
-            let d = (pos.v - p.v).magnitude();
-            if d < min_dist {
-                min_dist = d;
-                closest_map_pos = map_pos;
-            }
-        }
-        let pos = closest_map_pos;
-        let state = self.current_state();
-        if state.map().is_inboard(&pos) {
-            Some(pos)
-        } else {
-            None
-        }
+        pick_tile(context, &self.camera, self.current_state().map().size())
     }
 
     fn make_event_visualizer(
         &mut self,
         event: &CoreEvent,
     ) -> Box<EventVisualizer> {
-        let current_player_id = self.core.player_id();
-        let mut i = self.player_info.get_mut(current_player_id);
+        let view_id = self.view_id();
+        let mut i = self.player_info.get_mut(&view_id);
         let scene = &mut i.scene;
         let state = &i.game_state;
         match event {
@@ -949,7 +1564,10 @@ impl TacticalScreen {
                 let visual_info = self.unit_type_visual_info.get(&type_id);
                 EventMoveVisualizer::new(scene, unit_id, visual_info, to)
             },
-            &CoreEvent::EndTurn{..} => {
+            &CoreEvent::EndTurn{..}
+                | &CoreEvent::EndPlanning{..}
+                | &CoreEvent::ConvertAp{..} =>
+            {
                 EventEndTurnVisualizer::new()
             },
             &CoreEvent::CreateUnit{ref unit_info} => {
@@ -977,6 +1595,7 @@ impl TacticalScreen {
                 EventShowUnitVisualizer::new(
                     self.core.db(),
                     scene,
+                    state,
                     unit_info,
                     mesh_id,
                     marker_mesh_id,
@@ -996,6 +1615,7 @@ impl TacticalScreen {
                 let unit_type_visual_info
                     = self.unit_type_visual_info.get(&type_id);
                 EventLoadUnitVisualizer::new(
+                    self.core.db(),
                     scene,
                     state,
                     passenger_id,
@@ -1022,6 +1642,26 @@ impl TacticalScreen {
                     &mut self.map_text_manager,
                 )
             },
+            &CoreEvent::BailOut{ref unit_info, ref from, ref killed, ..} => {
+                let unit_type_visual_info
+                    = self.unit_type_visual_info.get(&unit_info.type_id);
+                let mesh_id = &self.unit_type_visual_info
+                    .get(&unit_info.type_id).mesh_id;
+                let marker_mesh_id = get_marker_mesh_id(
+                    &self.mesh_ids, &unit_info.player_id);
+                EventBailOutVisualizer::new(
+                    self.core.db(),
+                    state,
+                    scene,
+                    unit_info,
+                    *killed,
+                    mesh_id,
+                    marker_mesh_id,
+                    from,
+                    unit_type_visual_info,
+                    &mut self.map_text_manager,
+                )
+            },
             &CoreEvent::SetReactionFireMode{ref unit_id, ref mode} => {
                 EventSetReactionFireModeVisualizer::new(
                     state,
@@ -1030,6 +1670,9 @@ impl TacticalScreen {
                     &mut self.map_text_manager,
                 )
             },
+            &CoreEvent::Noise{ref pos, ref class, ..} => {
+                EventNoiseVisualizer::new(pos, class, &mut self.map_text_manager)
+            },
         }
     }
 
@@ -1046,7 +1689,8 @@ impl TacticalScreen {
         if self.is_event_visualization_finished() {
             self.end_event_visualization(context);
         } else {
-            let i = &mut self.player_info.get_mut(self.core.player_id());
+            let view_id = self.view_id();
+            let i = &mut self.player_info.get_mut(&view_id);
             self.selection_manager.deselect(&mut i.scene);
             self.walkable_mesh = None;
             self.targets_mesh = None;
@@ -1059,7 +1703,8 @@ impl TacticalScreen {
         if let Some(CoreEvent::AttackUnit{ref attack_info})
             = self.event
         {
-            let mut i = self.player_info.get_mut(self.core.player_id());
+            let view_id = self.view_id();
+            let mut i = self.player_info.get_mut(&view_id);
             let state = &mut i.game_state;
             let selected_unit_id = match self.selected_unit_id {
                 Some(ref id) => id.clone(),
@@ -1077,7 +1722,8 @@ impl TacticalScreen {
     fn end_event_visualization(&mut self, context: &mut Context) {
         self.attacker_died_from_reaction_fire();
         {
-            let i = self.player_info.get_mut(self.core.player_id());
+            let view_id = self.view_id();
+            let i = self.player_info.get_mut(&view_id);
             let scene = &mut i.scene;
             let state = &mut i.game_state;
             self.event_visualizer.as_mut().unwrap().end(scene, state);
@@ -1087,17 +1733,39 @@ impl TacticalScreen {
         }
         self.event_visualizer = None;
         self.event = None;
-        if let Some(event) = self.core.get_event() {
+        if let Some(event) = self.get_event() {
             self.start_event_visualization(context, event);
         } else if let Some(ref unit_id) = self.selected_unit_id.clone() {
             self.select_unit(context, unit_id);
         }
     }
 
+    /// Redraws the clock only when the shown seconds change
+    fn update_time_label(&mut self, context: &mut Context) {
+        let id = match self.button_time_id {
+            Some(ref id) => id.clone(),
+            None => return,
+        };
+        let label = time_label(&self.core, self.core.player_id());
+        if label == self.time_label {
+            return;
+        }
+        let pos = self.button_manager.buttons()[&id].pos().clone();
+        let button = Button::new(context, &label, &pos);
+        self.button_manager.buttons_mut().insert(id, button);
+        self.time_label = label;
+    }
+
     fn logic(&mut self, context: &mut Context) {
         if self.event_visualizer.is_none() {
-            if let Some(event) = self.core.get_event() {
+            if !self.is_observing() && self.core.is_out_of_time() {
+                println!("Time is out");
+                self.end_turn(context);
+            }
+            if let Some(event) = self.get_event() {
                 self.start_event_visualization(context, event);
+            } else {
+                self.core.play_bots();
             }
         } else if self.is_event_visualization_finished() {
             self.end_event_visualization(context);
@@ -1130,21 +1798,22 @@ impl TacticalScreen {
                     defender_id: id.clone(),
                 });
             },
+            context_menu_popup::Command::Assault{id} => {
+                self.core.do_command(Command::Assault {
+                    attacker_id: selected_unit_id.clone(),
+                    defender_id: id.clone(),
+                });
+            },
             context_menu_popup::Command::LoadUnit{passenger_id} => {
                 self.core.do_command(Command::LoadUnit {
                     transporter_id: selected_unit_id.clone(),
                     passenger_id: passenger_id.clone(),
                 });
             },
-            context_menu_popup::Command::UnloadUnit{pos} => {
-                let passenger_id = {
-                    let transporter = self.current_state()
-                        .unit(&selected_unit_id);
-                    transporter.passenger_id.clone().unwrap()
-                };
+            context_menu_popup::Command::UnloadUnit{passenger_id, pos} => {
                 self.core.do_command(Command::UnloadUnit {
                     transporter_id: selected_unit_id.clone(),
-                    passenger_id: passenger_id.clone(),
+                    passenger_id: passenger_id,
                     pos: pos,
                 });
             },
@@ -1161,6 +1830,10 @@ impl TacticalScreen {
                 });
             },
         }
+        // planned orders produce no events that would update the selection
+        if self.is_planning() {
+            self.deselect_unit();
+        }
     }
 
     fn handle_context_menu_popup_commands(&mut self, context: &mut Context) {
@@ -1173,6 +1846,7 @@ impl TacticalScreen {
 impl Screen for TacticalScreen {
     fn tick(&mut self, context: &mut Context, dtime: &Time) {
         self.logic(context);
+        self.update_time_label(context);
         self.draw(context, dtime);
         self.handle_context_menu_popup_commands(context);
     }